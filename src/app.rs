@@ -6,7 +6,7 @@ use leptos_router::{
 
 use crate::{
     components::navbar::NavBar, 
-    pages::{home::Home, not_found::NotFound, sign_in::SignIn, test::Test, sign_out::SignOut}, 
+    pages::{home::Home, not_found::NotFound, sign_in::SignIn, test::Test, sign_out::SignOut, study::{Lessons, Reviews}, import_deck::ImportDeck, export_deck::ExportDeck, browse_decks::BrowseDecks, collaborate::Collaborate}, 
     utils::user_types::setup_user
 };
 
@@ -46,6 +46,12 @@ pub fn App() -> impl IntoView {
                 <Route path=StaticSegment("/sign-in") view=SignIn/>
                 <Route path=StaticSegment("/sign-out") view=SignOut/>
                 <Route path=StaticSegment("/test") view=Test/>
+                <Route path=StaticSegment("/lessons") view=Lessons/>
+                <Route path=StaticSegment("/reviews") view=Reviews/>
+                <Route path=StaticSegment("/import-deck") view=ImportDeck/>
+                <Route path=StaticSegment("/export-deck") view=ExportDeck/>
+                <Route path=StaticSegment("/browse-decks") view=BrowseDecks/>
+                <Route path=StaticSegment("/collaborate") view=Collaborate/>
             </Routes>
         </Router>
     }
@@ -1,19 +1,32 @@
+use std::collections::HashMap;
+
 use leptos::either::Either;
 use leptos::{prelude::*, svg};
 use leptos::web_sys::js_sys;
 use leptos_icons::Icon;
 
-use crate::utils::date_and_time::ThreeCalendarMonths;
-use crate::utils::shared_utilities::get_fake_review_schedule;
+use crate::utils::cache_db_interface::get_review_states;
+use crate::utils::date_and_time::{PartialDate, ThreeCalendarMonths};
+use crate::utils::scheduler::get_review_schedule;
+use crate::utils::user_types::UserState;
 use crate::utils::{shared_truth::CALENDAR_BG, database_types::DeckId, date_and_time::{CalendarState, Date}, ui::{Color, Shadow}};
 
+pub type ReviewSchedule = (HashMap<PartialDate, usize>, usize);
+
 #[component]
 pub fn Calendar(current_deck: RwSignal<DeckId>) -> impl IntoView {
+    let user_state = expect_context::<RwSignal<UserState>>();
     let utc_date_on_init = Date::now();
     let todays_date = RwSignal::new(utc_date_on_init);
     let dates = RwSignal::new(utc_date_on_init.get_3_calendar_months());
     let selected_date = RwSignal::new(utc_date_on_init);
     let item_height = 2.5;
+
+    let review_states = Resource::new(
+        move || (current_deck.get(), user_state.get()),
+        |(deck_id, user_state)| get_review_states(deck_id, user_state)
+    );
+    let review_schedule = Memo::new(move |_| get_review_schedule(&review_states.get().unwrap_or_default()));
     
 
     let call_effect = RwSignal::new(0);
@@ -52,7 +65,7 @@ pub fn Calendar(current_deck: RwSignal<DeckId>) -> impl IntoView {
     
     let output_dates = move |date: Date, which_month: CalendarState| {
         view! {
-            <CalendarItem dates item_type=CalendarItemType::Day(date, selected_date, current_deck, which_month, item_height)/>
+            <CalendarItem dates item_type=CalendarItemType::Day(date, selected_date, review_schedule, which_month, item_height)/>
         }
     };
 
@@ -186,8 +199,8 @@ pub fn Calendar(current_deck: RwSignal<DeckId>) -> impl IntoView {
         hide_button(current_3_calendar.currently_displayed);
     };
 
-    let get_selected_date_review_count = move |selected_date: Date, todays_date: Date, deck_id: DeckId| {
-        let (schedule, _) = get_fake_review_schedule(deck_id);
+    let get_selected_date_review_count = move |selected_date: Date, todays_date: Date, review_schedule: ReviewSchedule| {
+        let (schedule, _) = review_schedule;
         let reviews_on_selected_date = *schedule.get(&selected_date.to_month_and_day()).unwrap_or(&0);
 
         let mut what_day = "that day";
@@ -226,7 +239,7 @@ pub fn Calendar(current_deck: RwSignal<DeckId>) -> impl IntoView {
                 {move || dates.get().next_month.map(next_month).into_iter().collect::<Vec<_>>()}
             </ol>
             <div>
-                {move || get_selected_date_review_count(selected_date.get(), todays_date.get(), current_deck.get())}
+                {move || get_selected_date_review_count(selected_date.get(), todays_date.get(), review_schedule.get())}
             </div>
         </div>
     }
@@ -237,8 +250,8 @@ pub fn CalendarItem(dates: RwSignal<ThreeCalendarMonths>, item_type: CalendarIte
     let classes = |date: Date| format!("calendar-day {} {} {}", date.get_day_of_week(), date.day, date.month.to_string());
     let bg_color = Color::OffWhite;
 
-    let apply_heat = move |deck_id: DeckId, date: Date, item_height: f64| {
-        let (schedule, highest_reviews) = get_fake_review_schedule(deck_id);
+    let apply_heat = move |review_schedule: ReviewSchedule, date: Date, item_height: f64| {
+        let (schedule, highest_reviews) = review_schedule;
         let review_count = *schedule.get(&date.to_month_and_day()).unwrap_or(&0);
 
         let mut box_shadow = Shadow::dark();
@@ -286,10 +299,10 @@ pub fn CalendarItem(dates: RwSignal<ThreeCalendarMonths>, item_type: CalendarIte
     view! {
         {match item_type {
             CalendarItemType::Label(heading) => Either::Left(view! {<li class="calendar-label">{heading}</li>}),
-            CalendarItemType::Day(date, selected_date, current_deck, which_month, item_height) => Either::Right(view! {
+            CalendarItemType::Day(date, selected_date, review_schedule, which_month, item_height) => Either::Right(view! {
                 <li class=classes(date) class=("selected", move || selected_date.get() == date) 
                 style=("display", move || {display(dates.get(), which_month, date)})  style=("opacity", move || {focus_month(dates.get(), date)}) 
-                style=("box-shadow", move || {apply_heat(current_deck.get(), date, item_height)}) style:background-color=bg_color.hex() 
+                style=("box-shadow", move || {apply_heat(review_schedule.get(), date, item_height)}) style:background-color=bg_color.hex() 
                 on:click=move |_| selected_date.set(date)>
                     {date.day.to_string()}
                 </li>
@@ -301,5 +314,5 @@ pub fn CalendarItem(dates: RwSignal<ThreeCalendarMonths>, item_type: CalendarIte
 #[derive(Clone)]
 pub enum CalendarItemType {
     Label(&'static str),
-    Day(Date /*date which item represents on calendar*/, RwSignal<Date> /*Calendar date selected*/, RwSignal<DeckId> /*id of deck selected*/, CalendarState /*which calendar block this item belongs in*/, f64 /*item_height*/),
+    Day(Date /*date which item represents on calendar*/, RwSignal<Date> /*Calendar date selected*/, Memo<ReviewSchedule> /*upcoming reviews of deck selected*/, CalendarState /*which calendar block this item belongs in*/, f64 /*item_height*/),
 }
\ No newline at end of file
@@ -4,4 +4,5 @@ pub mod button;
 pub mod avatar;
 pub mod navbar;
 pub mod calendar;
-pub mod toggle_slider;
\ No newline at end of file
+pub mod toggle_slider;
+pub mod offline_status;
\ No newline at end of file
@@ -1,7 +1,7 @@
 use leptos::prelude::*;
 
 use crate::{
-    components::{avatar::ThisUserAvatar, button::{Button, ButtonConfig, ButtonType}}, 
+    components::{avatar::ThisUserAvatar, button::{Button, ButtonConfig, ButtonType}, offline_status::OfflineStatus}, 
     utils::{
         shared_truth::LOGO_PATH, 
         user_types::UserState,
@@ -13,7 +13,7 @@ use crate::{
 pub fn NavBar() -> impl IntoView {
     let user_resource = expect_context::<Resource<UserState>>();
     // tuple is (name, link)
-    let navbar = [("Home", "/"), ("Create Deck", "/create-deck"), ("Import Deck", "#"), ("Search", "#")];
+    let navbar = [("Home", "/"), ("Create Deck", "/create-deck"), ("Import Deck", "/import-deck"), ("Export Deck", "/export-deck"), ("Search", "/browse-decks")];
 
     let no_auth_navlist = || view! {
         <h1 style:margin="0" style:font-size="1.8em">"LexLingua"</h1>
@@ -53,6 +53,7 @@ pub fn NavBar() -> impl IntoView {
                 </ol>
                 <Transition fallback=sign_in_button>
                 <Show when=move || {user_resource.get().unwrap_or_default().is_authenticated()} fallback=sign_in_button>
+                    <OfflineStatus/>
                     <ThisUserAvatar/>
                 </Show>
                 </Transition>
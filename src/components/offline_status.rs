@@ -0,0 +1,110 @@
+use leptos::prelude::*;
+
+use crate::utils::{
+    cache_db_interface::CacheStatus,
+    date_and_time::current_time_in_seconds,
+    sync_queue::{QueuedChange, SyncChange},
+    ui::Color,
+};
+
+#[cfg(feature="hydrate")]
+use crate::utils::user_types::UserState;
+
+/// Tells the user whether their active decks can be studied without a connection, and about any offline edits that
+/// lost to a newer version of their note.
+#[component]
+pub fn OfflineStatus() -> impl IntoView {
+    let cache_status = expect_context::<RwSignal<CacheStatus>>();
+    let sync_conflicts = expect_context::<RwSignal<Vec<QueuedChange>>>();
+    #[cfg(feature="hydrate")]
+    let user_state = expect_context::<RwSignal<UserState>>();
+
+    let styles = format!("
+    .offline-status {{
+        font-family: var(--font-family-default);
+        font-size: 0.8em;
+        padding: 0.2em 0.6em;
+        border-radius: 1em;
+        white-space: nowrap;
+        color: {darkslate};
+    }}
+    .offline-status-complete {{
+        background-color: {mint};
+    }}
+    .offline-status-incomplete {{
+        background-color: {jonquil};
+    }}
+    .offline-status-none {{
+        background-color: {light_gray};
+    }}
+    .offline-status-conflict {{
+        background-color: {red};
+        color: {white};
+        margin-left: 0.4em;
+        cursor: pointer;
+    }}",
+        darkslate = Color::DarkSlate.hex(),
+        mint = Color::Mint.hex(),
+        jonquil = Color::Jonquil.hex(),
+        light_gray = Color::LightGray.hex(),
+        red = Color::Red.hex(),
+        white = Color::White.hex(),
+    );
+
+    let status = move || match cache_status.get() {
+        CacheStatus::Complete(as_of) => (
+            "offline-status offline-status-complete",
+            "Ready offline",
+            format!("Every active deck was cached {}, studying offline is safe.", time_ago(as_of)),
+        ),
+        CacheStatus::Incomplete(since) => (
+            "offline-status offline-status-incomplete",
+            "Caching decks...",
+            format!("Caching started {}, some decks may be missing if you go offline.", time_ago(since)),
+        ),
+        CacheStatus::NoCache => (
+            "offline-status offline-status-none",
+            "Online only",
+            "Your decks have not been cached yet, studying needs a connection.".to_string(),
+        ),
+    };
+
+    let conflict_summary = move || {
+        let conflicts = sync_conflicts.get();
+        let notes = conflicts.iter().filter_map(|conflict| match &conflict.change {
+            SyncChange::EditNote {note, ..} => Some(format!("note {}", note.note_id)),
+            SyncChange::DeleteNote {note_id, ..} => Some(format!("deleting note {note_id}")),
+            _ => None,
+        }).collect::<Vec<String>>().join(", ");
+        format!("These offline edits were not synced because the note was changed elsewhere first, the newer version was kept: {notes}. Click to dismiss.")
+    };
+
+    let dismiss = move |_| {
+        #[cfg(feature="hydrate")]
+        {
+            use crate::utils::cache_db_interface::dismiss_sync_conflicts;
+            let _ = dismiss_sync_conflicts(&user_state.get_untracked());
+        }
+        sync_conflicts.set(Vec::new());
+    };
+
+    view! {
+        <style>{styles}</style>
+        <span class=move || status().0 title=move || status().2>{move || status().1}</span>
+        <Show when=move || !!!sync_conflicts.get().is_empty()>
+            <span class="offline-status offline-status-conflict" title=conflict_summary on:click=dismiss>
+                {move || format!("{} edits not synced", sync_conflicts.get().len())}
+            </span>
+        </Show>
+    }
+}
+
+fn time_ago(seconds: u64) -> String {
+    let elapsed = current_time_in_seconds().saturating_sub(seconds);
+    match elapsed {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} minutes ago", elapsed / 60),
+        3600..172800 => format!("{} hours ago", elapsed / 3600),
+        _ => format!("{} days ago", elapsed / 86400),
+    }
+}
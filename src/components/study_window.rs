@@ -1,6 +1,6 @@
 use leptos::prelude::*;
 
-use crate::{components::button::{Button, ButtonConfig}, utils::{shared_truth::{LESSONS_IMAGE, REVIEW_IMAGE}, ui::{Color, Shadow}}};
+use crate::{components::button::{Button, ButtonConfig, ButtonType}, utils::{shared_truth::{LESSONS_IMAGE, REVIEW_IMAGE}, ui::{Color, Shadow}}};
 
 #[component]
 pub fn StudyWindow(study_type: StudyType) -> impl IntoView {
@@ -21,6 +21,7 @@ pub fn StudyWindow(study_type: StudyType) -> impl IntoView {
             text_color: Color::DarkSlate,
             box_shadow: Shadow::dark(),
             padding: "1.7ch".to_string(),
+            button_type: ButtonType::Link("/lessons"),
             ..Default::default()
         },
         StudyType::Review => ButtonConfig {
@@ -29,6 +30,7 @@ pub fn StudyWindow(study_type: StudyType) -> impl IntoView {
             border_color: Color::Winter4,
             box_shadow: Shadow::dark(),
             padding: "1.7ch".to_string(),
+            button_type: ButtonType::Link("/reviews"),
             ..Default::default()
         },
     };
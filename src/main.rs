@@ -3,12 +3,17 @@
 #[allow(unused_variables)]
 #[tokio::main]
 async fn main() {
-    use axum::{middleware, Router};
+    use axum::{extract::DefaultBodyLimit, middleware, routing::{get, post}, Router};
     use axum_server::tls_rustls::RustlsConfig;
     use leptos::logging::log;
     use leptos::prelude::*;
     use leptos_axum::{generate_route_list, LeptosRoutes};
-    use lex_decks::{app::*, utils::middleware::auth_middleware};
+    use lex_decks::{app::*, utils::{
+        middleware::auth_middleware,
+        object_store::{local_object_handler, setup_object_store},
+        payments::{fake_checkout_handler, payment_webhook_handler, setup_payment_provider},
+        shared_truth::{FAKE_CHECKOUT_ROUTE, LOCAL_OBJECT_ROUTE, PAYMENT_WEBHOOK_ROUTE, RAW_DECK_SIZE_LIMIT},
+    }};
     use std::{net::SocketAddr, sync::Arc};
     use tower_cookies::CookieManagerLayer;
     use tower_governor::{governor::GovernorConfig, GovernorLayer};
@@ -17,6 +22,10 @@ async fn main() {
     #[cfg(debug_assertions)]
     dotenvy::dotenv().unwrap();
 
+    // Both panic without their secrets, so a misconfigured server never starts
+    setup_object_store().await;
+    setup_payment_provider();
+
     let governor_conf = Arc::new(GovernorConfig::default());
     let governor_limiter = governor_conf.limiter().clone();
     let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
@@ -48,6 +57,12 @@ async fn main() {
             let leptos_options = leptos_options.clone();
             move || shell(leptos_options.clone())
         })
+        .route(
+            &format!("{LOCAL_OBJECT_ROUTE}/{{bucket}}/{{*key}}"),
+            get(local_object_handler).put(local_object_handler).layer(DefaultBodyLimit::max(RAW_DECK_SIZE_LIMIT)),
+        )
+        .route(PAYMENT_WEBHOOK_ROUTE, post(payment_webhook_handler))
+        .route(FAKE_CHECKOUT_ROUTE, get(fake_checkout_handler))
         .fallback(leptos_axum::file_and_error_handler(shell))
         .layer(CookieManagerLayer::new())
         .layer(middleware::from_fn(auth_middleware))
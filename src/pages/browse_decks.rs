@@ -0,0 +1,318 @@
+use leptos::{prelude::*, task::spawn_local};
+use crate::{
+    components::{button::{Button, ButtonConfig, ButtonType}, message_box::MessageBox},
+    utils::{
+        cache_db_interface::get_asset,
+        catalog::{browse_catalog, CatalogCursor, CatalogEntry, CatalogQuery},
+        database_types::{Asset, DeckId},
+        outcomes::Outcome,
+        ui::{Color, Shadow},
+        user_types::{UserInfo, UserState},
+    },
+};
+
+#[component]
+pub fn BrowseDecks() -> impl IntoView {
+    let user_state = expect_context::<RwSignal<UserState>>();
+    let user_info = expect_context::<Resource<UserInfo>>();
+
+    let subject = RwSignal::new("Find public decks by name, language or tag.".to_string());
+    let urgent = RwSignal::new(false);
+    let message = RwSignal::new(String::new());
+    let searching = RwSignal::new(false);
+
+    let entries = RwSignal::new(Vec::<CatalogEntry>::new());
+    let last_query = RwSignal::new(CatalogQuery::default());
+    let next_cursor = RwSignal::new(None::<CatalogCursor>);
+
+    let name_input_ref: NodeRef<leptos::html::Input> = NodeRef::new();
+    let language_input_ref: NodeRef<leptos::html::Input> = NodeRef::new();
+    let tag_input_ref: NodeRef<leptos::html::Input> = NodeRef::new();
+
+    let load_page = move |query: CatalogQuery| {
+        if searching.get_untracked() {
+            return;
+        }
+        searching.set(true);
+
+        let user = Some(user_state.get_untracked().user().to_string());
+        spawn_local(async move {
+            match browse_catalog(query.clone(), user).await {
+                Ok(Outcome::CatalogPageFound(page)) => {
+                    if query.cursor.is_none() {
+                        entries.set(page.entries);
+                    } else {
+                        entries.update(|entries| entries.extend(page.entries));
+                    }
+                    next_cursor.set(page.next_cursor);
+                    last_query.set(query);
+
+                    let found = entries.with_untracked(|entries| entries.len());
+                    subject.set(if found == 0 {"No decks matched your search.".to_string()} else {format!("Showing {found} decks, most popular first.")});
+                    message.set(String::new());
+                    urgent.set(false);
+                },
+                Ok(any_other_outcome) => {
+                    subject.set("The catalog could not be loaded.".to_string());
+                    message.set(any_other_outcome.to_string());
+                    urgent.set(true);
+                },
+                Err(e) => {
+                    subject.set("The catalog could not be loaded.".to_string());
+                    message.set(e.to_string());
+                    urgent.set(true);
+                },
+            }
+            searching.set(false);
+        });
+    };
+
+    let on_submit = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        load_page(CatalogQuery {
+            name: name_input_ref.get_untracked().map(|input| input.value()).unwrap_or_default(),
+            language: language_input_ref.get_untracked().map(|input| input.value()).unwrap_or_default(),
+            tag: tag_input_ref.get_untracked().map(|input| input.value()).unwrap_or_default(),
+            cursor: None,
+        });
+    };
+
+    let load_more = move |_| {
+        let Some(cursor) = next_cursor.get_untracked() else {return};
+        let mut query = last_query.get_untracked();
+        query.cursor = Some(cursor);
+        load_page(query);
+    };
+
+    let on_subscribe = move |deck_id: DeckId| {
+        #[cfg(feature="hydrate")]
+        {
+            use crate::utils::cache_db_interface::{purchase, subscribe};
+
+            let user_state = user_state.get_untracked();
+            spawn_local(async move {
+                let outcome = match subscribe(deck_id, user_state.clone()).await {
+                    Outcome::PurchaseRequired(_, _) => purchase(deck_id, user_state).await,
+                    any_other_outcome => any_other_outcome,
+                };
+
+                if let Outcome::CheckoutStarted(_, checkout_url) = &outcome {
+                    subject.set("Taking you to checkout...".to_string());
+                    if let Some(window) = web_sys::window() {
+                        let _ = window.location().set_href(checkout_url);
+                    }
+                    return;
+                }
+
+                let (new_subject, is_urgent) = describe_subscribe_outcome(outcome);
+                subject.set(new_subject);
+                message.set(String::new());
+                urgent.set(is_urgent);
+                user_info.refetch();
+            });
+        }
+        #[cfg(not(feature="hydrate"))]
+        {
+            let _ = (deck_id, user_info);
+        }
+    };
+
+    // Buyers are sent back here from checkout with the deck they paid for in the url
+    let confirm_purchase = move || {
+        #[cfg(feature="hydrate")]
+        {
+            use std::str::FromStr;
+            use crate::utils::{cache_db_interface::subscribe, shared_truth::PURCHASE_URL_PARAM, shared_utilities::get_url_query_client};
+
+            let Some(deck_id) = get_url_query_client(PURCHASE_URL_PARAM).and_then(|deck| DeckId::from_str(&deck).ok()) else {return};
+            let user_state = user_state.get_untracked();
+            spawn_local(async move {
+                let (new_subject, is_urgent) = match subscribe(deck_id, user_state).await {
+                    Outcome::PurchaseRequired(_, _) => ("Your payment is still being confirmed, refresh the page in a moment to add the deck.".to_string(), false),
+                    Outcome::DatabaseUpdateSuccess(_) | Outcome::AlreadySubscribed => ("Thanks for your purchase, the deck is in your active decks.".to_string(), false),
+                    any_other_outcome => describe_subscribe_outcome(any_other_outcome),
+                };
+                subject.set(new_subject);
+                urgent.set(is_urgent);
+                user_info.refetch();
+            });
+        }
+    };
+
+    Effect::new(move || {
+        if user_state.get().is_authenticated() {
+            load_page(CatalogQuery::default());
+            confirm_purchase();
+        }
+    });
+
+    let is_active = move |deck_id: DeckId| user_info.get().unwrap_or_default().active_decks.contains(&deck_id);
+
+    let styles = format!("
+    .browse-decks-form {{
+        --gap: calc(0.5svw + 1.4svh);
+        display: grid;
+        grid-template-columns: repeat(auto-fit, minmax(12em, 1fr));
+        align-items: end;
+        gap: var(--gap);
+        padding: var(--gap);
+        margin-top: var(--default-div-margin);
+        border-radius: 6px;
+        box-shadow: {light};
+    }}
+    .browse-decks-form label {{
+        display: flex;
+        flex-direction: column;
+        gap: 0.4em;
+        font-weight: 600;
+    }}
+    .browse-decks-form input[type=text] {{
+        padding: 0.6ch;
+        border: 1px solid {winter3};
+        border-radius: 4px;
+    }}
+    .browse-decks-results {{
+        --gap: calc(0.5svw + 1.4svh);
+        display: grid;
+        grid-template-columns: repeat(auto-fill, minmax(16em, 1fr));
+        gap: var(--gap);
+        margin-top: var(--default-div-margin);
+    }}
+    .catalog-deck {{
+        display: flex;
+        flex-direction: column;
+        gap: 0.5em;
+        padding: 1em;
+        border-radius: 6px;
+        box-shadow: {light};
+    }}
+    .catalog-deck img {{
+        width: 100%;
+        aspect-ratio: 16 / 9;
+        object-fit: cover;
+        border-radius: 4px;
+    }}
+    .catalog-deck h3 {{
+        margin: 0;
+    }}
+    .catalog-deck-details {{
+        font-size: 0.9em;
+        color: {dark_slate};
+    }}
+    .catalog-deck-tags {{
+        display: flex;
+        flex-wrap: wrap;
+        gap: 0.4em;
+    }}
+    .catalog-deck-tag {{
+        padding: 0.1em 0.6em;
+        border-radius: 1em;
+        background-color: {winter1};
+        font-size: 0.8em;
+    }}",
+    light=Shadow::light().css(),
+    winter1=Color::Winter1.hex(),
+    winter3=Color::Winter3.hex(),
+    dark_slate=Color::DarkSlate.hex());
+
+    view! {
+        <style>{styles}</style>
+        <MessageBox subject urgent message margin_top="var(--default-div-margin)".into()/>
+        <form class="browse-decks-form" on:submit=on_submit>
+            <label>
+                "Name"
+                <input type="text" placeholder="Spanish Verbs" node_ref=name_input_ref/>
+            </label>
+            <label>
+                "Language"
+                <input type="text" placeholder="spanish" node_ref=language_input_ref/>
+            </label>
+            <label>
+                "Tag"
+                <input type="text" placeholder="grammar" node_ref=tag_input_ref/>
+            </label>
+            <Button config=ButtonConfig {text: "Search".to_string(), button_type: ButtonType::Submit, background_color: Color::Mint, border_color: Color::Mint, text_color: Color::DarkSlate, ..Default::default()}/>
+        </form>
+        <div class="browse-decks-results">
+            <For
+                each=move || entries.get()
+                key=|entry| entry.deck_id
+                children=move |entry: CatalogEntry| {
+                    let deck_id = entry.deck_id;
+                    view! {<CatalogDeck entry active=Signal::derive(move || is_active(deck_id)) on_subscribe/>}
+                }
+            />
+        </div>
+        <Show when=move || next_cursor.get().is_some()>
+            <div class="browse-decks-more" on:click=load_more>
+                <Button config=ButtonConfig {text: "Show More".to_string(), css_width: "100%".to_string(), ..Default::default()}/>
+            </div>
+        </Show>
+    }
+}
+
+#[component]
+fn CatalogDeck(entry: CatalogEntry, active: Signal<bool>, on_subscribe: impl Fn(DeckId) + Copy + Send + Sync + 'static) -> impl IntoView {
+    let user_state = expect_context::<RwSignal<UserState>>();
+
+    let deck_id = entry.deck_id;
+    let cover_image = entry.cover_image.clone();
+    let cover_url = LocalResource::new(move || {
+        let cover_image = cover_image.clone();
+        let user = Some(user_state.get_untracked().user().to_string());
+        async move {
+            if cover_image == Asset::None {
+                return None;
+            }
+            match get_asset(cover_image, user).await {
+                Outcome::PresignedUrlRetrieved(url) => Some(url),
+                _ => None,
+            }
+        }
+    });
+
+    let subscribe_text = if entry.is_free() {"Subscribe".to_string()} else {format!("Buy for ${:.2}", entry.price)};
+    let details = format!(
+        "{} notes · {} subscribers{}",
+        entry.total_notes,
+        entry.subscribers,
+        if entry.language.is_empty() {String::new()} else {format!(" · {}", entry.language)},
+    );
+
+    view! {
+        <article class="catalog-deck">
+            <Suspense>
+                {move || Suspend::new(async move {
+                    cover_url.await.map(|url| view! {<img src=url alt="deck cover"/>})
+                })}
+            </Suspense>
+            <h3>{entry.name.clone()}</h3>
+            <span class="catalog-deck-details">{details}</span>
+            <p>{entry.description.clone()}</p>
+            <div class="catalog-deck-tags">
+                {entry.tags.iter().map(|tag| view! {<span class="catalog-deck-tag">{tag.clone()}</span>}).collect_view()}
+            </div>
+            <Show
+                when=move || !!!active.get()
+                fallback=|| view! {<span class="catalog-deck-details">"In your active decks"</span>}
+            >
+                <div on:click=move |_| on_subscribe(deck_id)>
+                    <Button config=ButtonConfig {text: subscribe_text.clone(), background_color: Color::Winter3, border_color: Color::Winter3, text_color: Color::White, css_width: "100%".to_string(), ..Default::default()}/>
+                </div>
+            </Show>
+        </article>
+    }
+}
+
+#[cfg(feature="hydrate")]
+fn describe_subscribe_outcome(outcome: Outcome) -> (String, bool) {
+    match outcome {
+        Outcome::DatabaseUpdateSuccess(_) => ("The deck was added to your active decks.".to_string(), false),
+        Outcome::AlreadySubscribed => ("This deck is already in your active decks.".to_string(), false),
+        Outcome::PurchaseRequired(_, price) => (format!("This deck costs ${price:.2}, it has to be purchased before you can study it."), true),
+        Outcome::PaymentProviderFailure(_) => ("Checkout could not be started, please try again later.".to_string(), true),
+        Outcome::UserDoesNotHavePermission => ("You cannot add any more decks, or this deck is no longer public.".to_string(), true),
+        Outcome::UserSuspended(_) => ("Your account is suspended.".to_string(), true),
+        _ => ("The deck could not be added to your active decks.".to_string(), true),
+    }
+}
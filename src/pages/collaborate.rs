@@ -0,0 +1,67 @@
+use leptos::{prelude::*, task::spawn_local};
+use crate::{
+    components::message_box::MessageBox,
+    utils::{
+        outcomes::Outcome,
+        user_types::{UserInfo, UserState},
+    },
+};
+
+/// Where the accept and decline links in a collaborator invite email lead.
+#[component]
+pub fn Collaborate() -> impl IntoView {
+    let user_state = expect_context::<RwSignal<UserState>>();
+    let user_info = expect_context::<Resource<UserInfo>>();
+
+    let subject = RwSignal::new("Checking your invite...".to_string());
+    let urgent = RwSignal::new(false);
+    let message = RwSignal::new(String::new());
+    let responded = RwSignal::new(false);
+
+    Effect::new(move || {
+        #[cfg(feature="hydrate")]
+        {
+            use crate::utils::{cache_db_interface::respond_to_invite, shared_truth::COLAB_INVITE_URL_PARAM, shared_utilities::get_url_query_client};
+
+            // The link only works once so it must not be sent again when the user state changes
+            if responded.get_untracked() {
+                return;
+            }
+            responded.set(true);
+
+            let Some(invite) = get_url_query_client(COLAB_INVITE_URL_PARAM) else {
+                subject.set("This invite link is incomplete.".to_string());
+                urgent.set(true);
+                return;
+            };
+
+            let user_state = user_state.get_untracked();
+            spawn_local(async move {
+                let (new_subject, is_urgent) = match respond_to_invite(invite, user_state).await {
+                    Outcome::DatabaseUpdateSuccess(recipes) if recipes.recipes.is_empty() => ("The invite was declined.".to_string(), false),
+                    Outcome::DatabaseUpdateSuccess(_) => ("You're now a collaborator, the deck is in your colab decks.".to_string(), false),
+                    Outcome::VerificationFailure => ("This invite link is invalid or has expired.".to_string(), true),
+                    Outcome::UserDoesNotHavePermission => ("This invite was already used or withdrawn, or you have reached your colab deck limit.".to_string(), true),
+                    Outcome::InvalidRequest => ("You already collaborate on this deck.".to_string(), true),
+                    Outcome::UserNotFound => ("Sign up with the invited email first, then open the link again.".to_string(), true),
+                    Outcome::UserSuspended(_) => ("Your account is suspended.".to_string(), true),
+                    any_other_outcome => {
+                        message.set(any_other_outcome.to_string());
+                        ("Your response to the invite could not be saved.".to_string(), true)
+                    },
+                };
+                subject.set(new_subject);
+                urgent.set(is_urgent);
+                user_info.refetch();
+            });
+        }
+        #[cfg(not(feature="hydrate"))]
+        {
+            let _ = (user_state, user_info, responded);
+        }
+    });
+
+    view! {
+        <MessageBox subject urgent message margin_top="var(--default-div-margin)".into()/>
+    }
+}
@@ -0,0 +1,139 @@
+use std::str::FromStr;
+
+use leptos::{prelude::*, task::spawn_local};
+use crate::{
+    components::{button::{Button, ButtonConfig, ButtonType}, message_box::MessageBox},
+    utils::{
+        database_types::DeckId,
+        deck_export::export_deck,
+        deck_import::DeckFileType,
+        outcomes::Outcome,
+        ui::{Color, Shadow},
+        user_types::{UserInfo, UserState},
+    },
+};
+
+#[component]
+pub fn ExportDeck() -> impl IntoView {
+    let user_state = expect_context::<RwSignal<UserState>>();
+    let user_info = expect_context::<Resource<UserInfo>>();
+
+    let subject = RwSignal::new("Download a deck you own or help write as an Anki package or a CSV file.".to_string());
+    let urgent = RwSignal::new(false);
+    let message = RwSignal::new(String::new());
+    let exporting = RwSignal::new(false);
+    let download_url = RwSignal::new(None::<String>);
+
+    let deck_select_ref: NodeRef<leptos::html::Select> = NodeRef::new();
+    let file_type_select_ref: NodeRef<leptos::html::Select> = NodeRef::new();
+
+    let exportable_decks = move || {
+        let user_info = user_info.get().unwrap_or_default();
+        let mut decks = user_info.owned_decks.decks.clone();
+        decks.extend(user_info.colab_decks.iter().filter(|deck_id| !!!user_info.owned_decks.contains(deck_id)));
+        decks
+    };
+
+    let on_submit = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        if exporting.get_untracked() {
+            return;
+        }
+
+        let Some(deck_id) = deck_select_ref.get_untracked().and_then(|select| DeckId::from_str(&select.value()).ok()) else {
+            subject.set("Please choose a deck to export.".to_string());
+            urgent.set(true);
+            return;
+        };
+        let file_type = match file_type_select_ref.get_untracked().map(|select| select.value()).as_deref() {
+            Some("csv") => DeckFileType::Csv,
+            _ => DeckFileType::Apkg,
+        };
+
+        exporting.set(true);
+        download_url.set(None);
+        subject.set("Preparing your download...".to_string());
+        message.set(String::new());
+        urgent.set(false);
+
+        let user = Some(user_state.get_untracked().user().to_string());
+        spawn_local(async move {
+            match export_deck(deck_id, file_type, user).await {
+                Ok(Outcome::DeckExported(url)) => {
+                    subject.set("Your deck is ready to download.".to_string());
+                    download_url.set(Some(url));
+                },
+                Ok(Outcome::UserDoesNotHavePermission) => {
+                    subject.set("Only owners and collaborators can export a deck.".to_string());
+                    urgent.set(true);
+                },
+                Ok(any_other_outcome) => {
+                    subject.set("Your deck could not be exported.".to_string());
+                    message.set(any_other_outcome.to_string());
+                    urgent.set(true);
+                },
+                Err(e) => {
+                    subject.set("Your deck could not be exported.".to_string());
+                    message.set(e.to_string());
+                    urgent.set(true);
+                },
+            }
+            exporting.set(false);
+        });
+    };
+
+    let styles = format!("
+    .export-deck-form {{
+        --gap: calc(0.5svw + 1.4svh);
+        display: flex;
+        flex-direction: column;
+        gap: var(--gap);
+        padding: var(--gap);
+        margin-top: var(--default-div-margin);
+        border-radius: 6px;
+        box-shadow: {light};
+    }}
+    .export-deck-form label {{
+        display: flex;
+        flex-direction: column;
+        gap: 0.4em;
+        font-weight: 600;
+    }}
+    .export-deck-form select {{
+        padding: 0.6ch;
+        border: 1px solid {winter3};
+        border-radius: 4px;
+    }}
+    .export-deck-download {{
+        font-weight: 600;
+        color: {winter3};
+    }}",
+    light=Shadow::light().css(),
+    winter3=Color::Winter3.hex());
+
+    view! {
+        <style>{styles}</style>
+        <MessageBox subject urgent message margin_top="var(--default-div-margin)".into()/>
+        <form class="export-deck-form" on:submit=on_submit>
+            <label>
+                "Deck"
+                <select node_ref=deck_select_ref>
+                    <For each=exportable_decks key=|deck_id| *deck_id let:deck_id>
+                        <option value=deck_id.to_string()>{deck_id.to_string()}</option>
+                    </For>
+                </select>
+            </label>
+            <label>
+                "Format"
+                <select node_ref=file_type_select_ref>
+                    <option value="apkg">"Anki package (.apkg)"</option>
+                    <option value="csv">"CSV (.csv)"</option>
+                </select>
+            </label>
+            <Button config=ButtonConfig {text: "Export Deck".to_string(), button_type: ButtonType::Submit, background_color: Color::Mint, border_color: Color::Mint, text_color: Color::DarkSlate, ..Default::default()}/>
+            {move || download_url.get().map(|url| view! {
+                <a class="export-deck-download" href=url download>"Download"</a>
+            })}
+        </form>
+    }
+}
@@ -0,0 +1,223 @@
+use std::str::FromStr;
+
+use leptos::{prelude::*, web_sys::HtmlInputElement};
+use crate::{
+    components::{button::{Button, ButtonConfig, ButtonType}, message_box::MessageBox},
+    utils::{
+        deck_import::{ColumnMapping, CsvImportConfig, ImportedDeck},
+        outcomes::Outcome,
+        shared_truth::ALLOWED_UPLOAD_FILE_TYPES,
+        ui::{Color, Shadow},
+        user_types::{UserInfo, UserState},
+    },
+};
+
+#[component]
+pub fn ImportDeck() -> impl IntoView {
+    let user_state = expect_context::<RwSignal<UserState>>();
+    let user_info = expect_context::<Resource<UserInfo>>();
+
+    let subject = RwSignal::new("Import an Anki package or a CSV file as a new deck.".to_string());
+    let urgent = RwSignal::new(false);
+    let message = RwSignal::new(String::new());
+    let uploading = RwSignal::new(false);
+
+    let file_input_ref: NodeRef<leptos::html::Input> = NodeRef::new();
+    let deck_name_input_ref: NodeRef<leptos::html::Input> = NodeRef::new();
+    let delimiter_input_ref: NodeRef<leptos::html::Input> = NodeRef::new();
+    let has_header_input_ref: NodeRef<leptos::html::Input> = NodeRef::new();
+    let field_names_input_ref: NodeRef<leptos::html::Input> = NodeRef::new();
+
+    let show_outcome = move |outcome: Outcome| {
+        let (new_subject, new_message, is_urgent) = describe_upload_outcome(outcome);
+        subject.set(new_subject);
+        message.set(new_message);
+        urgent.set(is_urgent);
+    };
+
+    let on_file_change = move |_| {
+        #[cfg(feature="hydrate")]
+        {
+            use crate::utils::deck_upload::estimate_upload_cost;
+
+            let Some(file_input) = file_input_ref.get_untracked() else {return};
+            let Some(file) = file_input.files().and_then(|files| files.get(0)) else {return};
+            subject.set(format!("Importing {} will cost up to {:.2} upload tokens.", file.name(), estimate_upload_cost(file.size() as usize)));
+            message.set("Anything the stored deck does not use is refunded once it has been processed.".to_string());
+            urgent.set(false);
+        }
+    };
+
+    let on_submit = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        if uploading.get_untracked() {
+            return;
+        }
+
+        let Some(file_input) = file_input_ref.get_untracked() else {return};
+        let file_input: HtmlInputElement = file_input;
+
+        let csv_config = build_csv_config(
+            deck_name_input_ref.get_untracked().map(|input| input.value()).unwrap_or_default(),
+            delimiter_input_ref.get_untracked().map(|input| input.value()).unwrap_or_default(),
+            has_header_input_ref.get_untracked().map(|input| input.checked()).unwrap_or(true),
+            field_names_input_ref.get_untracked().map(|input| input.value()).unwrap_or_default(),
+        );
+
+        #[cfg(feature="hydrate")]
+        {
+            use crate::utils::cache_db_interface::upload_deck;
+
+            let Some(files) = file_input.files() else {return};
+            if files.length() > 1 {
+                show_outcome(Outcome::TooManyFiles);
+                return;
+            }
+            let Some(file) = files.get(0) else {
+                subject.set("Please choose a file to import.".to_string());
+                urgent.set(true);
+                return;
+            };
+
+            uploading.set(true);
+            subject.set(format!("Uploading {}...", file.name()));
+            message.set(String::new());
+            urgent.set(false);
+
+            let user_state = user_state.get_untracked();
+            leptos::task::spawn_local(async move {
+                let outcome = upload_deck(file, csv_config, user_state).await;
+                show_outcome(outcome);
+                uploading.set(false);
+                user_info.refetch();
+            });
+        }
+        #[cfg(not(feature="hydrate"))]
+        {
+            let _ = (file_input, csv_config, user_state, user_info, show_outcome);
+        }
+    };
+
+    let styles = format!("
+    .import-deck-form {{
+        --gap: calc(0.5svw + 1.4svh);
+        display: flex;
+        flex-direction: column;
+        gap: var(--gap);
+        padding: var(--gap);
+        margin-top: var(--default-div-margin);
+        border-radius: 6px;
+        box-shadow: {light};
+    }}
+    .import-deck-form label {{
+        display: flex;
+        flex-direction: column;
+        gap: 0.4em;
+        font-weight: 600;
+    }}
+    .import-deck-form input[type=text] {{
+        padding: 0.6ch;
+        border: 1px solid {winter3};
+        border-radius: 4px;
+    }}
+    .import-deck-csv-options {{
+        display: flex;
+        flex-direction: column;
+        gap: var(--gap);
+        border: none;
+        padding: 0;
+    }}
+    .import-deck-checkbox {{
+        flex-direction: row !important;
+        align-items: center;
+    }}",
+    light=Shadow::light().css(),
+    winter3=Color::Winter3.hex());
+
+    view! {
+        <style>{styles}</style>
+        <MessageBox subject urgent message margin_top="var(--default-div-margin)".into()/>
+        <form class="import-deck-form" on:submit=on_submit>
+            <label>
+                "Deck file"
+                <input type="file" accept=ALLOWED_UPLOAD_FILE_TYPES.join(",") node_ref=file_input_ref on:change=on_file_change required/>
+            </label>
+            <fieldset class="import-deck-csv-options">
+                <legend>"CSV options"</legend>
+                <label>
+                    "Deck name"
+                    <input type="text" placeholder="New Deck" node_ref=deck_name_input_ref/>
+                </label>
+                <label>
+                    "Delimiter"
+                    <input type="text" maxlength="1" value="," node_ref=delimiter_input_ref/>
+                </label>
+                <label class="import-deck-checkbox">
+                    <input type="checkbox" checked node_ref=has_header_input_ref/>
+                    "First row is a header"
+                </label>
+                <label>
+                    "Field names in column order, leave empty to use the header"
+                    <input type="text" placeholder="Front, Back" node_ref=field_names_input_ref/>
+                </label>
+            </fieldset>
+            <Button config=ButtonConfig {text: "Import Deck".to_string(), button_type: ButtonType::Submit, background_color: Color::Mint, border_color: Color::Mint, text_color: Color::DarkSlate, ..Default::default()}/>
+        </form>
+    }
+}
+
+fn build_csv_config(deck_name: String, delimiter: String, has_header: bool, field_names: String) -> CsvImportConfig {
+    let default_config = CsvImportConfig::default();
+
+    let column_mapping = field_names
+        .split(',')
+        .map(|field_name| field_name.trim())
+        .enumerate()
+        .filter(|(_, field_name)| !!!field_name.is_empty())
+        .map(|(column, field_name)| ColumnMapping {column, field_name: field_name.to_string()})
+        .collect();
+
+    CsvImportConfig {
+        deck_name: if deck_name.trim().is_empty() {default_config.deck_name} else {deck_name.trim().to_string()},
+        delimiter: delimiter.chars().next().unwrap_or(default_config.delimiter),
+        has_header,
+        column_mapping,
+    }
+}
+
+fn describe_upload_outcome(outcome: Outcome) -> (String, String, bool) {
+    for sub_outcome in outcome.multi_outcome_to_vec() {
+        if let Outcome::DeckProcessed(report) = sub_outcome {
+            let Ok(imported_deck) = ImportedDeck::from_str(&report) else {
+                return ("Your deck was imported.".to_string(), String::new(), false);
+            };
+            let row_errors = imported_deck.row_errors.iter().map(|row_error| match row_error.row {
+                Some(row) => format!("Row {row}: {}", row_error.reason),
+                None => row_error.reason.clone(),
+            }).collect::<Vec<String>>().join("\n");
+
+            return (
+                format!("Imported {} notes into {}.", imported_deck.meta.total_notes, imported_deck.meta.name),
+                row_errors,
+                false,
+            );
+        }
+    }
+
+    let subject = match outcome {
+        Outcome::TooManyFiles => "Only one deck can be imported at a time.",
+        Outcome::IncorrectType => "Only .apkg and .csv files can be imported.",
+        Outcome::NotEnoughUploadTokens(_) => "You do not have enough upload tokens for this deck.",
+        Outcome::UserDoesNotHavePermission => "You cannot import any more decks right now.",
+        Outcome::DeckNotUploadedToBucket => "Your file could not be uploaded.",
+        Outcome::InvalidRequest => "This file is over the upload size limit or was already imported.",
+        _ => "Your deck could not be imported.",
+    };
+
+    let message = match outcome {
+        Outcome::DeckCouldNotBeProcessed(reason) => reason,
+        _ => String::new(),
+    };
+
+    (subject.to_string(), message, true)
+}
@@ -2,4 +2,9 @@ pub mod home;
 pub mod not_found;
 pub mod sign_in;
 pub mod test;
-pub mod sign_out;
\ No newline at end of file
+pub mod sign_out;
+pub mod study;
+pub mod import_deck;
+pub mod export_deck;
+pub mod browse_decks;
+pub mod collaborate;
\ No newline at end of file
@@ -352,8 +352,9 @@ pub fn SignIn() -> impl IntoView {
 
 #[cfg(feature = "ssr")]
 use crate::utils::{
-    dynamo_utils::{setup_client, validate_user_and_return_rank},
-    back_utils::{build_auth_token, build_sign_up_token, build_refresh_token}, 
+    dynamo_utils::validate_user_and_return_rank,
+    storage::setup_storage,
+    back_utils::{build_auth_token, build_sign_up_token, build_refresh_token, send_mailtrap_email}, 
     user_types::UserInfo, 
     shared_truth::SIGN_IN_PAGE,
     email_template::{EmailTemplate, EMAIL_FIELD_1, EMAIL_FIELD_1_VALUE, EMAIL_FIELD_2, EMAIL_FIELD_2_VALUE, REDIRECT_LINK},
@@ -367,7 +368,7 @@ struct SignInUpInputs {
     full_name: String,
 }
 
-#[server]
+#[server(endpoint = "send_email")]
 async fn send_email(sign_in_form: SignInUpInputs) -> Result<Outcome, ServerFnError> {
     if !sign_in_form.full_name.is_empty() {
         return Ok(Outcome::VerificationSuccess("You're totally not a bot".to_string()));
@@ -379,9 +380,9 @@ async fn send_email(sign_in_form: SignInUpInputs) -> Result<Outcome, ServerFnErr
         None => false,
     };
 
-    let client = setup_client().await;
+    let storage = setup_storage().await;
 
-    let outcome = match validate_user_and_return_rank(&client, email_address).await {
+    let outcome = match validate_user_and_return_rank(&storage, email_address).await {
         Outcome::PermissionGrantedReturnUser(user) => sign_up_or_in(email_address, false, is_trusted, Some(user)).await,
         Outcome::UserNotFound => sign_up_or_in(email_address, true, is_trusted, None).await,
         any_other_outcome => return Ok(any_other_outcome)
@@ -408,9 +409,6 @@ async fn sign_up_or_in(email_address: &str, sign_up: bool, is_trusted: bool, use
         Some(user) => user,
         None => UserInfo::default(),
     };
-    let api_url = "https://send.api.mailtrap.io/api/send";
-    let api_key = std::env::var("MAILTRAP_PASSWORD").unwrap_or_default();
-
     let (refresh_token, auth_token, sign_up_token) = create_token(email_address, sign_up, is_trusted).await;
 
     let mut redirect_url = SIGN_IN_PAGE.to_string();
@@ -432,25 +430,5 @@ async fn sign_up_or_in(email_address: &str, sign_up: bool, is_trusted: bool, use
 
     html = html.replace(REDIRECT_LINK, &redirect_url);
 
-    let email_payload = serde_json::json!({
-        "from": {"email": &format!("{}", std::env::var("SENDER_EMAIL").unwrap_or_default()), "name": "LexLingua"},
-        "to": [{"email": email_address}],
-        "subject": subject,
-        "text": redirect_url,
-        "html": &html,
-    });
-
-    let client = reqwest::Client::new();
-
-    let outcome = match client
-    .post(api_url)
-    .header("Accept", "application/json")
-    .header("Content-Type", "application/json")
-    .header("Api-Token", api_key)
-    .body(email_payload.to_string())
-    .send().await {
-        Ok(resp) => resp.status().is_success().then(|| Outcome::EmailSendSuccess).unwrap_or(Outcome::EmailSendFailure(resp.text().await.unwrap())),
-        Err(e) =>  Outcome::EmailSendFailure(e.to_string()),
-    };
-    outcome
+    send_mailtrap_email(email_address, subject, &redirect_url, &html).await
 }
@@ -23,6 +23,36 @@ pub fn SignOut() -> impl IntoView {
     let sign_out_subject = RwSignal::new("You have been signed out".into());
     let sign_out_urgent = RwSignal::new(false);
     let sign_out_message = RwSignal::new(String::new());
+    let clearing = RwSignal::new(false);
+
+    // Shared devices should be able to drop the notes and review states they were left with
+    let on_clear_offline_data = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        if clearing.get_untracked() {
+            return;
+        }
+
+        #[cfg(feature="hydrate")]
+        {
+            use crate::utils::{cache_manager::clear_offline_data, outcomes::Outcome};
+
+            clearing.set(true);
+            leptos::task::spawn_local(async move {
+                match clear_offline_data().await {
+                    Outcome::CacheSucceeded => {
+                        sign_out_subject.set("Your offline data has been cleared".into());
+                        sign_out_urgent.set(false);
+                    },
+                    any_other_outcome => {
+                        sign_out_subject.set("Your offline data could not be cleared".into());
+                        sign_out_message.set(any_other_outcome.to_string());
+                        sign_out_urgent.set(true);
+                    },
+                }
+                clearing.set(false);
+            });
+        }
+    };
 
     let sign_in_height = "min(var(--sign-in-element-min-height), var(--sign-in-element-max-height))";
     let shadow_size = "min(calc(5svmax - 5svh), 15px)";
@@ -73,6 +103,9 @@ pub fn SignOut() -> impl IntoView {
             <img src=FULL_LOGO_PATH alt="LexLinguaLogo" class="sign-in-logo"/>
             <MessageBox subject=sign_out_subject urgent=sign_out_urgent message=sign_out_message width=email_input_width.into() only_subject=true top_padding="calc(var(--sign-in-element-height)/2 - 0.5em)".into()/>
             <Button config=ButtonConfig {id:"goback".into(), button_type: ButtonType::Link("/sign-in"),css_height: sign_in_height.into(), text:"Go Back".into(), css_width: email_input_width.into(), ..Default::default()}/>
+            <form on:submit=on_clear_offline_data style:width=email_input_width>
+                <Button config=ButtonConfig {id:"clearofflinedata".into(), button_type: ButtonType::Submit, css_height: sign_in_height.into(), text:"Clear Offline Data".into(), css_width: email_input_width.into(), ..Default::default()}/>
+            </form>
         </div>
     }
 }
\ No newline at end of file
@@ -0,0 +1,269 @@
+use std::str::FromStr;
+
+use leptos::{either::Either, logging::debug_warn, prelude::*, task::spawn_local};
+use crate::{
+    components::{
+        button::{Button, ButtonConfig, ButtonType},
+        message_box::MessageBox,
+        study_window::StudyType,
+    },
+    utils::{
+        cache_db_interface::{get_lesson_batch, get_review_batch},
+        database_types::{DeckId, DeckList},
+        note_templates::StudyCard,
+        outcomes::Outcome,
+        proceed,
+        scheduler::Answer,
+        shared_truth::STUDY_DECK_URL_PARAM,
+        shared_utilities::get_url_query_client,
+        ui::{Color, Shadow},
+        user_types::{UserInfo, UserState},
+    },
+};
+
+#[component]
+pub fn Lessons() -> impl IntoView {
+    view! {
+        <StudySession study_type=StudyType::Lesson/>
+    }
+}
+
+#[component]
+pub fn Reviews() -> impl IntoView {
+    view! {
+        <StudySession study_type=StudyType::Review/>
+    }
+}
+
+#[component]
+pub fn StudySession(study_type: StudyType) -> impl IntoView {
+    let user_state = expect_context::<RwSignal<UserState>>();
+    let user_info = expect_context::<Resource<UserInfo>>();
+
+    let cards = RwSignal::new(Vec::<StudyCard>::new());
+    let current_card = RwSignal::new(0_usize);
+    let revealed = RwSignal::new(false);
+
+    let subject = RwSignal::new(String::new());
+    let urgent = RwSignal::new(false);
+    let message = RwSignal::new(String::new());
+
+    let session = LocalResource::new(move || {
+        let user_state = user_state.get();
+        let user_info = user_info.get().unwrap_or_default();
+        async move {
+            if !!!user_state.is_authenticated() {
+                subject.set("Sign in to start studying.".to_string());
+                return;
+            }
+            if user_info == UserInfo::default() {
+                return;
+            }
+
+            let deck_id = match get_url_query_client(STUDY_DECK_URL_PARAM).and_then(|deck| DeckId::from_str(&deck).ok()) {
+                Some(deck_id) => deck_id,
+                None => match user_info.active_decks.first() {
+                    Some(deck_id) => *deck_id,
+                    None => {
+                        subject.set("You do not have any active decks yet.".to_string());
+                        return;
+                    },
+                },
+            };
+
+            let mut all_user_decks = DeckList::default();
+            all_user_decks.extend(user_info.active_decks.iter());
+            all_user_decks.extend(user_info.owned_decks.iter());
+            all_user_decks.extend(user_info.colab_decks.iter());
+
+            let outcome = match study_type {
+                StudyType::Lesson => get_lesson_batch(deck_id, user_state, all_user_decks).await,
+                StudyType::Review => get_review_batch(deck_id, user_state, all_user_decks).await,
+            };
+
+            match outcome {
+                Outcome::StudyCardsFound(study_cards) => {
+                    current_card.set(0);
+                    revealed.set(false);
+                    cards.set(study_cards);
+                },
+                Outcome::ItemsNotFound => match study_type {
+                    StudyType::Lesson => subject.set("There are no new lessons in this deck.".to_string()),
+                    StudyType::Review => subject.set("There are no reviews due right now.".to_string()),
+                },
+                any_other_outcome => {
+                    subject.set("Your study session could not be loaded.".to_string());
+                    message.set(any_other_outcome.to_string());
+                    urgent.set(true);
+                },
+            }
+        }
+    });
+
+    let answer_card = move |answer: Answer| {
+        let Some(card) = cards.with_untracked(|cards| cards.get(current_card.get_untracked()).cloned()) else {return};
+
+        // Cards that are forgotten during reviews are shown again at the end of the session
+        if answer == Answer::Again && matches!(study_type, StudyType::Review) {
+            cards.update(|cards| cards.push(card.clone()));
+        }
+
+        // Answers are queued so they survive losing the connection mid session
+        #[cfg(feature="hydrate")]
+        {
+            use crate::utils::cache_db_interface::answer_card;
+
+            let user_state = user_state.get_untracked();
+            spawn_local(async move {
+                match answer_card(&card, answer, user_state).await {
+                    Outcome::CardGraded(_) | Outcome::ChangeQueued => proceed(),
+                    any_other_outcome => debug_warn!("card could not be graded {}", any_other_outcome.to_string()),
+                }
+            });
+        }
+
+        revealed.set(false);
+        current_card.update(|current_card| *current_card += 1);
+    };
+
+    let card_front = move || {
+        cards.with(|cards| match cards.get(current_card.get()) {
+            Some(card) => card.front.clone(),
+            None => String::new(),
+        })
+    };
+    let card_back = move || {
+        cards.with(|cards| match cards.get(current_card.get()) {
+            Some(card) => card.back.lines().map(str::to_string).collect::<Vec<String>>(),
+            None => Vec::new(),
+        })
+    };
+    let show_back = move || revealed.get() || matches!(study_type, StudyType::Lesson);
+    let session_finished = move || !!!cards.with(|cards| cards.is_empty()) && current_card.get() >= cards.with(|cards| cards.len());
+    let cards_remaining = move || cards.with(|cards| cards.len()).saturating_sub(current_card.get());
+
+    Effect::new(move || {
+        if session_finished() {
+            match study_type {
+                StudyType::Lesson => subject.set("Lessons complete! These cards are now in your reviews.".to_string()),
+                StudyType::Review => subject.set("Reviews complete!".to_string()),
+            }
+        }
+    });
+
+    let session_title = match study_type {
+        StudyType::Lesson => "Lessons",
+        StudyType::Review => "Reviews",
+    };
+
+    let answer_button = move |answer: Answer, text: &str, color: Color| {
+        view! {
+            <div class="study-answer" on:click=move |_| answer_card(answer)>
+                <Button config=ButtonConfig {
+                    text: text.to_string(),
+                    background_color: color,
+                    border_color: color,
+                    box_shadow: Shadow::dark(),
+                    css_width: "100%".to_string(),
+                    ..Default::default()
+                }/>
+            </div>
+        }
+    };
+
+    let styles = format!("
+    .study-session {{
+        --gap: calc(0.5svw + 1.4svh);
+        display: flex;
+        flex-direction: column;
+        gap: var(--gap);
+        margin-top: var(--default-div-margin);
+    }}
+    .study-session-header {{
+        display: flex;
+        justify-content: space-between;
+        font-size: 24px;
+        font-weight: 600;
+    }}
+    .study-card {{
+        display: flex;
+        flex-direction: column;
+        align-items: center;
+        gap: var(--gap);
+        padding: calc(var(--gap) * 2);
+        border-radius: 6px;
+        box-shadow: {light};
+        background-color: {white};
+    }}
+    .study-card-front {{
+        white-space: pre-line;
+        text-align: center;
+        font-size: 40px;
+        font-weight: 600;
+        color: {winter4};
+    }}
+    .study-card-back {{
+        font-size: 18px;
+        color: {dark_slate};
+    }}
+    .study-answers {{
+        display: grid;
+        grid-template-columns: repeat(auto-fit, minmax(8em, 1fr));
+        gap: var(--gap);
+    }}",
+    light=Shadow::light().css(),
+    white=Color::White.hex(),
+    winter4=Color::Winter4.hex(),
+    dark_slate=Color::DarkSlate.hex());
+
+    view! {
+        <style>{styles}</style>
+        <div class="study-session">
+            <MessageBox subject urgent message/>
+            <div class="study-session-header">
+                <h2>{session_title}</h2>
+                <span>{cards_remaining}</span>
+            </div>
+            <Suspense>
+                {move || Suspend::new(async move {
+                    session.await;
+                    view! {
+                        <Show when=move || !!!session_finished() && cards_remaining() > 0>
+                            <div class="study-card">
+                                <p class="study-card-front">{card_front}</p>
+                                <Show when=show_back>
+                                    <For each=card_back key=|text| text.clone() let:text>
+                                        <p class="study-card-back">{text}</p>
+                                    </For>
+                                </Show>
+                            </div>
+                            <div class="study-answers">
+                                {match study_type {
+                                    StudyType::Lesson => Either::Left(answer_button(Answer::Good, "Got It", Color::Mint)),
+                                    StudyType::Review => Either::Right(view! {
+                                        <Show
+                                            when=move || revealed.get()
+                                            fallback=move || view! {
+                                                <div class="study-answer" on:click=move |_| revealed.set(true)>
+                                                    <Button config=ButtonConfig {text: "Show Answer".to_string(), css_width: "100%".to_string(), ..Default::default()}/>
+                                                </div>
+                                            }
+                                        >
+                                            {answer_button(Answer::Again, "Again", Color::Red)}
+                                            {answer_button(Answer::Hard, "Hard", Color::Winter4)}
+                                            {answer_button(Answer::Good, "Good", Color::Winter3)}
+                                            {answer_button(Answer::Easy, "Easy", Color::Mint)}
+                                        </Show>
+                                    }),
+                                }}
+                            </div>
+                        </Show>
+                        <Show when=session_finished>
+                            <Button config=ButtonConfig {text: "Back Home".to_string(), button_type: ButtonType::Link("/"), ..Default::default()}/>
+                        </Show>
+                    }
+                })}
+            </Suspense>
+        </div>
+    }
+}
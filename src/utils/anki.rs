@@ -0,0 +1,752 @@
+use std::{collections::{HashMap, HashSet}, io::{Cursor, Read, Write}, str::FromStr};
+
+use futures::{stream, StreamExt};
+use leptos::logging::error;
+use rusqlite::{params, Connection, OpenFlags};
+use serde::Deserialize;
+use serde_json::json;
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+use crate::utils::{
+    asset::{get_deck_asset, put_deck_asset},
+    back_utils::PUBLIC_DECKS_BUCKET,
+    database_types::{Asset, DeckId, Field, Note, NoteType, S3Address, CLOZE_NOTE_TYPE},
+    date_and_time::current_time_in_seconds,
+    deck_import::{strip_html, ImportedDeck, RowError},
+    note_templates::{CardTemplate, NoteTemplate},
+    object_store::ObjectStore,
+    outcomes::Outcome,
+    shared_truth::RAW_DECK_SIZE_LIMIT,
+};
+
+const ANKI_COLLECTION_FILES: [&str; 2] = ["collection.anki21", "collection.anki2"];
+const ANKI_COMPRESSED_COLLECTION_FILE: &str = "collection.anki21b";
+const ANKI_MEDIA_FILE: &str = "media";
+const ANKI_FIELD_SEPARATOR: char = '\u{001F}';
+const ANKI_DECK_NAME_SEPARATOR: char = '\u{001F}';
+const ANKI_CLOZE_MODEL_TYPE: u8 = 1;
+const ANKI_SOUND_START: &str = "[sound:";
+const ANKI_SOUND_END: &str = "]";
+const ANKI_COLLECTION_TEMP_FILE: &str = "collection.anki2";
+// Newer collections store note type settings as protobuf, the kind is field 1 and is left out for normal note types
+const ANKI_NOTETYPE_KIND_FIELD: u64 = 1;
+const ANKI_TEMPLATE_QUESTION_FIELD: u64 = 1;
+const ANKI_TEMPLATE_ANSWER_FIELD: u64 = 2;
+const CONCURRENT_MEDIA_UPLOADS: usize = 16;
+const ANKI_LEGACY_SCHEMA_VERSION: i64 = 11;
+const ANKI_DEFAULT_DECK_ID: i64 = 1;
+
+// The legacy collection layout, which every version of Anki can still import
+const ANKI_LEGACY_SCHEMA: &str = "
+CREATE TABLE col (id integer primary key, crt integer not null, mod integer not null, scm integer not null, ver integer not null, dty integer not null, usn integer not null, ls integer not null, conf text not null, models text not null, decks text not null, dconf text not null, tags text not null);
+CREATE TABLE notes (id integer primary key, guid text not null, mid integer not null, mod integer not null, usn integer not null, tags text not null, flds text not null, sfld integer not null, csum integer not null, flags integer not null, data text not null);
+CREATE TABLE cards (id integer primary key, nid integer not null, did integer not null, ord integer not null, mod integer not null, usn integer not null, type integer not null, queue integer not null, due integer not null, ivl integer not null, factor integer not null, reps integer not null, lapses integer not null, left integer not null, odue integer not null, odid integer not null, flags integer not null, data text not null);
+CREATE TABLE revlog (id integer primary key, cid integer not null, usn integer not null, ivl integer not null, lastIvl integer not null, factor integer not null, time integer not null, type integer not null);
+CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+CREATE INDEX ix_notes_usn on notes (usn);
+CREATE INDEX ix_cards_usn on cards (usn);
+CREATE INDEX ix_revlog_usn on revlog (usn);
+CREATE INDEX ix_cards_nid on cards (nid);
+CREATE INDEX ix_cards_sched on cards (did, queue, due);
+CREATE INDEX ix_revlog_cid on revlog (cid);
+CREATE INDEX ix_notes_csum on notes (csum);
+";
+
+#[derive(Deserialize)]
+struct AnkiModel {
+    #[serde(rename = "type", default)]
+    model_type: u8,
+    #[serde(default)]
+    name: String,
+    flds: Vec<AnkiModelField>,
+    #[serde(default)]
+    tmpls: Vec<AnkiModelTemplate>,
+}
+
+#[derive(Clone, Deserialize)]
+struct AnkiModelTemplate {
+    ord: usize,
+    qfmt: String,
+    afmt: String,
+}
+
+#[derive(Clone, Deserialize)]
+struct AnkiModelField {
+    name: String,
+    ord: usize,
+}
+
+#[derive(Deserialize)]
+struct AnkiDeck {
+    name: String,
+}
+
+struct AnkiCollection {
+    deck_name: String,
+    models: HashMap<i64, AnkiModel>,
+    notes: Vec<(i64, String)>,
+}
+
+struct AnkiPackage {
+    deck_name: String,
+    notes: Vec<(Option<usize>, Note)>,
+    note_templates: Vec<NoteTemplate>,
+    media: Vec<(String, Vec<u8>)>,
+    row_errors: Vec<RowError>,
+}
+
+pub async fn import_anki_deck(object_store: &impl ObjectStore, bytes: Vec<u8>, deck_id: DeckId, email: &str) -> Outcome {
+    if bytes.len() > RAW_DECK_SIZE_LIMIT {
+        return Outcome::DeckCouldNotBeProcessed("deck is over the upload size limit".to_string());
+    }
+
+    let package = match tokio::task::spawn_blocking(move || read_anki_package(bytes, deck_id)).await {
+        Ok(Ok(package)) => package,
+        Ok(Err(reason)) => return Outcome::DeckCouldNotBeProcessed(reason),
+        Err(e) => return Outcome::DeckCouldNotBeProcessed(e.to_string()),
+    };
+
+    let AnkiPackage {deck_name, notes, note_templates, media, mut row_errors} = package;
+
+    let uploads = stream::iter(media)
+        .map(|(file_name, file)| async move {
+            let file_size = file.len();
+            let outcome = put_deck_asset(object_store, deck_id, &file_name, file).await;
+            (file_name, file_size, outcome)
+        })
+        .buffer_unordered(CONCURRENT_MEDIA_UPLOADS)
+        .collect::<Vec<(String, usize, Outcome)>>()
+        .await;
+
+    let mut media_bytes = 0;
+    for (file_name, file_size, outcome) in uploads {
+        match outcome {
+            Outcome::AssetUploaded(_) => media_bytes += file_size,
+            any_other_outcome => row_errors.push(RowError::new(None, &format!("media file {file_name} could not be uploaded {}", any_other_outcome.to_string()))),
+        }
+    }
+
+    let mut imported_deck = ImportedDeck::new(deck_id, email, &deck_name, notes, note_templates, row_errors);
+    imported_deck.media_bytes = media_bytes;
+
+    Outcome::DeckImported(imported_deck)
+}
+
+fn read_anki_package(bytes: Vec<u8>, deck_id: DeckId) -> Result<AnkiPackage, String> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+    let mut row_errors = Vec::new();
+
+    let mut collection = None;
+    for file_name in ANKI_COLLECTION_FILES {
+        if let Ok(file) = archive.by_name(file_name) {
+            collection = Some(read_archive_file(file)?);
+            break;
+        }
+    }
+    let Some(collection) = collection else {
+        if archive.by_name(ANKI_COMPRESSED_COLLECTION_FILE).is_ok() {
+            return Err("deck was exported without legacy support, re-export it with \"Support older Anki versions\" checked".to_string());
+        }
+        return Err("package does not contain an anki collection".to_string());
+    };
+
+    let collection = read_collection(collection)?;
+
+    // The media file maps the numbered files in the archive to the names used inside the notes
+    let media_map: HashMap<String, String> = match archive.by_name(ANKI_MEDIA_FILE) {
+        Ok(file) => match serde_json::from_slice(&read_archive_file(file)?) {
+            Ok(media_map) => media_map,
+            Err(_) => {
+                row_errors.push(RowError::new(None, "media list could not be read so media was skipped"));
+                HashMap::new()
+            },
+        },
+        Err(_) => HashMap::new(),
+    };
+    let media_names: HashSet<String> = media_map.values().cloned().collect();
+    let mut referenced_media = HashSet::new();
+
+    let mut notes = Vec::with_capacity(collection.notes.len());
+    let mut note_templates: Vec<NoteTemplate> = Vec::new();
+    for (row, (model_id, flds)) in collection.notes.into_iter().enumerate() {
+        let Some(model) = collection.models.get(&model_id) else {
+            row_errors.push(RowError::new(Some(row + 1), "note type could not be found"));
+            continue;
+        };
+
+        let mut model_fields = model.flds.clone();
+        model_fields.sort_by_key(|model_field| model_field.ord);
+
+        let note = Note::new_from_function(flds, Some(ANKI_FIELD_SEPARATOR), &|flds, separator| {
+            let separator = separator.unwrap_or(ANKI_FIELD_SEPARATOR);
+            let fields = flds.split(separator).enumerate().map(|(i, html)| {
+                let name = match model_fields.get(i) {
+                    Some(model_field) => model_field.name.clone(),
+                    None => format!("Field {}", i + 1),
+                };
+                anki_field_to_field(name, html.to_string(), deck_id, &media_names)
+            }).collect();
+
+            let mut note = Note::new(0, fields);
+            note.note_type = note_type_from_model(model);
+            note
+        });
+
+        if !!!note_templates.iter().any(|note_template| note_template.note_type == note.note_type) {
+            if let Some(note_template) = note_template_from_model(model, note.note_type.clone()) {
+                note_templates.push(note_template);
+            }
+        }
+
+        for field in note.fields.iter() {
+            if let Some(Asset::DeckImage(address) | Asset::DeckAudio(address)) = &field.asset {
+                let file_name = address.key.split_once('/').map(|(_, file_name)| file_name).unwrap_or_default();
+                referenced_media.insert(file_name.to_string());
+            }
+        }
+
+        notes.push((Some(row + 1), note));
+    }
+
+    let mut media = Vec::with_capacity(referenced_media.len());
+    for (archive_name, file_name) in media_map {
+        if !!!referenced_media.contains(&file_name) {
+            continue;
+        }
+        let file = match archive.by_name(&archive_name) {
+            Ok(file) => read_archive_file(file)?,
+            Err(_) => {
+                row_errors.push(RowError::new(None, &format!("media file {file_name} is missing from the package")));
+                continue;
+            },
+        };
+        media.push((file_name, file));
+    }
+
+    Ok(AnkiPackage {
+        deck_name: collection.deck_name,
+        notes,
+        note_templates,
+        media,
+        row_errors,
+    })
+}
+
+fn read_archive_file(mut file: impl Read) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    // Limits how much a single entry can expand to so a small package cannot unpack into something enormous
+    let mut limited_file = (&mut file).take(RAW_DECK_SIZE_LIMIT as u64 + 1);
+    limited_file.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    if bytes.len() > RAW_DECK_SIZE_LIMIT {
+        return Err("package contents are over the upload size limit".to_string());
+    }
+    Ok(bytes)
+}
+
+fn read_collection(collection: Vec<u8>) -> Result<AnkiCollection, String> {
+    // sqlite can only open collections from disk so it is written to a directory of its own, which is removed when dropped
+    let directory = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let path = directory.path().join(ANKI_COLLECTION_TEMP_FILE);
+    std::fs::write(&path, collection).map_err(|e| e.to_string())?;
+
+    Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| e.to_string())
+        .and_then(|connection| query_collection(&connection).map_err(|e| e.to_string()))
+}
+
+fn query_collection(connection: &Connection) -> rusqlite::Result<AnkiCollection> {
+    let (models_json, decks_json): (String, String) = connection.query_row("SELECT models, decks FROM col", [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    let models = match serde_json::from_str::<HashMap<String, AnkiModel>>(&models_json) {
+        Ok(models) if !!!models.is_empty() => models.into_iter().filter_map(|(id, model)| Some((i64::from_str(&id).ok()?, model))).collect(),
+        // Newer collections keep note types in their own tables
+        _ => query_models(connection)?,
+    };
+
+    let main_deck_id: Option<i64> = connection.query_row(
+        "SELECT did FROM cards GROUP BY did ORDER BY COUNT(*) DESC LIMIT 1", [], |row| row.get(0)
+    ).ok();
+
+    let deck_name = match (main_deck_id, serde_json::from_str::<HashMap<String, AnkiDeck>>(&decks_json)) {
+        (Some(main_deck_id), Ok(decks)) => decks.get(&main_deck_id.to_string()).map(|deck| deck.name.clone()),
+        (Some(main_deck_id), Err(_)) => connection.query_row("SELECT name FROM decks WHERE id = ?1", [main_deck_id], |row| row.get::<_, String>(0)).ok(),
+        (None, _) => None,
+    };
+    let deck_name = deck_name.unwrap_or("Anki Deck".to_string()).replace(ANKI_DECK_NAME_SEPARATOR, "::");
+
+    let mut statement = connection.prepare("SELECT mid, flds FROM notes ORDER BY id")?;
+    let notes = statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<(i64, String)>>>()?;
+
+    Ok(AnkiCollection {
+        deck_name,
+        models,
+        notes,
+    })
+}
+
+fn query_models(connection: &Connection) -> rusqlite::Result<HashMap<i64, AnkiModel>> {
+    let mut models: HashMap<i64, AnkiModel> = HashMap::new();
+
+    let mut statement = connection.prepare("SELECT ntid, ord, name FROM fields ORDER BY ntid, ord")?;
+    let fields = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, usize>(1)?, row.get::<_, String>(2)?)))?;
+
+    for field in fields {
+        let (model_id, ord, name) = field?;
+        let model = models.entry(model_id).or_insert(AnkiModel {model_type: 0, name: String::new(), flds: Vec::new(), tmpls: Vec::new()});
+        model.flds.push(AnkiModelField {name, ord});
+    }
+
+    // Note type settings in these tables are stored as protobuf so only the kind and the card templates are read from them
+    let mut statement = connection.prepare("SELECT ntid, ord, config FROM templates ORDER BY ntid, ord")?;
+    let templates = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, usize>(1)?, row.get::<_, Vec<u8>>(2)?)))?;
+
+    for template in templates {
+        let (model_id, ord, config) = template?;
+        let Some(model) = models.get_mut(&model_id) else {continue};
+
+        let (mut qfmt, mut afmt) = (String::new(), String::new());
+        for (field_number, value) in protobuf_fields(&config) {
+            match (field_number, value) {
+                (ANKI_TEMPLATE_QUESTION_FIELD, ProtobufValue::Bytes(bytes)) => qfmt = String::from_utf8_lossy(bytes).to_string(),
+                (ANKI_TEMPLATE_ANSWER_FIELD, ProtobufValue::Bytes(bytes)) => afmt = String::from_utf8_lossy(bytes).to_string(),
+                _ => (),
+            }
+        }
+        model.tmpls.push(AnkiModelTemplate {ord, qfmt, afmt});
+    }
+
+    let mut statement = connection.prepare("SELECT id, name, config FROM notetypes")?;
+    let note_types = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Vec<u8>>(2)?)))?;
+
+    for note_type in note_types {
+        let (model_id, name, config) = note_type?;
+        if let Some(model) = models.get_mut(&model_id) {
+            model.name = name;
+            model.model_type = protobuf_fields(&config).into_iter()
+                .find_map(|(field_number, value)| match (field_number, value) {
+                    (ANKI_NOTETYPE_KIND_FIELD, ProtobufValue::Varint(kind)) => Some(kind as u8),
+                    _ => None,
+                })
+                .unwrap_or_default();
+        }
+    }
+
+    Ok(models)
+}
+
+enum ProtobufValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Reads the top level fields of a protobuf message, stopping at the first one that cannot be read.
+fn protobuf_fields(mut bytes: &[u8]) -> Vec<(u64, ProtobufValue)> {
+    let mut fields = Vec::new();
+
+    while !!!bytes.is_empty() {
+        let Some(key) = read_varint(&mut bytes) else {break};
+        let value = match key & 0b111 {
+            0 => match read_varint(&mut bytes) {
+                Some(value) => ProtobufValue::Varint(value),
+                None => break,
+            },
+            2 => {
+                let Some(length) = read_varint(&mut bytes) else {break};
+                let Some((value, rest)) = bytes.split_at_checked(length as usize) else {break};
+                bytes = rest;
+                ProtobufValue::Bytes(value)
+            },
+            fixed_width => {
+                let width = match fixed_width {
+                    1 => 8,
+                    5 => 4,
+                    _ => break,
+                };
+                let Some((_, rest)) = bytes.split_at_checked(width) else {break};
+                bytes = rest;
+                continue;
+            },
+        };
+        fields.push((key >> 3, value));
+    }
+
+    fields
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// A field keeps its text alongside the first media file it uses, images and sounds are stored as their own asset kinds.
+fn anki_field_to_field(name: String, html: String, deck_id: DeckId, media_names: &HashSet<String>) -> Field {
+    let media_file = find_media_references(&html).into_iter().find(|(file_name, _)| media_names.contains(file_name));
+
+    let Some((file_name, is_sound)) = media_file else {
+        return Field::new_from_html(name, html, &strip_html);
+    };
+
+    let address = S3Address {
+        bucket: PUBLIC_DECKS_BUCKET.to_string(),
+        key: format!("{}/{}", deck_id.to_string(), file_name),
+    };
+    let asset = match is_sound {
+        true => Asset::DeckAudio(address),
+        false => Asset::DeckImage(address),
+    };
+
+    let text = strip_html(&remove_sound_tags(&html));
+    let text = (!!!text.trim().is_empty()).then_some(text);
+
+    Field::new(name, text, Some(asset))
+}
+
+/// The media files the field uses and whether each one is a sound.
+fn find_media_references(html: &str) -> Vec<(String, bool)> {
+    let mut references = Vec::new();
+
+    for (start_pattern, end_pattern) in [("src=\"", "\""), ("src='", "'"), (ANKI_SOUND_START, ANKI_SOUND_END)] {
+        let mut remaining = html;
+        while let Some(start) = remaining.find(start_pattern) {
+            remaining = &remaining[start + start_pattern.len()..];
+            let Some(end) = remaining.find(end_pattern) else {break};
+            references.push((remaining[..end].to_string(), start_pattern == ANKI_SOUND_START));
+            remaining = &remaining[end..];
+        }
+    }
+
+    references
+}
+
+fn remove_sound_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut remaining = html;
+
+    while let Some(start) = remaining.find(ANKI_SOUND_START) {
+        let Some(end) = remaining[start..].find(ANKI_SOUND_END) else {break};
+        text.push_str(&remaining[..start]);
+        remaining = &remaining[start + end + ANKI_SOUND_END.len()..];
+    }
+    text.push_str(remaining);
+
+    text
+}
+
+/// Whether a note type is cloze comes from its kind, a cloze note type can have any name.
+fn note_type_from_model(model: &AnkiModel) -> NoteType {
+    match (model.model_type, model.name.trim().is_empty()) {
+        (_, false) => NoteType::new(&model.name),
+        (ANKI_CLOZE_MODEL_TYPE, true) => NoteType::new(CLOZE_NOTE_TYPE),
+        _ => NoteType::default(),
+    }
+}
+
+/// Anki templates use the same {{Field}} placeholders, so they only need their html stripped.
+/// Each card template becomes the card with the same ord, cloze models make their cards from the cloze markers instead.
+fn note_template_from_model(model: &AnkiModel, note_type: NoteType) -> Option<NoteTemplate> {
+    let mut model_fields = model.flds.clone();
+    model_fields.sort_by_key(|model_field| model_field.ord);
+
+    let mut templates = model.tmpls.clone();
+    templates.sort_by_key(|template| template.ord);
+    let Some(first_template) = templates.first() else {
+        // Cloze note types read without their templates still make their cards from the cloze markers
+        let note_template = NoteTemplate::cloze(note_type, model_fields.into_iter().map(|model_field| model_field.name).collect());
+        return (model.model_type == ANKI_CLOZE_MODEL_TYPE && note_template.is_valid()).then_some(note_template);
+    };
+
+    let note_template = NoteTemplate {
+        note_type,
+        fields: model_fields.into_iter().map(|model_field| model_field.name).collect(),
+        front: strip_html(&first_template.qfmt),
+        back: strip_html(&first_template.afmt),
+        reverse: false,
+        cloze: model.model_type == ANKI_CLOZE_MODEL_TYPE,
+        extra_cards: match model.model_type {
+            ANKI_CLOZE_MODEL_TYPE => Vec::new(),
+            _ => templates.iter().skip(1).map(|template| CardTemplate {
+                front: strip_html(&template.qfmt),
+                back: strip_html(&template.afmt),
+            }).collect(),
+        },
+    };
+
+    note_template.is_valid().then_some(note_template)
+}
+
+
+/// Packages the notes as an .apkg with one card per note, returning the package bytes as AssetRetrieved.
+pub async fn export_anki_deck(object_store: &impl ObjectStore, deck_name: &str, notes: &Vec<Note>, field_names: &Vec<String>) -> Outcome {
+    let mut media_addresses: Vec<S3Address> = Vec::new();
+    for note in notes {
+        for field in note.fields.iter() {
+            if let Some(Asset::DeckImage(address) | Asset::DeckAudio(address)) = &field.asset {
+                if !!!media_addresses.contains(address) {
+                    media_addresses.push(address.clone());
+                }
+            }
+        }
+    }
+
+    let downloads = stream::iter(media_addresses)
+        .map(|address| async move {
+            let outcome = get_deck_asset(object_store, &address).await;
+            (address, outcome)
+        })
+        .buffer_unordered(CONCURRENT_MEDIA_UPLOADS)
+        .collect::<Vec<(S3Address, Outcome)>>()
+        .await;
+
+    let mut media = Vec::with_capacity(downloads.len());
+    for (address, outcome) in downloads {
+        match outcome {
+            Outcome::AssetRetrieved(bytes) => media.push((media_file_name(&address), bytes)),
+            any_other_outcome => error!("media file {} was left out of the export {}", address.key, any_other_outcome.to_string()),
+        }
+    }
+
+    let rows = notes.iter().map(|note| {
+        field_names.iter().map(|field_name| match note.fields.iter().find(|field| &field.name == field_name) {
+            Some(field) => field_to_anki_field(field),
+            None => String::new(),
+        }).collect::<Vec<String>>()
+    }).collect::<Vec<Vec<String>>>();
+
+    let deck_name = deck_name.to_string();
+    let field_names = field_names.clone();
+
+    match tokio::task::spawn_blocking(move || write_anki_package(deck_name, field_names, rows, media)).await {
+        Ok(Ok(bytes)) => Outcome::AssetRetrieved(bytes),
+        Ok(Err(reason)) => Outcome::DeckCouldNotBeProcessed(reason),
+        Err(e) => Outcome::DeckCouldNotBeProcessed(e.to_string()),
+    }
+}
+
+fn write_anki_package(deck_name: String, field_names: Vec<String>, rows: Vec<Vec<String>>, media: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>, String> {
+    // sqlite can only build collections on disk so each export gets a directory of its own, which is removed when dropped
+    let directory = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let path = directory.path().join(ANKI_COLLECTION_TEMP_FILE);
+
+    Connection::open(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|connection| write_collection(&connection, &deck_name, &field_names, &rows).map_err(|e| e.to_string()))?;
+    let collection = std::fs::read(&path).map_err(|e| e.to_string())?;
+
+    let mut package = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+
+    package.start_file(ANKI_COLLECTION_FILES[1], options).map_err(|e| e.to_string())?;
+    package.write_all(&collection).map_err(|e| e.to_string())?;
+
+    // Media is stored under numbered names and the media file maps them back to the names used in the notes
+    let mut media_map = HashMap::with_capacity(media.len());
+    for (i, (file_name, bytes)) in media.into_iter().enumerate() {
+        package.start_file(i.to_string(), options).map_err(|e| e.to_string())?;
+        package.write_all(&bytes).map_err(|e| e.to_string())?;
+        media_map.insert(i.to_string(), file_name);
+    }
+
+    package.start_file(ANKI_MEDIA_FILE, options).map_err(|e| e.to_string())?;
+    package.write_all(json!(media_map).to_string().as_bytes()).map_err(|e| e.to_string())?;
+
+    let package = package.finish().map_err(|e| e.to_string())?;
+    Ok(package.into_inner())
+}
+
+fn write_collection(connection: &Connection, deck_name: &str, field_names: &Vec<String>, rows: &Vec<Vec<String>>) -> rusqlite::Result<()> {
+    connection.execute_batch(ANKI_LEGACY_SCHEMA)?;
+
+    let now = current_time_in_seconds() as i64;
+    let model_id = now * 1000;
+    let anki_deck_id = model_id + 1;
+
+    let fields = field_names.iter().enumerate().map(|(ord, name)| json!({
+        "name": name, "ord": ord, "sticky": false, "rtl": false, "font": "Arial", "size": 20, "media": [],
+    })).collect::<Vec<_>>();
+
+    let question = field_names.first().map(|name| format!("{{{{{name}}}}}")).unwrap_or_default();
+    let mut answer = "{{FrontSide}}<hr id=answer>".to_string();
+    answer.push_str(&field_names.iter().skip(1).map(|name| format!("{{{{{name}}}}}")).collect::<Vec<String>>().join("<br>"));
+
+    let models = json!({
+        model_id.to_string(): {
+            "id": model_id, "name": format!("{deck_name} Note"), "type": 0, "mod": now, "usn": -1, "sortf": 0, "did": anki_deck_id,
+            "tmpls": [{"name": "Card 1", "ord": 0, "qfmt": question, "afmt": answer, "bqfmt": "", "bafmt": "", "did": null}],
+            "flds": fields,
+            "css": ".card { font-family: arial; font-size: 20px; text-align: center; }",
+            "latexPre": "\\documentclass[12pt]{article}\n\\begin{document}\n", "latexPost": "\\end{document}", "latexsvg": false,
+            "req": [[0, "any", [0]]], "tags": [], "vers": [],
+        },
+    });
+
+    let deck = |id: i64, name: &str| json!({
+        "id": id, "name": name, "mod": now, "usn": -1, "desc": "", "dyn": 0, "conf": 1, "collapsed": false,
+        "newToday": [0, 0], "revToday": [0, 0], "lrnToday": [0, 0], "timeToday": [0, 0], "extendNew": 10, "extendRev": 50,
+    });
+    let decks = json!({
+        ANKI_DEFAULT_DECK_ID.to_string(): deck(ANKI_DEFAULT_DECK_ID, "Default"),
+        anki_deck_id.to_string(): deck(anki_deck_id, deck_name),
+    });
+
+    let deck_config = json!({
+        "1": {
+            "id": 1, "name": "Default", "mod": 0, "usn": 0, "maxTaken": 60, "autoplay": true, "timer": 0, "replayq": true, "dyn": false,
+            "new": {"delays": [1.0, 10.0], "ints": [1, 4, 7], "initialFactor": 2500, "order": 1, "perDay": 20, "bury": false},
+            "rev": {"perDay": 200, "ease4": 1.3, "ivlFct": 1.0, "maxIvl": 36500, "bury": false, "hardFactor": 1.2},
+            "lapse": {"delays": [10.0], "mult": 0.0, "minInt": 1, "leechFails": 8, "leechAction": 1},
+        },
+    });
+
+    let config = json!({
+        "nextPos": rows.len() + 1, "estTimes": true, "activeDecks": [anki_deck_id], "sortType": "noteFld", "timeLim": 0,
+        "sortBackwards": false, "addToCur": true, "curDeck": anki_deck_id, "newSpread": 0, "dueCounts": true, "curModel": model_id, "collapseTime": 1200,
+    });
+
+    connection.execute(
+        "INSERT INTO col VALUES (1, ?1, ?2, ?2, ?3, 0, 0, 0, ?4, ?5, ?6, ?7, '{}')",
+        params![now, model_id, ANKI_LEGACY_SCHEMA_VERSION, config.to_string(), models.to_string(), decks.to_string(), deck_config.to_string()],
+    )?;
+
+    // Anki works out sort fields and checksums again when a package is imported
+    for (i, row) in rows.iter().enumerate() {
+        let note_id = model_id + i as i64 + 1;
+        let sort_field = row.first().map(|field| strip_html(field)).unwrap_or_default();
+        connection.execute(
+            "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, '', ?5, ?6, 0, 0, '')",
+            params![note_id, format!("lex-{}", note_id), model_id, now, row.join(&ANKI_FIELD_SEPARATOR.to_string()), sort_field],
+        )?;
+        connection.execute(
+            "INSERT INTO cards VALUES (?1, ?1, ?2, 0, ?3, -1, 0, 0, ?4, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+            params![note_id, anki_deck_id, now, i as i64 + 1],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn media_file_name(address: &S3Address) -> String {
+    address.key.split_once('/').map(|(_, file_name)| file_name).unwrap_or(&address.key).to_string()
+}
+
+fn field_to_anki_field(field: &Field) -> String {
+    let text = field.text.as_deref().unwrap_or_default()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\n', "<br>");
+
+    match &field.asset {
+        Some(Asset::DeckImage(address)) => format!("{text}<img src=\"{}\">", media_file_name(address)),
+        Some(Asset::DeckAudio(address)) => format!("{text}{ANKI_SOUND_START}{}{ANKI_SOUND_END}", media_file_name(address)),
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::shared_truth::DECK_ID_LENGTH;
+
+    fn deck_id() -> DeckId {
+        DeckId {id: ['a'; DECK_ID_LENGTH]}
+    }
+
+    fn deck_address(file_name: &str) -> S3Address {
+        S3Address {bucket: PUBLIC_DECKS_BUCKET.to_string(), key: format!("{}/{file_name}", deck_id().to_string())}
+    }
+
+    fn model(model_type: u8, name: &str, templates: Vec<(usize, &str, &str)>) -> AnkiModel {
+        AnkiModel {
+            model_type,
+            name: name.to_string(),
+            flds: vec![
+                AnkiModelField {name: "Back".to_string(), ord: 1},
+                AnkiModelField {name: "Front".to_string(), ord: 0},
+            ],
+            tmpls: templates.into_iter().map(|(ord, qfmt, afmt)| AnkiModelTemplate {ord, qfmt: qfmt.to_string(), afmt: afmt.to_string()}).collect(),
+        }
+    }
+
+    #[test]
+    fn images_keep_the_text_beside_them() {
+        let media_names = HashSet::from(["cat.png".to_string()]);
+        let field = anki_field_to_field("Back".to_string(), "cat<img src=\"cat.png\">".to_string(), deck_id(), &media_names);
+
+        assert_eq!(field, Field::new("Back".to_string(), Some("cat".to_string()), Some(Asset::DeckImage(deck_address("cat.png")))));
+    }
+
+    #[test]
+    fn sounds_become_audio_assets() {
+        let media_names = HashSet::from(["gato.mp3".to_string()]);
+        let field = anki_field_to_field("Back".to_string(), "[sound:gato.mp3]".to_string(), deck_id(), &media_names);
+
+        assert_eq!(field, Field::new("Back".to_string(), None, Some(Asset::DeckAudio(deck_address("gato.mp3")))));
+    }
+
+    #[test]
+    fn media_missing_from_the_package_is_left_out() {
+        let field = anki_field_to_field("Back".to_string(), "cat<img src=\"cat.png\">".to_string(), deck_id(), &HashSet::new());
+
+        assert_eq!(field.text, Some("cat".to_string()));
+        assert_eq!(field.asset, None);
+    }
+
+    #[test]
+    fn cloze_note_types_are_found_by_kind_not_name() {
+        let named = model(ANKI_CLOZE_MODEL_TYPE, "Lückentext", vec![(0, "{{cloze:Front}}", "{{cloze:Front}}<br>{{Back}}")]);
+        let unnamed = model(ANKI_CLOZE_MODEL_TYPE, " ", Vec::new());
+
+        assert_eq!(note_type_from_model(&named), NoteType::new("Lückentext"));
+        assert_eq!(note_type_from_model(&unnamed), NoteType::new(CLOZE_NOTE_TYPE));
+
+        let note_template = note_template_from_model(&named, note_type_from_model(&named)).unwrap();
+        assert!(note_template.cloze);
+        assert!(note_template.extra_cards.is_empty());
+        assert!(note_template_from_model(&unnamed, note_type_from_model(&unnamed)).is_some_and(|note_template| note_template.cloze));
+    }
+
+    #[test]
+    fn card_templates_map_in_ord_order() {
+        let basic = model(0, "Basic (and reversed card)", vec![(1, "{{Back}}", "{{Front}}"), (0, "{{Front}}", "{{FrontSide}}<hr id=answer>{{Back}}")]);
+        let note_template = note_template_from_model(&basic, note_type_from_model(&basic)).unwrap();
+
+        assert_eq!(note_template.fields, vec!["Front".to_string(), "Back".to_string()]);
+        assert_eq!(note_template.front, "{{Front}}");
+        assert_eq!(note_template.back, "{{FrontSide}}\n{{Back}}");
+        assert_eq!(note_template.extra_cards, vec![CardTemplate {front: "{{Back}}".to_string(), back: "{{Front}}".to_string()}]);
+        assert!(!!!note_template.cloze);
+    }
+
+    #[test]
+    fn exported_packages_import_with_their_media() {
+        let package = write_anki_package(
+            "Spanish".to_string(),
+            vec!["Front".to_string(), "Back".to_string()],
+            vec![vec!["gato".to_string(), "cat<img src=\"cat.png\">".to_string()]],
+            vec![("cat.png".to_string(), vec![1, 2, 3])],
+        ).unwrap();
+
+        let imported = read_anki_package(package, deck_id()).unwrap();
+        assert_eq!(imported.deck_name, "Spanish");
+        assert!(imported.row_errors.is_empty());
+        assert_eq!(imported.media, vec![("cat.png".to_string(), vec![1, 2, 3])]);
+
+        let [(Some(1), note)] = imported.notes.as_slice() else {panic!("expected one note from the first row")};
+        assert_eq!(note.note_type, NoteType::new("Spanish Note"));
+        assert_eq!(note.fields, vec![
+            Field::new("Front".to_string(), Some("gato".to_string()), None),
+            Field::new("Back".to_string(), Some("cat".to_string()), Some(Asset::DeckImage(deck_address("cat.png")))),
+        ]);
+        assert!(imported.note_templates.iter().any(|note_template| note_template.note_type == note.note_type));
+    }
+}
@@ -4,16 +4,20 @@ use crate::utils::{
     database_types::Asset, outcomes::Outcome,
     auth_client::AuthClient,
 };
-use std::time::Duration;
 
 /// Server Imports
 #[cfg(feature="ssr")]
 use crate::utils::{
-    dynamo_utils::{setup_client, validate_active_decks_and_user_standing},
-    back_utils::{PFP_BUCKET, verify_user_header},
+    database_types::{DeckId, S3Address},
+    dynamo_utils::validate_active_decks_and_user_standing,
+    back_utils::{DECK_COVER_FILE_NAME, PFP_BUCKET, PUBLIC_DECKS_BUCKET, verify_user_header},
+    object_store::{setup_object_store, ObjectStore},
+    storage::{setup_storage, Storage},
 };
 #[cfg(feature="ssr")]
-use aws_sdk_s3::{presigning::PresigningConfig, Client as S3Client};
+use aws_sdk_s3::Client as S3Client;
+#[cfg(feature="ssr")]
+use std::str::FromStr;
 #[cfg(feature="ssr")]
 use aws_config::{retry::RetryConfig, BehaviorVersion, Region};
 
@@ -21,31 +25,47 @@ use aws_config::{retry::RetryConfig, BehaviorVersion, Region};
 pub async fn asset_from_s3(asset: Asset, email: Option<String>) -> Result<Outcome, ServerFnError> {
     let Outcome::VerificationSuccess(email) = verify_user_header(email).await else {return Ok(Outcome::VerificationFailure)};
 
-    let config = aws_config::defaults(BehaviorVersion::latest()).retry_config(RetryConfig::standard().with_max_attempts(15)).region(Region::new("us-east-2")).load().await;
-    let client = S3Client::new(&config);
+    let object_store = setup_object_store().await;
 
-    let outcome = get_asset_url(&client, &email, asset).await;
+    let outcome = get_asset_url(&object_store, &email, asset).await;
 
     Ok(outcome)
 }
 
 #[cfg(feature="ssr")]
-async fn get_asset_url(client: &S3Client, email: &str, asset: Asset) -> Outcome {
+pub async fn setup_s3_client() -> S3Client {
+    let config = aws_config::defaults(BehaviorVersion::latest()).retry_config(RetryConfig::standard().with_max_attempts(15)).region(Region::new("us-east-2")).load().await;
+    S3Client::new(&config)
+}
+
+#[cfg(feature="ssr")]
+async fn get_asset_url(object_store: &impl ObjectStore, email: &str, asset: Asset) -> Outcome {
     let outcome = match asset {
         Asset::PFP(address) => {
             if address.bucket != PFP_BUCKET {
                 return Outcome::InvalidRequest;
             }
-            get_presigned_url(client, &address.bucket, &address.key, 20).await
+            get_presigned_url(object_store, &address.bucket, &address.key, 20).await
         },
-        Asset::DeckImage(address) => {
+        Asset::DeckImage(address) | Asset::DeckAudio(address) => {
             let Some(split_index) = address.key.find("/") else {return Outcome::InvalidRequest};
-            let (deck_id, _file_id) = address.key.split_at(split_index);
+            let (deck_id, file_id) = address.key.split_at(split_index);
+
+            let storage = setup_storage().await;
 
-            let ddb_client = setup_client().await;
+            // Covers of catalog decks are shown to users who have not subscribed yet
+            if file_id[1..].starts_with(DECK_COVER_FILE_NAME) {
+                if let Ok(deck_id) = DeckId::from_str(deck_id) {
+                    if let Outcome::CatalogEntryFound(entry) = storage.get_catalog_entry(deck_id).await {
+                        if entry.cover_image == Asset::DeckImage(address.clone()) {
+                            return get_presigned_url(object_store, &address.bucket, &address.key, 20).await;
+                        }
+                    }
+                }
+            }
 
-            match validate_active_decks_and_user_standing(&ddb_client, email, deck_id).await {
-                Outcome::PermissionGranted(_) => get_presigned_url(client, &address.bucket, &address.key, 20).await,
+            match validate_active_decks_and_user_standing(&storage, email, deck_id).await {
+                Outcome::PermissionGranted(_) => get_presigned_url(object_store, &address.bucket, &address.key, 20).await,
                 any_other_outcome => any_other_outcome,
             }
         },
@@ -56,20 +76,33 @@ async fn get_asset_url(client: &S3Client, email: &str, asset: Asset) -> Outcome
 }
 
 #[cfg(feature="ssr")]
-async fn get_presigned_url(client: &S3Client, bucket: &str, key: &str, expires_in: u64) -> Outcome {
-    let duration = Duration::from_secs(expires_in);
-
-    let presigned_request = client
-        .get_object()
-        .bucket(bucket)
-        .key(key)
-        .presigned(PresigningConfig::expires_in(duration).expect("crazy"))
-        .await;
-
-    let url = match presigned_request {
-        Ok(request) => request.uri().to_string(),
-        Err(e) => return Outcome::PresignedUrlNotRetrieved(e.into_service_error().to_string()),
+pub async fn get_presigned_url(object_store: &impl ObjectStore, bucket: &str, key: &str, expires_in: u64) -> Outcome {
+    let address = S3Address {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
     };
-    
-    Outcome::PresignedUrlRetrieved(url)
-}
\ No newline at end of file
+
+    object_store.presigned_get_url(&address, expires_in).await
+}
+
+#[cfg(feature="ssr")]
+pub async fn put_deck_asset(object_store: &impl ObjectStore, deck_id: DeckId, file_name: &str, bytes: Vec<u8>) -> Outcome {
+    let address = S3Address {
+        bucket: PUBLIC_DECKS_BUCKET.to_string(),
+        key: format!("{}/{}", deck_id.to_string(), file_name),
+    };
+
+    match object_store.put_object(&address, bytes, None).await {
+        Outcome::ObjectStored(address) => Outcome::AssetUploaded(Asset::DeckImage(address)),
+        any_other_outcome => any_other_outcome,
+    }
+}
+
+#[cfg(feature="ssr")]
+pub async fn get_deck_asset(object_store: &impl ObjectStore, address: &S3Address) -> Outcome {
+    if address.bucket != PUBLIC_DECKS_BUCKET {
+        return Outcome::InvalidRequest;
+    }
+
+    object_store.get_object(address, usize::MAX).await
+}
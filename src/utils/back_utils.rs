@@ -1,13 +1,20 @@
-use std::{str::FromStr, time::{Duration, SystemTime, UNIX_EPOCH}};
+use std::{collections::hash_map::RandomState, hash::{BuildHasher, Hash, Hasher}, str::FromStr, time::{Duration, SystemTime, UNIX_EPOCH}};
 
-use crate::utils::database_types::{Asset, S3Address};
+use crate::utils::database_types::{Asset, DeckId, S3Address};
+use crate::utils::deck_import::DeckFileType;
 use crate::utils::user_types::Standing;
 use axum::http::{HeaderMap, Method};
 use pasetors::{claims::{Claims, ClaimsValidationRules}, errors::Error as PasetoError, keys::{AsymmetricPublicKey, AsymmetricSecretKey}, public, token::{TrustedToken, UntrustedToken}, version4::V4, Public};
 use serde::{Deserialize, Serialize};
 use leptos_axum::extract;
 
-use super::{date_and_time::current_time_in_seconds, outcomes::Outcome, shared_truth::{IS_TRUSTED_CLAIM, PUBLIC_KEY, USER_CLAIM_AUTH, USER_CLAIM_REFRESH, USER_CLAIM_SIGN_UP}, sign_in_lib::TokenPair};
+use super::{
+    collaborators::{CollaboratorRole, InviteResponse},
+    date_and_time::current_time_in_seconds,
+    outcomes::Outcome,
+    shared_truth::{COLAB_INVITE_CLAIM, DECK_CLAIM, INVITED_CLAIM, IS_TRUSTED_CLAIM, PUBLIC_KEY, RESPONSE_CLAIM, ROLE_CLAIM, USER_CLAIM_AUTH, USER_CLAIM_REFRESH, USER_CLAIM_SIGN_UP},
+    sign_in_lib::TokenPair,
+};
 
 pub const PUBLIC_DECKS_TABLE: &str = "LEXDecks";
 
@@ -19,8 +26,36 @@ pub const PFP_BUCKET: &str = "lexpfpbucket";
 
 pub const USERS_TABLE: &str = "LEXUsers";
 
+pub const REVIEWS_TABLE: &str = "LEXReviews";
+
+pub const UPLOAD_LEDGER_TABLE: &str = "LEXUploadLedger";
+
+pub const CATALOG_TABLE: &str = "LEXCatalog";
+
+pub const RECEIPTS_TABLE: &str = "LEXReceipts";
+
+pub const PAYOUT_ACCOUNTS_TABLE: &str = "LEXPayoutAccounts";
+
+pub const COLLABORATORS_TABLE: &str = "LEXCollaborators";
+pub const SYNC_RECORDS_TABLE: &str = "LEXSyncRecords";
+
+/// The part of each sale paid out to the deck's owner, the rest is kept as the platform fee.
+pub const OWNER_PAYOUT_SHARE: f64 = 0.7;
+
 pub const UPLOAD_TOKEN_PRICE_IN_DOLLARS: f64 = 0.20;
 
+pub const STAGING_UPLOAD_PREFIX: &str = "uploads";
+
+pub const UPLOAD_URL_EXPIRATION_IN_SECONDS: u64 = 900;
+
+pub const DECK_COVER_FILE_NAME: &str = "deck-cover";
+
+pub const EXPORT_PREFIX: &str = "exports";
+
+pub const EXPORT_URL_EXPIRATION_IN_SECONDS: u64 = 900;
+
+const DECK_ID_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789_-";
+
 #[derive(Serialize, Deserialize)]
 pub struct PasetoPrivateKey(#[serde(with = "serde_arrays")] [u8; 64]);
 
@@ -124,6 +159,32 @@ pub fn get_default_pfp() -> Asset {
     Asset::PFP(S3Address {bucket: PFP_BUCKET.to_owned(), key: format!("default{current_selection}.avif")})
 }
 
+pub fn generate_deck_id() -> DeckId {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    // Every RandomState is seeded with fresh random keys so hashing the time with it gives unpredictable ids
+    let random_state = RandomState::new();
+
+    let mut deck_id = DeckId::default();
+    for (i, character) in deck_id.id.iter_mut().enumerate() {
+        let mut hasher = random_state.build_hasher();
+        (nanos, i).hash(&mut hasher);
+        *character = DECK_ID_ALPHABET[(hasher.finish() % DECK_ID_ALPHABET.len() as u64) as usize] as char;
+    }
+    deck_id
+}
+
+pub fn staging_upload_key(email: &str, deck_id: DeckId, file_type: DeckFileType) -> String {
+    format!("{STAGING_UPLOAD_PREFIX}/{email}/{}{}", deck_id.to_string(), file_type.extension())
+}
+
+pub fn deck_cover_key(deck_id: DeckId, extension: &str) -> String {
+    format!("{}/{DECK_COVER_FILE_NAME}{extension}", deck_id.to_string())
+}
+
+pub fn export_key(email: &str, deck_id: DeckId, file_type: DeckFileType) -> String {
+    format!("{EXPORT_PREFIX}/{email}/{}{}", deck_id.to_string(), file_type.extension())
+}
+
 pub fn choose_table() -> String {
     let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
 
@@ -177,6 +238,23 @@ pub fn build_sign_up_token(is_trusted: bool, email_address: &str) -> Result<Stri
     Ok(sign_up_token)
 }
 
+/// Signs one answer to a collaborator invite, the invited claim ties the link to the invite it was sent for.
+pub fn build_collaborator_invite_token(email_address: &str, deck_id: DeckId, role: CollaboratorRole, invited: u64, response: InviteResponse) -> Result<String, PasetoError> {
+    let one_week = 604800;
+
+    let mut claims = Claims::new_expires_in(&Duration::from_secs(one_week))?;
+    claims.add_additional(COLAB_INVITE_CLAIM, email_address)?;
+    claims.add_additional(DECK_CLAIM, deck_id.to_string())?;
+    claims.add_additional(ROLE_CLAIM, role.to_string())?;
+    claims.add_additional(INVITED_CLAIM, invited.to_string())?;
+    claims.add_additional(RESPONSE_CLAIM, response.to_string())?;
+
+    let private_key = AsymmetricSecretKey::<V4>::from(&PasetoPrivateKey::get_key())?;
+    let invite_token = public::sign(&private_key, &claims, None, Some(b"implicit assertion"))?;
+
+    Ok(invite_token)
+}
+
 pub fn build_auth_token(is_trusted: bool, email_address: &str) -> Result<String, PasetoError> {
     let one_hour = 3600;
     let one_day = one_hour * 24;
@@ -241,3 +319,31 @@ pub async fn verify_user_header(user: Option<String>) -> Outcome {
 
     Outcome::VerificationSuccess(email.to_string())
 }
+
+pub async fn send_mailtrap_email(email_address: &str, subject: &str, text: &str, html: &str) -> Outcome {
+    let api_url = "https://send.api.mailtrap.io/api/send";
+    let api_key = std::env::var("MAILTRAP_PASSWORD").unwrap_or_default();
+
+    let email_payload = serde_json::json!({
+        "from": {"email": &format!("{}", std::env::var("SENDER_EMAIL").unwrap_or_default()), "name": "LexLingua"},
+        "to": [{"email": email_address}],
+        "subject": subject,
+        "text": text,
+        "html": html,
+    });
+
+    let client = reqwest::Client::new();
+
+    let outcome = match client
+    .post(api_url)
+    .header("Accept", "application/json")
+    .header("Content-Type", "application/json")
+    .header("Api-Token", api_key)
+    .body(email_payload.to_string())
+    .send().await {
+        Ok(resp) if resp.status().is_success() => Outcome::EmailSendSuccess,
+        Ok(resp) => Outcome::EmailSendFailure(resp.text().await.unwrap_or_default()),
+        Err(e) =>  Outcome::EmailSendFailure(e.to_string()),
+    };
+    outcome
+}
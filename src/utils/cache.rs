@@ -1,22 +1,20 @@
 use crate::utils::{
-    database_types::{Asset, DBItem, DeckList, Note, UpdateRecipe, UpdateRecipes, UpdateType, UpdateValues}, 
+    cache_manager,
+    database_types::{Asset, DBItem, DeckId, DeckList, DeckMeta, Note, NoteList, UpdateRecipe, UpdateRecipes, UpdateType, UpdateValues}, 
     outcomes::Outcome, 
     query::ValidQueryTypes, 
-    shared_truth::{LOCAL_USER_INFO_KEY, LOCAL_AUTH_TOKEN_KEY},
+    scheduler::ReviewState,
+    shared_truth::{LOCAL_USER_INFO_KEY, LOCAL_AUTH_TOKEN_KEY, LOCAL_REVIEW_STATES_KEY, LOCAL_SYNC_QUEUE_KEY},
+    sync_queue::SyncQueue,
     shared_utilities::{get_item_from_local_storage, store_item_in_local_storage}, 
     user_types::{UserInfo, UserState},
     cache_db_interface::get_asset,
     front_utils::{s3_url_expired, image_cached},
 };
-use serde::Serialize;
-use serde_wasm_bindgen::Serializer;
-use std::{io::Error as ioError, str::FromStr, sync::atomic::AtomicBool};
+use std::{collections::HashSet, str::FromStr};
 use leptos::logging::debug_warn;
-use indexed_db::Factory;
 use web_sys::window;
 
-const DECKS_CACHE_NAME: &str = "test";
-
 pub fn clear_cache(cache_key: &str) -> Result<(), ()> {
     match window()
         .unwrap()
@@ -36,81 +34,125 @@ pub fn clear_cache(cache_key: &str) -> Result<(), ()> {
     }
 }
 
-pub async fn get_notes_from_cache(_query: &ValidQueryTypes) -> Outcome {
-    Outcome::ItemsNotFound
+/// Only answers when the cache holds everything the query asks for, counts are checked against the cached deck meta.
+pub async fn get_notes_from_cache(query: &ValidQueryTypes) -> Outcome {
+    let (meta, notes) = match read_cached_notes(query).await {
+        Ok(cached) => cached,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    let count_where = |matches: &dyn Fn(&Note) -> bool| notes.iter().filter(|note| matches(note)).count();
+
+    let is_complete = match (query, &meta) {
+        (ValidQueryTypes::NotesById(_, note_ids), _) => {
+            let requested: HashSet<usize> = note_ids.iter().copied().collect();
+            !!!requested.is_empty() && notes.len() == requested.len()
+        },
+        (ValidQueryTypes::NotesByLevel(_, levels), Some(meta)) => !!!levels.is_empty() && levels.iter().all(|level| {
+            let expected = level.checked_sub(1).and_then(|index| meta.note_count_by_level.get(index));
+            expected == Some(&count_where(&|note: &Note| note.level as usize == *level))
+        }),
+        (ValidQueryTypes::NotesByType(_, note_types), Some(meta)) => !!!note_types.is_empty() && note_types.iter().all(|note_type| {
+            let expected = meta.note_count_by_type.get(note_type).copied().unwrap_or_default();
+            expected == count_where(&|note: &Note| &note.note_type == note_type)
+        }),
+        _ => false,
+    };
+
+    if !!!is_complete || notes.is_empty() {
+        return Outcome::ItemsNotFound;
+    }
+
+    let mut note_list = NoteList::default();
+    note_list.extend(notes);
+    Outcome::ItemsFound(note_list.to_string())
 }
 
-pub async fn update_notes_cache(cache_recipe: UpdateRecipe) -> Outcome {
-    let _update_key = cache_recipe.update_key.as_str();
-    let DBItem::Note(deck_id, _) = cache_recipe.update_item else {return Outcome::CacheFailed("Not a note".to_string())};
+/// Every cached note the query matches along with the cached deck meta, whether or not the cache holds all of them.
+pub async fn read_cached_notes(query: &ValidQueryTypes) -> Result<(Option<DeckMeta>, Vec<Note>), Outcome> {
+    cache_manager::read_notes(query).await
+}
 
-    let UpdateValues::Note(note) = cache_recipe.value else {return Outcome::CacheFailed("could not find note".to_string())};
+/// Takes out notes the server no longer has.
+pub async fn remove_notes_from_cache(deck_id: DeckId, note_ids: Vec<u64>) -> Outcome {
+    cache_manager::delete_notes(deck_id, note_ids).await
+}
 
-    let factory = match Factory::<ioError>::get() {
-        Ok(fac) => fac,
-        Err(e) => return Outcome::CacheFailed(e.to_string()),
-    };
+/// Review states are kept per user and deck so study sessions can be built without a connection.
+pub fn get_review_states_from_cache(user: &str, deck_id: DeckId) -> Option<Vec<ReviewState>> {
+    let cached = get_item_from_local_storage(&review_states_cache_key(user, deck_id))?;
+    serde_json::from_str(&cached).ok()
+}
 
-    static DELETE_DB: AtomicBool = AtomicBool::new(false);
-    if DELETE_DB.load(std::sync::atomic::Ordering::Relaxed) {
-        match factory.delete_database(DECKS_CACHE_NAME).await {
-            Ok(_) => return Outcome::CacheSucceeded,
-            Err(_) => return Outcome::CacheFailed("Could not delete db".to_string()),
-        }
+pub fn cache_review_states(user: &str, deck_id: DeckId, review_states: &Vec<ReviewState>) {
+    let Ok(review_states_str) = serde_json::to_string(review_states) else {return};
+    if store_item_in_local_storage(&review_states_cache_key(user, deck_id), &review_states_str).is_err() {
+        debug_warn!("review states could not be cached");
     }
+}
 
-    let db = match factory.open_latest_version(DECKS_CACHE_NAME).await {
-        Ok(db) => db,
-        Err(e) => return Outcome::CacheFailed(e.to_string()),
-    };
+/// Replaces the cached state of the card, or adds it when the card was just learned.
+pub fn cache_review_state(review_state: &ReviewState) {
+    let mut review_states = get_review_states_from_cache(&review_state.user, review_state.deck_id).unwrap_or_default();
+    match review_states.iter_mut().find(|cached| cached.card == review_state.card) {
+        Some(cached) => *cached = review_state.clone(),
+        None => review_states.push(review_state.clone()),
+    }
+    cache_review_states(&review_state.user, review_state.deck_id, &review_states);
+}
 
-    let current_stores = db.object_store_names();
-
-    let db = if !!!current_stores.contains(&deck_id.to_string()) {
-        debug_warn!("did we make it here {:?}", current_stores);
-        let new_version = db.version() + 1;
-        db.close();
-        match factory.open(DECKS_CACHE_NAME, new_version, move |evt| async move {
-            debug_warn!("did we make it here {}, {}", evt.old_version(), evt.new_version());
-            let db = evt.database();
-            let store = db.build_object_store(&deck_id.to_string()).auto_increment().key_path(&Note::FIELD_NAMES.note_id).create()?;
-            store.build_index(&Note::FIELD_NAMES.level, &Note::FIELD_NAMES.level).create()?;
-            store.build_index(&Note::FIELD_NAMES.version, &Note::FIELD_NAMES.version).create()?;
-            
-            Ok(())
-        }).await {
-            Ok(db) => db,
-            Err(e) => return Outcome::CacheFailed(e.to_string()),
-        }
-    } else {
-        db
+fn review_states_cache_key(user: &str, deck_id: DeckId) -> String {
+    format!("{LOCAL_REVIEW_STATES_KEY}-{user}-{deck_id}")
+}
+
+/// Each user has their own queue so nothing made while signed in as someone else is sent under their account.
+pub fn get_sync_queue_from_cache(user: &str) -> SyncQueue {
+    get_item_from_local_storage(&sync_queue_cache_key(user))
+        .and_then(|queue| SyncQueue::from_str(&queue).ok())
+        .unwrap_or_default()
+}
+
+pub fn cache_sync_queue(user: &str, queue: &SyncQueue) -> Result<(), ()> {
+    store_item_in_local_storage(&sync_queue_cache_key(user), &queue.to_string())
+}
+
+fn sync_queue_cache_key(user: &str) -> String {
+    format!("{LOCAL_SYNC_QUEUE_KEY}-{user}")
+}
+
+pub async fn update_notes_cache(cache_recipe: UpdateRecipe) -> Outcome {
+    let update_type = cache_recipe.update_type;
+    let DBItem::Note(deck_id, _) = cache_recipe.update_item else {return Outcome::CacheFailed("Not a note".to_string())};
+
+    // Deck metas live on note 0 which holds nothing else, so a new meta replaces the whole note
+    let note = match cache_recipe.value {
+        UpdateValues::Note(note) => note,
+        UpdateValues::DeckMeta(meta) => Note {
+            deck_id,
+            note_id: 0,
+            meta: Some(meta),
+            ..Default::default()
+        },
+        _ => return Outcome::CacheFailed("could not find note".to_string()),
     };
 
-    let transaction = db.transaction(&[&deck_id.to_string()]).rw();
-
-    match transaction.run(move |trans| async move {
-        let store = trans.object_store(&deck_id.to_string())?;
-        let note_json = match note.serialize(&Serializer::json_compatible()) {
-            Ok(json) => json,
-            Err(_) => return Err(indexed_db::Error::OperationNotSupported),
-        };
-        store.put(&note_json).await?;
-        Ok(())
-    }).await {
-        Ok(_) => (),
-        Err(e) => return Outcome::CacheFailed(e.to_string()),
+    match update_type {
+        UpdateType::Subtract => cache_manager::delete_notes(deck_id, vec![note.note_id]).await,
+        _ => cache_manager::put_notes(deck_id, vec![note]).await,
     }
-
-    db.close();
-    Outcome::CacheSucceeded
 }
 
 pub async fn update_cache(cache_recipes: UpdateRecipes) {
+    let mut active_decks_changed = false;
+
     for cache_recipe in cache_recipes.recipes {
         match cache_recipe.update_item {
-            DBItem::User(_) => match update_user_cache(cache_recipe) {
-                Ok(_) => debug_warn!("cache updated?"),
-                Err(_) => debug_warn!("could not update user cache"),
+            DBItem::User(_) => {
+                active_decks_changed |= cache_recipe.update_key == UserInfo::ACTIVE_DECKS_CACHE_KEY;
+                match update_user_cache(cache_recipe) {
+                    Ok(_) => debug_warn!("cache updated?"),
+                    Err(_) => debug_warn!("could not update user cache"),
+                }
             },
             DBItem::Note(_, _) => match update_notes_cache(cache_recipe).await {
                 Outcome::CacheSucceeded => debug_warn!("cache updated?"),
@@ -119,6 +161,20 @@ pub async fn update_cache(cache_recipes: UpdateRecipes) {
             },
         }
     }
+
+    if active_decks_changed {
+        if let Ok(user_info) = get_user_info_from_cache() {
+            evict_decks_user_left(&user_info).await;
+        }
+    }
+}
+
+/// Notes are only kept for the decks the user is studying, a deck is evicted once it leaves their active decks.
+pub async fn evict_decks_user_left(user_info: &UserInfo) {
+    match cache_manager::evict_decks_not_in(&user_info.active_decks).await {
+        Outcome::CacheSucceeded => (),
+        any_other_outcome => debug_warn!("decks could not be evicted from the note cache {}", any_other_outcome.to_string()),
+    }
 }
 
 fn update_user_cache(cache_recipe: UpdateRecipe) -> Result<(), ()> {
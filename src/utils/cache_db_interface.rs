@@ -1,11 +1,14 @@
-use std::str::FromStr;
+use std::{collections::{BTreeSet, HashMap, HashSet}, str::FromStr};
 use leptos::logging::debug_warn;
 use serde::{Deserialize, Serialize};
 
 use crate::utils::{
-    database_types::{Asset, DBItem, DeckList, Note, NoteList, UpdateRecipe, UpdateRecipes, UpdateType, UpdateValues}, 
+    database_types::{Asset, DBItem, DeckId, DeckList, Note, NoteList, UpdateRecipe, UpdateRecipes, UpdateType, UpdateValues}, 
+    note_templates::{template_for_note, NoteTemplate, StudyCard},
     outcomes::Outcome, proceed, query::{query_dynamo, ValidQueryTypes}, 
-    shared_truth::{LOCAL_USER_INFO_KEY, CACHE_STATUS_COOKIE_KEY},
+    scheduler::{review_states_from_dynamo, ReviewState},
+    date_and_time::current_time_in_seconds,
+    shared_truth::{LOCAL_USER_INFO_KEY, CACHE_STATUS_COOKIE_KEY, LESSON_BATCH_SIZE, MAX_LEVELS, MAX_NOTE_IDS_PER_QUERY, REVIEW_BATCH_SIZE},
     shared_utilities::{store_item_in_local_storage, get_cookie_value, clear_user_cache_and_cookies},
     user_types::{user_from_dynamo, UserInfo, UserState},
     asset::asset_from_s3,
@@ -15,31 +18,62 @@ use crate::utils::{
 #[allow(unused_imports)]
 #[cfg(feature="hydrate")]
 use crate::utils::{
-    front_utils::{frontend_query_validation, get_cookie_value_client},
-    cache::{get_notes_from_cache, update_cache, get_user_info_from_cache, clear_cache},
+    deck_import::{CsvImportConfig, DeckFileType},
+    catalog::subscribe_to_deck,
+    collaborators::respond_to_collaborator_invite,
+    query::note_versions_from_dynamo,
+    sync_queue::{is_transient_failure, replay_queued_change, QueuedChange, SyncChange},
+    cache_status::{report_cached_deck_versions, start_cache_prefetch},
+    cache_manager::{cached_deck_versions, read_asset},
+    purchases::{refund_deck_purchase, start_deck_purchase},
+    deck_settings::{request_deck_cover_upload, set_deck_cover, update_deck_meta, DeckMetaEdit},
+    deck_upload::{process_deck_upload, request_deck_upload},
+    front_utils::{frontend_query_validation, get_cookie_value_client, upload_file_to_presigned_url},
+    cache::{evict_decks_user_left, get_notes_from_cache, read_cached_notes, remove_notes_from_cache, update_cache, get_user_info_from_cache, clear_cache, cache_review_state, cache_review_states, get_review_states_from_cache, cache_sync_queue, get_sync_queue_from_cache},
+    date_and_time::current_time_in_millis,
+    scheduler::Answer,
 };
 
 pub async fn retrieve_notes(query: ValidQueryTypes, all_user_decks: DeckList, user: Option<String>) -> Outcome {
-    #[cfg(feature="hydrate")]
-    match get_notes_from_cache(&query).await {
-        Outcome::ItemsFound(note_list_str) => return Outcome::ItemsFound(note_list_str),
-        _ => proceed(),
-    }
     #[cfg(feature="hydrate")]
     match frontend_query_validation(&query, all_user_decks) {
         Outcome::PermissionGranted(_) => proceed(),
         any_other_outcome => return any_other_outcome,
     }
+    #[cfg(feature="hydrate")]
+    match fresh_notes_from_cache(&query, user.clone()).await {
+        Outcome::ItemsFound(note_list_str) => return Outcome::ItemsFound(note_list_str),
+        _ => proceed(),
+    }
 
-    let notes_str = match query_dynamo(query, user).await {
-        Ok(outcome) => match outcome {
-            Outcome::ItemsFound(string) => string,
-            any_other_outcome => return any_other_outcome,
-        },
-        Err(e) => return Outcome::UnspecifiedQueryFailure(e.to_string()),
-    };
+    fetch_and_cache_notes(query, user).await
+}
+
+/// Reads every page of the query from the server and caches the notes.
+async fn fetch_and_cache_notes(query: ValidQueryTypes, user: Option<String>) -> Outcome {
+    let mut notes = NoteList::default();
+    let mut cursor = None;
+
+    // Large levels come back a page at a time so the cursor is followed until every page has been read
+    loop {
+        let (page_str, next_cursor) = match query_dynamo(query.clone(), cursor.take(), user.clone()).await {
+            Ok(outcome) => match outcome {
+                Outcome::ItemsFound(string) => (string, None),
+                Outcome::PartialItemsFound(string, next_cursor) => (string, Some(next_cursor)),
+                any_other_outcome => return any_other_outcome,
+            },
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.to_string()),
+        };
 
-    let Ok(notes) = NoteList::from_str(&notes_str) else {return Outcome::UnspecifiedQueryFailure("Notes could not be parsed".to_string())};
+        let Ok(page) = NoteList::from_str(&page_str) else {return Outcome::UnspecifiedQueryFailure("Notes could not be parsed".to_string())};
+        notes.extend(page.notes);
+
+        match next_cursor {
+            Some(next_cursor) => cursor = Some(next_cursor),
+            None => break,
+        }
+    }
+    let notes_str = notes.to_string();
 
     let mut cache_recipes = UpdateRecipes::default();
 
@@ -58,6 +92,70 @@ pub async fn retrieve_notes(query: ValidQueryTypes, all_user_decks: DeckList, us
     Outcome::ItemsFound(notes_str)
 }
 
+/// Serves the query from the cache, asking the server only for the versions of the notes it matches and then
+/// downloading just the notes whose cached copy is missing or out of date. Without a connection whatever the cache
+/// fully holds is returned as is.
+#[cfg(feature="hydrate")]
+async fn fresh_notes_from_cache(query: &ValidQueryTypes, user: Option<String>) -> Outcome {
+    let (_, cached_notes) = match read_cached_notes(query).await {
+        Ok(cached) => cached,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    let server_versions = match note_versions_from_dynamo(query.clone(), user.clone()).await {
+        Ok(Outcome::NoteVersionsFound(server_versions)) => server_versions,
+        Ok(any_other_outcome) => return any_other_outcome,
+        Err(_) => return get_notes_from_cache(query).await,
+    };
+
+    if cached_notes.is_empty() {
+        return Outcome::ItemsNotFound;
+    }
+
+    let cached_versions: HashMap<u64, u64> = cached_notes.iter().map(|note| (note.note_id, note.version)).collect();
+    // Deck metas change without their version moving so they are always downloaded again
+    let stale_note_ids: Vec<usize> = server_versions.iter()
+        .filter(|(note_id, version)| **note_id == 0 || cached_versions.get(note_id) != Some(version))
+        .map(|(note_id, _)| *note_id as usize)
+        .collect();
+    let removed_note_ids: Vec<u64> = cached_versions.keys().filter(|note_id| !!!server_versions.contains_key(note_id)).copied().collect();
+
+    if !!!removed_note_ids.is_empty() {
+        let deck_id = cached_notes[0].deck_id;
+        match remove_notes_from_cache(deck_id, removed_note_ids).await {
+            Outcome::CacheSucceeded => proceed(),
+            any_other_outcome => debug_warn!("deleted notes could not be removed from the cache {}", any_other_outcome.to_string()),
+        }
+    }
+
+    // Past half the query it is cheaper to download it whole
+    if stale_note_ids.len() * 2 > server_versions.len() {
+        return Outcome::ItemsNotFound;
+    }
+
+    let mut notes = NoteList::default();
+    notes.extend(cached_notes.into_iter().filter(|note| server_versions.get(&note.note_id) == Some(&note.version) && note.note_id != 0));
+
+    if !!!stale_note_ids.is_empty() {
+        let deck_id = match query {
+            ValidQueryTypes::NotesById(deck_id, _) | ValidQueryTypes::NotesByLevel(deck_id, _) | ValidQueryTypes::NotesByType(deck_id, _) => *deck_id,
+            ValidQueryTypes::NoQuery => return Outcome::InvalidRequest,
+        };
+        // The server only takes so many ids in one query
+        for stale_note_ids in stale_note_ids.chunks(MAX_NOTE_IDS_PER_QUERY) {
+            let fetched_str = match fetch_and_cache_notes(ValidQueryTypes::NotesById(deck_id, stale_note_ids.to_vec()), user.clone()).await {
+                Outcome::ItemsFound(fetched_str) => fetched_str,
+                any_other_outcome => return any_other_outcome,
+            };
+            let Ok(fetched) = NoteList::from_str(&fetched_str) else {return Outcome::UnspecifiedQueryFailure("Notes could not be parsed".to_string())};
+            notes.extend(fetched.notes);
+        }
+    }
+
+    notes.sort_by_key(|note| note.note_id);
+    Outcome::ItemsFound(notes.to_string())
+}
+
 pub async fn get_user_info(user_state: UserState) -> UserInfo {
 
     if !!!user_state.is_authenticated() {
@@ -82,6 +180,8 @@ pub async fn get_user_info(user_state: UserState) -> UserInfo {
                     }
                 },
             }
+            #[cfg(feature="hydrate")]
+            evict_decks_user_left(&user).await;
             user
         },
         Outcome::UserNotFound => {clear_user_cache_and_cookies(); return UserInfo::default()},
@@ -91,6 +191,459 @@ pub async fn get_user_info(user_state: UserState) -> UserInfo {
     user
 }
 
+/// Review states for display, a deck whose states cannot be read shows as having none.
+pub async fn get_review_states(deck_id: DeckId, user_state: UserState) -> Vec<ReviewState> {
+    try_get_review_states(deck_id, user_state).await.unwrap_or_default()
+}
+
+/// Review states for building study sessions, a failed read is returned rather than treated as a deck with no started cards.
+pub async fn try_get_review_states(deck_id: DeckId, user_state: UserState) -> Result<Vec<ReviewState>, Outcome> {
+    if !!!user_state.is_authenticated() || deck_id == DeckId::default() {
+        return Err(Outcome::UserNotSignedIn);
+    }
+
+    match review_states_from_dynamo(deck_id, Some(user_state.user().into())).await.unwrap_or_default() {
+        Outcome::ReviewStatesFound(review_states) => {
+            #[cfg(feature="hydrate")]
+            cache_review_states(user_state.user(), deck_id, &review_states);
+            Ok(review_states)
+        },
+        any_other_outcome => {
+            debug_warn!("review states could not be retrieved {}", any_other_outcome.to_string());
+            // Offline sessions are built from the states cached the last time the server was reached
+            #[cfg(feature="hydrate")]
+            if let Some(review_states) = get_review_states_from_cache(user_state.user(), deck_id) {
+                return Ok(review_states);
+            }
+            Err(any_other_outcome)
+        },
+    }
+}
+
+/// The note templates saved on the deck, an empty list means every note is shown as a basic card.
+pub async fn get_deck_templates(deck_id: DeckId, all_user_decks: DeckList, user: Option<String>) -> Vec<NoteTemplate> {
+    let notes_str = match retrieve_notes(ValidQueryTypes::NotesById(deck_id, vec![0]), all_user_decks, user).await {
+        Outcome::ItemsFound(notes_str) => notes_str,
+        any_other_outcome => {
+            debug_warn!("deck meta could not be retrieved {}", any_other_outcome.to_string());
+            return Vec::new();
+        },
+    };
+
+    let meta = NoteList::from_str(&notes_str).unwrap_or_default().notes.into_iter().find_map(|note| note.meta);
+    meta.map(|meta| meta.note_templates).unwrap_or_default()
+}
+
+pub async fn get_lesson_batch(deck_id: DeckId, user_state: UserState, all_user_decks: DeckList) -> Outcome {
+    let review_states = match try_get_review_states(deck_id, user_state.clone()).await {
+        Ok(review_states) => review_states,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+    let started_cards: HashSet<(u64, u8)> = review_states.iter().map(|review_state| (review_state.note_id, review_state.card_ord)).collect();
+    let first_level = review_states.iter().map(|review_state| review_state.level as usize).max().unwrap_or(1).max(1);
+    let user = Some(user_state.user().to_string());
+    let templates = get_deck_templates(deck_id, all_user_decks.clone(), user.clone()).await;
+
+    let mut lesson_batch = Vec::new();
+
+    for level in first_level..=MAX_LEVELS {
+        let notes_str = match retrieve_notes(ValidQueryTypes::NotesByLevel(deck_id, vec![level]), all_user_decks.clone(), user.clone()).await {
+            Outcome::ItemsFound(notes_str) => notes_str,
+            Outcome::ItemsNotFound => break,
+            any_other_outcome => return any_other_outcome,
+        };
+        let Ok(mut notes) = NoteList::from_str(&notes_str) else {return Outcome::UnspecifiedQueryFailure("Notes could not be parsed".to_string())};
+        if notes.is_empty() {
+            break;
+        }
+
+        notes.sort_by_key(|note| note.note_id);
+        for note in notes.notes {
+            let template = template_for_note(&templates, &note);
+            for card_ord in template.card_ords(&note) {
+                if started_cards.contains(&(note.note_id, card_ord)) {
+                    continue;
+                }
+                let Some(card) = template.render_card(&note, card_ord) else {continue};
+                lesson_batch.push(card);
+                if lesson_batch.len() >= LESSON_BATCH_SIZE {
+                    return Outcome::StudyCardsFound(lesson_batch);
+                }
+            }
+        }
+    }
+
+    if lesson_batch.is_empty() {
+        return Outcome::ItemsNotFound;
+    }
+    Outcome::StudyCardsFound(lesson_batch)
+}
+
+pub async fn get_review_batch(deck_id: DeckId, user_state: UserState, all_user_decks: DeckList) -> Outcome {
+    let now = current_time_in_seconds();
+    let mut due_states = match try_get_review_states(deck_id, user_state.clone()).await {
+        Ok(review_states) => review_states,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+    due_states.retain(|review_state| review_state.is_due(now));
+    due_states.sort_by_key(|review_state| review_state.due);
+    due_states.truncate(REVIEW_BATCH_SIZE);
+
+    if due_states.is_empty() {
+        return Outcome::ItemsNotFound;
+    }
+
+    let due_levels: BTreeSet<usize> = due_states.iter().map(|review_state| review_state.level as usize).collect();
+    let user = Some(user_state.user().to_string());
+    let templates = get_deck_templates(deck_id, all_user_decks.clone(), user.clone()).await;
+
+    let mut due_notes: HashMap<u64, Note> = HashMap::with_capacity(due_states.len());
+
+    for level in due_levels {
+        let notes_str = match retrieve_notes(ValidQueryTypes::NotesByLevel(deck_id, vec![level]), all_user_decks.clone(), user.clone()).await {
+            Outcome::ItemsFound(notes_str) => notes_str,
+            Outcome::ItemsNotFound => continue,
+            any_other_outcome => return any_other_outcome,
+        };
+        let Ok(notes) = NoteList::from_str(&notes_str) else {return Outcome::UnspecifiedQueryFailure("Notes could not be parsed".to_string())};
+
+        for note in notes.notes {
+            if due_states.iter().any(|review_state| review_state.note_id == note.note_id) {
+                due_notes.insert(note.note_id, note);
+            }
+        }
+    }
+
+    // Cards stay in the order they became due
+    let review_batch: Vec<StudyCard> = due_states.iter().filter_map(|review_state| {
+        let note = due_notes.get(&review_state.note_id)?;
+        template_for_note(&templates, note).render_card(note, review_state.card_ord)
+    }).collect();
+
+    if review_batch.is_empty() {
+        return Outcome::ItemsNotFound;
+    }
+    Outcome::StudyCardsFound(review_batch)
+}
+
+#[cfg(feature="hydrate")]
+pub async fn upload_deck(file: web_sys::File, csv_config: CsvImportConfig, user_state: UserState) -> Outcome {
+    let user = Some(user_state.user().to_string());
+    let Some(file_type) = DeckFileType::from_file_name(&file.name()) else {return Outcome::IncorrectType};
+
+    let (deck_id, upload_url) = match request_deck_upload(file.name(), file.size() as usize, user.clone()).await {
+        Ok(Outcome::DeckUploadUrlRetrieved(deck_id, upload_url)) => (deck_id, upload_url),
+        Ok(any_other_outcome) => return any_other_outcome,
+        Err(e) => return Outcome::PresignedUrlNotRetrieved(e.to_string()),
+    };
+
+    match upload_file_to_presigned_url(&upload_url, &file).await {
+        Outcome::DeckUploadedToBucket(_) => proceed(),
+        any_other_outcome => return any_other_outcome,
+    }
+
+    let outcome = match process_deck_upload(deck_id, file_type, csv_config, user).await {
+        Ok(outcome) => outcome,
+        Err(e) => return Outcome::DeckCouldNotBeProcessed(e.to_string()),
+    };
+
+    for sub_outcome in outcome.multi_outcome_to_vec() {
+        if let Outcome::DatabaseUpdateSuccess(cache_recipes) = sub_outcome {
+            update_cache(cache_recipes).await;
+        }
+    }
+
+    outcome
+}
+
+#[cfg(feature="hydrate")]
+pub async fn edit_deck_meta(deck_id: DeckId, edit: DeckMetaEdit, user_state: UserState) -> Outcome {
+    let outcome = match update_deck_meta(deck_id, edit, Some(user_state.user().to_string())).await {
+        Ok(outcome) => outcome,
+        Err(e) => return Outcome::UpdateUserFailure(e.to_string()),
+    };
+
+    for sub_outcome in outcome.multi_outcome_to_vec() {
+        if let Outcome::DatabaseUpdateSuccess(cache_recipes) = sub_outcome {
+            update_cache(cache_recipes).await;
+        }
+    }
+
+    outcome
+}
+
+#[cfg(feature="hydrate")]
+pub async fn upload_deck_cover(deck_id: DeckId, file: web_sys::File, user_state: UserState) -> Outcome {
+    let user = Some(user_state.user().to_string());
+
+    let upload_url = match request_deck_cover_upload(deck_id, file.name(), file.size() as usize, user.clone()).await {
+        Ok(Outcome::PresignedUrlRetrieved(upload_url)) => upload_url,
+        Ok(any_other_outcome) => return any_other_outcome,
+        Err(e) => return Outcome::PresignedUrlNotRetrieved(e.to_string()),
+    };
+
+    match upload_file_to_presigned_url(&upload_url, &file).await {
+        Outcome::DeckUploadedToBucket(_) => proceed(),
+        any_other_outcome => return any_other_outcome,
+    }
+
+    let outcome = match set_deck_cover(deck_id, file.name(), user).await {
+        Ok(outcome) => outcome,
+        Err(e) => return Outcome::UpdateUserFailure(e.to_string()),
+    };
+
+    for sub_outcome in outcome.multi_outcome_to_vec() {
+        if let Outcome::DatabaseUpdateSuccess(cache_recipes) = sub_outcome {
+            update_cache(cache_recipes).await;
+        }
+    }
+
+    outcome
+}
+
+#[cfg(feature="hydrate")]
+pub async fn subscribe(deck_id: DeckId, user_state: UserState) -> Outcome {
+    let outcome = match subscribe_to_deck(deck_id, Some(user_state.user().to_string())).await {
+        Ok(outcome) => outcome,
+        Err(e) => return Outcome::UpdateUserFailure(e.to_string()),
+    };
+
+    match &outcome {
+        Outcome::DatabaseUpdateSuccess(cache_recipes) => update_cache(cache_recipes.clone()).await,
+        // Purchases are subscribed by the payment webhook, which cannot reach this cache
+        Outcome::AlreadySubscribed => update_cache(UpdateRecipes {
+            recipes: vec![UpdateRecipe {
+                update_type: UpdateType::Add,
+                update_key: UserInfo::ACTIVE_DECKS_CACHE_KEY.to_string(),
+                update_item: DBItem::User(user_state.user().to_string()),
+                value: UpdateValues::DeckList(DeckList {decks: vec![deck_id]}),
+            }],
+        }).await,
+        _ => proceed(),
+    };
+
+    outcome
+}
+
+/// Returns CheckoutStarted with the page to send the user to, decks that were already paid for are added straight away.
+#[cfg(feature="hydrate")]
+pub async fn purchase(deck_id: DeckId, user_state: UserState) -> Outcome {
+    let outcome = match start_deck_purchase(deck_id, Some(user_state.user().to_string())).await {
+        Ok(outcome) => outcome,
+        Err(e) => return Outcome::UpdateUserFailure(e.to_string()),
+    };
+
+    if let Outcome::DatabaseUpdateSuccess(cache_recipes) = &outcome {
+        update_cache(cache_recipes.clone()).await;
+    }
+
+    outcome
+}
+
+#[cfg(feature="hydrate")]
+pub async fn add_note(deck_id: DeckId, note: Note, user_state: UserState) -> Outcome {
+    queue_change(SyncChange::AddNote {deck_id, note}, user_state).await
+}
+
+/// A NoteConflict carries the server copy, the caller merges it with their edit and tries again at its version.
+#[cfg(feature="hydrate")]
+pub async fn edit_note(deck_id: DeckId, note: Note, user_state: UserState) -> Outcome {
+    queue_change(SyncChange::EditNote {deck_id, note}, user_state).await
+}
+
+#[cfg(feature="hydrate")]
+pub async fn delete_note(deck_id: DeckId, note_id: u64, version: u64, user_state: UserState) -> Outcome {
+    queue_change(SyncChange::DeleteNote {deck_id, note_id, version}, user_state).await
+}
+
+/// Grades the card in the local cache straight away so offline sessions carry on, the answer reaches the server through the sync queue.
+#[cfg(feature="hydrate")]
+pub async fn answer_card(card: &StudyCard, answer: Answer, user_state: UserState) -> Outcome {
+    let answered = current_time_in_seconds();
+    let user = user_state.user().to_string();
+
+    let cached_note = match get_notes_from_cache(&ValidQueryTypes::NotesById(card.deck_id, vec![card.note_id as usize])).await {
+        Outcome::ItemsFound(notes_str) => NoteList::from_str(&notes_str).ok().and_then(|notes| notes.notes.into_iter().next()),
+        _ => None,
+    };
+    if let Some(note) = cached_note {
+        let cached_states = get_review_states_from_cache(&user, card.deck_id).unwrap_or_default();
+        let card_key = ReviewState::card_key(card.deck_id, card.note_id, card.card_ord);
+        let review_state = match cached_states.into_iter().find(|review_state| review_state.card == card_key) {
+            Some(mut review_state) => {
+                review_state.answer(&note, answer, answered);
+                review_state
+            },
+            None => ReviewState::from_lesson(&user, &note, card.card_ord, answer, answered),
+        };
+        cache_review_state(&review_state);
+    }
+
+    queue_change(SyncChange::Answer {deck_id: card.deck_id, note_id: card.note_id, card_ord: card.card_ord, answer, answered}, user_state).await
+}
+
+/// Returns the change's outcome when it could be sent right away, or ChangeQueued when it is waiting for a connection.
+#[cfg(feature="hydrate")]
+pub async fn queue_change(change: SyncChange, user_state: UserState) -> Outcome {
+    let user = user_state.user().to_string();
+    let mut queue = get_sync_queue_from_cache(&user);
+    queue.push(change, current_time_in_millis() as u64);
+    let Some(idempotency_key) = queue.changes.last().map(|queued| queued.idempotency_key.clone()) else {return Outcome::InvalidRequest};
+    if cache_sync_queue(&user, &queue).is_err() {
+        return Outcome::CacheFailed("the change could not be saved for syncing".to_string());
+    }
+
+    flush_sync_queue(user_state).await
+        .into_iter()
+        .find_map(|(replayed_key, outcome)| (replayed_key == idempotency_key).then_some(outcome))
+        .unwrap_or(Outcome::ChangeQueued)
+}
+
+/// Replays queued changes in the order they were made, stopping at the first one the server could not take.
+/// Returns the outcome of every change that was replayed by its idempotency key.
+#[cfg(feature="hydrate")]
+pub async fn flush_sync_queue(user_state: UserState) -> Vec<(String, Outcome)> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static FLUSHING: AtomicBool = AtomicBool::new(false);
+    if !!!user_state.is_authenticated() || FLUSHING.swap(true, Ordering::Relaxed) {
+        return Vec::new();
+    }
+
+    let user = user_state.user().to_string();
+    let mut replayed = Vec::new();
+
+    while let Some(queued) = get_sync_queue_from_cache(&user).changes.first().cloned() {
+        let outcome = match replay_queued_change(queued.clone(), Some(user.clone())).await {
+            Ok(outcome) if !!!is_transient_failure(&outcome) => outcome,
+            Ok(any_other_outcome) => {
+                debug_warn!("queued change will be tried again later {}", any_other_outcome.to_string());
+                break;
+            },
+            Err(e) => {
+                debug_warn!("sync queue paused, the server could not be reached {}", e.to_string());
+                break;
+            },
+        };
+
+        match &outcome {
+            Outcome::CardGraded(review_state) => cache_review_state(review_state),
+            any_other_outcome => cache_note_edit_outcome(any_other_outcome).await,
+        }
+
+        // Changes may have been queued while this one was being sent so the queue is read again
+        let mut queue = get_sync_queue_from_cache(&user);
+        queue.changes.retain(|change| change.idempotency_key != queued.idempotency_key);
+        if let Outcome::NoteConflict(_) = &outcome {
+            queue.conflicts.push(queued.clone());
+        }
+        if cache_sync_queue(&user, &queue).is_err() {
+            debug_warn!("sync queue could not be saved");
+            break;
+        }
+        replayed.push((queued.idempotency_key, outcome));
+    }
+
+    FLUSHING.store(false, Ordering::Relaxed);
+    replayed
+}
+
+/// Edits that lost to a newer version of their note during a flush, oldest first.
+#[cfg(feature="hydrate")]
+pub fn get_sync_conflicts(user_state: &UserState) -> Vec<QueuedChange> {
+    get_sync_queue_from_cache(user_state.user()).conflicts
+}
+
+/// Called once the user has seen the conflicting edits, the notes already hold the server's version.
+#[cfg(feature="hydrate")]
+pub fn dismiss_sync_conflicts(user_state: &UserState) -> Result<(), ()> {
+    let mut queue = get_sync_queue_from_cache(user_state.user());
+    queue.conflicts.clear();
+    cache_sync_queue(user_state.user(), &queue)
+}
+
+/// Conflicts still tell us what the server holds, so the cached note is replaced with it either way.
+#[cfg(feature="hydrate")]
+async fn cache_note_edit_outcome(outcome: &Outcome) {
+    match outcome {
+        Outcome::DatabaseUpdateSuccess(cache_recipes) => update_cache(cache_recipes.clone()).await,
+        Outcome::NoteConflict(server_note) => update_cache(UpdateRecipes {
+            recipes: vec![UpdateRecipe {
+                update_type: UpdateType::Swap,
+                update_key: Note::FULL_NOTE_CACHE_KEY.to_string(),
+                update_item: DBItem::Note(server_note.deck_id, server_note.note_id),
+                value: UpdateValues::Note(server_note.clone()),
+            }],
+        }).await,
+        _ => proceed(),
+    }
+}
+
+/// Invite links can be opened while signed in as someone else, their cache is left alone.
+#[cfg(feature="hydrate")]
+pub async fn respond_to_invite(invite: String, user_state: UserState) -> Outcome {
+    let outcome = match respond_to_collaborator_invite(invite).await {
+        Ok(outcome) => outcome,
+        Err(e) => return Outcome::UpdateUserFailure(e.to_string()),
+    };
+
+    if let Outcome::DatabaseUpdateSuccess(cache_recipes) = &outcome {
+        let signed_in_user = DBItem::User(user_state.user().to_string());
+        let recipes = cache_recipes.recipes.iter().filter(|recipe| recipe.update_item == signed_in_user).cloned().collect::<Vec<UpdateRecipe>>();
+        if !!!recipes.is_empty() {
+            update_cache(UpdateRecipes {recipes}).await;
+        }
+    }
+
+    outcome
+}
+
+#[cfg(feature="hydrate")]
+pub async fn refund(deck_id: DeckId, user_state: UserState) -> Outcome {
+    let outcome = match refund_deck_purchase(deck_id, Some(user_state.user().to_string())).await {
+        Ok(outcome) => outcome,
+        Err(e) => return Outcome::UpdateUserFailure(e.to_string()),
+    };
+
+    if let Outcome::DatabaseUpdateSuccess(cache_recipes) = &outcome {
+        update_cache(cache_recipes.clone()).await;
+    }
+
+    outcome
+}
+
+/// Marks the cache incomplete before a prefetch starts writing decks into it.
+#[cfg(feature="hydrate")]
+pub async fn begin_cache_prefetch(user_state: UserState) -> Outcome {
+    if !!!user_state.is_authenticated() {
+        return Outcome::UserNotSignedIn;
+    }
+
+    match start_cache_prefetch(Some(user_state.user().to_string())).await {
+        Ok(outcome) => outcome,
+        Err(e) => Outcome::CacheFailed(e.to_string()),
+    }
+}
+
+/// Reports the cached deck versions to the server, which either marks the cache complete or returns
+/// DeckVersionsChanged with the active decks that have to be cached again.
+#[cfg(feature="hydrate")]
+pub async fn check_cache_status(user_state: UserState) -> Outcome {
+    if !!!user_state.is_authenticated() {
+        return Outcome::UserNotSignedIn;
+    }
+
+    let cached_versions = match cached_deck_versions().await {
+        Ok(cached_versions) => cached_versions,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    match report_cached_deck_versions(cached_versions, Some(user_state.user().to_string())).await {
+        Ok(outcome) => outcome,
+        Err(e) => Outcome::CacheFailed(e.to_string()),
+    }
+}
+
 pub async fn get_asset(asset: Asset, user: Option<String>) -> Outcome {
     if asset == Asset::default() {
         return Outcome::UnresolvedOutcome;
@@ -108,15 +661,23 @@ pub async fn get_asset(asset: Asset, user: Option<String>) -> Outcome {
         any_other_asset => any_other_asset,
     };
 
-    let outcome = match asset_from_s3(asset, user).await {
+    let outcome = match asset_from_s3(asset.clone(), user).await {
         Ok(outcome) => outcome,
-        Err(e) => return Outcome::PresignedUrlNotRetrieved(e.to_string()),
+        Err(e) => {
+            // Deck images prefetched for offline study are served from the cache while the server cannot be reached
+            #[cfg(feature="hydrate")]
+            if let Some(object_url) = read_asset(&asset).await {
+                return Outcome::PresignedUrlRetrieved(object_url);
+            }
+            return Outcome::PresignedUrlNotRetrieved(e.to_string());
+        },
     };
 
     outcome
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+/// Whether every active deck is cached at its latest version, set by the server and read before signing in.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Default)]
 pub enum CacheStatus {
     Complete(u64),
     Incomplete(u64),
@@ -0,0 +1,589 @@
+use std::{collections::BTreeSet, io::Error as ioError, str::FromStr, sync::atomic::{AtomicBool, Ordering}};
+
+use indexed_db::{Database, Factory, VersionChangeEvent};
+use leptos::logging::debug_warn;
+use serde::{Deserialize, Serialize};
+use serde_wasm_bindgen::Serializer;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{js_sys::Reflect, Blob, Url};
+
+use crate::utils::{
+    cache_status::DeckVersion,
+    database_types::{Asset, DeckId, DeckList, DeckMeta, Note, NoteType},
+    date_and_time::current_time_in_seconds,
+    outcomes::Outcome,
+    query::ValidQueryTypes,
+    shared_truth::{CACHE_SIZE_BUDGET_IN_BYTES, CACHE_STATUS_COOKIE_KEY, LOCAL_REVIEW_STATES_KEY},
+    shared_utilities::clear_cookie,
+};
+
+const CACHE_DB_NAME: &str = "lex-decks";
+/// Every deck used to get its own store in this database, which meant a version bump whenever a deck was cached.
+const LEGACY_CACHE_DB_NAME: &str = "test";
+
+/// Raise this and add a step to migrate whenever the stores or indexes change.
+const CACHE_SCHEMA_VERSION: u32 = 2;
+
+const NOTES_STORE: &str = "notes";
+const DECKS_STORE: &str = "decks";
+const ASSETS_STORE: &str = "assets";
+const DECK_INDEX: &str = "deck_id";
+const DECK_LEVEL_INDEX: &str = "deck_level";
+const DECK_NOTE_TYPE_INDEX: &str = "deck_note_type";
+const ASSET_BLOB_FIELD: &str = "blob";
+
+static LEGACY_CACHE_FOUND: AtomicBool = AtomicBool::new(false);
+
+/// Notes are stored with the keys their indexes need, IndexedDB cannot index inside the note or across two fields.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct CachedNote {
+    key: String,
+    deck_id: String,
+    deck_level: String,
+    deck_note_type: String,
+    bytes: u64,
+    note: Note,
+}
+
+impl CachedNote {
+    fn new(note: Note) -> Self {
+        let bytes = note.to_string().len() as u64;
+        Self {
+            key: note_key(note.deck_id, note.note_id),
+            deck_id: note.deck_id.to_string(),
+            deck_level: deck_level_key(note.deck_id, note.level as usize),
+            deck_note_type: note_type_key(note.deck_id, &note.note_type),
+            bytes,
+            note,
+        }
+    }
+}
+
+/// Deck images are kept as blobs, presigned urls expire long before the deck is studied offline.
+/// The blob is stored on the same object under ASSET_BLOB_FIELD since serde cannot carry it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct CachedAsset {
+    key: String,
+    deck_id: String,
+    bytes: u64,
+}
+
+/// How much of the budget a deck is using and when it was last read, for least recently used eviction.
+/// The version is the deck meta version the deck was last fully cached at, 0 until it has been.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct CachedDeck {
+    deck_id: String,
+    bytes: u64,
+    last_used: u64,
+    #[serde(default)]
+    version: u64,
+}
+
+fn note_key(deck_id: DeckId, note_id: u64) -> String {
+    format!("{}|{note_id}", deck_id.to_string())
+}
+
+fn deck_level_key(deck_id: DeckId, level: usize) -> String {
+    format!("{}|{level}", deck_id.to_string())
+}
+
+fn note_type_key(deck_id: DeckId, note_type: &NoteType) -> String {
+    format!("{}|{note_type}", deck_id.to_string())
+}
+
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, indexed_db::Error<ioError>> {
+    value.serialize(&Serializer::json_compatible()).map_err(|_| indexed_db::Error::OperationNotSupported)
+}
+
+fn from_js<T: for<'de> Deserialize<'de>>(value: JsValue) -> Result<T, indexed_db::Error<ioError>> {
+    serde_wasm_bindgen::from_value(value).map_err(|_| indexed_db::Error::OperationNotSupported)
+}
+
+fn asset_to_js(cached_asset: &CachedAsset, blob: &Blob) -> Result<JsValue, indexed_db::Error<ioError>> {
+    let value = to_js(cached_asset)?;
+    Reflect::set(&value, &JsValue::from(ASSET_BLOB_FIELD), blob).map_err(|_| indexed_db::Error::OperationNotSupported)?;
+    Ok(value)
+}
+
+/// Steps run in order from the version the browser has up to CACHE_SCHEMA_VERSION,
+/// released steps are never changed so every browser ends up with the same stores.
+async fn migrate(evt: VersionChangeEvent<ioError>) -> Result<(), indexed_db::Error<ioError>> {
+    let db = evt.database();
+    let old_version = evt.old_version();
+
+    if old_version < 1 {
+        let notes = db.build_object_store(NOTES_STORE).key_path("key").create()?;
+        notes.build_index(DECK_INDEX, DECK_INDEX).create()?;
+        notes.build_index(DECK_LEVEL_INDEX, DECK_LEVEL_INDEX).create()?;
+        notes.build_index(DECK_NOTE_TYPE_INDEX, DECK_NOTE_TYPE_INDEX).create()?;
+        db.build_object_store(DECKS_STORE).key_path(DECK_INDEX).create()?;
+        LEGACY_CACHE_FOUND.store(true, Ordering::Relaxed);
+    }
+
+    if old_version < 2 {
+        let assets = db.build_object_store(ASSETS_STORE).key_path("key").create()?;
+        assets.build_index(DECK_INDEX, DECK_INDEX).create()?;
+    }
+
+    Ok(())
+}
+
+async fn open_cache() -> Result<Database<ioError>, Outcome> {
+    let factory = match Factory::<ioError>::get() {
+        Ok(factory) => factory,
+        Err(e) => return Err(Outcome::CacheFailed(e.to_string())),
+    };
+
+    let db = match factory.open(CACHE_DB_NAME, CACHE_SCHEMA_VERSION, migrate).await {
+        Ok(db) => db,
+        Err(e) => return Err(Outcome::CacheFailed(e.to_string())),
+    };
+
+    // A new cache means any notes in the old one were never going to be read again
+    if LEGACY_CACHE_FOUND.swap(false, Ordering::Relaxed) {
+        if let Err(e) = factory.delete_database(LEGACY_CACHE_DB_NAME).await {
+            debug_warn!("legacy note cache could not be deleted {}", e.to_string());
+        }
+    }
+
+    Ok(db)
+}
+
+/// Writes the notes and keeps the deck's share of the budget up to date, then evicts other decks if the cache has grown past it.
+pub async fn put_notes(deck_id: DeckId, notes: Vec<Note>) -> Outcome {
+    if notes.is_empty() {
+        return Outcome::CacheSucceeded;
+    }
+
+    let db = match open_cache().await {
+        Ok(db) => db,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    let now = current_time_in_seconds();
+    let write_result = db.transaction(&[NOTES_STORE, DECKS_STORE]).rw().run(move |trans| async move {
+        let notes_store = trans.object_store(NOTES_STORE)?;
+        let decks_store = trans.object_store(DECKS_STORE)?;
+
+        let deck_key = JsValue::from(deck_id.to_string());
+        let mut cached_deck = match decks_store.get(&deck_key).await? {
+            Some(cached_deck) => from_js::<CachedDeck>(cached_deck)?,
+            None => CachedDeck {deck_id: deck_id.to_string(), ..Default::default()},
+        };
+
+        for note in notes {
+            // A write made right after the cached version is replayed here with every note it changed, so the deck stays current
+            if let Some(meta) = note.meta.as_ref().filter(|_| note.note_id == 0) {
+                if cached_deck.version > 0 && meta.version == cached_deck.version + 1 {
+                    cached_deck.version = meta.version;
+                }
+            }
+            let cached_note = CachedNote::new(note);
+            if let Some(replaced) = notes_store.get(&JsValue::from(cached_note.key.clone())).await? {
+                cached_deck.bytes = cached_deck.bytes.saturating_sub(from_js::<CachedNote>(replaced)?.bytes);
+            }
+            cached_deck.bytes += cached_note.bytes;
+            notes_store.put(&to_js(&cached_note)?).await?;
+        }
+
+        cached_deck.last_used = now;
+        decks_store.put(&to_js(&cached_deck)?).await?;
+        Ok(())
+    }).await;
+    db.close();
+
+    if let Err(e) = write_result {
+        return Outcome::CacheFailed(e.to_string());
+    }
+
+    enforce_size_budget(deck_id).await
+}
+
+pub async fn delete_notes(deck_id: DeckId, note_ids: Vec<u64>) -> Outcome {
+    let db = match open_cache().await {
+        Ok(db) => db,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    let delete_result = db.transaction(&[NOTES_STORE, DECKS_STORE]).rw().run(move |trans| async move {
+        let notes_store = trans.object_store(NOTES_STORE)?;
+        let decks_store = trans.object_store(DECKS_STORE)?;
+
+        let mut freed_bytes = 0;
+        for note_id in note_ids {
+            let key = JsValue::from(note_key(deck_id, note_id));
+            if let Some(deleted) = notes_store.get(&key).await? {
+                freed_bytes += from_js::<CachedNote>(deleted)?.bytes;
+                notes_store.delete(&key).await?;
+            }
+        }
+
+        let deck_key = JsValue::from(deck_id.to_string());
+        if let Some(cached_deck) = decks_store.get(&deck_key).await? {
+            let mut cached_deck = from_js::<CachedDeck>(cached_deck)?;
+            cached_deck.bytes = cached_deck.bytes.saturating_sub(freed_bytes);
+            decks_store.put(&to_js(&cached_deck)?).await?;
+        }
+        Ok(())
+    }).await;
+    db.close();
+
+    match delete_result {
+        Ok(_) => Outcome::CacheSucceeded,
+        Err(e) => Outcome::CacheFailed(e.to_string()),
+    }
+}
+
+/// Every cached note the query matches along with the cached deck meta, reading a deck counts as using it.
+pub async fn read_notes(query: &ValidQueryTypes) -> Result<(Option<DeckMeta>, Vec<Note>), Outcome> {
+    let deck_id = match query {
+        ValidQueryTypes::NotesById(deck_id, _) | ValidQueryTypes::NotesByLevel(deck_id, _) | ValidQueryTypes::NotesByType(deck_id, _) => *deck_id,
+        ValidQueryTypes::NoQuery => return Err(Outcome::InvalidRequest),
+    };
+
+    let db = open_cache().await?;
+
+    let now = current_time_in_seconds();
+    let transaction_query = query.clone();
+    let read_result = db.transaction(&[NOTES_STORE, DECKS_STORE]).rw().run(move |trans| async move {
+        let notes_store = trans.object_store(NOTES_STORE)?;
+        let decks_store = trans.object_store(DECKS_STORE)?;
+
+        let meta = notes_store.get(&JsValue::from(note_key(deck_id, 0))).await?;
+
+        let mut values = Vec::new();
+        match transaction_query {
+            ValidQueryTypes::NotesById(_, note_ids) => {
+                let unique_note_ids: BTreeSet<usize> = note_ids.into_iter().collect();
+                for note_id in unique_note_ids {
+                    values.extend(notes_store.get(&JsValue::from(note_key(deck_id, note_id as u64))).await?);
+                }
+            },
+            ValidQueryTypes::NotesByLevel(_, levels) => {
+                let level_index = notes_store.index(DECK_LEVEL_INDEX)?;
+                let unique_levels: BTreeSet<usize> = levels.into_iter().collect();
+                for level in unique_levels {
+                    let level_key = JsValue::from(deck_level_key(deck_id, level));
+                    values.extend(level_index.get_all_in(level_key.clone()..=level_key, None).await?);
+                }
+            },
+            ValidQueryTypes::NotesByType(_, note_types) => {
+                let note_type_index = notes_store.index(DECK_NOTE_TYPE_INDEX)?;
+                let unique_note_types: BTreeSet<String> = note_types.iter().map(|note_type| note_type_key(deck_id, note_type)).collect();
+                for note_type_key in unique_note_types {
+                    let note_type_key = JsValue::from(note_type_key);
+                    values.extend(note_type_index.get_all_in(note_type_key.clone()..=note_type_key, None).await?);
+                }
+            },
+            ValidQueryTypes::NoQuery => (),
+        }
+
+        if let Some(cached_deck) = decks_store.get(&JsValue::from(deck_id.to_string())).await? {
+            let mut cached_deck = from_js::<CachedDeck>(cached_deck)?;
+            cached_deck.last_used = now;
+            decks_store.put(&to_js(&cached_deck)?).await?;
+        }
+
+        Ok((meta, values))
+    }).await;
+    db.close();
+
+    let (meta, values) = match read_result {
+        Ok(read) => read,
+        Err(e) => return Err(Outcome::CacheFailed(e.to_string())),
+    };
+
+    let meta = meta.and_then(|meta| from_js::<CachedNote>(meta).ok()).and_then(|cached_meta| cached_meta.note.meta);
+    let Ok(mut notes) = values.into_iter().map(|value| from_js::<CachedNote>(value).map(|cached_note| cached_note.note)).collect::<Result<Vec<Note>, _>>() else {
+        return Err(Outcome::CacheFailed("cached notes could not be parsed".to_string()));
+    };
+    // The meta lives on note 0 which has the default type and level, it only comes back when asked for by id
+    if !!!matches!(query, ValidQueryTypes::NotesById(_, _)) {
+        notes.retain(|note| note.note_id != 0);
+    }
+
+    Ok((meta, notes))
+}
+
+/// Stores the image under the asset it was downloaded for, it counts towards the deck's share of the budget.
+pub async fn put_asset(deck_id: DeckId, asset: &Asset, blob: Blob) -> Outcome {
+    let db = match open_cache().await {
+        Ok(db) => db,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    let cached_asset = CachedAsset {
+        key: asset.to_string(),
+        deck_id: deck_id.to_string(),
+        bytes: blob.size() as u64,
+    };
+    let now = current_time_in_seconds();
+    let write_result = db.transaction(&[ASSETS_STORE, DECKS_STORE]).rw().run(move |trans| async move {
+        let assets_store = trans.object_store(ASSETS_STORE)?;
+        let decks_store = trans.object_store(DECKS_STORE)?;
+
+        let mut cached_deck = match decks_store.get(&JsValue::from(deck_id.to_string())).await? {
+            Some(cached_deck) => from_js::<CachedDeck>(cached_deck)?,
+            None => CachedDeck {deck_id: deck_id.to_string(), ..Default::default()},
+        };
+        if let Some(replaced) = assets_store.get(&JsValue::from(cached_asset.key.clone())).await? {
+            cached_deck.bytes = cached_deck.bytes.saturating_sub(from_js::<CachedAsset>(replaced)?.bytes);
+        }
+        cached_deck.bytes += cached_asset.bytes;
+        cached_deck.last_used = now;
+
+        assets_store.put(&asset_to_js(&cached_asset, &blob)?).await?;
+        decks_store.put(&to_js(&cached_deck)?).await?;
+        Ok(())
+    }).await;
+    db.close();
+
+    if let Err(e) = write_result {
+        return Outcome::CacheFailed(e.to_string());
+    }
+
+    enforce_size_budget(deck_id).await
+}
+
+/// An object url for a cached image, None when it has not been cached or the cache cannot be read.
+/// Images cached as data urls before they were kept as blobs read as missing so they are downloaded again.
+pub async fn read_asset(asset: &Asset) -> Option<String> {
+    let db = open_cache().await.ok()?;
+
+    let key = JsValue::from(asset.to_string());
+    let read_result = db.transaction(&[ASSETS_STORE]).run(move |trans| async move {
+        trans.object_store(ASSETS_STORE)?.get(&key).await
+    }).await;
+    db.close();
+
+    let blob = Reflect::get(&read_result.ok()??, &JsValue::from(ASSET_BLOB_FIELD)).ok()?.dyn_into::<Blob>().ok()?;
+    // The url is only revoked when the page closes, which is as long as anything showing the image lives
+    Url::create_object_url_with_blob(&blob).ok()
+}
+
+/// Records that every note the deck needs offline is cached as of the given deck meta version.
+pub async fn mark_deck_cached(deck_id: DeckId, version: u64) -> Outcome {
+    let db = match open_cache().await {
+        Ok(db) => db,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    let now = current_time_in_seconds();
+    let mark_result = db.transaction(&[DECKS_STORE]).rw().run(move |trans| async move {
+        let decks_store = trans.object_store(DECKS_STORE)?;
+        let mut cached_deck = match decks_store.get(&JsValue::from(deck_id.to_string())).await? {
+            Some(cached_deck) => from_js::<CachedDeck>(cached_deck)?,
+            None => CachedDeck {deck_id: deck_id.to_string(), ..Default::default()},
+        };
+        cached_deck.version = version;
+        cached_deck.last_used = now;
+        decks_store.put(&to_js(&cached_deck)?).await?;
+        Ok(())
+    }).await;
+    db.close();
+
+    match mark_result {
+        Ok(_) => Outcome::CacheSucceeded,
+        Err(e) => Outcome::CacheFailed(e.to_string()),
+    }
+}
+
+/// The versions of the decks that have been fully cached, for the server to compare against.
+pub async fn cached_deck_versions() -> Result<Vec<DeckVersion>, Outcome> {
+    let db = open_cache().await?;
+    let read_result = cached_decks(&db).await;
+    db.close();
+
+    let cached_decks = match read_result {
+        Ok(cached_decks) => cached_decks,
+        Err(e) => return Err(Outcome::CacheFailed(e.to_string())),
+    };
+
+    Ok(cached_decks.into_iter()
+        .filter(|cached_deck| cached_deck.version > 0)
+        .filter_map(|cached_deck| Some(DeckVersion {deck_id: DeckId::from_str(&cached_deck.deck_id).ok()?, version: cached_deck.version}))
+        .collect())
+}
+
+/// Removes every note of the deck along with its usage.
+pub async fn evict_deck(deck_id: DeckId) -> Outcome {
+    let db = match open_cache().await {
+        Ok(db) => db,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    let evict_result = evict_decks_from_db(&db, vec![deck_id.to_string()]).await;
+    db.close();
+
+    match evict_result {
+        Ok(_) => Outcome::CacheSucceeded,
+        Err(e) => Outcome::CacheFailed(e.to_string()),
+    }
+}
+
+/// Evicts every cached deck that is not in the list.
+pub async fn evict_decks_not_in(kept_decks: &DeckList) -> Outcome {
+    let db = match open_cache().await {
+        Ok(db) => db,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    let evict_result = match cached_decks(&db).await {
+        Ok(cached_decks) => evict_decks_from_db(&db, decks_not_in(cached_decks, kept_decks)).await,
+        Err(e) => Err(e),
+    };
+    db.close();
+
+    match evict_result {
+        Ok(_) => Outcome::CacheSucceeded,
+        Err(e) => Outcome::CacheFailed(e.to_string()),
+    }
+}
+
+/// Evicts the least recently used decks until the cache fits the budget, the deck being written is never evicted.
+async fn enforce_size_budget(protected_deck: DeckId) -> Outcome {
+    let db = match open_cache().await {
+        Ok(db) => db,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    let evict_result = match cached_decks(&db).await {
+        Ok(cached_decks) => evict_decks_from_db(&db, decks_over_budget(cached_decks, protected_deck, CACHE_SIZE_BUDGET_IN_BYTES)).await,
+        Err(e) => Err(e),
+    };
+    db.close();
+
+    match evict_result {
+        Ok(_) => Outcome::CacheSucceeded,
+        Err(e) => Outcome::CacheFailed(e.to_string()),
+    }
+}
+
+fn decks_not_in(cached_decks: Vec<CachedDeck>, kept_decks: &DeckList) -> Vec<String> {
+    let kept: BTreeSet<String> = kept_decks.iter().map(|deck_id| deck_id.to_string()).collect();
+    cached_decks.into_iter().map(|cached_deck| cached_deck.deck_id).filter(|deck_id| !!!kept.contains(deck_id)).collect()
+}
+
+/// The least recently used decks that have to go for the rest to fit the budget.
+fn decks_over_budget(mut cached_decks: Vec<CachedDeck>, protected_deck: DeckId, budget_in_bytes: u64) -> Vec<String> {
+    let mut total_bytes: u64 = cached_decks.iter().map(|cached_deck| cached_deck.bytes).sum();
+    cached_decks.sort_by_key(|cached_deck| cached_deck.last_used);
+
+    let mut evicted = Vec::new();
+    for cached_deck in cached_decks {
+        if total_bytes <= budget_in_bytes {
+            break;
+        }
+        if cached_deck.deck_id == protected_deck.to_string() {
+            continue;
+        }
+        total_bytes = total_bytes.saturating_sub(cached_deck.bytes);
+        evicted.push(cached_deck.deck_id);
+    }
+    evicted
+}
+
+async fn cached_decks(db: &Database<ioError>) -> Result<Vec<CachedDeck>, indexed_db::Error<ioError>> {
+    db.transaction(&[DECKS_STORE]).run(|trans| async move {
+        let decks_store = trans.object_store(DECKS_STORE)?;
+        decks_store.get_all(None).await?.into_iter().map(from_js::<CachedDeck>).collect()
+    }).await
+}
+
+async fn evict_decks_from_db(db: &Database<ioError>, deck_ids: Vec<String>) -> Result<(), indexed_db::Error<ioError>> {
+    if deck_ids.is_empty() {
+        return Ok(());
+    }
+
+    db.transaction(&[NOTES_STORE, ASSETS_STORE, DECKS_STORE]).rw().run(move |trans| async move {
+        let notes_store = trans.object_store(NOTES_STORE)?;
+        let assets_store = trans.object_store(ASSETS_STORE)?;
+        let decks_store = trans.object_store(DECKS_STORE)?;
+        let note_deck_index = notes_store.index(DECK_INDEX)?;
+        let asset_deck_index = assets_store.index(DECK_INDEX)?;
+
+        for deck_id in deck_ids {
+            let deck_key = JsValue::from(deck_id.clone());
+            for cached_note in note_deck_index.get_all_in(deck_key.clone()..=deck_key.clone(), None).await? {
+                let cached_note = from_js::<CachedNote>(cached_note)?;
+                notes_store.delete(&JsValue::from(cached_note.key)).await?;
+            }
+            for cached_asset in asset_deck_index.get_all_in(deck_key.clone()..=deck_key.clone(), None).await? {
+                let cached_asset = from_js::<CachedAsset>(cached_asset)?;
+                assets_store.delete(&JsValue::from(cached_asset.key)).await?;
+            }
+            decks_store.delete(&deck_key).await?;
+            debug_warn!("evicted deck {deck_id} from the note cache");
+        }
+        Ok(())
+    }).await
+}
+
+/// Deletes every cached note and review state, changes still waiting to be synced are kept so no work is lost.
+pub async fn clear_offline_data() -> Outcome {
+    let factory = match Factory::<ioError>::get() {
+        Ok(factory) => factory,
+        Err(e) => return Outcome::CacheFailed(e.to_string()),
+    };
+
+    for db_name in [CACHE_DB_NAME, LEGACY_CACHE_DB_NAME] {
+        if let Err(e) = factory.delete_database(db_name).await {
+            return Outcome::CacheFailed(e.to_string());
+        }
+    }
+
+    let Some(local_storage) = web_sys::window().and_then(|window| window.local_storage().ok().flatten()) else {
+        return Outcome::CacheFailed("local storage not found".to_string());
+    };
+    let stored_keys = (0..local_storage.length().unwrap_or_default()).filter_map(|index| local_storage.key(index).ok().flatten()).collect::<Vec<String>>();
+    for key in stored_keys.into_iter().filter(|key| key.starts_with(LOCAL_REVIEW_STATES_KEY)) {
+        let _ = local_storage.remove_item(&key);
+    }
+    let _ = clear_cookie(CACHE_STATUS_COOKIE_KEY);
+
+    Outcome::CacheSucceeded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::shared_truth::DECK_ID_LENGTH;
+
+    fn deck_id(fill: char) -> DeckId {
+        DeckId {id: [fill; DECK_ID_LENGTH]}
+    }
+
+    fn cached_deck(fill: char, bytes: u64, last_used: u64) -> CachedDeck {
+        CachedDeck {deck_id: deck_id(fill).to_string(), bytes, last_used, version: 1}
+    }
+
+    #[test]
+    fn decks_the_user_left_are_evicted() {
+        let mut kept_decks = DeckList::default();
+        kept_decks.push(deck_id('a'));
+        let cached_decks = vec![cached_deck('a', 10, 1), cached_deck('b', 10, 2)];
+
+        assert_eq!(decks_not_in(cached_decks, &kept_decks), vec![deck_id('b').to_string()]);
+    }
+
+    #[test]
+    fn least_recently_used_decks_are_evicted_until_the_cache_fits() {
+        let cached_decks = vec![cached_deck('a', 40, 3), cached_deck('b', 40, 1), cached_deck('c', 40, 2)];
+
+        assert_eq!(decks_over_budget(cached_decks, deck_id('a'), 50), vec![deck_id('b').to_string(), deck_id('c').to_string()]);
+    }
+
+    #[test]
+    fn the_deck_being_written_is_never_evicted() {
+        let cached_decks = vec![cached_deck('a', 80, 1), cached_deck('b', 40, 2)];
+
+        assert_eq!(decks_over_budget(cached_decks, deck_id('a'), 50), vec![deck_id('b').to_string()]);
+    }
+
+    #[test]
+    fn nothing_is_evicted_within_the_budget() {
+        let cached_decks = vec![cached_deck('a', 20, 1), cached_deck('b', 30, 2)];
+
+        assert!(decks_over_budget(cached_decks, deck_id('a'), 50).is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+use leptos::server;
+use serde::{Deserialize, Serialize};
+use server_fn::ServerFnError;
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::DeckId,
+    outcomes::Outcome,
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use std::str::FromStr;
+#[cfg(feature="ssr")]
+use crate::utils::{
+    back_utils::verify_user_header,
+    cache_db_interface::{get_cache_status, CacheStatus},
+    database_types::NoteList,
+    date_and_time::current_time_in_seconds,
+    query::ValidQueryTypes,
+    shared_truth::{CACHE_OUT_OF_DATE_LIMIT, CACHE_STATUS_COOKIE_KEY},
+    shared_utilities::{clear_cookie, set_cookie_value},
+    storage::{setup_storage, Storage},
+};
+
+/// The deck meta version a deck was last fully cached at.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeckVersion {
+    pub deck_id: DeckId,
+    pub version: u64,
+}
+
+/// Marks the cache incomplete for as long as a prefetch is running, so nothing relies on it being safe to study from.
+#[server(client=AuthClient)]
+pub async fn start_cache_prefetch(user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(_) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    Ok(set_cache_status(CacheStatus::Incomplete(current_time_in_seconds())))
+}
+
+/// Compares the versions the client cached against every active deck. When they all match the cache is marked complete
+/// as of now, otherwise a complete cache is invalidated and the decks that changed are returned to be cached again.
+#[server(client=AuthClient)]
+pub async fn report_cached_deck_versions(cached_versions: Vec<DeckVersion>, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    let user_info = match storage.get_user(&email, None).await {
+        Outcome::UserFound(user_info) => user_info,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let mut changed_decks = Vec::new();
+    for deck_id in user_info.active_decks.iter() {
+        let server_version = match deck_version(&storage, *deck_id).await {
+            Ok(server_version) => server_version,
+            Err(any_other_outcome) => return Ok(any_other_outcome),
+        };
+        let cached_version = cached_versions.iter().find(|cached| cached.deck_id == *deck_id).map(|cached| cached.version);
+        if cached_version != Some(server_version) {
+            changed_decks.push(*deck_id);
+        }
+    }
+
+    if changed_decks.is_empty() {
+        return Ok(set_cache_status(CacheStatus::Complete(current_time_in_seconds())));
+    }
+
+    // An incomplete cache stays incomplete, the prefetch that marked it will cache the changed decks too
+    if let CacheStatus::Complete(_) = get_cache_status().await {
+        let _ = clear_cookie(CACHE_STATUS_COOKIE_KEY);
+    }
+
+    Ok(Outcome::DeckVersionsChanged(changed_decks))
+}
+
+#[cfg(feature="ssr")]
+async fn deck_version(storage: &impl Storage, deck_id: DeckId) -> Result<u64, Outcome> {
+    let meta_query = ValidQueryTypes::NotesById(deck_id, vec![0]);
+    let notes_str = match storage.query_notes(&meta_query, None, None).await {
+        Outcome::ItemsFound(notes_str) => notes_str,
+        any_other_outcome => return Err(any_other_outcome),
+    };
+
+    let Ok(notes) = NoteList::from_str(&notes_str) else {return Err(Outcome::IncorrectType)};
+    notes.notes.into_iter().find_map(|note| note.meta).map(|meta| meta.version).ok_or(Outcome::ItemsNotFound)
+}
+
+#[cfg(feature="ssr")]
+fn set_cache_status(cache_status: CacheStatus) -> Outcome {
+    match set_cookie_value(CACHE_STATUS_COOKIE_KEY, &cache_status.to_string(), CACHE_OUT_OF_DATE_LIMIT) {
+        Ok(_) => Outcome::CacheStatusSet(cache_status),
+        Err(_) => Outcome::CacheFailed("cache status could not be set".to_string()),
+    }
+}
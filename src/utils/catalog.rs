@@ -0,0 +1,167 @@
+use leptos::server;
+use serde::{Deserialize, Serialize};
+use server_fn::ServerFnError;
+use struct_field_names::StructFieldNames;
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::{Asset, DeckId, DeckMeta},
+    deck_settings::normalize_catalog_term,
+    outcomes::Outcome,
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use crate::utils::{
+    back_utils::verify_user_header,
+    dynamo_utils::{
+        permission_if_good_standing, permission_if_in_active_decks, permission_if_in_colab_decks, permission_if_in_owned_decks, permission_if_under_active_deck_limit,
+        ACTIVE_DECKS_DB_KEY, COLAB_DECKS_DB_KEY, OWNED_DECKS_DB_KEY, STANDING_DB_KEY,
+    },
+    proceed,
+    purchases::ReceiptStatus,
+    shared_truth::CATALOG_PAGE_SIZE,
+    storage::{setup_storage, Storage},
+};
+
+/// Every public deck sits on this shelf so the popularity index can list them all in one query.
+pub const PUBLIC_SHELF: &str = "public";
+
+/// What the catalog shows for a public deck, kept in LEXCatalog and rewritten whenever the deck's meta changes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, StructFieldNames)]
+pub struct CatalogEntry {
+    pub deck_id: DeckId,
+    pub shelf: String,
+    pub name: String,
+    pub search_name: String,
+    pub description: String,
+    pub language: String,
+    pub tags: Vec<String>,
+    pub price: f32,
+    pub cover_image: Asset,
+    pub total_notes: usize,
+    pub subscribers: u64,
+}
+
+impl CatalogEntry {
+    pub fn from_meta(deck_id: DeckId, meta: &DeckMeta) -> Self {
+        Self {
+            deck_id,
+            shelf: PUBLIC_SHELF.to_string(),
+            name: meta.name.clone(),
+            search_name: normalize_catalog_term(&meta.name),
+            description: meta.description.clone(),
+            language: meta.language.clone(),
+            tags: meta.tags.clone(),
+            price: meta.price,
+            cover_image: meta.cover_image.clone(),
+            total_notes: meta.total_notes,
+            subscribers: 0,
+        }
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.price <= 0.0
+    }
+}
+
+/// Where the next page starts on the popularity index, the deck the previous page stopped at.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CatalogCursor {
+    pub deck_id: DeckId,
+    pub subscribers: u64,
+}
+
+impl CatalogCursor {
+    pub fn after(entry: &CatalogEntry) -> Self {
+        Self {
+            deck_id: entry.deck_id,
+            subscribers: entry.subscribers,
+        }
+    }
+}
+
+/// Empty terms match every deck, the name matches anywhere in the deck's name while language and tag must match exactly.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CatalogQuery {
+    pub name: String,
+    pub language: String,
+    pub tag: String,
+    pub cursor: Option<CatalogCursor>,
+}
+
+impl CatalogQuery {
+    pub fn normalized(&self) -> Self {
+        Self {
+            name: normalize_catalog_term(&self.name),
+            language: normalize_catalog_term(&self.language),
+            tag: normalize_catalog_term(&self.tag),
+            cursor: self.cursor.clone(),
+        }
+    }
+}
+
+/// One page of matching decks from most to least subscribed, next_cursor is None on the last page.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CatalogPage {
+    pub entries: Vec<CatalogEntry>,
+    pub next_cursor: Option<CatalogCursor>,
+}
+
+#[server(client=AuthClient)]
+pub async fn browse_catalog(query: CatalogQuery, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(_) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    Ok(storage.query_catalog(&query.normalized(), CATALOG_PAGE_SIZE).await)
+}
+
+/// Adds a public deck to the user's active decks, paid decks return PurchaseRequired unless the user bought, owns or helps write them.
+#[server(client=AuthClient)]
+pub async fn subscribe_to_deck(deck_id: DeckId, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+    let attributes_to_get = [STANDING_DB_KEY, ACTIVE_DECKS_DB_KEY, OWNED_DECKS_DB_KEY, COLAB_DECKS_DB_KEY];
+
+    let user = match storage.get_user(&email, Some(&attributes_to_get.join(","))).await {
+        Outcome::UserFound(user) => user,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    match permission_if_good_standing(&user) {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    if let Outcome::PermissionGranted(_) = permission_if_in_active_decks(&user, deck_id) {
+        return Ok(Outcome::AlreadySubscribed);
+    }
+
+    match permission_if_under_active_deck_limit(&user) {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let entry = match storage.get_catalog_entry(deck_id).await {
+        Outcome::CatalogEntryFound(entry) => entry,
+        Outcome::ItemsNotFound => return Ok(Outcome::UserDoesNotHavePermission),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let writes_deck = matches!(permission_if_in_owned_decks(&user, deck_id), Outcome::PermissionGranted(_))
+        || matches!(permission_if_in_colab_decks(&user, deck_id), Outcome::PermissionGranted(_));
+    if !!!entry.is_free() && !!!writes_deck {
+        let has_paid = match storage.get_receipt(&email, deck_id).await {
+            Outcome::ReceiptFound(receipt) => receipt.status == ReceiptStatus::Paid,
+            Outcome::ItemsNotFound => false,
+            any_other_outcome => return Ok(any_other_outcome),
+        };
+        if !!!has_paid {
+            return Ok(Outcome::PurchaseRequired(deck_id, entry.price));
+        }
+    }
+
+    Ok(storage.subscribe_user_to_deck(&email, deck_id).await)
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeSet;
+
+const CLOZE_START: &str = "{{c";
+const CLOZE_END: &str = "}}";
+const CLOZE_SEPARATOR: &str = "::";
+const HIDDEN_CLOZE: &str = "[...]";
+
+/// One {{c1::answer::hint}} marker, the hint is optional.
+#[derive(Clone, Debug, PartialEq)]
+struct ClozeMarker<'a> {
+    index: u8,
+    answer: &'a str,
+    hint: Option<&'a str>,
+    start: usize,
+    end: usize,
+}
+
+/// Every cloze index used in the text, each one becomes its own card.
+pub fn cloze_indices(text: &str) -> BTreeSet<u8> {
+    cloze_markers(text).into_iter().map(|marker| marker.index).collect()
+}
+
+/// Hides the answers for the active index, showing the hint in their place if there is one.
+/// Answers for every other index are shown as plain text.
+pub fn render_cloze_front(text: &str, active_index: u8) -> String {
+    render_cloze(text, |marker| {
+        if marker.index != active_index {
+            return marker.answer.to_string();
+        }
+        match marker.hint {
+            Some(hint) => format!("[{hint}]"),
+            None => HIDDEN_CLOZE.to_string(),
+        }
+    })
+}
+
+/// Shows the text with the answers for the active index marked out.
+pub fn render_cloze_back(text: &str, active_index: u8) -> String {
+    render_cloze(text, |marker| {
+        if marker.index == active_index {
+            format!("[{}]", marker.answer)
+        } else {
+            marker.answer.to_string()
+        }
+    })
+}
+
+fn render_cloze(text: &str, render_marker: impl Fn(&ClozeMarker) -> String) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    let mut last_end = 0;
+
+    for marker in cloze_markers(text) {
+        rendered.push_str(&text[last_end..marker.start]);
+        rendered.push_str(&render_marker(&marker));
+        last_end = marker.end;
+    }
+    rendered.push_str(&text[last_end..]);
+
+    rendered
+}
+
+fn cloze_markers(text: &str) -> Vec<ClozeMarker<'_>> {
+    let mut markers = Vec::new();
+    let mut search_from = 0;
+
+    while let Some(found) = text[search_from..].find(CLOZE_START) {
+        let start = search_from + found;
+        let after_start = start + CLOZE_START.len();
+        search_from = after_start;
+
+        let digits = text[after_start..].chars().take_while(|character| character.is_ascii_digit()).count();
+        let Ok(index) = text[after_start..after_start + digits].parse::<u8>() else {continue};
+        if index == 0 || !!!text[after_start + digits..].starts_with(CLOZE_SEPARATOR) {
+            continue;
+        }
+
+        let content_start = after_start + digits + CLOZE_SEPARATOR.len();
+        let Some(content_length) = text[content_start..].find(CLOZE_END) else {break};
+        let content = &text[content_start..content_start + content_length];
+        let end = content_start + content_length + CLOZE_END.len();
+
+        let (answer, hint) = match content.split_once(CLOZE_SEPARATOR) {
+            Some((answer, hint)) => (answer, Some(hint).filter(|hint| !!!hint.trim().is_empty())),
+            None => (content, None),
+        };
+
+        markers.push(ClozeMarker {index, answer, hint, start, end});
+        search_from = end;
+    }
+
+    markers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indices_are_collected_once_each() {
+        assert_eq!(cloze_indices("{{c1::uno}} {{c2::dos}} {{c1::tres}}"), BTreeSet::from([1, 2]));
+        assert!(cloze_indices("no clozes {{Field}} {{c0::zero}} {{cx::bad}}").is_empty());
+    }
+
+    #[test]
+    fn front_hides_only_the_active_index() {
+        let text = "{{c1::Madrid}} is the capital of {{c2::Spain::country}}";
+
+        assert_eq!(render_cloze_front(text, 1), "[...] is the capital of Spain");
+        assert_eq!(render_cloze_front(text, 2), "Madrid is the capital of [country]");
+    }
+
+    #[test]
+    fn back_marks_the_active_answers() {
+        let text = "{{c1::Madrid}} is the capital of {{c2::Spain::country}}";
+
+        assert_eq!(render_cloze_back(text, 2), "Madrid is the capital of [Spain]");
+    }
+
+    #[test]
+    fn unclosed_markers_are_left_as_text() {
+        let text = "{{c1::open ended";
+
+        assert!(cloze_indices(text).is_empty());
+        assert_eq!(render_cloze_front(text, 1), text);
+    }
+}
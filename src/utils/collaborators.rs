@@ -0,0 +1,302 @@
+use std::str::FromStr;
+
+use leptos::server;
+use serde::{Deserialize, Serialize};
+use server_fn::ServerFnError;
+use struct_field_names::StructFieldNames;
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::DeckId,
+    outcomes::Outcome,
+    shared_truth::MAX_EMAIL_SIZE,
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use crate::utils::{
+    back_utils::{build_collaborator_invite_token, send_mailtrap_email, verify_token, verify_user_header},
+    database_types::UpdateRecipes,
+    date_and_time::current_time_in_seconds,
+    dynamo_utils::{
+        permission_if_good_standing, permission_if_under_colab_deck_limit, validate_deck_role_and_user_standing, validate_owned_decks_and_user_standing,
+        COLAB_DECKS_DB_KEY, STANDING_DB_KEY,
+    },
+    email_template::{EmailTemplate, DECLINE_LINK, EMAIL_FIELD_1, EMAIL_FIELD_1_VALUE, EMAIL_FIELD_2, EMAIL_FIELD_2_VALUE, REDIRECT_LINK},
+    proceed,
+    shared_truth::{COLAB_INVITE_CLAIM, COLAB_INVITE_URL_PARAM, COLLABORATE_PAGE, DECK_CLAIM, INVITED_CLAIM, MAX_COLLABORATORS, RESPONSE_CLAIM, ROLE_CLAIM},
+    shared_utilities::get_claim,
+    storage::{setup_storage, Storage},
+    user_types::UserInfo,
+};
+
+/// Viewers can study the deck, editors can also change its notes and everything in its meta but the price.
+/// Admins can also change the price and manage the other collaborators, only the owner can make or remove admins.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Display, EnumIter, Serialize, Deserialize)]
+pub enum CollaboratorRole {
+    #[default] Viewer,
+    Editor,
+    Admin,
+}
+
+impl FromStr for CollaboratorRole {
+
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        for variant in Self::iter() {
+            if input == &variant.to_string() {
+                return Ok(variant);
+            }
+        }
+        Err(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Display, EnumIter, Serialize, Deserialize)]
+pub enum InviteStatus {
+    #[default] Invited,
+    Accepted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Display, EnumIter, Serialize, Deserialize)]
+pub enum InviteResponse {
+    Accept,
+    Decline,
+}
+
+impl FromStr for InviteResponse {
+
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        for variant in Self::iter() {
+            if input == &variant.to_string() {
+                return Ok(variant);
+            }
+        }
+        Err(())
+    }
+}
+
+/// Someone invited to help with a deck, kept in LEXCollaborators under the deck.
+/// The deck is only added to the user's colab decks once they accept.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, StructFieldNames)]
+pub struct Collaborator {
+    pub deck_id: DeckId,
+    pub user: String,
+    pub role: CollaboratorRole,
+    pub status: InviteStatus,
+    pub invited_by: String,
+    pub invited: u64,
+}
+
+pub fn normalize_collaborator_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+pub fn is_valid_collaborator_email(email: &str) -> bool {
+    let Some((name, domain)) = email.split_once('@') else {return false};
+    !!!name.is_empty() && domain.contains('.') && !!!email.contains(char::is_whitespace) && email.len() <= MAX_EMAIL_SIZE
+}
+
+/// Emails the invitee links to accept or decline, inviting someone again replaces their earlier invite.
+#[server(client=AuthClient)]
+pub async fn invite_collaborator(deck_id: DeckId, invitee: String, role: CollaboratorRole, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let invitee = normalize_collaborator_email(&invitee);
+    if !!!is_valid_collaborator_email(&invitee) || invitee == email {
+        return Ok(Outcome::InvalidRequest);
+    }
+
+    let storage = setup_storage().await;
+
+    // Inviting someone again replaces their row, so the manager also needs permission over the role being replaced
+    let existing_role = match storage.get_collaborator(deck_id, &invitee).await {
+        Outcome::CollaboratorFound(existing) => existing.role,
+        Outcome::ItemsNotFound => role,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    match manager_permission(&storage, &email, deck_id, role.max(existing_role)).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let Outcome::NoteFound(meta_note) = storage.get_note(deck_id, 0).await else {return Ok(Outcome::ItemsNotFound)};
+    let Some(meta) = meta_note.meta else {return Ok(Outcome::ItemsNotFound)};
+    if invitee == meta.owner {
+        return Ok(Outcome::InvalidRequest);
+    }
+
+    let collaborators = match storage.get_collaborators(deck_id).await {
+        Outcome::CollaboratorsFound(collaborators) => collaborators,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+    let is_new_collaborator = collaborators.iter().all(|collaborator| collaborator.user != invitee);
+    if is_new_collaborator && collaborators.len() >= MAX_COLLABORATORS {
+        return Ok(Outcome::TooManyCollaborators);
+    }
+
+    let collaborator = Collaborator {
+        deck_id,
+        user: invitee.clone(),
+        role,
+        status: InviteStatus::Invited,
+        invited_by: email,
+        invited: current_time_in_seconds(),
+    };
+
+    match storage.put_collaborator_invite(&collaborator).await {
+        Outcome::DatabaseUpdateSuccess(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let invite_link = |response: InviteResponse| {
+        build_collaborator_invite_token(&invitee, deck_id, role, collaborator.invited, response)
+            .map(|token| format!("{COLLABORATE_PAGE}?{COLAB_INVITE_URL_PARAM}={token}"))
+    };
+    let (Ok(accept_link), Ok(decline_link)) = (invite_link(InviteResponse::Accept), invite_link(InviteResponse::Decline)) else {
+        return Ok(Outcome::EmailSendFailure("invite links could not be signed".to_string()));
+    };
+
+    let html = EmailTemplate::CollaboratorInvite.get_template()
+        .replace(EMAIL_FIELD_1_VALUE, &meta.name).replace(EMAIL_FIELD_1, "Deck")
+        .replace(EMAIL_FIELD_2_VALUE, &role.to_string()).replace(EMAIL_FIELD_2, "Role")
+        .replace(REDIRECT_LINK, &accept_link)
+        .replace(DECLINE_LINK, &decline_link);
+    let text = format!("Accept: {accept_link}\nDecline: {decline_link}");
+
+    Ok(send_mailtrap_email(&invitee, &format!("You've been invited to help with {}", meta.name), &text, &html).await)
+}
+
+/// Accepts or declines an invite through a link from the invite email, the signed link stands in for signing in.
+#[server(endpoint = "respond_to_collaborator_invite")]
+pub async fn respond_to_collaborator_invite(invite: String) -> Result<Outcome, ServerFnError> {
+    let Ok(trusted_token) = verify_token(&invite) else {return Ok(Outcome::VerificationFailure)};
+
+    let claims = (
+        get_claim(&trusted_token, COLAB_INVITE_CLAIM),
+        get_claim(&trusted_token, DECK_CLAIM).and_then(|deck_id| DeckId::from_str(&deck_id).ok()),
+        get_claim(&trusted_token, ROLE_CLAIM).and_then(|role| CollaboratorRole::from_str(&role).ok()),
+        get_claim(&trusted_token, INVITED_CLAIM).and_then(|invited| invited.parse::<u64>().ok()),
+        get_claim(&trusted_token, RESPONSE_CLAIM).and_then(|response| InviteResponse::from_str(&response).ok()),
+    );
+    let (Some(email), Some(deck_id), Some(role), Some(invited), Some(response)) = claims else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    // Links from an invite that was replaced or withdrawn no longer work
+    let collaborator = match storage.get_collaborator(deck_id, &email).await {
+        Outcome::CollaboratorFound(collaborator) => collaborator,
+        Outcome::ItemsNotFound => return Ok(Outcome::UserDoesNotHavePermission),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+    if collaborator.status != InviteStatus::Invited || collaborator.role != role || collaborator.invited != invited {
+        return Ok(Outcome::UserDoesNotHavePermission);
+    }
+
+    if response == InviteResponse::Decline {
+        return Ok(storage.delete_collaborator(deck_id, &email, Some(InviteStatus::Invited)).await);
+    }
+
+    let attributes_to_get = [STANDING_DB_KEY, COLAB_DECKS_DB_KEY];
+    let user = match storage.get_user(&email, Some(&attributes_to_get.join(","))).await {
+        Outcome::UserFound(user) => user,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    match permission_if_good_standing(&user) {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    match permission_if_under_colab_deck_limit(&user) {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    Ok(storage.accept_collaborator_invite(&collaborator).await)
+}
+
+/// Only the owner can make someone an admin or change an admin's role.
+#[server(client=AuthClient)]
+pub async fn set_collaborator_role(deck_id: DeckId, collaborator: String, role: CollaboratorRole, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let collaborator = normalize_collaborator_email(&collaborator);
+    let storage = setup_storage().await;
+
+    let current_role = match storage.get_collaborator(deck_id, &collaborator).await {
+        Outcome::CollaboratorFound(existing) => existing.role,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    match manager_permission(&storage, &email, deck_id, role.max(current_role)).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    Ok(storage.update_collaborator_role(deck_id, &collaborator, role).await)
+}
+
+/// Removes a collaborator or withdraws their invite, collaborators can always remove themselves.
+#[server(client=AuthClient)]
+pub async fn remove_collaborator(deck_id: DeckId, collaborator: String, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let collaborator = normalize_collaborator_email(&collaborator);
+    let storage = setup_storage().await;
+
+    let existing = match storage.get_collaborator(deck_id, &collaborator).await {
+        Outcome::CollaboratorFound(existing) => existing,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    if collaborator != email {
+        match manager_permission(&storage, &email, deck_id, existing.role).await {
+            Outcome::PermissionGranted(_) => proceed(),
+            any_other_outcome => return Ok(any_other_outcome),
+        };
+    }
+
+    match storage.delete_collaborator(deck_id, &collaborator, None).await {
+        Outcome::DatabaseUpdateSuccess(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    if existing.status == InviteStatus::Invited {
+        return Ok(Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()));
+    }
+
+    // The recipes only belong in the caller's cache when they removed themselves
+    match storage.remove_deck_from_user_deck_list(&collaborator, deck_id, COLAB_DECKS_DB_KEY, UserInfo::COLAB_DECKS_CACHE_KEY).await {
+        Outcome::DatabaseUpdateSuccess(_) if collaborator != email => Ok(Outcome::DatabaseUpdateSuccess(UpdateRecipes::default())),
+        any_other_outcome => Ok(any_other_outcome),
+    }
+}
+
+#[server(client=AuthClient)]
+pub async fn collaborators_from_dynamo(deck_id: DeckId, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    match validate_deck_role_and_user_standing(&storage, &email, deck_id, CollaboratorRole::Viewer).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    Ok(storage.get_collaborators(deck_id).await)
+}
+
+/// Admins manage viewers and editors while the owner manages everyone.
+#[cfg(feature="ssr")]
+async fn manager_permission(storage: &impl Storage, email: &str, deck_id: DeckId, managed_role: CollaboratorRole) -> Outcome {
+    match managed_role {
+        CollaboratorRole::Admin => validate_owned_decks_and_user_standing(storage, email, deck_id).await,
+        _ => validate_deck_role_and_user_standing(storage, email, deck_id, CollaboratorRole::Admin).await,
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+
+use csv::{ReaderBuilder, StringRecord, Trim};
+
+use crate::utils::{
+    database_types::{DeckId, Field, Note},
+    deck_import::{ColumnMapping, CsvImportConfig, ImportedDeck, RowError},
+    outcomes::Outcome,
+    shared_truth::{MAX_NOTE_FIELDS, NOTE_LIMIT, RAW_DECK_SIZE_LIMIT},
+};
+
+pub fn import_csv_deck(bytes: &[u8], config: &CsvImportConfig, deck_id: DeckId, email: &str) -> Outcome {
+    if bytes.len() > RAW_DECK_SIZE_LIMIT {
+        return Outcome::DeckCouldNotBeProcessed("deck is over the upload size limit".to_string());
+    }
+    if !!!config.delimiter.is_ascii() {
+        return Outcome::DeckCouldNotBeProcessed("delimiter must be a single ascii character".to_string());
+    }
+    if config.column_mapping.len() > MAX_NOTE_FIELDS {
+        return Outcome::DeckCouldNotBeProcessed(format!("notes cannot have more than {MAX_NOTE_FIELDS} fields"));
+    }
+    if let Some(field_name) = duplicate_field_name(&config.column_mapping) {
+        return Outcome::DeckCouldNotBeProcessed(format!("more than one column is mapped to the field {field_name}"));
+    }
+
+    let mut reader = ReaderBuilder::new()
+        .delimiter(config.delimiter as u8)
+        .has_headers(config.has_header)
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(bytes);
+
+    let headers = match config.has_header {
+        true => match reader.headers() {
+            Ok(headers) => Some(headers.clone()),
+            Err(e) => return Outcome::DeckCouldNotBeProcessed(e.to_string()),
+        },
+        false => None,
+    };
+
+    let mut column_mapping = config.column_mapping.clone();
+    let mut row_errors = Vec::new();
+    let mut notes = Vec::new();
+    let first_row = if config.has_header {2} else {1};
+
+    for (i, record) in reader.records().enumerate() {
+        let row = i + first_row;
+
+        if notes.len() >= NOTE_LIMIT {
+            row_errors.push(RowError::new(Some(row), &format!("deck is over the {NOTE_LIMIT} note limit so this row and every row after it was skipped")));
+            break;
+        }
+
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                row_errors.push(RowError::new(Some(row), &e.to_string()));
+                continue;
+            },
+        };
+
+        if column_mapping.is_empty() {
+            column_mapping = default_column_mapping(headers.as_ref(), &record);
+            if column_mapping.len() > MAX_NOTE_FIELDS {
+                return Outcome::DeckCouldNotBeProcessed(format!("notes cannot have more than {MAX_NOTE_FIELDS} fields"));
+            }
+            if let Some(field_name) = duplicate_field_name(&column_mapping) {
+                return Outcome::DeckCouldNotBeProcessed(format!("more than one column is named {field_name}"));
+            }
+        }
+
+        match record_to_note(&record, &column_mapping) {
+            Ok(note) => notes.push((Some(row), note)),
+            Err(reason) => row_errors.push(RowError::new(Some(row), &reason)),
+        }
+    }
+
+    let deck_name = match config.deck_name.trim().is_empty() {
+        true => CsvImportConfig::default().deck_name,
+        false => config.deck_name.trim().to_string(),
+    };
+
+    Outcome::DeckImported(ImportedDeck::new(deck_id, email, &deck_name, notes, Vec::new(), row_errors))
+}
+
+fn default_column_mapping(headers: Option<&StringRecord>, first_record: &StringRecord) -> Vec<ColumnMapping> {
+    let column_count = match headers {
+        Some(headers) => headers.len(),
+        None => first_record.len(),
+    };
+
+    (0..column_count).map(|column| {
+        let header = headers.and_then(|headers| headers.get(column)).unwrap_or_default();
+        let field_name = match header.is_empty() {
+            true => format!("Field {}", column + 1),
+            false => header.to_string(),
+        };
+        ColumnMapping {column, field_name}
+    }).collect()
+}
+
+/// Notes are stored with an attribute per field name, so two columns with the same name would overwrite each other.
+fn duplicate_field_name(column_mapping: &Vec<ColumnMapping>) -> Option<&str> {
+    let mut field_names = HashSet::with_capacity(column_mapping.len());
+    column_mapping.iter().map(|mapping| mapping.field_name.as_str()).find(|field_name| !!!field_names.insert(*field_name))
+}
+
+fn record_to_note(record: &StringRecord, column_mapping: &Vec<ColumnMapping>) -> Result<Note, String> {
+    if record.iter().all(|value| value.is_empty()) {
+        return Err("row is empty".to_string());
+    }
+
+    let mut fields = Vec::with_capacity(column_mapping.len());
+    for mapping in column_mapping {
+        let Some(value) = record.get(mapping.column) else {
+            return Err(format!("column {} is missing", mapping.column + 1));
+        };
+        fields.push(Field::new(mapping.field_name.clone(), Some(value.to_string()), None));
+    }
+
+    Ok(Note::new(0, fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::shared_truth::DECK_ID_LENGTH;
+
+    const EMAIL: &str = "learner@lexlingua.io";
+
+    fn import(csv: &str, config: &CsvImportConfig) -> Result<ImportedDeck, String> {
+        match import_csv_deck(csv.as_bytes(), config, DeckId {id: ['a'; DECK_ID_LENGTH]}, EMAIL) {
+            Outcome::DeckImported(imported_deck) => Ok(imported_deck),
+            Outcome::DeckCouldNotBeProcessed(reason) => Err(reason),
+            any_other_outcome => Err(any_other_outcome.to_string()),
+        }
+    }
+
+    fn field_values(note: &Note) -> Vec<(String, String)> {
+        note.fields.iter().map(|field| (field.name.clone(), field.text.clone().unwrap_or_default())).collect()
+    }
+
+    fn mapping(column: usize, field_name: &str) -> ColumnMapping {
+        ColumnMapping {column, field_name: field_name.to_string()}
+    }
+
+    #[test]
+    fn headers_name_the_fields() {
+        let imported_deck = import("Spanish,English\ngato,cat\nperro,dog\n", &CsvImportConfig::default()).unwrap();
+
+        assert_eq!(imported_deck.notes.len(), 2);
+        assert_eq!(field_values(&imported_deck.notes[0]), vec![("Spanish".to_string(), "gato".to_string()), ("English".to_string(), "cat".to_string())]);
+        assert_eq!(imported_deck.meta.name, CsvImportConfig::default().deck_name);
+    }
+
+    #[test]
+    fn without_a_header_every_row_is_a_note() {
+        let config = CsvImportConfig {has_header: false, ..Default::default()};
+        let imported_deck = import("gato,cat\nperro,dog\n", &config).unwrap();
+
+        assert_eq!(imported_deck.notes.len(), 2);
+        assert_eq!(field_values(&imported_deck.notes[0]), vec![("Field 1".to_string(), "gato".to_string()), ("Field 2".to_string(), "cat".to_string())]);
+    }
+
+    #[test]
+    fn the_delimiter_splits_the_columns() {
+        let config = CsvImportConfig {delimiter: ';', deck_name: " Animals ".to_string(), ..Default::default()};
+        let imported_deck = import("Spanish;English\ngato, el;cat\n", &config).unwrap();
+
+        assert_eq!(field_values(&imported_deck.notes[0]), vec![("Spanish".to_string(), "gato, el".to_string()), ("English".to_string(), "cat".to_string())]);
+        assert_eq!(imported_deck.meta.name, "Animals");
+        assert!(import("a,b\n", &CsvImportConfig {delimiter: 'é', ..Default::default()}).is_err());
+    }
+
+    #[test]
+    fn the_column_mapping_picks_and_names_columns() {
+        let config = CsvImportConfig {column_mapping: vec![mapping(2, "Meaning"), mapping(0, "Word")], ..Default::default()};
+        let imported_deck = import("Spanish,Notes,English\ngato,animal,cat\n", &config).unwrap();
+
+        assert_eq!(field_values(&imported_deck.notes[0]), vec![("Meaning".to_string(), "cat".to_string()), ("Word".to_string(), "gato".to_string())]);
+    }
+
+    #[test]
+    fn bad_rows_are_reported_and_the_rest_imported() {
+        let config = CsvImportConfig {column_mapping: vec![mapping(0, "Spanish"), mapping(1, "English")], ..Default::default()};
+        let imported_deck = import("Spanish,English\ngato,cat\nperro\n,\nraton,mouse\n", &config).unwrap();
+
+        assert_eq!(imported_deck.notes.len(), 2);
+        assert_eq!(imported_deck.row_errors, vec![
+            RowError::new(Some(3), "column 2 is missing"),
+            RowError::new(Some(4), "row is empty"),
+        ]);
+    }
+
+    #[test]
+    fn field_names_must_be_unique() {
+        let config = CsvImportConfig {column_mapping: vec![mapping(0, "Word"), mapping(1, "Word")], ..Default::default()};
+
+        assert!(import("gato,cat\n", &config).is_err());
+        assert!(import("Word,Word\ngato,cat\n", &CsvImportConfig::default()).is_err());
+    }
+}
@@ -1,10 +1,16 @@
 use::core::str::FromStr;
+use std::collections::BTreeMap;
 use core::ops::{Deref, DerefMut};
 use struct_field_names::StructFieldNames;
-use strum::{Display, EnumCount, EnumIter, IntoEnumIterator};
+use strum::{Display, EnumIter, IntoEnumIterator};
 use serde::{de::Error, Deserialize, Serialize, Serializer};
 
-use super::{shared_truth::{DECK_ID_LENGTH, MAX_LEVELS, SEPARATOR, SEPARATOR3, SEPARATOR4, SEPARATOR5}, user_types::UserInfo};
+use super::{
+    shared_truth::{DECK_ID_LENGTH, MAX_LEVELS, SEPARATOR, SEPARATOR3, SEPARATOR4, SEPARATOR5},
+    note_templates::NoteTemplate,
+    user_types::UserInfo,
+    wire_format::{decode_wire, encode_wire, WireEncoding, NOTE_LIST_WIRE_ENCODING},
+};
 
 pub const ASSET_HEADER: &str = "asset";
 
@@ -198,6 +204,7 @@ pub enum Asset {
     None,
     PFP(S3Address),
     DeckImage(S3Address),
+    DeckAudio(S3Address),
     CachedPFP(String /* Should be asset as string */, String /* URL */),
 }
 
@@ -236,14 +243,27 @@ pub struct DeckMeta {
     pub price: f32,
     #[serde(with = "serde_arrays")]
     pub note_count_by_level: [usize; MAX_LEVELS],
-    pub note_count_by_type: [usize; NoteType::COUNT],
+    pub note_count_by_type: BTreeMap<NoteType, usize>,
     pub total_notes: usize,
+    #[serde(default)]
+    pub note_templates: Vec<NoteTemplate>,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub cover_image: Asset,
+    #[serde(default)]
+    pub language: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub highest_note_id: u64,
+    #[serde(default = "DeckMeta::first_version")]
+    pub version: u64,
 }
 
 impl DeckMeta {
     pub fn new_deck_meta(email: &str, total_notes: usize) -> DeckMeta {
-        let mut type_array = [0; NoteType::COUNT];
-        type_array[0] = total_notes as usize;
+        let note_count_by_type = BTreeMap::from([(NoteType::default(), total_notes)]);
         let note_count_by_level = get_level_map(total_notes);
 
         DeckMeta {
@@ -252,47 +272,90 @@ impl DeckMeta {
             public: false,
             price: 0.0,
             note_count_by_level,
-            note_count_by_type: type_array,
+            note_count_by_type,
             total_notes,
+            note_templates: Vec::new(),
+            description: String::new(),
+            cover_image: Asset::None,
+            language: String::new(),
+            tags: Vec::new(),
+            highest_note_id: total_notes as u64,
+            version: DeckMeta::first_version(),
         }
     }
-}
 
-impl ToString for DeckMeta {
-    fn to_string(&self) -> String {
-        let mut meta_str = String::from("");
-        meta_str.push_str(&self.name);
-        meta_str.push(SEPARATOR);
-        meta_str.push_str(&self.owner);
-        meta_str.push(SEPARATOR);
-        meta_str.push_str(&self.public.to_string());
-        meta_str.push(SEPARATOR);
-        meta_str.push_str(&self.price.to_string());
-        meta_str.push(SEPARATOR);
-        for count in self.note_count_by_level {
-            let level_count_str = format!("{}|", count);
-            meta_str.push_str(&level_count_str);
+    pub fn set_note_template(&mut self, template: NoteTemplate) {
+        match self.note_templates.iter_mut().find(|existing| existing.note_type == template.note_type) {
+            Some(existing) => *existing = template,
+            None => self.note_templates.push(template),
         }
-        meta_str.pop();
-        meta_str.push(SEPARATOR);
-        for count in self.note_count_by_type {
-            let type_count_str = format!("{}|", count);
-            meta_str.push_str(&type_count_str);
+    }
+
+    /// Ids are never handed out twice so a cached copy of a deleted note cannot be mistaken for a new one.
+    /// Decks written before highest_note_id was kept numbered their notes 1 to total_notes.
+    pub fn next_note_id(&mut self) -> u64 {
+        self.highest_note_id = self.highest_note_id.max(self.total_notes as u64) + 1;
+        self.highest_note_id
+    }
+
+    /// The level a note added to the end of the deck belongs in.
+    pub fn level_for_new_note(&self, note_id: u64) -> u32 {
+        let notes_per_level = get_notes_per_level(self.total_notes + 1);
+        get_note_level(note_id, notes_per_level).clamp(1, MAX_LEVELS) as u32
+    }
+
+    /// Metas written before versions were kept count as the first version, 0 means a deck was never fully cached.
+    fn first_version() -> u64 {
+        1
+    }
+
+    /// Moves whenever the meta or any note in the deck is written, offline caches compare it to know the deck changed.
+    /// Only change_deck_meta and write_note_and_deck_meta write an existing meta, both bump it from the stored version.
+    pub fn bump_version(&mut self) {
+        self.version += 1;
+    }
+
+    pub fn count_note(&mut self, note: &Note) {
+        self.total_notes += 1;
+        *self.note_count_by_type.entry(note.note_type.clone()).or_default() += 1;
+        if let Some(count) = self.note_count_by_level.get_mut((note.level as usize).saturating_sub(1)) {
+            *count += 1;
         }
-        meta_str.pop();
-        meta_str.push(SEPARATOR);
-        meta_str.push_str(&self.total_notes.to_string());
+    }
 
+    pub fn uncount_note(&mut self, note: &Note) {
+        // Deleting a note must not hand its id out again
+        self.highest_note_id = self.highest_note_id.max(self.total_notes as u64);
+        self.total_notes = self.total_notes.saturating_sub(1);
+        if let Some(count) = self.note_count_by_type.get_mut(&note.note_type) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.note_count_by_type.remove(&note.note_type);
+            }
+        }
+        if let Some(count) = self.note_count_by_level.get_mut((note.level as usize).saturating_sub(1)) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
 
-        meta_str
+impl ToString for DeckMeta {
+    fn to_string(&self) -> String {
+        encode_wire(self, WireEncoding::Json)
     }
 }
 
 impl FromStr for DeckMeta {
-
     type Err = ();
 
+    /// Metas written before the wire format are still stored in dynamo so they fall back to the legacy reader.
     fn from_str(input: &str) -> Result<DeckMeta, Self::Err> {
+        decode_wire(input).or_else(|_| DeckMeta::from_legacy_str(input))
+    }
+}
+
+impl DeckMeta {
+    fn from_legacy_str(input: &str) -> Result<DeckMeta, ()> {
         let mut metas = input.split(SEPARATOR);
         let name = metas.next().unwrap_or_default().to_string();
         let owner = metas.next().unwrap_or_default().to_string();
@@ -310,11 +373,11 @@ impl FromStr for DeckMeta {
 
         let note_count_by_type = metas.next().unwrap_or_default();
         let note_count_by_type_splitter = note_count_by_type.split('|');
-        let mut note_count_by_type = [0; NoteType::COUNT];
-        for (i, count) in note_count_by_type_splitter.enumerate() {
+        let mut note_count_by_type = BTreeMap::new();
+        for ((_, note_type), count) in LEGACY_NOTE_TYPES.iter().zip(note_count_by_type_splitter) {
             let Ok(count) = usize::from_str(count) else {return Err(())};
 
-            note_count_by_type[i] = count;
+            note_count_by_type.insert(NoteType::new(note_type), count);
         }
         let total_notes = usize::from_str(metas.next().unwrap_or_default()).unwrap_or_default();
 
@@ -326,6 +389,13 @@ impl FromStr for DeckMeta {
             note_count_by_level,
             note_count_by_type,
             total_notes,
+            note_templates: Vec::new(),
+            description: String::new(),
+            cover_image: Asset::None,
+            language: String::new(),
+            tags: Vec::new(),
+            highest_note_id: total_notes as u64,
+            version: DeckMeta::first_version(),
         })
     }
 }
@@ -350,6 +420,22 @@ pub fn get_level_map(total_notes: usize) -> [usize; 100] {
     level_map
 }
 
+/// Cloze notes make a card per cloze so levels are filled by card rather than by note,
+/// each note takes the level of its first card.
+pub fn get_card_levels(cards_per_note: &[usize]) -> Vec<usize> {
+    let total_cards: usize = cards_per_note.iter().sum();
+    let cards_per_level = get_notes_per_level(total_cards);
+
+    let mut first_card = 1;
+    let mut levels = Vec::with_capacity(cards_per_note.len());
+    for &card_count in cards_per_note {
+        levels.push(get_note_level(first_card as u64, cards_per_level).clamp(1, MAX_LEVELS));
+        first_card += card_count.max(1);
+    }
+
+    levels
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Display, EnumIter, Serialize, Deserialize)]
 pub enum UpdateType {
     Add,
@@ -430,6 +516,7 @@ pub enum UpdateValues {
     Note(Note),
     DeckList(DeckList),
     Unsigned64(u64),
+    DeckMeta(DeckMeta),
 }
 
 impl ToString for UpdateValues {
@@ -502,29 +589,7 @@ impl Note {
 
 impl ToString for Note {
     fn to_string(&self) -> String {
-        let mut string = String::new();
-        for field in self.fields.iter() {
-            string.push_str(&field.to_string());
-            string.push(SEPARATOR4);
-        }
-        string.push_str(&self.note_id.to_string());
-        string.push(SEPARATOR3);
-        string.push_str(&self.deck_id.to_string());
-        string.push(SEPARATOR3);
-        string.push_str(&self.note_type.to_string());
-        string.push(SEPARATOR3);
-        string.push_str(&self.version.to_string());
-        string.push(SEPARATOR3);
-        string.push_str(&self.reviews_per_stage.to_string());
-        string.push(SEPARATOR3);
-        string.push_str(&self.level.to_string());
-        string.push(SEPARATOR3);
-        match &self.meta {
-            Some(meta) => string.push_str(&meta.to_string()),
-            None => string.push_str("None"),
-        }
-        
-        string
+        encode_wire(self, WireEncoding::Json)
     }
 }
 
@@ -532,13 +597,19 @@ impl FromStr for Note {
     type Err = ();
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
+        decode_wire(input).or_else(|_| Note::from_legacy_str(input))
+    }
+}
+
+impl Note {
+    fn from_legacy_str(input: &str) -> Result<Self, ()> {
         let mut splitter = input.split(SEPARATOR4);
 
         let Some(universal_fields) = splitter.next_back() else {return Err(())};
 
         let mut fields = Vec::new();
         for field in splitter {
-            let Ok(fld) = Field::from_str(field) else {return Err(())};
+            let Ok(fld) = Field::from_legacy_str(field) else {return Err(())};
             
             fields.push(fld);
         }
@@ -564,7 +635,7 @@ impl FromStr for Note {
         let Ok(level) = u32::from_str(level) else {return Err(())};
 
         let Some(meta) = universal_fields.next() else {return Err(())};
-        let meta = if let Ok(meta) = DeckMeta::from_str(meta) {
+        let meta = if let Ok(meta) = DeckMeta::from_legacy_str(meta) {
             Some(meta)
         } else {
             None
@@ -613,16 +684,20 @@ impl Field {
             asset,
         }
     }
+    /// Fields with only text or only an asset are stored as just that, fields with both are stored whole.
     pub fn to_database_string(&self) -> String {
-        if self.asset.is_none() {
-            let Some(text) = &self.text else {return "".to_string()};
-            return text.to_string();
-        } else {
-            let Some(asset) = &self.asset else {return "".to_string()};
-            return asset.to_string();
+        match (&self.text, &self.asset) {
+            (Some(_), Some(_)) => encode_wire(self, WireEncoding::Json),
+            (Some(text), None) => text.to_string(),
+            (None, Some(asset)) => asset.to_string(),
+            (None, None) => "".to_string(),
         }
     }
     pub fn from_database_string(db_entry: String, name: String) -> Self {
+        if let Ok(field) = decode_wire::<Field>(&db_entry) {
+            return Self {name, ..field};
+        }
+
         let asset = match Asset::from_str(&db_entry) {
             Ok(asset) => Some(asset),
             Err(_) => None,
@@ -643,14 +718,7 @@ impl Field {
 
 impl ToString for Field {
     fn to_string(&self) -> String {
-        let data = match &self.asset {
-            Some(asset) => asset.to_string(),
-            None => match &self.text {
-                Some(text) => text.to_owned(),
-                None => "".to_owned(),
-            },
-        };
-        format!("{}{}{}", self.name, SEPARATOR3, data)
+        encode_wire(self, WireEncoding::Json)
     }
 }
 
@@ -658,6 +726,12 @@ impl FromStr for Field {
     type Err = ();
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
+        decode_wire(input).or_else(|_| Field::from_legacy_str(input))
+    }
+}
+
+impl Field {
+    fn from_legacy_str(input: &str) -> Result<Self, ()> {
         let mut splitter = input.split(SEPARATOR3);
         let Some(name) = splitter.next() else {return Err(())};
         let data = splitter.next().unwrap_or_else(|| "");
@@ -680,23 +754,14 @@ impl FromStr for Field {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NoteList {
     pub notes: Vec<Note>
 }
 
 impl ToString for NoteList {
     fn to_string(&self) -> String {
-        let mut notes_str = String::new();
-        for (i, note) in self.iter().enumerate() {
-            if i == 0 {
-                notes_str.push_str(&note.to_string());
-            } else {
-                notes_str.push_str(SEPARATOR5);
-                notes_str.push_str(&note.to_string());
-            }
-        }
-        notes_str
+        encode_wire(self, NOTE_LIST_WIRE_ENCODING)
     }
 }
 
@@ -704,10 +769,19 @@ impl FromStr for NoteList {
     type Err = ();
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        let note_strs = input.split(SEPARATOR5);
+        if input.is_empty() {
+            return Ok(Self::default());
+        }
+        decode_wire(input).or_else(|_| NoteList::from_legacy_str(input))
+    }
+}
+
+impl NoteList {
+    fn from_legacy_str(input: &str) -> Result<Self, ()> {
         let mut note_list = Self::default();
+        let note_strs = input.split(SEPARATOR5);
         for note_str in note_strs{
-            let Ok(note) = Note::from_str(note_str) else {return Err(())};
+            let Ok(note) = Note::from_legacy_str(note_str) else {return Err(())};
             note_list.push(note);
         }
         Ok(note_list)
@@ -728,10 +802,47 @@ impl DerefMut for NoteList {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, EnumIter, EnumCount, Display, Serialize, Deserialize)]
-pub enum NoteType {
-    #[default] WhatDa,
-    HellNah,
+pub const BASIC_NOTE_TYPE: &str = "Basic";
+pub const CLOZE_NOTE_TYPE: &str = "Cloze";
+// Placeholder types notes were stored with before note types could be named
+const LEGACY_NOTE_TYPES: [(&str, &str); 2] = [("WhatDa", BASIC_NOTE_TYPE), ("HellNah", CLOZE_NOTE_TYPE)];
+
+/// The name of a note type, its fields and card templates live in the deck meta.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NoteType(String);
+
+impl NoteType {
+    pub fn new(name: &str) -> Self {
+        let name = name.trim();
+        match LEGACY_NOTE_TYPES.iter().find(|(legacy_name, _)| *legacy_name == name) {
+            Some((_, current_name)) => Self(current_name.to_string()),
+            None => Self(name.to_string()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    /// Every name notes of this type may be stored under, including the legacy placeholder names.
+    pub fn stored_names(&self) -> Vec<&str> {
+        let mut names = vec![self.name()];
+        names.extend(LEGACY_NOTE_TYPES.iter().filter(|(_, current_name)| *current_name == self.name()).map(|(legacy_name, _)| *legacy_name));
+        names
+    }
+}
+
+impl Default for NoteType {
+    fn default() -> Self {
+        Self(BASIC_NOTE_TYPE.to_string())
+    }
+}
+
+impl std::fmt::Display for NoteType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
 impl FromStr for NoteType {
@@ -739,11 +850,9 @@ impl FromStr for NoteType {
     type Err = ();
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        for variant in Self::iter() {
-            if input == &variant.to_string() {
-                return Ok(variant);
-            }
+        if input.trim().is_empty() {
+            return Err(());
         }
-        Err(())
+        Ok(NoteType::new(input))
     }
 }
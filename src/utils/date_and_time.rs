@@ -255,6 +255,12 @@ impl Date {
         epoch.get_advance_by(days_to_advance as usize)
     }
 
+    pub fn from_secs(seconds: u64) -> Date {
+        let days_to_advance = seconds / Date::SECONDS_IN_DAY;
+
+        Date::UNIX_EPOCH.get_advance_by(days_to_advance as usize)
+    }
+
     pub fn now_with_time_zone_offset(offset_in_seconds: u64) -> Date {
         let epoch = Date::UNIX_EPOCH;
 
@@ -0,0 +1,120 @@
+use leptos::server;
+use server_fn::ServerFnError;
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::DeckId,
+    deck_import::DeckFileType,
+    outcomes::Outcome,
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use crate::utils::{
+    anki::export_anki_deck,
+    asset::get_presigned_url,
+    back_utils::{export_key, verify_user_header, EXPORT_URL_EXPIRATION_IN_SECONDS, PUBLIC_DECKS_STAGING_BUCKET},
+    collaborators::CollaboratorRole,
+    database_types::{Note, S3Address},
+    dynamo_utils::validate_deck_role_and_user_standing,
+    object_store::{setup_object_store, ObjectStore},
+    proceed,
+    storage::{setup_storage, Storage},
+};
+#[cfg(feature="ssr")]
+use csv::WriterBuilder;
+
+/// Builds the deck as a file in the staging bucket and returns a short lived link to download it.
+#[server(client=AuthClient)]
+pub async fn export_deck(deck_id: DeckId, file_type: DeckFileType, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    // Viewers can study a deck but only those who can edit it can take a copy of every note
+    match validate_deck_role_and_user_standing(&storage, &email, deck_id, CollaboratorRole::Editor).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let mut notes = match storage.get_deck_notes(deck_id).await {
+        Outcome::DeckNotesFound(notes) => notes,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let deck_name = match notes.iter().position(|note| note.note_id == 0) {
+        Some(meta_index) => notes.remove(meta_index).meta.map(|meta| meta.name).unwrap_or_default(),
+        None => String::new(),
+    };
+    let deck_name = if deck_name.trim().is_empty() {deck_id.to_string()} else {deck_name};
+    let field_names = export_field_names(&notes);
+
+    let object_store = setup_object_store().await;
+
+    let export_outcome = match file_type {
+        DeckFileType::Csv => export_csv_deck(&notes, &field_names),
+        DeckFileType::Apkg => export_anki_deck(&object_store, &deck_name, &notes, &field_names).await,
+    };
+    let bytes = match export_outcome {
+        Outcome::AssetRetrieved(bytes) => bytes,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let address = S3Address {
+        bucket: PUBLIC_DECKS_STAGING_BUCKET.to_string(),
+        key: export_key(&email, deck_id, file_type),
+    };
+    let file_name = format!("{}{}", deck_name.replace(['"', '/', '\\'], ""), file_type.extension());
+
+    match object_store.put_object(&address, bytes, Some(format!("attachment; filename=\"{file_name}\""))).await {
+        Outcome::ObjectStored(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let outcome = match get_presigned_url(&object_store, &address.bucket, &address.key, EXPORT_URL_EXPIRATION_IN_SECONDS).await {
+        Outcome::PresignedUrlRetrieved(url) => Outcome::DeckExported(url),
+        any_other_outcome => any_other_outcome,
+    };
+
+    Ok(outcome)
+}
+
+/// Every field name used in the deck, in the order they are first seen.
+#[cfg(feature="ssr")]
+fn export_field_names(notes: &Vec<Note>) -> Vec<String> {
+    let mut field_names: Vec<String> = Vec::new();
+    for note in notes {
+        for field in note.fields.iter() {
+            if !!!field_names.contains(&field.name) {
+                field_names.push(field.name.clone());
+            }
+        }
+    }
+    field_names
+}
+
+#[cfg(feature="ssr")]
+fn export_csv_deck(notes: &Vec<Note>, field_names: &Vec<String>) -> Outcome {
+    let mut writer = WriterBuilder::new().from_writer(Vec::new());
+
+    if let Err(e) = writer.write_record(field_names) {
+        return Outcome::DeckCouldNotBeProcessed(e.to_string());
+    }
+
+    for note in notes {
+        let record = field_names.iter().map(|field_name| {
+            match note.fields.iter().find(|field| &field.name == field_name) {
+                Some(field) => field.to_database_string(),
+                None => String::new(),
+            }
+        });
+        if let Err(e) = writer.write_record(record) {
+            return Outcome::DeckCouldNotBeProcessed(e.to_string());
+        }
+    }
+
+    match writer.into_inner() {
+        Ok(bytes) => Outcome::AssetRetrieved(bytes),
+        Err(e) => Outcome::DeckCouldNotBeProcessed(e.to_string()),
+    }
+}
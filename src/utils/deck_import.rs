@@ -0,0 +1,256 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{
+    database_types::{get_card_levels, DeckId, DeckMeta, Note, NoteType, CLOZE_NOTE_TYPE},
+    note_templates::{default_template_for_note, note_cloze_indices, template_for_note, NoteTemplate},
+    shared_truth::{ALLOWED_UPLOAD_FILE_TYPES, MAX_LEVELS, MAX_NOTE_FIELDS, NOTE_LIMIT},
+};
+
+// Fields are stored as their own attributes next to these, so a field can never share one of their names
+pub const RESERVED_FIELD_NAMES: [&str; 8] = [
+    Note::FIELD_NAMES.note_id,
+    Note::FIELD_NAMES.deck_id,
+    Note::FIELD_NAMES.fields,
+    Note::FIELD_NAMES.note_type,
+    Note::FIELD_NAMES.version,
+    Note::FIELD_NAMES.reviews_per_stage,
+    Note::FIELD_NAMES.level,
+    Note::FIELD_NAMES.meta,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DeckFileType {
+    Apkg,
+    Csv,
+}
+
+impl DeckFileType {
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let file_name = file_name.to_lowercase();
+        let Some(extension) = ALLOWED_UPLOAD_FILE_TYPES.into_iter().find(|extension| file_name.ends_with(extension)) else {return None};
+        match extension {
+            ".apkg" => Some(DeckFileType::Apkg),
+            ".csv" => Some(DeckFileType::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DeckFileType::Apkg => ".apkg",
+            DeckFileType::Csv => ".csv",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RowError {
+    pub row: Option<usize>,
+    pub reason: String,
+}
+
+impl RowError {
+    pub fn new(row: Option<usize>, reason: &str) -> Self {
+        Self {
+            row,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColumnMapping {
+    pub column: usize,
+    pub field_name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CsvImportConfig {
+    pub deck_name: String,
+    pub delimiter: char,
+    pub has_header: bool,
+    /// When empty every column becomes a field, named after the header if there is one.
+    pub column_mapping: Vec<ColumnMapping>,
+}
+
+impl Default for CsvImportConfig {
+    fn default() -> Self {
+        Self {
+            deck_name: "New Deck".to_string(),
+            delimiter: ',',
+            has_header: true,
+            column_mapping: Vec::new(),
+        }
+    }
+}
+
+impl ToString for CsvImportConfig {
+    fn to_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl FromStr for CsvImportConfig {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config = match serde_json::from_str(s) {
+            Ok(c) => c,
+            Err(_) => return Err(()),
+        };
+        Ok(config)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImportedDeck {
+    pub deck_id: DeckId,
+    pub meta: DeckMeta,
+    pub notes: Vec<Note>,
+    pub row_errors: Vec<RowError>,
+    #[serde(default)]
+    pub media_bytes: usize,
+}
+
+impl ToString for ImportedDeck {
+    fn to_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl FromStr for ImportedDeck {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let imported_deck = match serde_json::from_str(s) {
+            Ok(i) => i,
+            Err(_) => return Err(()),
+        };
+        Ok(imported_deck)
+    }
+}
+
+impl ImportedDeck {
+    /// Takes notes in the order they should be learned, numbers them from 1 and places them in levels.
+    pub fn new(deck_id: DeckId, email: &str, deck_name: &str, notes: Vec<(Option<usize>, Note)>, note_templates: Vec<NoteTemplate>, mut row_errors: Vec<RowError>) -> Self {
+        let mut accepted_notes = Vec::with_capacity(notes.len().min(NOTE_LIMIT));
+
+        for (row, note) in notes {
+            if note.fields.is_empty() {
+                row_errors.push(RowError::new(row, "note has no fields"));
+                continue;
+            }
+            if note.fields.len() > MAX_NOTE_FIELDS {
+                row_errors.push(RowError::new(row, &format!("note has more than {MAX_NOTE_FIELDS} fields")));
+                continue;
+            }
+            if accepted_notes.len() >= NOTE_LIMIT {
+                row_errors.push(RowError::new(row, &format!("deck is over the {NOTE_LIMIT} note limit")));
+                continue;
+            }
+            accepted_notes.push(rename_reserved_fields(note));
+        }
+
+        let total_notes = accepted_notes.len();
+        let mut meta = DeckMeta::new_deck_meta(email, total_notes);
+        meta.name = deck_name.to_string();
+        meta.note_count_by_type = BTreeMap::new();
+        for note_template in note_templates {
+            meta.set_note_template(note_template);
+        }
+
+        let mut cards_per_note = Vec::with_capacity(total_notes);
+        for (i, note) in accepted_notes.iter_mut().enumerate() {
+            note.note_id = i as u64 + 1;
+            note.deck_id = deck_id;
+
+            // Untyped notes written with cloze markers are cloze notes
+            if note.note_type == NoteType::default() && !!!note_cloze_indices(note).is_empty() {
+                note.note_type = NoteType::new(CLOZE_NOTE_TYPE);
+            }
+            *meta.note_count_by_type.entry(note.note_type.clone()).or_insert(0) += 1;
+
+            // Every note type starts out as a basic or cloze card built from the fields of its first note
+            if !!!meta.note_templates.iter().any(|template| template.note_type == note.note_type) {
+                meta.note_templates.push(default_template_for_note(note));
+            }
+            cards_per_note.push(template_for_note(&meta.note_templates, note).card_ords(note).len());
+        }
+
+        meta.note_count_by_level = [0; MAX_LEVELS];
+        for (note, level) in accepted_notes.iter_mut().zip(get_card_levels(&cards_per_note)) {
+            note.level = level as u32;
+            meta.note_count_by_level[level - 1] += 1;
+        }
+
+        Self {
+            deck_id,
+            meta,
+            notes: accepted_notes,
+            row_errors,
+            media_bytes: 0,
+        }
+    }
+
+    /// Roughly how much space the deck takes up once stored, used to work out what the upload actually cost.
+    pub fn stored_size(&self) -> usize {
+        let notes_size: usize = self.notes.iter().map(|note| note.to_string().len()).sum();
+        notes_size + self.meta_note().to_string().len() + self.media_bytes
+    }
+
+    pub fn meta_note(&self) -> Note {
+        Note {
+            note_id: 0,
+            deck_id: self.deck_id,
+            meta: Some(self.meta.clone()),
+            ..Default::default()
+        }
+    }
+}
+
+fn rename_reserved_fields(mut note: Note) -> Note {
+    for (i, field) in note.fields.iter_mut().enumerate() {
+        if field.name.trim().is_empty() {
+            field.name = format!("Field {}", i + 1);
+        } else if RESERVED_FIELD_NAMES.contains(&field.name.as_str()) {
+            field.name = format!("{} field", field.name);
+        }
+    }
+    note
+}
+
+/// Removes tags from field html, keeping line breaks and decoding the common entities.
+pub fn strip_html(html: &String) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut tag = String::new();
+    let mut in_tag = false;
+
+    for character in html.chars() {
+        match character {
+            '<' => {
+                in_tag = true;
+                tag.clear();
+            },
+            '>' if in_tag => {
+                in_tag = false;
+                let tag_name = tag.trim_start_matches('/').split_whitespace().next().unwrap_or_default().to_lowercase();
+                if matches!(tag_name.trim_end_matches('/'), "br" | "div" | "p" | "li" | "hr") && !!!text.is_empty() && !!!text.ends_with('\n') {
+                    text.push('\n');
+                }
+            },
+            any_character if in_tag => tag.push(any_character),
+            any_character => text.push(any_character),
+        }
+    }
+
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,207 @@
+use leptos::server;
+use serde::{Deserialize, Serialize};
+use server_fn::ServerFnError;
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::{DeckId, DeckMeta},
+    outcomes::Outcome,
+    shared_truth::{ALLOWED_DECK_COVER_FILE_TYPES, MAX_DECK_DESCRIPTION_LENGTH, MAX_DECK_LANGUAGE_LENGTH, MAX_DECK_NAME_LENGTH, MAX_DECK_PRICE, MAX_DECK_TAGS, MAX_DECK_TAG_LENGTH},
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use crate::utils::{
+    back_utils::{deck_cover_key, verify_user_header, PUBLIC_DECKS_BUCKET, UPLOAD_URL_EXPIRATION_IN_SECONDS},
+    catalog::CatalogEntry,
+    collaborators::CollaboratorRole,
+    database_types::{Asset, S3Address, UpdateRecipes},
+    dynamo_utils::{change_deck_meta, deck_meta_update_recipe, validate_deck_role_and_user_standing},
+    object_store::{setup_object_store, ObjectStore},
+    proceed,
+    shared_truth::DECK_COVER_SIZE_LIMIT,
+    storage::{setup_storage, Storage},
+};
+
+/// The parts of a deck's meta its owner and collaborators can change, anything left as None stays as it is.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeckMetaEdit {
+    pub name: Option<String>,
+    pub public: Option<bool>,
+    pub price: Option<f32>,
+    pub description: Option<String>,
+    pub language: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+impl DeckMetaEdit {
+    pub fn is_valid(&self) -> bool {
+        let name_is_valid = self.name.as_ref().is_none_or(|name| {
+            let name = name.trim();
+            !!!name.is_empty() && name.chars().count() <= MAX_DECK_NAME_LENGTH
+        });
+        let price_is_valid = self.price.is_none_or(|price| price.is_finite() && (0.0..=MAX_DECK_PRICE).contains(&price));
+        let description_is_valid = self.description.as_ref().is_none_or(|description| description.chars().count() <= MAX_DECK_DESCRIPTION_LENGTH);
+        let language_is_valid = self.language.as_ref().is_none_or(|language| language.trim().chars().count() <= MAX_DECK_LANGUAGE_LENGTH);
+        let tags_are_valid = self.tags.as_ref().is_none_or(|tags| {
+            tags.len() <= MAX_DECK_TAGS && tags.iter().all(|tag| !!!tag.trim().is_empty() && tag.trim().chars().count() <= MAX_DECK_TAG_LENGTH)
+        });
+
+        name_is_valid && price_is_valid && description_is_valid && language_is_valid && tags_are_valid
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &DeckMetaEdit::default()
+    }
+
+    /// Prices are kept to whole cents, languages and tags are lowercased so the catalog can match them exactly.
+    pub fn apply(&self, meta: &mut DeckMeta) {
+        if let Some(name) = &self.name {
+            meta.name = name.trim().to_string();
+        }
+        if let Some(public) = self.public {
+            meta.public = public;
+        }
+        if let Some(price) = self.price {
+            meta.price = (price * 100.0).round() / 100.0;
+        }
+        if let Some(description) = &self.description {
+            meta.description = description.trim().to_string();
+        }
+        if let Some(language) = &self.language {
+            meta.language = normalize_catalog_term(language);
+        }
+        if let Some(tags) = &self.tags {
+            meta.tags = tags.iter().map(|tag| normalize_catalog_term(tag)).collect();
+            meta.tags.sort();
+            meta.tags.dedup();
+        }
+    }
+}
+
+pub fn normalize_catalog_term(term: &str) -> String {
+    term.trim().to_lowercase()
+}
+
+pub fn deck_cover_extension(file_name: &str) -> Option<&'static str> {
+    let file_name = file_name.to_lowercase();
+    ALLOWED_DECK_COVER_FILE_TYPES.into_iter().find(|extension| file_name.ends_with(extension))
+}
+
+#[server(client=AuthClient)]
+pub async fn update_deck_meta(deck_id: DeckId, edit: DeckMetaEdit, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    if edit.is_empty() || !!!edit.is_valid() {
+        return Ok(Outcome::InvalidRequest);
+    }
+
+    let storage = setup_storage().await;
+
+    // Editors can describe the deck but only admins and the owner can put a price on it
+    let minimum_role = if edit.price.is_some() {CollaboratorRole::Admin} else {CollaboratorRole::Editor};
+
+    match validate_deck_role_and_user_standing(&storage, &email, deck_id, minimum_role).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    Ok(change_deck_settings(&storage, deck_id, |meta| edit.apply(meta)).await)
+}
+
+/// Returns a url the cover image can be put to, set_deck_cover is called once it has been uploaded.
+#[server(client=AuthClient)]
+pub async fn request_deck_cover_upload(deck_id: DeckId, file_name: String, file_size: usize, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let Some(extension) = deck_cover_extension(&file_name) else {return Ok(Outcome::IncorrectType)};
+    if file_size > DECK_COVER_SIZE_LIMIT {
+        return Ok(Outcome::InvalidRequest);
+    }
+
+    let storage = setup_storage().await;
+
+    match validate_deck_role_and_user_standing(&storage, &email, deck_id, CollaboratorRole::Editor).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let object_store = setup_object_store().await;
+    let address = S3Address {
+        bucket: PUBLIC_DECKS_BUCKET.to_string(),
+        key: deck_cover_key(deck_id, extension),
+    };
+
+    Ok(object_store.presigned_put_url(&address, UPLOAD_URL_EXPIRATION_IN_SECONDS).await)
+}
+
+/// Points the deck at the cover image uploaded through request_deck_cover_upload, removing the old cover if it was stored elsewhere.
+#[server(client=AuthClient)]
+pub async fn set_deck_cover(deck_id: DeckId, file_name: String, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let Some(extension) = deck_cover_extension(&file_name) else {return Ok(Outcome::IncorrectType)};
+
+    let storage = setup_storage().await;
+
+    // Nothing in the bucket is read or removed for someone who cannot change the deck
+    match validate_deck_role_and_user_standing(&storage, &email, deck_id, CollaboratorRole::Editor).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let object_store = setup_object_store().await;
+    let address = S3Address {
+        bucket: PUBLIC_DECKS_BUCKET.to_string(),
+        key: deck_cover_key(deck_id, extension),
+    };
+
+    // The upload went straight to the bucket so its size is checked again here
+    match object_store.get_object(&address, DECK_COVER_SIZE_LIMIT).await {
+        Outcome::AssetRetrieved(_) => proceed(),
+        Outcome::InvalidRequest => {
+            object_store.delete_object(&address).await;
+            return Ok(Outcome::InvalidRequest);
+        },
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let mut old_cover = Asset::None;
+    let outcome = change_deck_settings(&storage, deck_id, |meta| {
+        old_cover = std::mem::replace(&mut meta.cover_image, Asset::DeckImage(address.clone()));
+    }).await;
+
+    if let (Outcome::DatabaseUpdateSuccess(_), Asset::DeckImage(old_address)) = (&outcome, &old_cover) {
+        if old_address != &address {
+            object_store.delete_object(old_address).await;
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Writes back the deck meta after the change, which runs again if the deck changed in the meantime. Returns the
+/// recipes for the cache once the catalog has the new meta too.
+#[cfg(feature="ssr")]
+async fn change_deck_settings(storage: &impl Storage, deck_id: DeckId, mut change: impl FnMut(&mut DeckMeta)) -> Outcome {
+    let meta = match change_deck_meta(storage, deck_id, |meta| {
+        change(meta);
+        Ok(())
+    }).await {
+        Ok(meta) => meta,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+    let cache_recipes = UpdateRecipes {recipes: vec![deck_meta_update_recipe(deck_id, meta.clone())]};
+
+    // Public decks are listed in the catalog with their latest meta and private ones are taken out of it
+    let catalog_outcome = if meta.public {
+        storage.put_catalog_entry(&CatalogEntry::from_meta(deck_id, &meta)).await
+    } else {
+        storage.remove_catalog_entry(deck_id).await
+    };
+
+    match catalog_outcome {
+        Outcome::DatabaseUpdateSuccess(_) => Outcome::DatabaseUpdateSuccess(cache_recipes),
+        any_other_outcome => Outcome::new_multi_outcome(Outcome::DatabaseUpdateSuccess(cache_recipes), any_other_outcome),
+    }
+}
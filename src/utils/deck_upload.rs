@@ -0,0 +1,210 @@
+use leptos::server;
+use server_fn::ServerFnError;
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::DeckId,
+    deck_import::{CsvImportConfig, DeckFileType},
+    outcomes::Outcome,
+    shared_truth::TOKEN_COST_PER_KB,
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use crate::utils::{
+    anki::import_anki_deck,
+    back_utils::{generate_deck_id, staging_upload_key, verify_user_header, PUBLIC_DECKS_STAGING_BUCKET, UPLOAD_URL_EXPIRATION_IN_SECONDS},
+    csv_import::import_csv_deck,
+    database_types::{Note, S3Address, UpdateRecipes},
+    dynamo_utils::validate_user_type_user_standing_upload_tokens_and_deck_limits,
+    object_store::{setup_object_store, ObjectStore},
+    proceed,
+    shared_truth::RAW_DECK_SIZE_LIMIT,
+    storage::{setup_storage, Storage},
+    upload_ledger::{LedgerEntry, TransactionType},
+};
+#[cfg(feature="ssr")]
+use leptos::logging::error;
+#[cfg(feature="ssr")]
+use std::time::Duration;
+
+#[cfg(feature="ssr")]
+const REFUND_ATTEMPTS: u32 = 5;
+
+pub fn estimate_upload_cost(file_size_in_bytes: usize) -> f64 {
+    (file_size_in_bytes as f64 / 1000.0) * TOKEN_COST_PER_KB
+}
+
+#[server(client=AuthClient)]
+pub async fn request_deck_upload(file_name: String, file_size: usize, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let Some(file_type) = DeckFileType::from_file_name(&file_name) else {return Ok(Outcome::IncorrectType)};
+    if file_size > RAW_DECK_SIZE_LIMIT {
+        return Ok(Outcome::InvalidRequest);
+    }
+
+    let storage = setup_storage().await;
+
+    match validate_user_type_user_standing_upload_tokens_and_deck_limits(&storage, &email, estimate_upload_cost(file_size)).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let deck_id = generate_deck_id();
+    let object_store = setup_object_store().await;
+    let address = S3Address {
+        bucket: PUBLIC_DECKS_STAGING_BUCKET.to_string(),
+        key: staging_upload_key(&email, deck_id, file_type),
+    };
+
+    let outcome = match object_store.presigned_put_url(&address, UPLOAD_URL_EXPIRATION_IN_SECONDS).await {
+        Outcome::PresignedUrlRetrieved(url) => Outcome::DeckUploadUrlRetrieved(deck_id, url),
+        any_other_outcome => any_other_outcome,
+    };
+
+    Ok(outcome)
+}
+
+#[server(client=AuthClient)]
+pub async fn process_deck_upload(deck_id: DeckId, file_type: DeckFileType, csv_config: CsvImportConfig, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+    let object_store = setup_object_store().await;
+    let staging_address = S3Address {
+        bucket: PUBLIC_DECKS_STAGING_BUCKET.to_string(),
+        key: staging_upload_key(&email, deck_id, file_type),
+    };
+
+    let bytes = match object_store.get_object(&staging_address, RAW_DECK_SIZE_LIMIT).await {
+        Outcome::AssetRetrieved(bytes) => bytes,
+        Outcome::InvalidRequest => return Ok(Outcome::InvalidRequest),
+        _ => return Ok(Outcome::DeckNotUploadedToBucket),
+    };
+
+    // Limits are checked again because the uploaded file may not match what was requested
+    match validate_user_type_user_standing_upload_tokens_and_deck_limits(&storage, &email, estimate_upload_cost(bytes.len())).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    // Claiming the id is what makes an upload the only one processing the deck, a repeated submit stops here without
+    // charging again or touching the deck the first one is writing
+    match storage.claim_deck_id(deck_id).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    // The estimate is charged up front and whatever the stored deck did not use is refunded afterwards
+    let charged_cost = estimate_upload_cost(bytes.len());
+    let charge = LedgerEntry::new(&email, deck_id, TransactionType::Charge, charged_cost, "deck upload");
+    let mut cache_recipes = match storage.record_upload_token_transaction(&charge).await {
+        Outcome::DatabaseUpdateSuccess(cache_recipes) => cache_recipes,
+        any_other_outcome => {
+            release_deck_claim(&storage, deck_id).await;
+            return Ok(any_other_outcome);
+        },
+    };
+
+    let mut imported_deck = match store_deck(&storage, &object_store, bytes, deck_id, file_type, &csv_config, &email).await {
+        Outcome::DeckImported(imported_deck) => imported_deck,
+        any_other_outcome => {
+            let refund_recipes = refund_upload_tokens(&storage, &email, deck_id, charged_cost, "deck could not be processed").await;
+            return Ok(Outcome::new_multi_outcome(any_other_outcome, Outcome::DatabaseUpdateSuccess(refund_recipes)));
+        },
+    };
+
+    match storage.add_deck_to_user_active_decks_and_owned_decks(&email, deck_id).await {
+        Outcome::DatabaseUpdateSuccess(deck_recipes) => cache_recipes.recipes.extend(deck_recipes.recipes),
+        any_other_outcome => {
+            remove_stored_deck(&storage, deck_id, &imported_deck.notes).await;
+            let refund_recipes = refund_upload_tokens(&storage, &email, deck_id, charged_cost, "deck could not be added to user").await;
+            return Ok(Outcome::new_multi_outcome(any_other_outcome, Outcome::DatabaseUpdateSuccess(refund_recipes)));
+        },
+    };
+
+    let actual_cost = estimate_upload_cost(imported_deck.stored_size()).min(charged_cost);
+    if charged_cost - actual_cost > 0.0 {
+        let refund_recipes = refund_upload_tokens(&storage, &email, deck_id, charged_cost - actual_cost, "unused upload estimate").await;
+        cache_recipes.recipes.extend(refund_recipes.recipes);
+    }
+
+    imported_deck.notes.clear();
+    let _ = object_store.delete_object(&staging_address).await;
+
+    Ok(Outcome::new_multi_outcome(Outcome::DeckProcessed(imported_deck.to_string()), Outcome::DatabaseUpdateSuccess(cache_recipes)))
+}
+
+/// Imports the file and writes its notes and meta note to the deck table.
+#[cfg(feature="ssr")]
+async fn store_deck(storage: &impl Storage, object_store: &impl ObjectStore, bytes: Vec<u8>, deck_id: DeckId, file_type: DeckFileType, csv_config: &CsvImportConfig, email: &str) -> Outcome {
+    let import_outcome = match file_type {
+        DeckFileType::Apkg => import_anki_deck(object_store, bytes, deck_id, email).await,
+        DeckFileType::Csv => import_csv_deck(&bytes, csv_config, deck_id, email),
+    };
+    let mut imported_deck = match import_outcome {
+        Outcome::DeckImported(imported_deck) => imported_deck,
+        any_other_outcome => return any_other_outcome,
+    };
+
+    if imported_deck.notes.is_empty() {
+        return Outcome::DeckCouldNotBeProcessed("no notes could be imported from the file".to_string());
+    }
+
+    // The meta note goes last so the claim's placeholder is only replaced once all of the notes have been written
+    imported_deck.notes.push(imported_deck.meta_note());
+    let put_outcome = storage.put_notes(&imported_deck.notes).await;
+    imported_deck.notes.pop();
+
+    match put_outcome {
+        Outcome::NoteUpdateSuccess => Outcome::DeckImported(imported_deck),
+        any_other_outcome => {
+            remove_stored_deck(storage, deck_id, &imported_deck.notes).await;
+            any_other_outcome
+        },
+    }
+}
+
+/// Deletes whatever was written of a deck that could not be finished. The deck was already charged for so its meta note
+/// goes back to the claim's placeholder instead of being deleted, which keeps the id from ever being processed again.
+#[cfg(feature="ssr")]
+async fn remove_stored_deck(storage: &impl Storage, deck_id: DeckId, notes: &Vec<Note>) {
+    // The meta note goes first so the deck stops being readable straight away
+    match storage.put_notes(&vec![Note {deck_id, note_id: 0, ..Default::default()}]).await {
+        Outcome::NoteUpdateSuccess => proceed(),
+        any_other_outcome => error!("meta note of unfinished deck {} could not be reset {}", deck_id.to_string(), any_other_outcome.to_string()),
+    }
+
+    let note_ids = notes.iter().map(|note| note.note_id).collect::<Vec<u64>>();
+    match storage.delete_notes(deck_id, &note_ids).await {
+        Outcome::NoteUpdateSuccess => proceed(),
+        any_other_outcome => error!("notes of unfinished deck {} could not be removed {}", deck_id.to_string(), any_other_outcome.to_string()),
+    }
+}
+
+/// Gives up a claim whose upload was never charged, so the same upload can be processed again.
+#[cfg(feature="ssr")]
+async fn release_deck_claim(storage: &impl Storage, deck_id: DeckId) {
+    match storage.delete_notes(deck_id, &[0]).await {
+        Outcome::NoteUpdateSuccess => proceed(),
+        any_other_outcome => error!("claim on deck {} could not be released {}", deck_id.to_string(), any_other_outcome.to_string()),
+    }
+}
+
+/// The refund keeps its transaction id across attempts, so a retry after an attempt that went through is not refunded twice.
+#[cfg(feature="ssr")]
+async fn refund_upload_tokens(storage: &impl Storage, email: &str, deck_id: DeckId, amount: f64, reason: &str) -> UpdateRecipes {
+    let refund = LedgerEntry::new(email, deck_id, TransactionType::Refund, amount, reason);
+
+    for attempt in 1..=REFUND_ATTEMPTS {
+        match storage.record_upload_token_transaction(&refund).await {
+            Outcome::DatabaseUpdateSuccess(cache_recipes) => return cache_recipes,
+            any_other_outcome => error!("refund {} attempt {attempt} failed {}", refund.transaction_id, any_other_outcome.to_string()),
+        }
+        tokio::time::sleep(Duration::from_millis(100 * 2_u64.pow(attempt))).await;
+    }
+
+    error!("refund {} of {amount} upload tokens to {email} was not recorded after {REFUND_ATTEMPTS} attempts", refund.transaction_id);
+    UpdateRecipes::default()
+}
@@ -1,10 +1,18 @@
-use std::str::FromStr;
+use std::{cmp::Reverse, collections::HashMap, str::FromStr, time::Duration};
 
 use aws_config::{BehaviorVersion, Region};
-use aws_sdk_dynamodb::{types::AttributeValue, Client};
-use crate::utils::{database_types::{DBItem, DeckId, DeckList, Note, UpdateRecipe, UpdateRecipes, UpdateType, UpdateValues}, user_types::{PartialUserInfo, Standing, UserInfo}, outcomes::Outcome, proceed, shared_truth::DECK_LIMIT};
-use serde_dynamo::{aws_sdk_dynamodb_1::to_attribute_value, from_item};
-use crate::utils::back_utils::{PUBLIC_DECKS_TABLE, USERS_TABLE, is_in_active_decks};
+use aws_sdk_dynamodb::{operation::{put_item::PutItemError, transact_write_items::TransactWriteItemsError, update_item::UpdateItemError}, types::{AttributeValue, Delete, DeleteRequest, Put, PutRequest, TransactWriteItem, Update, WriteRequest}, Client};
+use crate::utils::{database_types::{DBItem, DeckId, DeckList, DeckMeta, Note, UpdateRecipe, UpdateRecipes, UpdateType, UpdateValues}, user_types::{PartialUserInfo, Standing, UserInfo}, outcomes::Outcome, proceed, scheduler::ReviewState, shared_truth::DECK_LIMIT};
+use serde_dynamo::{aws_sdk_dynamodb_1::to_attribute_value, from_item, to_item};
+use crate::utils::query::construct_note_from_database_item;
+use crate::utils::date_and_time::current_time_in_seconds;
+use crate::utils::back_utils::{CATALOG_TABLE, COLLABORATORS_TABLE, PAYOUT_ACCOUNTS_TABLE, PUBLIC_DECKS_TABLE, RECEIPTS_TABLE, REVIEWS_TABLE, SYNC_RECORDS_TABLE, UPLOAD_LEDGER_TABLE, USERS_TABLE, is_in_active_decks};
+use crate::utils::catalog::{CatalogEntry, CatalogPage, CatalogQuery, PUBLIC_SHELF};
+use crate::utils::collaborators::{Collaborator, CollaboratorRole, InviteStatus};
+use crate::utils::purchases::{PayoutAccount, Receipt, ReceiptStatus};
+use crate::utils::sync_queue::SyncRecord;
+use crate::utils::upload_ledger::{LedgerEntry, TransactionType};
+use crate::utils::storage::Storage;
 
 // User DB keys
 pub const PHONE_NUMBER_DB_KEY: &str = UserInfo::FIELD_NAMES.phone;
@@ -31,6 +39,51 @@ pub const REVIEWS_PER_STAGE_DB_KEY: &str = Note::FIELD_NAMES.reviews_per_stage;
 pub const DECK_META_DB_KEY: &str = Note::FIELD_NAMES.meta;
 pub const VERSION_DB_KEY: &str = Note::FIELD_NAMES.version;
 
+// Review DB keys
+pub const REVIEW_USER_DB_KEY: &str = ReviewState::FIELD_NAMES.user;
+pub const REVIEW_CARD_DB_KEY: &str = ReviewState::FIELD_NAMES.card;
+
+// Ledger DB keys
+pub const LEDGER_USER_DB_KEY: &str = LedgerEntry::FIELD_NAMES.user;
+pub const LEDGER_TRANSACTION_ID_DB_KEY: &str = LedgerEntry::FIELD_NAMES.transaction_id;
+
+// Catalog DB keys
+pub const CATALOG_DECK_ID_DB_KEY: &str = CatalogEntry::FIELD_NAMES.deck_id;
+pub const CATALOG_SHELF_DB_KEY: &str = CatalogEntry::FIELD_NAMES.shelf;
+pub const CATALOG_SEARCH_NAME_DB_KEY: &str = CatalogEntry::FIELD_NAMES.search_name;
+pub const CATALOG_LANGUAGE_DB_KEY: &str = CatalogEntry::FIELD_NAMES.language;
+pub const CATALOG_TAGS_DB_KEY: &str = CatalogEntry::FIELD_NAMES.tags;
+pub const CATALOG_SUBSCRIBERS_DB_KEY: &str = CatalogEntry::FIELD_NAMES.subscribers;
+
+// Receipt DB keys
+pub const RECEIPT_USER_DB_KEY: &str = Receipt::FIELD_NAMES.user;
+pub const RECEIPT_DECK_ID_DB_KEY: &str = Receipt::FIELD_NAMES.deck_id;
+pub const RECEIPT_OWNER_DB_KEY: &str = Receipt::FIELD_NAMES.owner;
+pub const RECEIPT_PAYMENT_ID_DB_KEY: &str = Receipt::FIELD_NAMES.payment_id;
+pub const RECEIPT_PAYOUT_ID_DB_KEY: &str = Receipt::FIELD_NAMES.payout_id;
+pub const RECEIPT_STATUS_DB_KEY: &str = Receipt::FIELD_NAMES.status;
+
+// Collaborator DB keys
+pub const COLLABORATOR_DECK_ID_DB_KEY: &str = Collaborator::FIELD_NAMES.deck_id;
+pub const COLLABORATOR_USER_DB_KEY: &str = Collaborator::FIELD_NAMES.user;
+pub const COLLABORATOR_ROLE_DB_KEY: &str = Collaborator::FIELD_NAMES.role;
+pub const COLLABORATOR_STATUS_DB_KEY: &str = Collaborator::FIELD_NAMES.status;
+pub const COLLABORATOR_INVITED_DB_KEY: &str = Collaborator::FIELD_NAMES.invited;
+
+// Payout account DB keys
+pub const PAYOUT_ACCOUNT_USER_DB_KEY: &str = PayoutAccount::FIELD_NAMES.user;
+pub const PAYOUT_ACCOUNT_ID_DB_KEY: &str = PayoutAccount::FIELD_NAMES.account_id;
+
+// Sync record DB keys
+pub const SYNC_RECORD_USER_DB_KEY: &str = SyncRecord::FIELD_NAMES.user;
+pub const SYNC_RECORD_KEY_DB_KEY: &str = SyncRecord::FIELD_NAMES.idempotency_key;
+pub const SYNC_RECORD_OUTCOME_DB_KEY: &str = SyncRecord::FIELD_NAMES.outcome;
+pub const SYNC_RECORD_EXPIRES_DB_KEY: &str = SyncRecord::FIELD_NAMES.expires;
+
+pub const BATCH_WRITE_LIMIT: usize = 25;
+pub const BATCH_WRITE_ATTEMPTS: u32 = 5;
+pub const NOTE_WRITE_ATTEMPTS: u32 = 5;
+
 pub async fn setup_client() -> Client {
     let config = aws_config::defaults(BehaviorVersion::latest()).region(Region::new("us-east-2")).load().await;
     Client::new(&config)
@@ -73,6 +126,22 @@ pub async fn get_user(client: &Client, email: &str, projection_expression: Optio
     Outcome::UserFound(user)
 }
 
+pub async fn put_user(client: &Client, user: &UserInfo) -> Outcome {
+    let item = match to_item(user) {
+        Ok(item) => item,
+        Err(e) => return Outcome::CreateUserFailure(e.to_string()),
+    };
+
+    match client.put_item().table_name(USERS_TABLE).set_item(Some(item))
+    .condition_expression(format!("attribute_not_exists({EMAIL_DB_KEY})")).send().await {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => match e.into_service_error() {
+            PutItemError::ConditionalCheckFailedException(_) => Outcome::EmailAlreadyInUse,
+            error => Outcome::CreateUserFailure(error.to_string()),
+        },
+    }
+}
+
 pub fn permission_if_good_standing(user: &UserInfo) -> Outcome {
     match user.standing {
         Standing::WUser => return Outcome::PermissionGranted("User is not suspended".to_string()),
@@ -152,7 +221,7 @@ pub fn permission_if_in_colab_decks(user: &UserInfo, deck: DeckId) -> Outcome {
     } else {Outcome::UserDoesNotHavePermission}
 }
 
-pub async fn validate_active_decks_and_user_standing(client: &Client, email: &str, deck_id: &str) -> Outcome {
+pub async fn validate_active_decks_and_user_standing(storage: &impl Storage, email: &str, deck_id: &str) -> Outcome {
     let deck_id = match DeckId::from_str(deck_id) {
         Ok(id) => id,
         Err(_) => return Outcome::IncorrectType,
@@ -160,7 +229,7 @@ pub async fn validate_active_decks_and_user_standing(client: &Client, email: &st
 
     let attributes_to_get = [STANDING_DB_KEY, ACTIVE_DECKS_DB_KEY];
 
-    let user = match get_user(client, email, Some(&attributes_to_get.join(","))).await {
+    let user = match storage.get_user(email, Some(&attributes_to_get.join(","))).await {
         Outcome::UserFound(user) => user,
         any_other_outcome => return any_other_outcome,
     };
@@ -173,11 +242,11 @@ pub async fn validate_active_decks_and_user_standing(client: &Client, email: &st
     permission_if_in_active_decks(&user, deck_id)
 }
 
-pub async fn validate_if_in_any_decks_and_if_good_standing(client: &Client, email: &str, deck_id: &str) -> Outcome {
+pub async fn validate_if_in_any_decks_and_if_good_standing(storage: &impl Storage, email: &str, deck_id: &str) -> Outcome {
     let Ok(deck_id) = DeckId::from_str(deck_id) else {return Outcome::IncorrectType};
     let attributes_to_get = [STANDING_DB_KEY, ACTIVE_DECKS_DB_KEY, OWNED_DECKS_DB_KEY, COLAB_DECKS_DB_KEY];
 
-    let user = match get_user(client, email, Some(&attributes_to_get.join(","))).await {
+    let user = match storage.get_user(email, Some(&attributes_to_get.join(","))).await {
         Outcome::UserFound(user) => user,
         any_other_outcome => return any_other_outcome,
     };
@@ -200,10 +269,54 @@ pub async fn validate_if_in_any_decks_and_if_good_standing(client: &Client, emai
     permission_if_in_owned_decks(&user, deck_id)
 }
 
-pub async fn validate_user_type_user_standing_upload_tokens_and_deck_limits(client: &Client, email: &str, estimated_token_cost: f64) -> Outcome {
+pub async fn validate_owned_decks_and_user_standing(storage: &impl Storage, email: &str, deck_id: DeckId) -> Outcome {
+    let attributes_to_get = [STANDING_DB_KEY, OWNED_DECKS_DB_KEY];
+
+    let user = match storage.get_user(email, Some(&attributes_to_get.join(","))).await {
+        Outcome::UserFound(user) => user,
+        any_other_outcome => return any_other_outcome,
+    };
+
+    match permission_if_good_standing(&user) {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return any_other_outcome,
+    };
+
+    permission_if_in_owned_decks(&user, deck_id)
+}
+
+/// Owners have every role, collaborators need an accepted invite with at least the minimum role.
+pub async fn validate_deck_role_and_user_standing(storage: &impl Storage, email: &str, deck_id: DeckId, minimum_role: CollaboratorRole) -> Outcome {
+    let attributes_to_get = [STANDING_DB_KEY, OWNED_DECKS_DB_KEY];
+
+    let user = match storage.get_user(email, Some(&attributes_to_get.join(","))).await {
+        Outcome::UserFound(user) => user,
+        any_other_outcome => return any_other_outcome,
+    };
+
+    match permission_if_good_standing(&user) {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return any_other_outcome,
+    };
+
+    match permission_if_in_owned_decks(&user, deck_id) {
+        Outcome::PermissionGranted(message) => return Outcome::PermissionGranted(message),
+        _any_other_outcome => proceed(),
+    }
+
+    match storage.get_collaborator(deck_id, email).await {
+        Outcome::CollaboratorFound(collaborator) if collaborator.status == InviteStatus::Accepted && collaborator.role >= minimum_role => {
+            Outcome::PermissionGranted(format!("User is a deck {}", collaborator.role))
+        },
+        Outcome::CollaboratorFound(_) | Outcome::ItemsNotFound => Outcome::UserDoesNotHavePermission,
+        any_other_outcome => any_other_outcome,
+    }
+}
+
+pub async fn validate_user_type_user_standing_upload_tokens_and_deck_limits(storage: &impl Storage, email: &str, estimated_token_cost: f64) -> Outcome {
     let attributes_to_get = [STANDING_DB_KEY, UPLOAD_TOKENS_DB_KEY, USER_TYPE_DB_KEY, ACTIVE_DECKS_DB_KEY, OWNED_DECKS_DB_KEY];
 
-    let user = match get_user(client, email, Some(&attributes_to_get.join(","))).await {
+    let user = match storage.get_user(email, Some(&attributes_to_get.join(","))).await {
         Outcome::UserFound(user) => user,
         any_other_outcome => return any_other_outcome,
     };
@@ -231,8 +344,8 @@ pub async fn validate_user_type_user_standing_upload_tokens_and_deck_limits(clie
     return permission_if_enough_tokens(&user, estimated_token_cost);
 }
 
-pub async fn validate_user_standing(client: &Client, email: &str) -> Outcome {
-    let user = match get_user(client, email, Some(STANDING_DB_KEY)).await {
+pub async fn validate_user_standing(storage: &impl Storage, email: &str) -> Outcome {
+    let user = match storage.get_user(email, Some(STANDING_DB_KEY)).await {
         Outcome::UserFound(user) => user,
         any_other_outcome => return any_other_outcome,
     };
@@ -240,15 +353,15 @@ pub async fn validate_user_standing(client: &Client, email: &str) -> Outcome {
     permission_if_good_standing(&user)
 }
 
-pub async fn validate_user_existence(client: &Client, email: &str) -> Outcome {
-    let outcome = get_user(client, email, Some(STANDING_DB_KEY)).await;
+pub async fn validate_user_existence(storage: &impl Storage, email: &str) -> Outcome {
+    let outcome = storage.get_user(email, Some(STANDING_DB_KEY)).await;
     return outcome;
 }
 
-pub async fn validate_user_and_return_rank(client: &Client, email: &str) -> Outcome {
+pub async fn validate_user_and_return_rank(storage: &impl Storage, email: &str) -> Outcome {
     let attributes_to_get = [STANDING_DB_KEY, RANK_DB_KEY];
 
-    let user = match get_user(client, email, Some(&attributes_to_get.join(","))).await {
+    let user = match storage.get_user(email, Some(&attributes_to_get.join(","))).await {
         Outcome::UserFound(user) => user,
         any_other_outcome => {println!("{}", any_other_outcome.to_string()); return any_other_outcome},
     };
@@ -262,22 +375,1248 @@ pub async fn validate_user_and_return_rank(client: &Client, email: &str) -> Outc
 // email (String)   active_decks   colab_decks   last_login   name   owned_decks   pfp   phone   rank   settings   sign_up_date   standing   upload_tokens   user_type
 
 pub async fn add_deck_to_user_active_decks_and_owned_decks(client: Client, email: &str, deck_id: &str) -> Outcome {
-    let email = AttributeValue::S(email.to_string());
     let Ok(id) = DeckId::from_str(deck_id) else {return Outcome::DeckCouldNotBeProcessed("Could not parse deck id".to_owned())};
-    let deck = AttributeValue::Ss(vec![id.to_string()]);
-    match client.update_item().table_name(USERS_TABLE).key(EMAIL_DB_KEY, email)
-    .update_expression("ADD #ActiveDecks :deck, ADD #OwnedDecks :deck")
+    let deck = AttributeValue::L(vec![AttributeValue::S(id.to_string())]);
+    match client.update_item().table_name(USERS_TABLE).key(EMAIL_DB_KEY, AttributeValue::S(email.to_string()))
+    .update_expression("SET #ActiveDecks = list_append(if_not_exists(#ActiveDecks, :empty), :deck), #OwnedDecks = list_append(if_not_exists(#OwnedDecks, :empty), :deck)")
     .expression_attribute_names("#OwnedDecks", OWNED_DECKS_DB_KEY)
     .expression_attribute_names("#ActiveDecks", ACTIVE_DECKS_DB_KEY)
-    .expression_attribute_values("deck", deck).send().await {
+    .expression_attribute_values(":deck", deck)
+    .expression_attribute_values(":empty", AttributeValue::L(Vec::new())).send().await {
         Ok(_) => (),
         Err(e) => return Outcome::DeckCouldNotBeProcessed(e.into_service_error().to_string())
     };
 
-    Outcome::UnresolvedOutcome
+    let mut cache_recipes = UpdateRecipes::default();
+    for update_key in [UserInfo::ACTIVE_DECKS_CACHE_KEY, UserInfo::OWNED_DECKS_CACHE_KEY] {
+        cache_recipes.recipes.push(UpdateRecipe {
+            update_type: UpdateType::Add,
+            update_key: update_key.to_string(),
+            update_item: DBItem::User(email.to_string()),
+            value: UpdateValues::DeckList(DeckList {decks: vec![id]}),
+        });
+    }
+
+    Outcome::DatabaseUpdateSuccess(cache_recipes)
+}
+
+pub fn note_to_database_item(note: &Note) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::with_capacity(note.fields.len() + 7);
+    item.insert(DECK_ID_DB_KEY.to_string(), AttributeValue::S(note.deck_id.to_string()));
+    item.insert(NOTE_ID_DB_KEY.to_string(), AttributeValue::N(note.note_id.to_string()));
+    item.insert(NOTE_TYPE_DB_KEY.to_string(), AttributeValue::S(note.note_type.to_string()));
+    item.insert(VERSION_DB_KEY.to_string(), AttributeValue::N(note.version.to_string()));
+    item.insert(REVIEWS_PER_STAGE_DB_KEY.to_string(), AttributeValue::N(note.reviews_per_stage.to_string()));
+    item.insert(LEVEL_DB_KEY.to_string(), AttributeValue::N(note.level.to_string()));
+    if let Some(meta) = &note.meta {
+        item.insert(DECK_META_DB_KEY.to_string(), AttributeValue::S(meta.to_string()));
+    }
+    for field in note.fields.iter() {
+        item.insert(field.name.clone(), AttributeValue::S(field.to_database_string()));
+    }
+    item
+}
+
+pub async fn deck_exists(client: &Client, deck_id: DeckId) -> Outcome {
+    match get_note(client, deck_id, 0).await {
+        Outcome::NoteFound(_) => Outcome::PermissionGranted(deck_id.to_string()),
+        any_other_outcome => any_other_outcome,
+    }
+}
+
+/// Puts an empty meta note for a deck that is not stored yet so only one upload can ever write the deck, returning
+/// InvalidRequest if the deck exists or another upload claimed it first. The real meta note replaces it once the deck is written.
+pub async fn claim_deck_id(client: &Client, deck_id: DeckId) -> Outcome {
+    let claim = Note {deck_id, note_id: 0, ..Default::default()};
+
+    let put_item_result = client.put_item()
+    .table_name(PUBLIC_DECKS_TABLE)
+    .set_item(Some(note_to_database_item(&claim)))
+    .condition_expression("attribute_not_exists(#NoteId)")
+    .expression_attribute_names("#NoteId", NOTE_ID_DB_KEY)
+    .send().await;
+
+    match put_item_result {
+        Ok(_) => Outcome::PermissionGranted(deck_id.to_string()),
+        Err(e) => match e.into_service_error() {
+            PutItemError::ConditionalCheckFailedException(_) => Outcome::InvalidRequest,
+            any_other_error => Outcome::UnspecifiedQueryFailure(any_other_error.to_string()),
+        },
+    }
+}
+
+pub async fn put_notes(client: &Client, notes: &Vec<Note>) -> Outcome {
+    let mut write_requests = Vec::with_capacity(notes.len());
+    for note in notes {
+        let Ok(put_request) = PutRequest::builder().set_item(Some(note_to_database_item(note))).build() else {return Outcome::IncorrectType};
+        write_requests.push(WriteRequest::builder().put_request(put_request).build());
+    }
+
+    write_note_batches(client, write_requests).await
+}
+
+/// Removes the notes from the deck, used to clean up a deck that could not be finished.
+pub async fn delete_notes(client: &Client, deck_id: DeckId, note_ids: &[u64]) -> Outcome {
+    let mut write_requests = Vec::with_capacity(note_ids.len());
+    for note_id in note_ids {
+        let Ok(delete_request) = DeleteRequest::builder()
+        .key(DECK_ID_DB_KEY, AttributeValue::S(deck_id.to_string()))
+        .key(NOTE_ID_DB_KEY, AttributeValue::N(note_id.to_string()))
+        .build() else {return Outcome::IncorrectType};
+        write_requests.push(WriteRequest::builder().delete_request(delete_request).build());
+    }
+
+    write_note_batches(client, write_requests).await
+}
+
+async fn write_note_batches(client: &Client, write_requests: Vec<WriteRequest>) -> Outcome {
+    for write_batch in write_requests.chunks(BATCH_WRITE_LIMIT) {
+        let mut write_requests = write_batch.to_vec();
+
+        let mut attempts = 0;
+        while !!!write_requests.is_empty() {
+            if attempts >= BATCH_WRITE_ATTEMPTS {
+                return Outcome::NoteUpdateFailed(format!("{} notes could not be written", write_requests.len()));
+            }
+            attempts += 1;
+
+            let output = match client.batch_write_item().request_items(PUBLIC_DECKS_TABLE, write_requests).send().await {
+                Ok(output) => output,
+                Err(e) => return Outcome::NoteUpdateFailed(e.into_service_error().to_string()),
+            };
+
+            // Throttled writes come back unprocessed and are retried with a growing delay
+            write_requests = output.unprocessed_items.and_then(|mut unprocessed| unprocessed.remove(PUBLIC_DECKS_TABLE)).unwrap_or_default();
+            if !!!write_requests.is_empty() {
+                tokio::time::sleep(Duration::from_millis(100 * 2_u64.pow(attempts))).await;
+            }
+        }
+    }
+
+    Outcome::NoteUpdateSuccess
+}
+
+pub async fn get_note(client: &Client, deck_id: DeckId, note_id: u64) -> Outcome {
+    let get_item_result = client.get_item()
+    .table_name(PUBLIC_DECKS_TABLE)
+    .key(DECK_ID_DB_KEY, AttributeValue::S(deck_id.to_string()))
+    .key(NOTE_ID_DB_KEY, AttributeValue::N(note_id.to_string()))
+    .send().await;
+
+    let item = match get_item_result {
+        Ok(output) => match output.item {
+            Some(item) => item,
+            None => return Outcome::ItemsNotFound,
+        },
+        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+    };
+
+    Outcome::NoteFound(construct_note_from_database_item(&item))
+}
+
+pub async fn get_deck_notes(client: &Client, deck_id: DeckId) -> Outcome {
+    let mut notes = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+        let output = match client.query()
+        .table_name(PUBLIC_DECKS_TABLE)
+        .key_condition_expression("#DeckID = :pk")
+        .expression_attribute_names("#DeckID", DECK_ID_DB_KEY)
+        .expression_attribute_values(":pk", AttributeValue::S(deck_id.to_string()))
+        .set_exclusive_start_key(exclusive_start_key)
+        .send().await {
+            Ok(output) => output,
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+        };
+
+        for item in output.items() {
+            notes.push(construct_note_from_database_item(item));
+        }
+
+        match output.last_evaluated_key {
+            Some(last_key) => exclusive_start_key = Some(last_key),
+            None => break,
+        }
+    }
+
+    if notes.is_empty() {
+        return Outcome::ItemsNotFound;
+    }
+
+    Outcome::DeckNotesFound(notes)
+}
+
+/// A change to a single note, edits and deletes carry the copy they were made against.
+pub enum NoteWrite {
+    Add(Note),
+    Edit {current: Note, edited: Note},
+    Delete(Note),
+}
+
+/// Writes the note and the deck meta counts together. The note must still be at the version the change was made
+/// against, otherwise the server copy is returned in a NoteConflict. The meta only has to be unchanged since it was
+/// read here, so a concurrent write to another note just means reading the meta again.
+pub async fn write_note_and_deck_meta(client: &Client, deck_id: DeckId, note_write: NoteWrite) -> Outcome {
+    for attempt in 0..NOTE_WRITE_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_millis(50 * 2_u64.pow(attempt))).await;
+        }
+
+        let stored_meta = match client.get_item()
+        .table_name(PUBLIC_DECKS_TABLE)
+        .key(DECK_ID_DB_KEY, AttributeValue::S(deck_id.to_string()))
+        .key(NOTE_ID_DB_KEY, AttributeValue::N("0".to_string()))
+        .projection_expression("#Meta")
+        .expression_attribute_names("#Meta", DECK_META_DB_KEY)
+        .consistent_read(true)
+        .send().await {
+            Ok(output) => match output.item.and_then(|mut item| item.remove(DECK_META_DB_KEY)) {
+                Some(stored_meta) => stored_meta,
+                None => return Outcome::ItemsNotFound,
+            },
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+        };
+        let Ok(stored_meta_str) = stored_meta.as_s() else {return Outcome::IncorrectType};
+        let Ok(mut meta) = DeckMeta::from_str(stored_meta_str) else {return Outcome::IncorrectType};
+
+        let (note_item, note_recipe) = match &note_write {
+            NoteWrite::Add(note) => {
+                let mut note = note.clone();
+                note.note_id = meta.next_note_id();
+                note.level = meta.level_for_new_note(note.note_id);
+                meta.count_note(&note);
+
+                let Ok(put) = Put::builder()
+                .table_name(PUBLIC_DECKS_TABLE)
+                .set_item(Some(note_to_database_item(&note)))
+                .condition_expression("attribute_not_exists(#NoteId)")
+                .expression_attribute_names("#NoteId", NOTE_ID_DB_KEY)
+                .build() else {return Outcome::IncorrectType};
+
+                (TransactWriteItem::builder().put(put).build(), note_update_recipe(UpdateType::Swap, note))
+            },
+            NoteWrite::Edit {current, edited} => {
+                meta.uncount_note(current);
+                meta.count_note(edited);
+
+                let Ok(put) = Put::builder()
+                .table_name(PUBLIC_DECKS_TABLE)
+                .set_item(Some(note_to_database_item(edited)))
+                .condition_expression("#Version = :version")
+                .expression_attribute_names("#Version", VERSION_DB_KEY)
+                .expression_attribute_values(":version", AttributeValue::N(current.version.to_string()))
+                .build() else {return Outcome::IncorrectType};
+
+                (TransactWriteItem::builder().put(put).build(), note_update_recipe(UpdateType::Swap, edited.clone()))
+            },
+            NoteWrite::Delete(current) => {
+                meta.uncount_note(current);
+
+                let Ok(delete) = Delete::builder()
+                .table_name(PUBLIC_DECKS_TABLE)
+                .key(DECK_ID_DB_KEY, AttributeValue::S(deck_id.to_string()))
+                .key(NOTE_ID_DB_KEY, AttributeValue::N(current.note_id.to_string()))
+                .condition_expression("#Version = :version")
+                .expression_attribute_names("#Version", VERSION_DB_KEY)
+                .expression_attribute_values(":version", AttributeValue::N(current.version.to_string()))
+                .build() else {return Outcome::IncorrectType};
+
+                (TransactWriteItem::builder().delete(delete).build(), note_update_recipe(UpdateType::Subtract, current.clone()))
+            },
+        };
+
+        meta.bump_version();
+
+        let Ok(meta_update) = Update::builder()
+        .table_name(PUBLIC_DECKS_TABLE)
+        .key(DECK_ID_DB_KEY, AttributeValue::S(deck_id.to_string()))
+        .key(NOTE_ID_DB_KEY, AttributeValue::N("0".to_string()))
+        .update_expression("SET #Meta = :meta")
+        .condition_expression("#Meta = :stored_meta")
+        .expression_attribute_names("#Meta", DECK_META_DB_KEY)
+        .expression_attribute_values(":meta", AttributeValue::S(meta.to_string()))
+        .expression_attribute_values(":stored_meta", stored_meta.clone())
+        .build() else {return Outcome::IncorrectType};
+
+        let transaction_result = client.transact_write_items()
+        .transact_items(note_item)
+        .transact_items(TransactWriteItem::builder().update(meta_update).build())
+        .send().await;
+
+        match transaction_result {
+            Ok(_) => return Outcome::DatabaseUpdateSuccess(UpdateRecipes {
+                recipes: vec![note_recipe, deck_meta_update_recipe(deck_id, meta)],
+            }),
+            Err(e) => match e.into_service_error() {
+                TransactWriteItemsError::TransactionCanceledException(exception) => {
+                    let reasons = exception.cancellation_reasons();
+                    let failed_check = |i: usize| reasons.get(i).and_then(|reason| reason.code()) == Some("ConditionalCheckFailed");
+                    match (&note_write, failed_check(0)) {
+                        (NoteWrite::Edit {current, ..} | NoteWrite::Delete(current), true) => {
+                            return match get_note(client, deck_id, current.note_id).await {
+                                Outcome::NoteFound(server_note) => Outcome::NoteConflict(server_note),
+                                any_other_outcome => any_other_outcome,
+                            };
+                        },
+                        _ if failed_check(1) || failed_check(0) => continue,
+                        _ => return Outcome::NoteUpdateFailed(exception.to_string()),
+                    }
+                },
+                any_other_error => return Outcome::NoteUpdateFailed(any_other_error.to_string()),
+            },
+        }
+    }
+
+    Outcome::NoteUpdateFailed(format!("the deck kept changing after {NOTE_WRITE_ATTEMPTS} attempts"))
+}
+
+pub fn note_update_recipe(update_type: UpdateType, note: Note) -> UpdateRecipe {
+    UpdateRecipe {
+        update_type,
+        update_key: Note::FULL_NOTE_CACHE_KEY.to_string(),
+        update_item: DBItem::Note(note.deck_id, note.note_id),
+        value: UpdateValues::Note(note),
+    }
+}
+
+pub fn changes_deck_meta(update_recipes: &[UpdateRecipe]) -> bool {
+    update_recipes.iter().any(|recipe| matches!(recipe.value, UpdateValues::DeckMeta(_)))
+}
+
+pub fn deck_meta_update_recipe(deck_id: DeckId, meta: DeckMeta) -> UpdateRecipe {
+    UpdateRecipe {
+        update_type: UpdateType::Swap,
+        update_key: DECK_META_DB_KEY.to_string(),
+        update_item: DBItem::Note(deck_id, 0),
+        value: UpdateValues::DeckMeta(meta),
+    }
+}
+
+/// Reads the deck meta, applies the change and writes it back with its version bumped. The write only goes through if
+/// the meta is unchanged since it was read, otherwise the change is applied again to the meta as it is now. A change
+/// that returns an outcome stops without writing anything.
+pub async fn change_deck_meta(storage: &impl Storage, deck_id: DeckId, mut change: impl FnMut(&mut DeckMeta) -> Result<(), Outcome>) -> Result<DeckMeta, Outcome> {
+    let mut stored_meta = match storage.get_note(deck_id, 0).await {
+        Outcome::NoteFound(meta_note) => meta_note.meta.ok_or(Outcome::ItemsNotFound)?,
+        any_other_outcome => return Err(any_other_outcome),
+    };
+
+    for attempt in 0..NOTE_WRITE_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_millis(50 * 2_u64.pow(attempt))).await;
+        }
+
+        let mut meta = stored_meta.clone();
+        change(&mut meta)?;
+        // The version always follows on from the stored one whatever the change did to it
+        meta.version = stored_meta.version;
+        meta.bump_version();
+
+        match storage.swap_deck_meta(deck_id, &stored_meta, &meta).await {
+            Outcome::DatabaseUpdateSuccess(_) => return Ok(meta),
+            Outcome::NoteConflict(meta_note) => stored_meta = meta_note.meta.ok_or(Outcome::ItemsNotFound)?,
+            any_other_outcome => return Err(any_other_outcome),
+        }
+    }
+
+    Err(Outcome::NoteUpdateFailed(format!("the deck kept changing after {NOTE_WRITE_ATTEMPTS} attempts")))
+}
+
+/// Writes the meta as long as the stored one still reads as the meta it was changed from, otherwise returns the meta
+/// note as it is now in a NoteConflict. The condition is on the stored attribute itself so a meta written in an older
+/// format can still be swapped.
+pub async fn swap_deck_meta(client: &Client, deck_id: DeckId, stored_meta: &DeckMeta, meta: &DeckMeta) -> Outcome {
+    let stored_attribute = match client.get_item()
+    .table_name(PUBLIC_DECKS_TABLE)
+    .key(DECK_ID_DB_KEY, AttributeValue::S(deck_id.to_string()))
+    .key(NOTE_ID_DB_KEY, AttributeValue::N("0".to_string()))
+    .projection_expression("#Meta")
+    .expression_attribute_names("#Meta", DECK_META_DB_KEY)
+    .consistent_read(true)
+    .send().await {
+        Ok(output) => match output.item.and_then(|mut item| item.remove(DECK_META_DB_KEY)) {
+            Some(stored_attribute) => stored_attribute,
+            None => return Outcome::ItemsNotFound,
+        },
+        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+    };
+    let Ok(stored_meta_str) = stored_attribute.as_s() else {return Outcome::IncorrectType};
+    let Ok(current_meta) = DeckMeta::from_str(stored_meta_str) else {return Outcome::IncorrectType};
+
+    let meta_note = |meta: DeckMeta| Note {deck_id, note_id: 0, meta: Some(meta), ..Default::default()};
+    if &current_meta != stored_meta {
+        return Outcome::NoteConflict(meta_note(current_meta));
+    }
+
+    let update_item_result = client.update_item()
+    .table_name(PUBLIC_DECKS_TABLE)
+    .key(DECK_ID_DB_KEY, AttributeValue::S(deck_id.to_string()))
+    .key(NOTE_ID_DB_KEY, AttributeValue::N("0".to_string()))
+    .update_expression("SET #Meta = :meta")
+    .condition_expression("#Meta = :stored_meta")
+    .expression_attribute_names("#Meta", DECK_META_DB_KEY)
+    .expression_attribute_values(":meta", AttributeValue::S(meta.to_string()))
+    .expression_attribute_values(":stored_meta", stored_attribute)
+    .send().await;
+
+    match update_item_result {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes {recipes: vec![deck_meta_update_recipe(deck_id, meta.clone())]}),
+        Err(e) => match e.into_service_error() {
+            // The next swap reads the meta again and returns it
+            UpdateItemError::ConditionalCheckFailedException(_) => Outcome::NoteConflict(meta_note(current_meta)),
+            any_other_error => Outcome::UnspecifiedQueryFailure(any_other_error.to_string()),
+        },
+    }
+}
+
+pub async fn get_review_state(client: &Client, email: &str, deck_id: DeckId, note_id: u64, card_ord: u8) -> Outcome {
+    let get_item_result = client.get_item()
+    .table_name(REVIEWS_TABLE)
+    .key(REVIEW_USER_DB_KEY, AttributeValue::S(email.to_string()))
+    .key(REVIEW_CARD_DB_KEY, AttributeValue::S(ReviewState::card_key(deck_id, note_id, card_ord)))
+    .send().await;
+
+    let item = match get_item_result {
+        Ok(output) => match output.item {
+            Some(item) => item,
+            None => return Outcome::ItemsNotFound,
+        },
+        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+    };
+
+    match from_item(item) {
+        Ok(review_state) => Outcome::ReviewStatesFound(vec![review_state]),
+        Err(_) => Outcome::IncorrectType,
+    }
+}
+
+pub async fn put_review_state(client: &Client, review_state: &ReviewState) -> Outcome {
+    let item = match to_item(review_state) {
+        Ok(item) => item,
+        Err(_) => return Outcome::IncorrectType,
+    };
+
+    match client.put_item().table_name(REVIEWS_TABLE).set_item(Some(item)).send().await {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => Outcome::ReviewStateUpdateFailed(e.into_service_error().to_string()),
+    }
+}
+
+pub async fn get_review_states(client: &Client, email: &str, deck_id: DeckId) -> Outcome {
+    let mut review_states: Vec<ReviewState> = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+        let output = match client.query()
+        .table_name(REVIEWS_TABLE)
+        .key_condition_expression("#User = :user AND begins_with(#Card, :deck)")
+        .expression_attribute_names("#User", REVIEW_USER_DB_KEY)
+        .expression_attribute_names("#Card", REVIEW_CARD_DB_KEY)
+        .expression_attribute_values(":user", AttributeValue::S(email.to_string()))
+        .expression_attribute_values(":deck", AttributeValue::S(ReviewState::deck_key_prefix(deck_id)))
+        .set_exclusive_start_key(exclusive_start_key)
+        .send().await {
+            Ok(output) => output,
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+        };
+
+        for item in output.items() {
+            match from_item(item.clone()) {
+                Ok(review_state) => review_states.push(review_state),
+                Err(_) => return Outcome::IncorrectType,
+            }
+        }
+
+        match output.last_evaluated_key {
+            Some(last_key) => exclusive_start_key = Some(last_key),
+            None => break,
+        }
+    }
+
+    Outcome::ReviewStatesFound(review_states)
+}
+
+pub async fn record_upload_token_transaction(client: &Client, ledger_entry: &LedgerEntry) -> Outcome {
+    let Ok(ledger_item) = to_item(ledger_entry) else {return Outcome::IncorrectType};
+    let amount = AttributeValue::N(ledger_entry.amount.to_string());
+
+    let (update_expression, condition_expression, update_type) = match ledger_entry.transaction_type {
+        TransactionType::Charge => ("SET #Tokens = #Tokens - :amount", Some("#Tokens >= :amount".to_string()), UpdateType::Subtract),
+        TransactionType::Refund => ("SET #Tokens = #Tokens + :amount", None, UpdateType::Add),
+    };
+
+    let Ok(balance_update) = Update::builder()
+    .table_name(USERS_TABLE)
+    .key(EMAIL_DB_KEY, AttributeValue::S(ledger_entry.user.clone()))
+    .update_expression(update_expression)
+    .set_condition_expression(condition_expression)
+    .expression_attribute_names("#Tokens", UPLOAD_TOKENS_DB_KEY)
+    .expression_attribute_values(":amount", amount)
+    .build() else {return Outcome::IncorrectType};
+
+    let Ok(ledger_put) = Put::builder()
+    .table_name(UPLOAD_LEDGER_TABLE)
+    .set_item(Some(ledger_item))
+    .condition_expression("attribute_not_exists(#TransactionId)")
+    .expression_attribute_names("#TransactionId", LEDGER_TRANSACTION_ID_DB_KEY)
+    .build() else {return Outcome::IncorrectType};
+
+    // The balance change and its ledger entry either both happen or neither does
+    let transaction_result = client.transact_write_items()
+    .transact_items(TransactWriteItem::builder().update(balance_update).build())
+    .transact_items(TransactWriteItem::builder().put(ledger_put).build())
+    .send().await;
+
+    match transaction_result {
+        Ok(_) => proceed(),
+        Err(e) => match e.into_service_error() {
+            // A retried transaction whose first attempt went through finds its own ledger entry, so it is already done
+            TransactWriteItemsError::TransactionCanceledException(exception) if exception.cancellation_reasons().get(1).and_then(|reason| reason.code()) == Some("ConditionalCheckFailed") => proceed(),
+            TransactWriteItemsError::TransactionCanceledException(exception) => {
+                let balance_check_failed = exception.cancellation_reasons().first().and_then(|reason| reason.code()) == Some("ConditionalCheckFailed");
+                if balance_check_failed && ledger_entry.transaction_type == TransactionType::Charge {
+                    return match get_user(client, &ledger_entry.user, Some(UPLOAD_TOKENS_DB_KEY)).await {
+                        Outcome::UserFound(user) => Outcome::NotEnoughUploadTokens(user.upload_tokens),
+                        any_other_outcome => any_other_outcome,
+                    };
+                }
+                return Outcome::UpdateUserFailure(exception.to_string());
+            },
+            any_other_error => return Outcome::UpdateUserFailure(any_other_error.to_string()),
+        },
+    }
+
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes {
+        recipes: vec![UpdateRecipe {
+            update_type,
+            update_key: UserInfo::UPLOAD_TOKENS_CACHE_KEY.to_string(),
+            update_item: DBItem::User(ledger_entry.user.clone()),
+            value: UpdateValues::Float64(ledger_entry.amount),
+        }],
+    })
+}
+
+pub async fn get_ledger_entries(client: &Client, email: &str) -> Outcome {
+    let mut ledger_entries: Vec<LedgerEntry> = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+        let output = match client.query()
+        .table_name(UPLOAD_LEDGER_TABLE)
+        .key_condition_expression("#User = :user")
+        .expression_attribute_names("#User", LEDGER_USER_DB_KEY)
+        .expression_attribute_values(":user", AttributeValue::S(email.to_string()))
+        .set_exclusive_start_key(exclusive_start_key)
+        .send().await {
+            Ok(output) => output,
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+        };
+
+        for item in output.items() {
+            match from_item(item.clone()) {
+                Ok(ledger_entry) => ledger_entries.push(ledger_entry),
+                Err(_) => return Outcome::IncorrectType,
+            }
+        }
+
+        match output.last_evaluated_key {
+            Some(last_key) => exclusive_start_key = Some(last_key),
+            None => break,
+        }
+    }
+
+    // Transaction ids are named after the deck, so the newest entries are found by when they were made
+    ledger_entries.sort_by_key(|ledger_entry| Reverse(ledger_entry.created));
+
+    Outcome::LedgerEntriesFound(ledger_entries)
+}
+
+/// Writes the entry while keeping the subscriber count the deck already has.
+pub async fn put_catalog_entry(client: &Client, entry: &CatalogEntry) -> Outcome {
+    let Ok(deck_id) = to_attribute_value(entry.deck_id) else {return Outcome::IncorrectType};
+    let Ok(mut item) = to_item(entry) else {return Outcome::IncorrectType};
+    item.remove(CATALOG_DECK_ID_DB_KEY);
+    item.remove(CATALOG_SUBSCRIBERS_DB_KEY);
+
+    let mut update_request = client.update_item().table_name(CATALOG_TABLE).key(CATALOG_DECK_ID_DB_KEY, deck_id);
+    let mut set_expressions = Vec::with_capacity(item.len() + 1);
+    for (i, (key, value)) in item.into_iter().enumerate() {
+        set_expressions.push(format!("#Attribute{i} = :value{i}"));
+        update_request = update_request.expression_attribute_names(format!("#Attribute{i}"), key).expression_attribute_values(format!(":value{i}"), value);
+    }
+    set_expressions.push("#Subscribers = if_not_exists(#Subscribers, :zero)".to_string());
+
+    match update_request
+    .update_expression(format!("SET {}", set_expressions.join(", ")))
+    .expression_attribute_names("#Subscribers", CATALOG_SUBSCRIBERS_DB_KEY)
+    .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+    .send().await {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => Outcome::UpdateUserFailure(e.into_service_error().to_string()),
+    }
+}
+
+pub async fn remove_catalog_entry(client: &Client, deck_id: DeckId) -> Outcome {
+    let Ok(deck_id) = to_attribute_value(deck_id) else {return Outcome::IncorrectType};
+
+    match client.delete_item().table_name(CATALOG_TABLE).key(CATALOG_DECK_ID_DB_KEY, deck_id).send().await {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => Outcome::UpdateUserFailure(e.into_service_error().to_string()),
+    }
+}
+
+pub async fn get_catalog_entry(client: &Client, deck_id: DeckId) -> Outcome {
+    let Ok(deck_id) = to_attribute_value(deck_id) else {return Outcome::IncorrectType};
+
+    let item = match client.get_item().table_name(CATALOG_TABLE).key(CATALOG_DECK_ID_DB_KEY, deck_id).send().await {
+        Ok(output) => match output.item {
+            Some(item) => item,
+            None => return Outcome::ItemsNotFound,
+        },
+        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+    };
+
+    match from_item(item) {
+        Ok(entry) => Outcome::CatalogEntryFound(entry),
+        Err(_) => Outcome::IncorrectType,
+    }
+}
+
+/// Reads every matching deck from the popularity index, most subscribed first.
+pub async fn query_catalog(client: &Client, query: &CatalogQuery, limit: usize) -> Outcome {
+    let mut query_request = client.query()
+    .table_name(CATALOG_TABLE)
+    .index_name(format!("{CATALOG_SHELF_DB_KEY}-index"))
+    .key_condition_expression("#Shelf = :shelf")
+    .expression_attribute_names("#Shelf", CATALOG_SHELF_DB_KEY)
+    .expression_attribute_values(":shelf", AttributeValue::S(PUBLIC_SHELF.to_string()))
+    .scan_index_forward(false);
+
+    let mut filter_expressions = Vec::new();
+    if !!!query.name.is_empty() {
+        filter_expressions.push("contains(#SearchName, :name)");
+        query_request = query_request.expression_attribute_names("#SearchName", CATALOG_SEARCH_NAME_DB_KEY).expression_attribute_values(":name", AttributeValue::S(query.name.clone()));
+    }
+    if !!!query.language.is_empty() {
+        filter_expressions.push("#Language = :language");
+        query_request = query_request.expression_attribute_names("#Language", CATALOG_LANGUAGE_DB_KEY).expression_attribute_values(":language", AttributeValue::S(query.language.clone()));
+    }
+    if !!!query.tag.is_empty() {
+        filter_expressions.push("contains(#Tags, :tag)");
+        query_request = query_request.expression_attribute_names("#Tags", CATALOG_TAGS_DB_KEY).expression_attribute_values(":tag", AttributeValue::S(query.tag.clone()));
+    }
+    if !!!filter_expressions.is_empty() {
+        query_request = query_request.filter_expression(filter_expressions.join(" AND "));
+    }
+
+    let mut entries: Vec<CatalogEntry> = Vec::new();
+    let mut exclusive_start_key = match &query.cursor {
+        Some(cursor) => match to_item(cursor) {
+            Ok(mut start_key) => {
+                start_key.insert(CATALOG_SHELF_DB_KEY.to_string(), AttributeValue::S(PUBLIC_SHELF.to_string()));
+                Some(start_key)
+            },
+            Err(_) => return Outcome::IncorrectType,
+        },
+        None => None,
+    };
+
+    // The limit counts decks before the filter drops any, so filling a page can take a few requests
+    while entries.len() < limit {
+        let output = match query_request.clone()
+        .limit((limit - entries.len()) as i32)
+        .set_exclusive_start_key(exclusive_start_key.take())
+        .send().await {
+            Ok(output) => output,
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+        };
+
+        for item in output.items() {
+            match from_item(item.clone()) {
+                Ok(entry) => entries.push(entry),
+                Err(_) => return Outcome::IncorrectType,
+            }
+        }
+
+        match output.last_evaluated_key {
+            Some(last_key) => exclusive_start_key = Some(last_key),
+            None => break,
+        }
+    }
+
+    let next_cursor = match exclusive_start_key.map(from_item) {
+        Some(Ok(cursor)) => Some(cursor),
+        Some(Err(_)) => return Outcome::IncorrectType,
+        None => None,
+    };
+
+    Outcome::CatalogPageFound(CatalogPage {entries, next_cursor})
+}
+
+/// Adds the deck to the user's active decks and counts the new subscriber, both happen or neither does.
+pub async fn subscribe_user_to_deck(client: &Client, email: &str, deck_id: DeckId) -> Outcome {
+    let Ok(catalog_key) = to_attribute_value(deck_id) else {return Outcome::IncorrectType};
+    let deck = AttributeValue::S(deck_id.to_string());
+
+    let Ok(active_decks_update) = Update::builder()
+    .table_name(USERS_TABLE)
+    .key(EMAIL_DB_KEY, AttributeValue::S(email.to_string()))
+    .update_expression("SET #ActiveDecks = list_append(if_not_exists(#ActiveDecks, :empty), :decks)")
+    .condition_expression("attribute_not_exists(#ActiveDecks) OR NOT contains(#ActiveDecks, :deck)")
+    .expression_attribute_names("#ActiveDecks", ACTIVE_DECKS_DB_KEY)
+    .expression_attribute_values(":decks", AttributeValue::L(vec![deck.clone()]))
+    .expression_attribute_values(":deck", deck)
+    .expression_attribute_values(":empty", AttributeValue::L(Vec::new()))
+    .build() else {return Outcome::IncorrectType};
+
+    let Ok(subscribers_update) = Update::builder()
+    .table_name(CATALOG_TABLE)
+    .key(CATALOG_DECK_ID_DB_KEY, catalog_key)
+    .update_expression("ADD #Subscribers :one")
+    .condition_expression("attribute_exists(#DeckId)")
+    .expression_attribute_names("#Subscribers", CATALOG_SUBSCRIBERS_DB_KEY)
+    .expression_attribute_names("#DeckId", CATALOG_DECK_ID_DB_KEY)
+    .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+    .build() else {return Outcome::IncorrectType};
+
+    let transaction_result = client.transact_write_items()
+    .transact_items(TransactWriteItem::builder().update(active_decks_update).build())
+    .transact_items(TransactWriteItem::builder().update(subscribers_update).build())
+    .send().await;
+
+    match transaction_result {
+        Ok(_) => proceed(),
+        Err(e) => match e.into_service_error() {
+            TransactWriteItemsError::TransactionCanceledException(exception) => {
+                let reasons = exception.cancellation_reasons();
+                let failed_check = |i: usize| reasons.get(i).and_then(|reason| reason.code()) == Some("ConditionalCheckFailed");
+                if failed_check(0) {
+                    return Outcome::AlreadySubscribed;
+                }
+                if failed_check(1) {
+                    return Outcome::UserDoesNotHavePermission;
+                }
+                return Outcome::UpdateUserFailure(exception.to_string());
+            },
+            any_other_error => return Outcome::UpdateUserFailure(any_other_error.to_string()),
+        },
+    }
+
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes {
+        recipes: vec![UpdateRecipe {
+            update_type: UpdateType::Add,
+            update_key: UserInfo::ACTIVE_DECKS_CACHE_KEY.to_string(),
+            update_item: DBItem::User(email.to_string()),
+            value: UpdateValues::DeckList(DeckList {decks: vec![deck_id]}),
+        }],
+    })
+}
+
+/// Takes the deck out of the user's active decks and stops counting them as a subscriber.
+pub async fn unsubscribe_user_from_deck(client: &Client, email: &str, deck_id: DeckId) -> Outcome {
+    let cache_recipes = match remove_deck_from_user_deck_list(client, email, deck_id, ACTIVE_DECKS_DB_KEY, UserInfo::ACTIVE_DECKS_CACHE_KEY).await {
+        Outcome::DatabaseUpdateSuccess(cache_recipes) => cache_recipes,
+        any_other_outcome => return any_other_outcome,
+    };
+    if cache_recipes.recipes.is_empty() {
+        return Outcome::DatabaseUpdateSuccess(cache_recipes);
+    }
+
+    // Decks that have left the catalog have no subscriber count to change
+    let Ok(catalog_key) = to_attribute_value(deck_id) else {return Outcome::IncorrectType};
+    let _ = client.update_item()
+    .table_name(CATALOG_TABLE)
+    .key(CATALOG_DECK_ID_DB_KEY, catalog_key)
+    .update_expression("ADD #Subscribers :minus_one")
+    .condition_expression("attribute_exists(#DeckId) AND #Subscribers > :zero")
+    .expression_attribute_names("#Subscribers", CATALOG_SUBSCRIBERS_DB_KEY)
+    .expression_attribute_names("#DeckId", CATALOG_DECK_ID_DB_KEY)
+    .expression_attribute_values(":minus_one", AttributeValue::N("-1".to_string()))
+    .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+    .send().await;
+
+    Outcome::DatabaseUpdateSuccess(cache_recipes)
+}
+
+/// Removes the deck from one of the user's deck lists, the recipes are empty if it was not in the list.
+pub async fn remove_deck_from_user_deck_list(client: &Client, email: &str, deck_id: DeckId, deck_list_db_key: &str, deck_list_cache_key: &str) -> Outcome {
+    let user = match get_user(client, email, Some(deck_list_db_key)).await {
+        Outcome::UserFound(user) => user,
+        any_other_outcome => return any_other_outcome,
+    };
+
+    let deck_list = match deck_list_db_key {
+        ACTIVE_DECKS_DB_KEY => &user.active_decks,
+        OWNED_DECKS_DB_KEY => &user.owned_decks,
+        COLAB_DECKS_DB_KEY => &user.colab_decks,
+        _ => return Outcome::InvalidRequest,
+    };
+
+    let Some(position) = deck_list.iter().position(|listed_deck| listed_deck == &deck_id) else {
+        return Outcome::DatabaseUpdateSuccess(UpdateRecipes::default());
+    };
+
+    // The condition stops a deck that moved in the list since it was read from being removed in its place
+    let remove_result = client.update_item()
+    .table_name(USERS_TABLE)
+    .key(EMAIL_DB_KEY, AttributeValue::S(email.to_string()))
+    .update_expression(format!("REMOVE #DeckList[{position}]"))
+    .condition_expression(format!("#DeckList[{position}] = :deck"))
+    .expression_attribute_names("#DeckList", deck_list_db_key)
+    .expression_attribute_values(":deck", AttributeValue::S(deck_id.to_string()))
+    .send().await;
+
+    if let Err(e) = remove_result {
+        return Outcome::UpdateUserFailure(e.into_service_error().to_string());
+    }
+
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes {
+        recipes: vec![UpdateRecipe {
+            update_type: UpdateType::Subtract,
+            update_key: deck_list_cache_key.to_string(),
+            update_item: DBItem::User(email.to_string()),
+            value: UpdateValues::DeckList(DeckList {decks: vec![deck_id]}),
+        }],
+    })
+}
+
+/// Writes the receipt as long as there is none yet or the one stored has one of the replaced statuses.
+/// Returns InvalidRequest if the stored receipt moved on to another status first.
+pub async fn put_receipt(client: &Client, receipt: &Receipt, replaces: &[ReceiptStatus]) -> Outcome {
+    let Ok(item) = to_item(receipt) else {return Outcome::IncorrectType};
+
+    let mut put_request = client.put_item()
+    .table_name(RECEIPTS_TABLE)
+    .set_item(Some(item))
+    .expression_attribute_names("#User", RECEIPT_USER_DB_KEY);
+
+    let mut replaced_statuses = Vec::with_capacity(replaces.len());
+    for (i, status) in replaces.iter().enumerate() {
+        replaced_statuses.push(format!(":status{i}"));
+        put_request = put_request.expression_attribute_values(format!(":status{i}"), AttributeValue::S(status.to_string()));
+    }
+
+    let condition_expression = match replaced_statuses.is_empty() {
+        true => "attribute_not_exists(#User)".to_string(),
+        false => {
+            put_request = put_request.expression_attribute_names("#Status", RECEIPT_STATUS_DB_KEY);
+            format!("attribute_not_exists(#User) OR #Status IN ({})", replaced_statuses.join(", "))
+        },
+    };
+
+    match put_request.condition_expression(condition_expression).send().await {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => match e.into_service_error() {
+            PutItemError::ConditionalCheckFailedException(_) => Outcome::InvalidRequest,
+            any_other_error => Outcome::UpdateUserFailure(any_other_error.to_string()),
+        },
+    }
+}
+
+pub async fn get_receipt(client: &Client, email: &str, deck_id: DeckId) -> Outcome {
+    let Ok(deck_id) = to_attribute_value(deck_id) else {return Outcome::IncorrectType};
+
+    let item = match client.get_item()
+    .table_name(RECEIPTS_TABLE)
+    .key(RECEIPT_USER_DB_KEY, AttributeValue::S(email.to_string()))
+    .key(RECEIPT_DECK_ID_DB_KEY, deck_id)
+    .consistent_read(true)
+    .send().await {
+        Ok(output) => match output.item {
+            Some(item) => item,
+            None => return Outcome::ItemsNotFound,
+        },
+        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+    };
+
+    match from_item(item) {
+        Ok(receipt) => Outcome::ReceiptFound(receipt),
+        Err(_) => Outcome::IncorrectType,
+    }
+}
+
+/// Finds the receipt a payment belongs to through the payment id index.
+pub async fn get_receipt_by_payment_id(client: &Client, payment_id: &str) -> Outcome {
+    let output = match client.query()
+    .table_name(RECEIPTS_TABLE)
+    .index_name(format!("{RECEIPT_PAYMENT_ID_DB_KEY}-index"))
+    .key_condition_expression("#PaymentId = :payment_id")
+    .expression_attribute_names("#PaymentId", RECEIPT_PAYMENT_ID_DB_KEY)
+    .expression_attribute_values(":payment_id", AttributeValue::S(payment_id.to_string()))
+    .send().await {
+        Ok(output) => output,
+        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+    };
+
+    let Some(item) = output.items().first() else {return Outcome::ItemsNotFound};
+
+    // The index can lag behind the table so the receipt is read again before it is changed
+    let Ok(index_receipt) = from_item::<_, Receipt>(item.clone()) else {return Outcome::IncorrectType};
+    match get_receipt(client, &index_receipt.user, index_receipt.deck_id).await {
+        Outcome::ReceiptFound(receipt) if receipt.payment_id == payment_id => Outcome::ReceiptFound(receipt),
+        Outcome::ReceiptFound(_) => Outcome::ItemsNotFound,
+        any_other_outcome => any_other_outcome,
+    }
+}
+
+pub async fn get_receipts(client: &Client, email: &str) -> Outcome {
+    let mut receipts: Vec<Receipt> = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+        let output = match client.query()
+        .table_name(RECEIPTS_TABLE)
+        .key_condition_expression("#User = :user")
+        .expression_attribute_names("#User", RECEIPT_USER_DB_KEY)
+        .expression_attribute_values(":user", AttributeValue::S(email.to_string()))
+        .set_exclusive_start_key(exclusive_start_key)
+        .send().await {
+            Ok(output) => output,
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+        };
+
+        for item in output.items() {
+            match from_item(item.clone()) {
+                Ok(receipt) => receipts.push(receipt),
+                Err(_) => return Outcome::IncorrectType,
+            }
+        }
+
+        match output.last_evaluated_key {
+            Some(last_key) => exclusive_start_key = Some(last_key),
+            None => break,
+        }
+    }
+
+    Outcome::ReceiptsFound(receipts)
+}
+
+/// Paid receipts for the owner's decks whose payout has not been sent yet, read through the owner index.
+pub async fn get_owed_receipts(client: &Client, owner: &str) -> Outcome {
+    let mut receipts: Vec<Receipt> = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+        let output = match client.query()
+        .table_name(RECEIPTS_TABLE)
+        .index_name(format!("{RECEIPT_OWNER_DB_KEY}-index"))
+        .key_condition_expression("#Owner = :owner")
+        .filter_expression("#Status = :paid AND #PayoutId = :no_payout")
+        .expression_attribute_names("#Owner", RECEIPT_OWNER_DB_KEY)
+        .expression_attribute_names("#Status", RECEIPT_STATUS_DB_KEY)
+        .expression_attribute_names("#PayoutId", RECEIPT_PAYOUT_ID_DB_KEY)
+        .expression_attribute_values(":owner", AttributeValue::S(owner.to_string()))
+        .expression_attribute_values(":paid", AttributeValue::S(ReceiptStatus::Paid.to_string()))
+        .expression_attribute_values(":no_payout", AttributeValue::S(String::new()))
+        .set_exclusive_start_key(exclusive_start_key)
+        .send().await {
+            Ok(output) => output,
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+        };
+
+        for item in output.items() {
+            match from_item(item.clone()) {
+                Ok(receipt) => receipts.push(receipt),
+                Err(_) => return Outcome::IncorrectType,
+            }
+        }
+
+        match output.last_evaluated_key {
+            Some(last_key) => exclusive_start_key = Some(last_key),
+            None => break,
+        }
+    }
+
+    Outcome::ReceiptsFound(receipts)
+}
+
+pub async fn put_payout_account(client: &Client, account: &PayoutAccount) -> Outcome {
+    let Ok(item) = to_item(account) else {return Outcome::IncorrectType};
+
+    match client.put_item().table_name(PAYOUT_ACCOUNTS_TABLE).set_item(Some(item)).send().await {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => Outcome::UpdateUserFailure(e.into_service_error().to_string()),
+    }
+}
+
+pub async fn get_payout_account(client: &Client, email: &str) -> Outcome {
+    let item = match client.get_item()
+    .table_name(PAYOUT_ACCOUNTS_TABLE)
+    .key(PAYOUT_ACCOUNT_USER_DB_KEY, AttributeValue::S(email.to_string()))
+    .send().await {
+        Ok(output) => match output.item {
+            Some(item) => item,
+            None => return Outcome::ItemsNotFound,
+        },
+        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+    };
+
+    match from_item(item) {
+        Ok(account) => Outcome::PayoutAccountFound(account),
+        Err(_) => Outcome::IncorrectType,
+    }
+}
+
+pub async fn get_payout_account_by_account_id(client: &Client, account_id: &str) -> Outcome {
+    let output = match client.query()
+    .table_name(PAYOUT_ACCOUNTS_TABLE)
+    .index_name(format!("{PAYOUT_ACCOUNT_ID_DB_KEY}-index"))
+    .key_condition_expression("#AccountId = :account_id")
+    .expression_attribute_names("#AccountId", PAYOUT_ACCOUNT_ID_DB_KEY)
+    .expression_attribute_values(":account_id", AttributeValue::S(account_id.to_string()))
+    .send().await {
+        Ok(output) => output,
+        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+    };
+
+    let Some(item) = output.items().first() else {return Outcome::ItemsNotFound};
+    match from_item(item.clone()) {
+        Ok(account) => Outcome::PayoutAccountFound(account),
+        Err(_) => Outcome::IncorrectType,
+    }
+}
+
+/// Writes the invite unless the user has already accepted one for the deck.
+pub async fn put_collaborator_invite(client: &Client, collaborator: &Collaborator) -> Outcome {
+    let Ok(item) = to_item(collaborator) else {return Outcome::IncorrectType};
+
+    match client.put_item()
+    .table_name(COLLABORATORS_TABLE)
+    .set_item(Some(item))
+    .condition_expression("attribute_not_exists(#User) OR #Status = :invited")
+    .expression_attribute_names("#User", COLLABORATOR_USER_DB_KEY)
+    .expression_attribute_names("#Status", COLLABORATOR_STATUS_DB_KEY)
+    .expression_attribute_values(":invited", AttributeValue::S(InviteStatus::Invited.to_string()))
+    .send().await {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => match e.into_service_error() {
+            PutItemError::ConditionalCheckFailedException(_) => Outcome::InvalidRequest,
+            any_other_error => Outcome::UpdateUserFailure(any_other_error.to_string()),
+        },
+    }
+}
+
+pub async fn get_collaborator(client: &Client, deck_id: DeckId, email: &str) -> Outcome {
+    let Ok(deck_id) = to_attribute_value(deck_id) else {return Outcome::IncorrectType};
+
+    let item = match client.get_item()
+    .table_name(COLLABORATORS_TABLE)
+    .key(COLLABORATOR_DECK_ID_DB_KEY, deck_id)
+    .key(COLLABORATOR_USER_DB_KEY, AttributeValue::S(email.to_string()))
+    .consistent_read(true)
+    .send().await {
+        Ok(output) => match output.item {
+            Some(item) => item,
+            None => return Outcome::ItemsNotFound,
+        },
+        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+    };
+
+    match from_item(item) {
+        Ok(collaborator) => Outcome::CollaboratorFound(collaborator),
+        Err(_) => Outcome::IncorrectType,
+    }
+}
+
+/// Everyone invited to the deck, whether or not they have accepted.
+pub async fn get_collaborators(client: &Client, deck_id: DeckId) -> Outcome {
+    let Ok(deck_id) = to_attribute_value(deck_id) else {return Outcome::IncorrectType};
+    let mut collaborators: Vec<Collaborator> = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+        let output = match client.query()
+        .table_name(COLLABORATORS_TABLE)
+        .key_condition_expression("#DeckId = :deck_id")
+        .expression_attribute_names("#DeckId", COLLABORATOR_DECK_ID_DB_KEY)
+        .expression_attribute_values(":deck_id", deck_id.clone())
+        .set_exclusive_start_key(exclusive_start_key)
+        .send().await {
+            Ok(output) => output,
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+        };
+
+        for item in output.items() {
+            match from_item(item.clone()) {
+                Ok(collaborator) => collaborators.push(collaborator),
+                Err(_) => return Outcome::IncorrectType,
+            }
+        }
+
+        match output.last_evaluated_key {
+            Some(last_key) => exclusive_start_key = Some(last_key),
+            None => break,
+        }
+    }
+
+    Outcome::CollaboratorsFound(collaborators)
+}
+
+pub async fn update_collaborator_role(client: &Client, deck_id: DeckId, email: &str, role: CollaboratorRole) -> Outcome {
+    let Ok(deck_id) = to_attribute_value(deck_id) else {return Outcome::IncorrectType};
+
+    match client.update_item()
+    .table_name(COLLABORATORS_TABLE)
+    .key(COLLABORATOR_DECK_ID_DB_KEY, deck_id)
+    .key(COLLABORATOR_USER_DB_KEY, AttributeValue::S(email.to_string()))
+    .update_expression("SET #Role = :role")
+    .condition_expression("attribute_exists(#User)")
+    .expression_attribute_names("#Role", COLLABORATOR_ROLE_DB_KEY)
+    .expression_attribute_names("#User", COLLABORATOR_USER_DB_KEY)
+    .expression_attribute_values(":role", AttributeValue::S(role.to_string()))
+    .send().await {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => Outcome::UpdateUserFailure(e.into_service_error().to_string()),
+    }
+}
+
+/// Deletes the collaborator, only_if leaves them in place unless their invite has that status.
+pub async fn delete_collaborator(client: &Client, deck_id: DeckId, email: &str, only_if: Option<InviteStatus>) -> Outcome {
+    let Ok(deck_id) = to_attribute_value(deck_id) else {return Outcome::IncorrectType};
+
+    let mut delete_request = client.delete_item()
+    .table_name(COLLABORATORS_TABLE)
+    .key(COLLABORATOR_DECK_ID_DB_KEY, deck_id)
+    .key(COLLABORATOR_USER_DB_KEY, AttributeValue::S(email.to_string()));
+
+    if let Some(status) = only_if {
+        delete_request = delete_request
+        .condition_expression("#Status = :status")
+        .expression_attribute_names("#Status", COLLABORATOR_STATUS_DB_KEY)
+        .expression_attribute_values(":status", AttributeValue::S(status.to_string()));
+    }
+
+    match delete_request.send().await {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => Outcome::UpdateUserFailure(e.into_service_error().to_string()),
+    }
+}
+
+/// Marks the invite accepted and adds the deck to the user's colab decks, both happen or neither does.
+pub async fn accept_collaborator_invite(client: &Client, collaborator: &Collaborator) -> Outcome {
+    let Ok(deck_key) = to_attribute_value(collaborator.deck_id) else {return Outcome::IncorrectType};
+    let deck = AttributeValue::S(collaborator.deck_id.to_string());
+
+    let Ok(invite_update) = Update::builder()
+    .table_name(COLLABORATORS_TABLE)
+    .key(COLLABORATOR_DECK_ID_DB_KEY, deck_key)
+    .key(COLLABORATOR_USER_DB_KEY, AttributeValue::S(collaborator.user.clone()))
+    .update_expression("SET #Status = :accepted")
+    .condition_expression("#Status = :invited AND #Invited = :invited_at")
+    .expression_attribute_names("#Status", COLLABORATOR_STATUS_DB_KEY)
+    .expression_attribute_names("#Invited", COLLABORATOR_INVITED_DB_KEY)
+    .expression_attribute_values(":accepted", AttributeValue::S(InviteStatus::Accepted.to_string()))
+    .expression_attribute_values(":invited", AttributeValue::S(InviteStatus::Invited.to_string()))
+    .expression_attribute_values(":invited_at", AttributeValue::N(collaborator.invited.to_string()))
+    .build() else {return Outcome::IncorrectType};
+
+    let Ok(colab_decks_update) = Update::builder()
+    .table_name(USERS_TABLE)
+    .key(EMAIL_DB_KEY, AttributeValue::S(collaborator.user.clone()))
+    .update_expression("SET #ColabDecks = list_append(if_not_exists(#ColabDecks, :empty), :decks)")
+    .condition_expression("attribute_exists(#Email) AND (attribute_not_exists(#ColabDecks) OR NOT contains(#ColabDecks, :deck))")
+    .expression_attribute_names("#ColabDecks", COLAB_DECKS_DB_KEY)
+    .expression_attribute_names("#Email", EMAIL_DB_KEY)
+    .expression_attribute_values(":decks", AttributeValue::L(vec![deck.clone()]))
+    .expression_attribute_values(":deck", deck)
+    .expression_attribute_values(":empty", AttributeValue::L(Vec::new()))
+    .build() else {return Outcome::IncorrectType};
+
+    let transaction_result = client.transact_write_items()
+    .transact_items(TransactWriteItem::builder().update(invite_update).build())
+    .transact_items(TransactWriteItem::builder().update(colab_decks_update).build())
+    .send().await;
+
+    match transaction_result {
+        Ok(_) => proceed(),
+        Err(e) => match e.into_service_error() {
+            TransactWriteItemsError::TransactionCanceledException(exception) => {
+                let reasons = exception.cancellation_reasons();
+                let failed_check = |i: usize| reasons.get(i).and_then(|reason| reason.code()) == Some("ConditionalCheckFailed");
+                if failed_check(0) {
+                    return Outcome::UserDoesNotHavePermission;
+                }
+                if failed_check(1) {
+                    return Outcome::InvalidRequest;
+                }
+                return Outcome::UpdateUserFailure(exception.to_string());
+            },
+            any_other_error => return Outcome::UpdateUserFailure(any_other_error.to_string()),
+        },
+    }
+
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes {
+        recipes: vec![UpdateRecipe {
+            update_type: UpdateType::Add,
+            update_key: UserInfo::COLAB_DECKS_CACHE_KEY.to_string(),
+            update_item: DBItem::User(collaborator.user.clone()),
+            value: UpdateValues::DeckList(DeckList {decks: vec![collaborator.deck_id]}),
+        }],
+    })
+}
+
+/// Records expire through the table's TTL on the expires attribute.
+pub async fn put_sync_record(client: &Client, record: &SyncRecord) -> Outcome {
+    let Ok(item) = to_item(record) else {return Outcome::IncorrectType};
+
+    match client.put_item().table_name(SYNC_RECORDS_TABLE).set_item(Some(item)).send().await {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => Outcome::UpdateUserFailure(e.into_service_error().to_string()),
+    }
+}
+
+/// Puts the claim only if the key was never used or the replay that claimed it never finished, so two replays of the
+/// same change can never both apply it. Otherwise returns the stored record.
+pub async fn claim_sync_record(client: &Client, claim: &SyncRecord) -> Outcome {
+    let Ok(item) = to_item(claim) else {return Outcome::IncorrectType};
+
+    let put_item_result = client.put_item()
+    .table_name(SYNC_RECORDS_TABLE)
+    .set_item(Some(item))
+    .condition_expression("attribute_not_exists(#Key) OR (#Outcome = :unfinished AND #Expires < :now)")
+    .expression_attribute_names("#Key", SYNC_RECORD_KEY_DB_KEY)
+    .expression_attribute_names("#Outcome", SYNC_RECORD_OUTCOME_DB_KEY)
+    .expression_attribute_names("#Expires", SYNC_RECORD_EXPIRES_DB_KEY)
+    .expression_attribute_values(":unfinished", AttributeValue::S(String::new()))
+    .expression_attribute_values(":now", AttributeValue::N(current_time_in_seconds().to_string()))
+    .send().await;
+
+    match put_item_result {
+        Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        Err(e) => match e.into_service_error() {
+            PutItemError::ConditionalCheckFailedException(_) => get_sync_record(client, &claim.user, &claim.idempotency_key).await,
+            any_other_error => Outcome::UpdateUserFailure(any_other_error.to_string()),
+        },
+    }
+}
+
+pub async fn get_sync_record(client: &Client, email: &str, idempotency_key: &str) -> Outcome {
+    let item = match client.get_item()
+    .table_name(SYNC_RECORDS_TABLE)
+    .key(SYNC_RECORD_USER_DB_KEY, AttributeValue::S(email.to_string()))
+    .key(SYNC_RECORD_KEY_DB_KEY, AttributeValue::S(idempotency_key.to_string()))
+    .consistent_read(true)
+    .send().await {
+        Ok(output) => match output.item {
+            Some(item) => item,
+            None => return Outcome::ItemsNotFound,
+        },
+        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+    };
+
+    match from_item(item) {
+        Ok(record) => Outcome::SyncRecordFound(record),
+        Err(_) => Outcome::IncorrectType,
+    }
 }
 
 pub async fn update_item(client: &Client, update_recipes: Vec<UpdateRecipe>) -> Outcome {
+    // Metas are only written by change_deck_meta and write_note_and_deck_meta, which move the version with them
+    if changes_deck_meta(&update_recipes) {
+        return Outcome::InvalidRequest;
+    }
+
     for recipe_list in sort_recipes_by_update_item(&update_recipes) {
         let mut update_request = match &recipe_list[0].update_item {
             DBItem::User(email) => {
@@ -493,5 +1832,7 @@ pub fn convert_to_attr_val(update_value: &UpdateValues) -> Result<AttributeValue
         UpdateValues::Note(note) => to_attribute_value(note),
         UpdateValues::DeckList(deck_list) => to_attribute_value(deck_list),
         UpdateValues::Unsigned64(number) => to_attribute_value(number),
+        // Metas are kept as a single encoded string so they are read back the same way as the rest of the note
+        UpdateValues::DeckMeta(meta) => Ok(AttributeValue::S(meta.to_string())),
     }
 }
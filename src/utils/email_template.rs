@@ -11,10 +11,12 @@ pub const EMAIL_FIELD_1_VALUE: &str = "EMAIL_FIELD_1_VALUE";
 pub const EMAIL_FIELD_2: &str = "EMAIL_FIELD_2";
 pub const EMAIL_FIELD_2_VALUE: &str = "EMAIL_FIELD_2_VALUE";
 pub const REDIRECT_LINK: &str = "REDIRECT_LINK";
+pub const DECLINE_LINK: &str = "DECLINE_LINK";
 
 pub enum EmailTemplate {
     SignUp,
     SignIn,
+    CollaboratorInvite,
 }
 impl EmailTemplate {
     pub fn get_template(&self) -> String {
@@ -123,6 +125,12 @@ impl EmailTemplate {
             .replace(TEXT_LINE_2, "Clicking the link below will sign you in,")
             .replace(TEXT_LINE_3, "If you did not request this email please inform support service@lexlingua.io")
             .replace(BUTTON_TEXT, "Sign In"),
+            EmailTemplate::CollaboratorInvite => email_template
+            .replace(TITLE_TEXT, "You've Been Invited")
+            .replace(TEXT_LINE_1, "You have been invited to help write a deck.")
+            .replace(TEXT_LINE_2, "Clicking the link below will add it to your collaborations,")
+            .replace(TEXT_LINE_3, &format!(r#"If you do not want to collaborate you can <a href="{DECLINE_LINK}">decline the invite</a>"#))
+            .replace(BUTTON_TEXT, "Accept Invite"),
         }
     }
 }
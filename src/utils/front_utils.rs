@@ -2,9 +2,12 @@ use super::database_types::DeckList;
 use super::date_and_time::current_time_in_seconds;
 use super::outcomes::Outcome;
 use super::query::ValidQueryTypes;
-use super::shared_truth::{MAX_LEVELS, S3_CREATION_DATE_URL_PARAM, S3_EXPIRATION_URL_PARAM};
+use super::shared_truth::{MAX_LEVELS, MAX_NOTE_IDS_PER_QUERY, S3_CREATION_DATE_URL_PARAM, S3_EXPIRATION_URL_PARAM};
 use leptos::logging::debug_warn;
-use web_sys::{self, window, Element, HtmlImageElement};
+use std::time::Duration;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{self, js_sys::Promise, window, Blob, Element, File, HtmlImageElement, RequestInit, Response};
 
 pub fn clear_element_classes_and_add_new(element: Element, class: String) {
     let classes = element.class_name();
@@ -118,8 +121,9 @@ pub fn frontend_query_validation(query: &ValidQueryTypes, valid_decks: DeckList)
                 if level > &MAX_LEVELS {return Outcome::InvalidRequest}
             }
         },
-        ValidQueryTypes::NotesById(deck_id, _) => {
+        ValidQueryTypes::NotesById(deck_id, note_ids) => {
             if !!!valid_decks.contains(&deck_id) {return Outcome::UserDoesNotHavePermission};
+            if note_ids.len() > MAX_NOTE_IDS_PER_QUERY {return Outcome::InvalidRequest}
         },
         ValidQueryTypes::NotesByType(deck_id, _) => {
             if !!!valid_decks.contains(&deck_id) {return Outcome::UserDoesNotHavePermission};
@@ -142,4 +146,50 @@ pub fn get_cookie_value_client(cookie_name: &str) -> Option<String> {
         .map(|s| s.to_string());
 
     return value;
-}
\ No newline at end of file
+}
+
+pub async fn upload_file_to_presigned_url(url: &str, file: &File) -> Outcome {
+    let Some(window) = window() else {return Outcome::DeckNotUploadedToBucket};
+
+    let request_init = RequestInit::new();
+    request_init.set_method("PUT");
+    request_init.set_body(file);
+
+    let response = match JsFuture::from(window.fetch_with_str_and_init(url, &request_init)).await {
+        Ok(response) => response,
+        Err(_) => return Outcome::DeckNotUploadedToBucket,
+    };
+    let Ok(response) = response.dyn_into::<Response>() else {return Outcome::DeckNotUploadedToBucket};
+
+    if response.ok() {
+        Outcome::DeckUploadedToBucket(file.name())
+    } else {
+        debug_warn!("deck upload failed with status {}", response.status());
+        Outcome::DeckNotUploadedToBucket
+    }
+}
+
+/// Downloads the file behind the url so it can still be shown once the url has expired or the user is offline.
+pub async fn download_as_blob(url: &str) -> Option<Blob> {
+    let window = window()?;
+
+    let response = JsFuture::from(window.fetch_with_str(url)).await.ok()?.dyn_into::<Response>().ok()?;
+    if !!!response.ok() {
+        debug_warn!("download failed with status {}", response.status());
+        return None;
+    }
+
+    JsFuture::from(response.blob().ok()?).await.ok()?.dyn_into::<Blob>().ok()
+}
+
+pub async fn sleep_client(duration: Duration) {
+    let promise = Promise::new(&mut |resolve, _| {
+        let timeout_set = window().is_some_and(|window| {
+            window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, duration.as_millis() as i32).is_ok()
+        });
+        if !!!timeout_set {
+            let _ = resolve.call0(&JsValue::NULL);
+        }
+    });
+    let _ = JsFuture::from(promise).await;
+}
@@ -4,20 +4,19 @@ use axum::{
 
 use crate::utils::{outcomes::Outcome, shared_truth::{AUTH_TOKEN_HEADER, USER_CLAIM_AUTH}, shared_utilities::{get_claim, verify_token}};
 
-use super::{proceed, shared_utilities::excluded_from_auth};
+use super::shared_utilities::excluded_from_auth;
 
 pub async fn auth_middleware(
     mut request: Request,
     next: Next,
 ) -> Response<Body> {
-    if request.method() != Method::GET && !!!excluded_from_auth(request.uri().to_string()) {
+    // The user claim is only ever set from a verified token, whatever the route
+    request.headers_mut().remove(USER_CLAIM_AUTH);
+
+    if request.method() != Method::GET && !!!excluded_from_auth(request.uri().path()) {
         println!("{}", request.uri().to_string());
         let early_response = Response::builder().status(404).body(Outcome::VerificationFailure.to_string().into()).unwrap_or_default();
         let headers = request.headers_mut();
-        match headers.get(USER_CLAIM_AUTH) {
-            Some(_) => return early_response,
-            None => proceed(),
-        }
         let Some(auth_header) = headers.get(AUTH_TOKEN_HEADER) else {return early_response};
         println!("auth_header {}", auth_header.to_str().unwrap_or_default());
         let Ok(trusted_token) = verify_token(auth_header.to_str().unwrap_or_default()) else {return early_response};
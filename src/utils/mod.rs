@@ -12,6 +12,21 @@ pub mod shared_utilities;
 pub mod cache_db_interface;
 pub mod auth_client;
 pub mod query;
+pub mod scheduler;
+pub mod deck_import;
+pub mod deck_upload;
+pub mod upload_ledger;
+pub mod deck_export;
+pub mod wire_format;
+pub mod note_templates;
+pub mod cloze;
+pub mod deck_settings;
+pub mod catalog;
+pub mod purchases;
+pub mod collaborators;
+pub mod note_editing;
+pub mod sync_queue;
+pub mod cache_status;
 #[cfg(feature = "ssr")]
 pub mod dynamo_utils;
 #[cfg(feature = "ssr")]
@@ -20,10 +35,24 @@ pub mod back_utils;
 pub mod middleware;
 #[cfg(feature = "ssr")]
 pub mod email_template;
+#[cfg(feature = "ssr")]
+pub mod anki;
+#[cfg(feature = "ssr")]
+pub mod csv_import;
+#[cfg(feature = "ssr")]
+pub mod storage;
+#[cfg(feature = "ssr")]
+pub mod object_store;
+#[cfg(feature = "ssr")]
+pub mod payments;
 #[cfg(feature = "hydrate")]
 pub mod front_utils;
 #[cfg(feature = "hydrate")]
 pub mod cache;
+#[cfg(feature = "hydrate")]
+pub mod cache_manager;
+#[cfg(feature = "hydrate")]
+pub mod prefetch;
 
 pub fn proceed() {
     ()
@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+
+use leptos::server;
+use server_fn::ServerFnError;
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::{DeckId, Note},
+    outcomes::Outcome,
+    shared_truth::{MAX_NOTE_FIELDS, MAX_NOTE_SIZE},
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use crate::utils::{
+    back_utils::verify_user_header,
+    catalog::CatalogEntry,
+    collaborators::CollaboratorRole,
+    database_types::UpdateValues,
+    dynamo_utils::{validate_deck_role_and_user_standing, NoteWrite},
+    proceed,
+    storage::{setup_storage, Storage},
+};
+
+/// Fields are stored as attributes beside the note's own, so they cannot take those names.
+pub fn is_valid_note_edit(note: &Note) -> bool {
+    let reserved_names = [
+        Note::FIELD_NAMES.note_id,
+        Note::FIELD_NAMES.deck_id,
+        Note::FIELD_NAMES.note_type,
+        Note::FIELD_NAMES.version,
+        Note::FIELD_NAMES.reviews_per_stage,
+        Note::FIELD_NAMES.level,
+        Note::FIELD_NAMES.meta,
+    ];
+
+    let mut field_names = HashSet::with_capacity(note.fields.len());
+    let fields_are_valid = note.fields.iter().all(|field| {
+        let name = field.name.as_str();
+        !!!name.trim().is_empty() && !!!reserved_names.contains(&name) && field_names.insert(name)
+    });
+
+    !!!note.fields.is_empty()
+        && note.fields.len() <= MAX_NOTE_FIELDS
+        && fields_are_valid
+        && note.meta.is_none()
+        && note.to_string().len() <= MAX_NOTE_SIZE
+}
+
+/// Adds the note to the end of the deck, the note id and level it was given are in the returned recipes.
+#[server(client=AuthClient)]
+pub async fn add_note_to_deck(deck_id: DeckId, note: Note, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    Ok(add_note_to_deck_for(&storage, &email, deck_id, note).await)
+}
+
+/// The note's version must be the one the edit was made against, otherwise NoteConflict returns the server copy to merge with.
+#[server(client=AuthClient)]
+pub async fn edit_deck_note(deck_id: DeckId, note: Note, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    Ok(edit_deck_note_for(&storage, &email, deck_id, note).await)
+}
+
+/// Deletes the note as long as it has not changed since the given version.
+#[server(client=AuthClient)]
+pub async fn delete_deck_note(deck_id: DeckId, note_id: u64, version: u64, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    Ok(delete_deck_note_for(&storage, &email, deck_id, note_id, version).await)
+}
+
+/// Adds the note for a user who has already been verified, offline changes are replayed through here.
+#[cfg(feature="ssr")]
+pub async fn add_note_to_deck_for(storage: &impl Storage, email: &str, deck_id: DeckId, note: Note) -> Outcome {
+    if !!!is_valid_note_edit(&note) {
+        return Outcome::InvalidRequest;
+    }
+
+    match validate_deck_role_and_user_standing(storage, email, deck_id, CollaboratorRole::Editor).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return any_other_outcome,
+    };
+
+    let note = Note {
+        deck_id,
+        version: 1,
+        meta: None,
+        ..note
+    };
+
+    write_note_and_sync_catalog(storage, deck_id, NoteWrite::Add(note)).await
+}
+
+#[cfg(feature="ssr")]
+pub async fn edit_deck_note_for(storage: &impl Storage, email: &str, deck_id: DeckId, note: Note) -> Outcome {
+    if note.note_id == 0 || !!!is_valid_note_edit(&note) {
+        return Outcome::InvalidRequest;
+    }
+
+    match validate_deck_role_and_user_standing(storage, email, deck_id, CollaboratorRole::Editor).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return any_other_outcome,
+    };
+
+    let current = match storage.get_note(deck_id, note.note_id).await {
+        Outcome::NoteFound(current) => current,
+        any_other_outcome => return any_other_outcome,
+    };
+    if current.version != note.version {
+        return Outcome::NoteConflict(current);
+    }
+
+    // Levels belong to the deck's layout so only the note's content comes from the edit
+    let edited = Note {
+        deck_id,
+        version: current.version + 1,
+        level: current.level,
+        meta: None,
+        ..note
+    };
+
+    write_note_and_sync_catalog(storage, deck_id, NoteWrite::Edit {current, edited}).await
+}
+
+#[cfg(feature="ssr")]
+pub async fn delete_deck_note_for(storage: &impl Storage, email: &str, deck_id: DeckId, note_id: u64, version: u64) -> Outcome {
+    if note_id == 0 {
+        return Outcome::InvalidRequest;
+    }
+
+    match validate_deck_role_and_user_standing(storage, email, deck_id, CollaboratorRole::Editor).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return any_other_outcome,
+    };
+
+    let current = match storage.get_note(deck_id, note_id).await {
+        Outcome::NoteFound(current) => current,
+        any_other_outcome => return any_other_outcome,
+    };
+    if current.version != version {
+        return Outcome::NoteConflict(current);
+    }
+
+    write_note_and_sync_catalog(storage, deck_id, NoteWrite::Delete(current)).await
+}
+
+/// Public decks show their note count in the catalog, a stale count is fixed by the next edit so failures are ignored.
+#[cfg(feature="ssr")]
+async fn write_note_and_sync_catalog(storage: &impl Storage, deck_id: DeckId, note_write: NoteWrite) -> Outcome {
+    let outcome = storage.write_note_and_deck_meta(deck_id, note_write).await;
+
+    if let Outcome::DatabaseUpdateSuccess(cache_recipes) = &outcome {
+        let new_meta = cache_recipes.recipes.iter().find_map(|recipe| match &recipe.value {
+            UpdateValues::DeckMeta(meta) => Some(meta),
+            _ => None,
+        });
+        if let Some(meta) = new_meta.filter(|meta| meta.public) {
+            storage.put_catalog_entry(&CatalogEntry::from_meta(deck_id, meta)).await;
+        }
+    }
+
+    outcome
+}
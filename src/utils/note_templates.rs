@@ -0,0 +1,401 @@
+use std::collections::BTreeSet;
+
+use leptos::server;
+use serde::{Deserialize, Serialize};
+use server_fn::ServerFnError;
+
+use crate::utils::{
+    auth_client::AuthClient,
+    cloze::{cloze_indices, render_cloze_back, render_cloze_front},
+    database_types::{DeckId, Note, NoteType},
+    outcomes::Outcome,
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use crate::utils::{
+    back_utils::verify_user_header,
+    database_types::UpdateRecipes,
+    dynamo_utils::{change_deck_meta, deck_meta_update_recipe, validate_owned_decks_and_user_standing},
+    proceed,
+    shared_truth::MAX_NOTE_TEMPLATES,
+    storage::setup_storage,
+};
+
+pub const FRONT_SIDE_PLACEHOLDER: &str = "FrontSide";
+const CLOZE_FILTER: &str = "cloze:";
+const CLOZE_FILTER_NAME: &str = "cloze";
+const TYPE_FILTER_NAME: &str = "type";
+const FILTER_SEPARATOR: char = ':';
+const PLACEHOLDER_START: &str = "{{";
+const PLACEHOLDER_END: &str = "}}";
+const SECTION_START: &str = "{{#";
+const INVERTED_SECTION_START: &str = "{{^";
+const SECTION_END: &str = "{{/";
+
+/// How a note type turns a note into cards, {{Field}} in either side is replaced by the text of that field
+/// and {{FrontSide}} on the back is replaced by the rendered front.
+/// {{#Field}}...{{/Field}} is only shown when the field has something in it and {{^Field}}...{{/Field}} only when it is empty.
+/// Cloze templates make one card per {{c1::...}} index found in the note's fields instead.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoteTemplate {
+    pub note_type: NoteType,
+    pub fields: Vec<String>,
+    pub front: String,
+    pub back: String,
+    pub reverse: bool,
+    #[serde(default)]
+    pub cloze: bool,
+    /// Cards after the first and the reverse card, as Anki note types with several card types have.
+    #[serde(default)]
+    pub extra_cards: Vec<CardTemplate>,
+}
+
+/// The two sides of one of a note type's extra cards.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CardTemplate {
+    pub front: String,
+    pub back: String,
+}
+
+impl NoteTemplate {
+    /// The first field on the front and every other field on the back.
+    pub fn basic(note_type: NoteType, fields: Vec<String>) -> Self {
+        let front = fields.first().map(|field| placeholder(field)).unwrap_or_default();
+        let back = fields.iter().skip(1).map(|field| placeholder(field)).collect::<Vec<String>>().join("\n");
+
+        Self {
+            note_type,
+            fields,
+            front,
+            back,
+            reverse: false,
+            cloze: false,
+            extra_cards: Vec::new(),
+        }
+    }
+
+    /// The first field with its clozes hidden on the front and every field on the back with the answers shown.
+    pub fn cloze(note_type: NoteType, fields: Vec<String>) -> Self {
+        let front = fields.first().map(|field| placeholder(&format!("{CLOZE_FILTER}{field}"))).unwrap_or_default();
+        let extra = fields.iter().skip(1).map(|field| placeholder(field));
+        let back = std::iter::once(front.clone()).chain(extra).collect::<Vec<String>>().join("\n");
+
+        Self {
+            note_type,
+            fields,
+            front,
+            back,
+            reverse: false,
+            cloze: true,
+            extra_cards: Vec::new(),
+        }
+    }
+
+    /// The cards the note makes, cloze card n is the cloze index n + 1 so editing one cloze leaves the others' schedules alone.
+    /// Like Anki, a card whose front comes out empty for this note is not made.
+    pub fn card_ords(&self, note: &Note) -> Vec<u8> {
+        if self.cloze {
+            return note_cloze_indices(note).into_iter().map(|index| index - 1).collect();
+        }
+
+        let card_count = 1 + self.reverse as usize + self.extra_cards.len();
+        (0..card_count.min(u8::MAX as usize + 1))
+            .map(|card_ord| card_ord as u8)
+            .filter(|card_ord| self.render_card(note, *card_ord).is_some_and(|card| !!!card.front.trim().is_empty()))
+            .collect()
+    }
+
+    /// Card 0 is the note as written, card 1 is the reverse card which swaps the two sides and the extra cards follow.
+    /// Cloze cards hide the answers of their own index on the front and reveal them on the back.
+    pub fn render_card(&self, note: &Note, card_ord: u8) -> Option<StudyCard> {
+        let (front, back) = if self.cloze {
+            let index = card_ord.checked_add(1)?;
+            if !!!note_cloze_indices(note).contains(&index) {
+                return None;
+            }
+
+            let front = render_template_with(&self.front, note, CardSide::Front, |text| render_cloze_front(text, index));
+            let back = render_template_with(&self.back, note, CardSide::Back(&front), |text| render_cloze_back(text, index));
+            (front, back)
+        } else {
+            let extra_card_ord = (card_ord as usize).checked_sub(1 + self.reverse as usize);
+            let (front_template, back_template) = match (card_ord, extra_card_ord.and_then(|index| self.extra_cards.get(index))) {
+                (0, _) => (&self.front, &self.back),
+                (1, _) if self.reverse => (&self.back, &self.front),
+                (_, Some(extra_card)) => (&extra_card.front, &extra_card.back),
+                _ => return None,
+            };
+
+            let front = render_template(front_template, note, "");
+            let back = render_template(back_template, note, &front);
+            (front, back)
+        };
+
+        Some(StudyCard {
+            deck_id: note.deck_id,
+            note_id: note.note_id,
+            card_ord,
+            front,
+            back,
+        })
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !!!self.note_type.name().trim().is_empty() && !!!self.fields.is_empty() && !!!self.front.trim().is_empty()
+    }
+}
+
+/// A single card ready to be studied, notes with reverse cards produce more than one.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StudyCard {
+    pub deck_id: DeckId,
+    pub note_id: u64,
+    pub card_ord: u8,
+    pub front: String,
+    pub back: String,
+}
+
+/// The deck's template for the note's type, notes without one are shown as basic cards built from their own fields
+/// or as cloze cards if their fields have cloze markers.
+pub fn template_for_note(templates: &Vec<NoteTemplate>, note: &Note) -> NoteTemplate {
+    match templates.iter().find(|template| template.note_type == note.note_type) {
+        Some(template) => template.clone(),
+        None => default_template_for_note(note),
+    }
+}
+
+pub fn default_template_for_note(note: &Note) -> NoteTemplate {
+    let field_names = note.fields.iter().map(|field| field.name.clone()).collect();
+    if note_cloze_indices(note).is_empty() {
+        NoteTemplate::basic(note.note_type.clone(), field_names)
+    } else {
+        NoteTemplate::cloze(note.note_type.clone(), field_names)
+    }
+}
+
+/// Every cloze index used across the note's fields.
+pub fn note_cloze_indices(note: &Note) -> BTreeSet<u8> {
+    note.fields.iter().filter_map(|field| field.text.as_deref()).flat_map(cloze_indices).collect()
+}
+
+/// Renders the front when front_side is empty, otherwise the back with front_side in place of {{FrontSide}}.
+pub fn render_template(template: &str, note: &Note, front_side: &str) -> String {
+    let side = match front_side.is_empty() {
+        true => CardSide::Front,
+        false => CardSide::Back(front_side),
+    };
+    render_template_with(template, note, side, str::to_string)
+}
+
+#[derive(Clone, Copy)]
+enum CardSide<'a> {
+    Front,
+    Back(&'a str),
+}
+
+/// Fields behind the cloze: filter, as Anki writes them, are passed through render_cloze.
+/// There is nothing to type an answer into so {{type:Field}} is left off the front and shows the answer on the back,
+/// every other Anki filter such as hint: or text: shows the field as is.
+fn render_template_with(template: &str, note: &Note, side: CardSide, render_cloze: impl Fn(&str) -> String) -> String {
+    let template = render_sections(template, note);
+    let mut rendered = String::with_capacity(template.len());
+    let mut remaining = template.as_str();
+
+    while let Some(start) = remaining.find(PLACEHOLDER_START) {
+        rendered.push_str(&remaining[..start]);
+        let after_start = &remaining[start + PLACEHOLDER_START.len()..];
+        let Some(end) = after_start.find(PLACEHOLDER_END) else {
+            rendered.push_str(&remaining[start..]);
+            return rendered;
+        };
+
+        let (filters, field_name) = split_filters(&after_start[..end]);
+        let is_cloze = filters.contains(&CLOZE_FILTER_NAME);
+        let is_typed = filters.contains(&TYPE_FILTER_NAME);
+
+        if field_name == FRONT_SIDE_PLACEHOLDER {
+            if let CardSide::Back(front_side) = side {
+                rendered.push_str(front_side);
+            }
+        } else if let Some(field) = note.fields.iter().find(|field| field.name == field_name) {
+            let text = field.text.as_deref().unwrap_or_default();
+            match (is_cloze, is_typed, side) {
+                (true, _, _) => rendered.push_str(&render_cloze(text)),
+                (false, true, CardSide::Front) => (),
+                (false, _, _) => rendered.push_str(text),
+            }
+        }
+
+        remaining = &after_start[end + PLACEHOLDER_END.len()..];
+    }
+    rendered.push_str(remaining);
+
+    rendered.lines().map(str::trim).filter(|line| !!!line.is_empty()).collect::<Vec<&str>>().join("\n")
+}
+
+/// Splits "type:cloze:Field" into its filters and the field name, field names cannot contain the separator.
+fn split_filters(placeholder_name: &str) -> (Vec<&str>, &str) {
+    let mut parts = placeholder_name.trim().split(FILTER_SEPARATOR).map(str::trim).collect::<Vec<&str>>();
+    let field_name = parts.pop().unwrap_or_default();
+    (parts, field_name)
+}
+
+/// Keeps or drops each {{#Field}} and {{^Field}} section depending on whether the field is empty, sections can be nested.
+fn render_sections(template: &str, note: &Note) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut remaining = template;
+
+    loop {
+        let next_section = [SECTION_START, INVERTED_SECTION_START].into_iter()
+            .filter_map(|section_start| remaining.find(section_start).map(|start| (start, section_start)))
+            .min_by_key(|(start, _)| *start);
+        let Some((start, section_start)) = next_section else {break};
+
+        let after_start = &remaining[start + section_start.len()..];
+        let Some(name_end) = after_start.find(PLACEHOLDER_END) else {break};
+        let field_name = after_start[..name_end].trim();
+        let section_end = format!("{SECTION_END}{field_name}{PLACEHOLDER_END}");
+        let inner_and_rest = &after_start[name_end + PLACEHOLDER_END.len()..];
+        let Some(inner_end) = inner_and_rest.find(&section_end) else {break};
+
+        let field_is_empty = note.fields.iter()
+            .find(|field| field.name == field_name)
+            .is_none_or(|field| field.asset.is_none() && field.text.as_deref().unwrap_or_default().trim().is_empty());
+
+        rendered.push_str(&remaining[..start]);
+        if field_is_empty == (section_start == INVERTED_SECTION_START) {
+            rendered.push_str(&render_sections(&inner_and_rest[..inner_end], note));
+        }
+        remaining = &inner_and_rest[inner_end + section_end.len()..];
+    }
+    rendered.push_str(remaining);
+
+    rendered
+}
+
+fn placeholder(field_name: &str) -> String {
+    format!("{PLACEHOLDER_START}{field_name}{PLACEHOLDER_END}")
+}
+
+/// Adds the note type to the deck or replaces the template it already has.
+#[server(client=AuthClient)]
+pub async fn save_note_template(deck_id: DeckId, template: NoteTemplate, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    if !!!template.is_valid() {
+        return Ok(Outcome::InvalidRequest);
+    }
+
+    let storage = setup_storage().await;
+
+    match validate_owned_decks_and_user_standing(&storage, &email, deck_id).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let outcome = change_deck_meta(&storage, deck_id, |meta| {
+        meta.set_note_template(template.clone());
+        if meta.note_templates.len() > MAX_NOTE_TEMPLATES {
+            return Err(Outcome::InvalidRequest);
+        }
+        Ok(())
+    }).await;
+
+    match outcome {
+        Ok(meta) => Ok(Outcome::DatabaseUpdateSuccess(UpdateRecipes {recipes: vec![deck_meta_update_recipe(deck_id, meta)]})),
+        Err(any_other_outcome) => Ok(any_other_outcome),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::database_types::Field;
+
+    fn note(fields: &[(&str, &str)]) -> Note {
+        Note::new(1, fields.iter().map(|(name, text)| Field::new(name.to_string(), Some(text.to_string()), None)).collect())
+    }
+
+    fn template(front: &str, back: &str) -> NoteTemplate {
+        let mut template = NoteTemplate::basic(NoteType::new("Vocab"), vec!["Word".to_string(), "Meaning".to_string(), "Hint".to_string()]);
+        template.front = front.to_string();
+        template.back = back.to_string();
+        template
+    }
+
+    #[test]
+    fn basic_cards_render_fields_and_front_side() {
+        let note = note(&[("Word", "perro"), ("Meaning", "dog")]);
+        let card = template("{{Word}}", "{{FrontSide}}\n{{Meaning}}").render_card(&note, 0).unwrap();
+
+        assert_eq!(card.front, "perro");
+        assert_eq!(card.back, "perro\ndog");
+    }
+
+    #[test]
+    fn extra_cards_have_their_own_ords() {
+        let note = note(&[("Word", "perro"), ("Meaning", "dog")]);
+        let mut template = template("{{Word}}", "{{Meaning}}");
+        template.extra_cards = vec![
+            CardTemplate {front: "{{Meaning}}".to_string(), back: "{{Word}}".to_string()},
+            CardTemplate {front: "Spell {{Meaning}}".to_string(), back: "{{Word}}".to_string()},
+        ];
+
+        assert_eq!(template.card_ords(&note), vec![0, 1, 2]);
+        assert_eq!(template.render_card(&note, 1).unwrap().front, "dog");
+        assert_eq!(template.render_card(&note, 2).unwrap().front, "Spell dog");
+        assert!(template.render_card(&note, 3).is_none());
+    }
+
+    #[test]
+    fn reverse_card_comes_before_extra_cards() {
+        let note = note(&[("Word", "perro"), ("Meaning", "dog")]);
+        let mut template = template("{{Word}}", "{{Meaning}}");
+        template.reverse = true;
+        template.extra_cards = vec![CardTemplate {front: "Say {{Word}}".to_string(), back: "{{Meaning}}".to_string()}];
+
+        assert_eq!(template.card_ords(&note), vec![0, 1, 2]);
+        assert_eq!(template.render_card(&note, 1).unwrap().front, "dog");
+        assert_eq!(template.render_card(&note, 2).unwrap().front, "Say perro");
+    }
+
+    #[test]
+    fn sections_follow_whether_the_field_is_empty() {
+        let with_hint = note(&[("Word", "perro"), ("Meaning", "dog"), ("Hint", "barks")]);
+        let without_hint = note(&[("Word", "perro"), ("Meaning", "dog"), ("Hint", "")]);
+        let template = template("{{Word}}{{#Hint}} ({{Hint}}){{/Hint}}{{^Hint}} (no hint){{/Hint}}", "{{Meaning}}");
+
+        assert_eq!(template.render_card(&with_hint, 0).unwrap().front, "perro (barks)");
+        assert_eq!(template.render_card(&without_hint, 0).unwrap().front, "perro (no hint)");
+    }
+
+    #[test]
+    fn cards_with_an_empty_front_are_not_made() {
+        let note = note(&[("Word", "perro"), ("Meaning", "dog"), ("Hint", "")]);
+        let mut template = template("{{Word}}", "{{Meaning}}");
+        template.extra_cards = vec![CardTemplate {front: "{{#Hint}}{{Hint}}{{/Hint}}".to_string(), back: "{{Word}}".to_string()}];
+
+        assert_eq!(template.card_ords(&note), vec![0]);
+    }
+
+    #[test]
+    fn type_and_hint_filters_show_the_field() {
+        let note = note(&[("Word", "perro"), ("Meaning", "dog"), ("Hint", "barks")]);
+        let card = template("{{Meaning}} {{hint:Hint}}\n{{type:Word}}", "{{FrontSide}}\n{{type:Word}}").render_card(&note, 0).unwrap();
+
+        assert_eq!(card.front, "dog barks");
+        assert_eq!(card.back, "dog barks\nperro");
+    }
+
+    #[test]
+    fn cloze_cards_follow_the_cloze_indices() {
+        let note = note(&[("Text", "{{c1::Madrid}} is in {{c2::Spain}}")]);
+        let template = NoteTemplate::cloze(NoteType::new("Cloze"), vec!["Text".to_string()]);
+
+        assert_eq!(template.card_ords(&note), vec![0, 1]);
+        assert_eq!(template.render_card(&note, 0).unwrap().front, "[...] is in Spain");
+        assert_eq!(template.render_card(&note, 1).unwrap().back, "Madrid is in [Spain]");
+        assert!(template.render_card(&note, 2).is_none());
+    }
+}
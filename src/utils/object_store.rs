@@ -0,0 +1,462 @@
+use std::{collections::HashMap, future::Future, path::PathBuf, time::Duration};
+
+use aws_sdk_s3::{presigning::PresigningConfig, primitives::ByteStream, Client as S3Client};
+use axum::{body::{Body, Bytes}, extract::{Path, Query}, http::{Method, Response, StatusCode}};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::utils::{
+    asset::setup_s3_client,
+    database_types::S3Address,
+    date_and_time::{current_time_in_seconds, Date},
+    outcomes::Outcome,
+    shared_truth::{LOCAL_OBJECT_ROUTE, S3_CREATION_DATE_URL_PARAM, S3_EXPIRATION_URL_PARAM},
+};
+
+pub const OBJECT_STORE_BACKEND_ENV_KEY: &str = "OBJECT_STORE_BACKEND";
+pub const OBJECT_STORE_PATH_ENV_KEY: &str = "OBJECT_STORE_PATH";
+pub const OBJECT_STORE_SECRET_ENV_KEY: &str = "OBJECT_STORE_SECRET";
+pub const DEFAULT_OBJECT_STORE_PATH: &str = "local-objects";
+
+const SIGNATURE_URL_PARAM: &str = "X-Amz-Signature=";
+
+pub(crate) type HmacSha256 = Hmac<Sha256>;
+
+/// Everything the app needs from a bucket, so assets can live in S3 or on the local disk.
+pub trait ObjectStore: Send + Sync {
+    /// Returns AssetRetrieved, or InvalidRequest if the object is bigger than the size limit.
+    fn get_object(&self, address: &S3Address, size_limit: usize) -> impl Future<Output = Outcome> + Send;
+    /// Returns ObjectStored with the address that was written.
+    fn put_object(&self, address: &S3Address, bytes: Vec<u8>, content_disposition: Option<String>) -> impl Future<Output = Outcome> + Send;
+    fn delete_object(&self, address: &S3Address) -> impl Future<Output = Outcome> + Send;
+    /// Returns PresignedUrlRetrieved with a link anyone can use to download the object until it expires.
+    fn presigned_get_url(&self, address: &S3Address, expires_in: u64) -> impl Future<Output = Outcome> + Send;
+    /// Returns PresignedUrlRetrieved with a link anyone can use to upload the object until it expires.
+    fn presigned_put_url(&self, address: &S3Address, expires_in: u64) -> impl Future<Output = Outcome> + Send;
+}
+
+#[derive(Clone, Debug)]
+pub enum ObjectStoreBackend {
+    S3(S3Client),
+    Local(LocalObjectStore),
+}
+
+/// Reads OBJECT_STORE_BACKEND (s3 or local) and OBJECT_STORE_PATH, defaulting to s3.
+pub async fn setup_object_store() -> ObjectStoreBackend {
+    let backend = std::env::var(OBJECT_STORE_BACKEND_ENV_KEY).unwrap_or_default().to_lowercase();
+    match backend.as_str() {
+        "local" => ObjectStoreBackend::Local(LocalObjectStore::from_env()),
+        _ => ObjectStoreBackend::S3(setup_s3_client().await),
+    }
+}
+
+impl ObjectStore for ObjectStoreBackend {
+    async fn get_object(&self, address: &S3Address, size_limit: usize) -> Outcome {
+        match self {
+            ObjectStoreBackend::S3(client) => client.get_object(address, size_limit).await,
+            ObjectStoreBackend::Local(local) => local.get_object(address, size_limit).await,
+        }
+    }
+
+    async fn put_object(&self, address: &S3Address, bytes: Vec<u8>, content_disposition: Option<String>) -> Outcome {
+        match self {
+            ObjectStoreBackend::S3(client) => client.put_object(address, bytes, content_disposition).await,
+            ObjectStoreBackend::Local(local) => local.put_object(address, bytes, content_disposition).await,
+        }
+    }
+
+    async fn delete_object(&self, address: &S3Address) -> Outcome {
+        match self {
+            ObjectStoreBackend::S3(client) => client.delete_object(address).await,
+            ObjectStoreBackend::Local(local) => local.delete_object(address).await,
+        }
+    }
+
+    async fn presigned_get_url(&self, address: &S3Address, expires_in: u64) -> Outcome {
+        match self {
+            ObjectStoreBackend::S3(client) => client.presigned_get_url(address, expires_in).await,
+            ObjectStoreBackend::Local(local) => local.presigned_get_url(address, expires_in).await,
+        }
+    }
+
+    async fn presigned_put_url(&self, address: &S3Address, expires_in: u64) -> Outcome {
+        match self {
+            ObjectStoreBackend::S3(client) => client.presigned_put_url(address, expires_in).await,
+            ObjectStoreBackend::Local(local) => local.presigned_put_url(address, expires_in).await,
+        }
+    }
+}
+
+impl ObjectStore for S3Client {
+    async fn get_object(&self, address: &S3Address, size_limit: usize) -> Outcome {
+        let output = match S3Client::get_object(self).bucket(&address.bucket).key(&address.key).send().await {
+            Ok(output) => output,
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+        };
+
+        if output.content_length().unwrap_or_default() as usize > size_limit {
+            return Outcome::InvalidRequest;
+        }
+
+        match output.body.collect().await {
+            Ok(bytes) => Outcome::AssetRetrieved(bytes.into_bytes().to_vec()),
+            Err(e) => Outcome::UnspecifiedQueryFailure(e.to_string()),
+        }
+    }
+
+    async fn put_object(&self, address: &S3Address, bytes: Vec<u8>, content_disposition: Option<String>) -> Outcome {
+        let put_request = S3Client::put_object(self)
+            .bucket(&address.bucket)
+            .key(&address.key)
+            .set_content_disposition(content_disposition)
+            .body(ByteStream::from(bytes))
+            .send()
+            .await;
+
+        match put_request {
+            Ok(_) => Outcome::ObjectStored(address.clone()),
+            Err(e) => Outcome::AssetNotUploaded(e.into_service_error().to_string()),
+        }
+    }
+
+    async fn delete_object(&self, address: &S3Address) -> Outcome {
+        match S3Client::delete_object(self).bucket(&address.bucket).key(&address.key).send().await {
+            Ok(_) => Outcome::ObjectDeleted(address.clone()),
+            Err(e) => Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+        }
+    }
+
+    async fn presigned_get_url(&self, address: &S3Address, expires_in: u64) -> Outcome {
+        let presigned_request = S3Client::get_object(self)
+            .bucket(&address.bucket)
+            .key(&address.key)
+            .presigned(PresigningConfig::expires_in(Duration::from_secs(expires_in)).expect("crazy"))
+            .await;
+
+        match presigned_request {
+            Ok(request) => Outcome::PresignedUrlRetrieved(request.uri().to_string()),
+            Err(e) => Outcome::PresignedUrlNotRetrieved(e.into_service_error().to_string()),
+        }
+    }
+
+    async fn presigned_put_url(&self, address: &S3Address, expires_in: u64) -> Outcome {
+        let presigned_request = S3Client::put_object(self)
+            .bucket(&address.bucket)
+            .key(&address.key)
+            .presigned(PresigningConfig::expires_in(Duration::from_secs(expires_in)).expect("crazy"))
+            .await;
+
+        match presigned_request {
+            Ok(request) => Outcome::PresignedUrlRetrieved(request.uri().to_string()),
+            Err(e) => Outcome::PresignedUrlNotRetrieved(e.into_service_error().to_string()),
+        }
+    }
+}
+
+/// Keeps objects at root/bucket/key and hands out links to the local object route that are signed like S3 links.
+#[derive(Clone, Debug)]
+pub struct LocalObjectStore {
+    root: PathBuf,
+    secret: Vec<u8>,
+}
+
+impl LocalObjectStore {
+    pub fn new(root: PathBuf, secret: Vec<u8>) -> Self {
+        Self {root, secret}
+    }
+
+    /// Signs links with OBJECT_STORE_SECRET and panics without it, anyone could sign links with a missing or empty secret.
+    pub fn from_env() -> Self {
+        let root = PathBuf::from(std::env::var(OBJECT_STORE_PATH_ENV_KEY).unwrap_or(DEFAULT_OBJECT_STORE_PATH.to_string()));
+        let secret = std::env::var(OBJECT_STORE_SECRET_ENV_KEY)
+            .ok()
+            .filter(|secret| !!!secret.trim().is_empty())
+            .expect("OBJECT_STORE_SECRET must be set to use the local object store");
+        Self::new(root, secret.into_bytes())
+    }
+
+    fn object_path(&self, address: &S3Address) -> Option<PathBuf> {
+        if !!!is_safe_address(address) {
+            return None;
+        }
+        Some(self.root.join(&address.bucket).join(&address.key))
+    }
+
+    fn signed_url(&self, method: &Method, address: &S3Address, expires_in: u64) -> Outcome {
+        if !!!is_safe_address(address) {
+            return Outcome::PresignedUrlNotRetrieved("object address is not valid".to_string());
+        }
+        let amz_date = amz_date_from_secs(current_time_in_seconds());
+        let expires_in = expires_in.to_string();
+        let Some(signature) = self.signature(method, address, &amz_date, &expires_in) else {
+            return Outcome::PresignedUrlNotRetrieved("object store secret is not valid".to_string());
+        };
+
+        let encoded_key = address.key.split('/').map(percent_encode).collect::<Vec<String>>().join("/");
+        Outcome::PresignedUrlRetrieved(format!(
+            "{LOCAL_OBJECT_ROUTE}/{}/{encoded_key}?{S3_CREATION_DATE_URL_PARAM}{amz_date}&{S3_EXPIRATION_URL_PARAM}{expires_in}&{SIGNATURE_URL_PARAM}{signature}",
+            address.bucket,
+        ))
+    }
+
+    fn signature(&self, method: &Method, address: &S3Address, amz_date: &str, expires_in: &str) -> Option<String> {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).ok()?;
+        mac.update(signing_string(method, address, amz_date, expires_in).as_bytes());
+        Some(mac.finalize().into_bytes().iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+
+    fn verify_signature(&self, method: &Method, address: &S3Address, params: &HashMap<String, String>) -> bool {
+        let param = |name: &str| params.get(name.trim_end_matches('=')).cloned().unwrap_or_default();
+        let (amz_date, expires_in, signature) = (param(S3_CREATION_DATE_URL_PARAM), param(S3_EXPIRATION_URL_PARAM), param(SIGNATURE_URL_PARAM));
+
+        let (Some(created), Ok(expires_in_secs), Some(signature)) = (secs_from_amz_date(&amz_date), expires_in.parse::<u64>(), hex_decode(&signature)) else {
+            return false;
+        };
+
+        let Ok(mut mac) = HmacSha256::new_from_slice(&self.secret) else {return false};
+        mac.update(signing_string(method, address, &amz_date, &expires_in).as_bytes());
+        if mac.verify_slice(&signature).is_err() {
+            return false;
+        }
+
+        // The dates come from the link so they are only used once the signature shows this server set them
+        created.checked_add(expires_in_secs).is_some_and(|expires| current_time_in_seconds() <= expires)
+    }
+}
+
+impl ObjectStore for LocalObjectStore {
+    async fn get_object(&self, address: &S3Address, size_limit: usize) -> Outcome {
+        let Some(path) = self.object_path(address) else {return Outcome::InvalidRequest};
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.len() as usize > size_limit => return Outcome::InvalidRequest,
+            Ok(_) => (),
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.to_string()),
+        }
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Outcome::AssetRetrieved(bytes),
+            Err(e) => Outcome::UnspecifiedQueryFailure(e.to_string()),
+        }
+    }
+
+    async fn put_object(&self, address: &S3Address, bytes: Vec<u8>, _content_disposition: Option<String>) -> Outcome {
+        let Some(path) = self.object_path(address) else {return Outcome::InvalidRequest};
+        if let Some(parent) = path.parent() {
+            if let Err(e) = tokio::fs::create_dir_all(parent).await {
+                return Outcome::AssetNotUploaded(e.to_string());
+            }
+        }
+        match tokio::fs::write(&path, bytes).await {
+            Ok(_) => Outcome::ObjectStored(address.clone()),
+            Err(e) => Outcome::AssetNotUploaded(e.to_string()),
+        }
+    }
+
+    async fn delete_object(&self, address: &S3Address) -> Outcome {
+        let Some(path) = self.object_path(address) else {return Outcome::InvalidRequest};
+        match tokio::fs::remove_file(&path).await {
+            Ok(_) => Outcome::ObjectDeleted(address.clone()),
+            Err(e) => Outcome::UnspecifiedQueryFailure(e.to_string()),
+        }
+    }
+
+    async fn presigned_get_url(&self, address: &S3Address, expires_in: u64) -> Outcome {
+        self.signed_url(&Method::GET, address, expires_in)
+    }
+
+    async fn presigned_put_url(&self, address: &S3Address, expires_in: u64) -> Outcome {
+        self.signed_url(&Method::PUT, address, expires_in)
+    }
+}
+
+/// Serves and accepts objects for the local object store, but only through links it signed that have not expired.
+pub async fn local_object_handler(method: Method, Path((bucket, key)): Path<(String, String)>, Query(params): Query<HashMap<String, String>>, body: Bytes) -> Response<Body> {
+    let store = LocalObjectStore::from_env();
+    let address = S3Address {bucket, key};
+
+    let response = |status: StatusCode, body: Body| Response::builder().status(status).body(body).unwrap_or_default();
+
+    if !!!store.verify_signature(&method, &address, &params) {
+        return response(StatusCode::FORBIDDEN, Body::empty());
+    }
+
+    let outcome = match method {
+        Method::GET => store.get_object(&address, usize::MAX).await,
+        Method::PUT => store.put_object(&address, body.to_vec(), None).await,
+        _ => return response(StatusCode::METHOD_NOT_ALLOWED, Body::empty()),
+    };
+
+    match outcome {
+        Outcome::AssetRetrieved(bytes) => response(StatusCode::OK, Body::from(bytes)),
+        Outcome::ObjectStored(_) => response(StatusCode::OK, Body::empty()),
+        Outcome::InvalidRequest => response(StatusCode::BAD_REQUEST, Body::empty()),
+        _ => response(StatusCode::NOT_FOUND, Body::empty()),
+    }
+}
+
+fn signing_string(method: &Method, address: &S3Address, amz_date: &str, expires_in: &str) -> String {
+    format!("{method}\n{}/{}\n{amz_date}\n{expires_in}", address.bucket, address.key)
+}
+
+fn is_safe_address(address: &S3Address) -> bool {
+    let bucket_is_safe = !!!address.bucket.is_empty() && address.bucket.chars().all(|character| character.is_ascii_alphanumeric() || character == '-');
+    let key_is_safe = !!!address.key.is_empty() && !!!address.key.starts_with('/') && !!!address.key.contains('\\')
+        && address.key.split('/').all(|segment| !!!segment.is_empty() && segment != "." && segment != "..");
+    bucket_is_safe && key_is_safe
+}
+
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            any_other_byte => encoded.push_str(&format!("%{any_other_byte:02X}")),
+        }
+    }
+    encoded
+}
+
+pub(crate) fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Formats seconds since the epoch the way S3 does in X-Amz-Date, e.g. 20240509T053932Z.
+fn amz_date_from_secs(seconds: u64) -> String {
+    let (year, month, day) = civil_from_days((seconds / Date::SECONDS_IN_DAY) as i64);
+    let seconds_today = seconds % Date::SECONDS_IN_DAY;
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        seconds_today / Date::SECONDS_IN_HOUR,
+        (seconds_today % Date::SECONDS_IN_HOUR) / Date::SECONDS_IN_MINUTE,
+        seconds_today % Date::SECONDS_IN_MINUTE,
+    )
+}
+
+fn secs_from_amz_date(amz_date: &str) -> Option<u64> {
+    if amz_date.len() != 16 || amz_date.get(8..9)? != "T" || !!!amz_date.ends_with('Z') {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| amz_date.get(range)?.parse::<i64>().ok();
+
+    let days = days_from_civil(number(0..4)?, number(4..6)?, number(6..8)?);
+    let seconds = days * Date::SECONDS_IN_DAY as i64 + number(9..11)? * Date::SECONDS_IN_HOUR as i64 + number(11..13)? * Date::SECONDS_IN_MINUTE as i64 + number(13..15)?;
+    u64::try_from(seconds).ok()
+}
+
+// Converts between days since the epoch and the gregorian calendar, see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {month_index + 3} else {month_index - 9};
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 {year - 1} else {year};
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 {month - 3} else {month + 9};
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> LocalObjectStore {
+        LocalObjectStore::new(PathBuf::from(DEFAULT_OBJECT_STORE_PATH), b"object-store-secret".to_vec())
+    }
+
+    fn address(key: &str) -> S3Address {
+        S3Address {bucket: "lexdecks".to_string(), key: key.to_string()}
+    }
+
+    fn url_params(url: &str) -> HashMap<String, String> {
+        let (_, query) = url.split_once('?').unwrap_or_default();
+        query.split('&').filter_map(|param| param.split_once('=')).map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    fn signed_params(store: &LocalObjectStore, method: &Method, address: &S3Address, created: u64, expires_in: u64) -> HashMap<String, String> {
+        let amz_date = amz_date_from_secs(created);
+        let expires_in = expires_in.to_string();
+        let signature = store.signature(method, address, &amz_date, &expires_in).unwrap();
+        HashMap::from([
+            (S3_CREATION_DATE_URL_PARAM.trim_end_matches('=').to_string(), amz_date),
+            (S3_EXPIRATION_URL_PARAM.trim_end_matches('=').to_string(), expires_in),
+            (SIGNATURE_URL_PARAM.trim_end_matches('=').to_string(), signature),
+        ])
+    }
+
+    #[test]
+    fn signed_links_verify_for_their_method_and_address() {
+        let store = store();
+        let address = address("deck/cover.avif");
+        let Outcome::PresignedUrlRetrieved(url) = store.signed_url(&Method::GET, &address, 60) else {panic!("link was not signed")};
+        let params = url_params(&url);
+
+        assert!(url.starts_with(&format!("{LOCAL_OBJECT_ROUTE}/lexdecks/deck/cover.avif?")));
+        assert!(store.verify_signature(&Method::GET, &address, &params));
+        assert!(!!!store.verify_signature(&Method::PUT, &address, &params));
+        assert!(!!!store.verify_signature(&Method::GET, &self::address("deck/other.avif"), &params));
+        assert!(!!!LocalObjectStore::new(PathBuf::new(), b"another-secret".to_vec()).verify_signature(&Method::GET, &address, &params));
+    }
+
+    #[test]
+    fn tampered_links_are_refused() {
+        let store = store();
+        let address = address("deck/cover.avif");
+        let mut params = signed_params(&store, &Method::GET, &address, current_time_in_seconds(), 60);
+        params.insert(S3_EXPIRATION_URL_PARAM.trim_end_matches('=').to_string(), "600000".to_string());
+
+        assert!(!!!store.verify_signature(&Method::GET, &address, &params));
+    }
+
+    #[test]
+    fn expired_links_are_refused() {
+        let store = store();
+        let address = address("deck/cover.avif");
+        let params = signed_params(&store, &Method::GET, &address, current_time_in_seconds() - 120, 60);
+
+        assert!(!!!store.verify_signature(&Method::GET, &address, &params));
+    }
+
+    #[test]
+    fn expiries_too_large_to_add_are_refused() {
+        let store = store();
+        let address = address("deck/cover.avif");
+        let params = signed_params(&store, &Method::GET, &address, current_time_in_seconds(), u64::MAX);
+
+        assert!(!!!store.verify_signature(&Method::GET, &address, &params));
+    }
+
+    #[test]
+    fn addresses_cannot_leave_the_store() {
+        assert!(is_safe_address(&address("deck/cover.avif")));
+        assert!(!!!is_safe_address(&address("../secrets")));
+        assert!(!!!is_safe_address(&address("deck/../../secrets")));
+        assert!(!!!is_safe_address(&address("/etc/passwd")));
+        assert!(!!!is_safe_address(&address("deck\\cover.avif")));
+        assert!(!!!is_safe_address(&address("deck//cover.avif")));
+        assert!(!!!is_safe_address(&S3Address {bucket: "../lexdecks".to_string(), key: "cover.avif".to_string()}));
+    }
+
+    #[test]
+    fn amz_dates_match_s3() {
+        assert_eq!(amz_date_from_secs(1715233172), "20240509T053932Z");
+        assert_eq!(secs_from_amz_date("20240509T053932Z"), Some(1715233172));
+        assert_eq!(secs_from_amz_date(&amz_date_from_secs(951782400)), Some(951782400));
+        assert_eq!(secs_from_amz_date("2024-05-09T05:39Z"), None);
+        assert_eq!(secs_from_amz_date("20240509 053932Z"), None);
+        assert_eq!(secs_from_amz_date("19690101T000000Z"), None);
+    }
+}
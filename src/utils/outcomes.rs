@@ -1,9 +1,19 @@
-use std::str::FromStr;
+use std::{collections::BTreeMap, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 use super::sign_in_lib::TokenPair;
 use super::user_types::{PartialUserInfo, UserInfo};
-use super::database_types::UpdateRecipes;
+use super::database_types::{Asset, DeckId, Note, S3Address, UpdateRecipes};
+use super::deck_import::ImportedDeck;
+use super::upload_ledger::LedgerEntry;
+use super::scheduler::ReviewState;
+use super::query::QueryCursor;
+use super::note_templates::StudyCard;
+use super::catalog::{CatalogEntry, CatalogPage};
+use super::purchases::{PayoutAccount, Receipt};
+use super::collaborators::Collaborator;
+use super::sync_queue::SyncRecord;
+use super::cache_db_interface::CacheStatus;
 
 pub const OUTCOME_SEPARATOR: &str = "|x|X|x|X|x|";
 
@@ -43,25 +53,68 @@ pub enum Outcome {
     CacheFailed(String),
     CacheSucceeded,
     AlreadyCached(String),
+    PrefetchAlreadyRunning,
+    CacheStatusSet(CacheStatus),
+    DeckVersionsChanged(Vec<DeckId>),
 
     ItemsNotFound,
     UnspecifiedQueryFailure(String),
     ItemsFound(String),
+    PartialItemsFound(String, QueryCursor),
     AssetRetrieved(Vec<u8>),
 
     PresignedUrlNotRetrieved(String),
     PresignedUrlRetrieved(String),
+    AssetUploaded(Asset),
+    AssetNotUploaded(String),
+    ObjectStored(S3Address),
+    ObjectDeleted(S3Address),
+    DeckUploadUrlRetrieved(DeckId, String),
     DeckNotUploadedToBucket,
     DeckUploadedToBucket(String),
     DeckProcessed(String),
     DeckCouldNotBeProcessed(String),
+    DeckImported(ImportedDeck),
+    DeckExported(String),
     NotEnoughUploadTokens(f64),
+    LedgerEntriesFound(Vec<LedgerEntry>),
     IncorrectType,
     TooManyFiles,
     TooManyDecks,
 
     NoteUpdateFailed(String),
     NoteUpdateSuccess,
+    NoteConflict(Note),
+    NoteVersionsFound(BTreeMap<u64, u64>),
+
+    ReviewStatesFound(Vec<ReviewState>),
+    ReviewStateUpdateFailed(String),
+    CardGraded(ReviewState),
+    StudyCardsFound(Vec<StudyCard>),
+    NoteFound(Note),
+    DeckNotesFound(Vec<Note>),
+
+    CatalogEntryFound(CatalogEntry),
+    CatalogPageFound(CatalogPage),
+    AlreadySubscribed,
+    PurchaseRequired(DeckId, f32),
+
+    CheckoutStarted(String, String),
+    PaymentProviderFailure(String),
+    RefundIssued(String),
+    PayoutSent(String),
+    PayoutReversed(String),
+    PayoutOnboardingStarted(String, String),
+    PayoutAccountFound(PayoutAccount),
+    ReceiptFound(Receipt),
+    ReceiptsFound(Vec<Receipt>),
+
+    CollaboratorFound(Collaborator),
+    CollaboratorsFound(Vec<Collaborator>),
+    TooManyCollaborators,
+
+    SyncRecordFound(SyncRecord),
+    ChangeQueued,
 
     MultiOutcome(Vec<Outcome>),
 }
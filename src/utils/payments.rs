@@ -0,0 +1,546 @@
+use std::{collections::HashMap, future::Future};
+
+use axum::{body::{Body, Bytes}, extract::Query, http::{header::LOCATION, HeaderMap, Response, StatusCode}};
+use hmac::Mac;
+use leptos::logging::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::{
+    database_types::DeckId,
+    date_and_time::current_time_in_seconds,
+    object_store::{hex_decode, HmacSha256},
+    outcomes::Outcome,
+    purchases::handle_payment_event,
+    shared_truth::FAKE_CHECKOUT_ROUTE,
+    storage::setup_storage,
+};
+
+pub const PAYMENT_PROVIDER_ENV_KEY: &str = "PAYMENT_PROVIDER";
+pub const STRIPE_SECRET_KEY_ENV_KEY: &str = "STRIPE_SECRET_KEY";
+pub const STRIPE_WEBHOOK_SECRET_ENV_KEY: &str = "STRIPE_WEBHOOK_SECRET";
+pub const FAKE_PAYMENT_SECRET_ENV_KEY: &str = "FAKE_PAYMENT_SECRET";
+pub const FAKE_SIGNATURE_HEADER: &str = "Fake-Signature";
+
+const STRIPE_API_URL: &str = "https://api.stripe.com/v1";
+const STRIPE_SIGNATURE_HEADER: &str = "Stripe-Signature";
+const WEBHOOK_TOLERANCE_IN_SECONDS: u64 = 300;
+const CURRENCY: &str = "usd";
+
+/// What the buyer is asked to pay for, amounts are in cents so they never drift through float math.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkout {
+    pub user: String,
+    pub deck_id: DeckId,
+    pub deck_name: String,
+    pub amount_in_cents: u64,
+    pub success_url: String,
+    pub cancel_url: String,
+}
+
+/// The webhook events the app acts on, everything else the provider sends is Ignored.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PaymentEvent {
+    PaymentSucceeded {checkout_id: String, payment_id: String, user: String, deck_id: DeckId, amount_in_cents: u64},
+    PaymentRefunded {payment_id: String},
+    PayoutAccountReady {account_id: String},
+    Ignored,
+}
+
+/// Everything the app needs from a payment provider, so purchases can go through Stripe or a local fake.
+pub trait PaymentProvider: Send + Sync {
+    /// Returns CheckoutStarted with the checkout id and the page the buyer pays on.
+    fn create_checkout(&self, checkout: &Checkout) -> impl Future<Output = Outcome> + Send;
+    /// Returns None unless the provider signed the body recently.
+    fn verify_webhook(&self, headers: &HeaderMap, body: &[u8]) -> Option<PaymentEvent>;
+    /// Returns RefundIssued, the full amount of the payment goes back to the buyer.
+    fn refund(&self, payment_id: &str) -> impl Future<Output = Outcome> + Send;
+    /// Returns PayoutSent with the id of the transfer to the owner's account.
+    fn pay_out(&self, account_id: &str, amount_in_cents: u64, payment_id: &str) -> impl Future<Output = Outcome> + Send;
+    /// Returns PayoutReversed, taking a payout back from the owner's account.
+    fn reverse_payout(&self, payout_id: &str) -> impl Future<Output = Outcome> + Send;
+    /// Returns PayoutOnboardingStarted with the owner's account id and the page they finish setting it up on.
+    /// A new account is made when account_id is None.
+    fn payout_onboarding(&self, email: &str, account_id: Option<&str>, return_url: &str) -> impl Future<Output = Outcome> + Send;
+}
+
+#[derive(Clone, Debug)]
+pub enum PaymentProviderBackend {
+    Stripe(StripeProvider),
+    Fake(FakePaymentProvider),
+}
+
+/// Reads PAYMENT_PROVIDER (stripe or fake), defaulting to stripe. Panics if the provider's webhook secret is missing.
+pub fn setup_payment_provider() -> PaymentProviderBackend {
+    let backend = std::env::var(PAYMENT_PROVIDER_ENV_KEY).unwrap_or_default().to_lowercase();
+    match backend.as_str() {
+        "fake" => PaymentProviderBackend::Fake(FakePaymentProvider::from_env()),
+        _ => PaymentProviderBackend::Stripe(StripeProvider::from_env()),
+    }
+}
+
+impl PaymentProvider for PaymentProviderBackend {
+    async fn create_checkout(&self, checkout: &Checkout) -> Outcome {
+        match self {
+            PaymentProviderBackend::Stripe(stripe) => stripe.create_checkout(checkout).await,
+            PaymentProviderBackend::Fake(fake) => fake.create_checkout(checkout).await,
+        }
+    }
+
+    fn verify_webhook(&self, headers: &HeaderMap, body: &[u8]) -> Option<PaymentEvent> {
+        match self {
+            PaymentProviderBackend::Stripe(stripe) => stripe.verify_webhook(headers, body),
+            PaymentProviderBackend::Fake(fake) => fake.verify_webhook(headers, body),
+        }
+    }
+
+    async fn refund(&self, payment_id: &str) -> Outcome {
+        match self {
+            PaymentProviderBackend::Stripe(stripe) => stripe.refund(payment_id).await,
+            PaymentProviderBackend::Fake(fake) => fake.refund(payment_id).await,
+        }
+    }
+
+    async fn pay_out(&self, account_id: &str, amount_in_cents: u64, payment_id: &str) -> Outcome {
+        match self {
+            PaymentProviderBackend::Stripe(stripe) => stripe.pay_out(account_id, amount_in_cents, payment_id).await,
+            PaymentProviderBackend::Fake(fake) => fake.pay_out(account_id, amount_in_cents, payment_id).await,
+        }
+    }
+
+    async fn reverse_payout(&self, payout_id: &str) -> Outcome {
+        match self {
+            PaymentProviderBackend::Stripe(stripe) => stripe.reverse_payout(payout_id).await,
+            PaymentProviderBackend::Fake(fake) => fake.reverse_payout(payout_id).await,
+        }
+    }
+
+    async fn payout_onboarding(&self, email: &str, account_id: Option<&str>, return_url: &str) -> Outcome {
+        match self {
+            PaymentProviderBackend::Stripe(stripe) => stripe.payout_onboarding(email, account_id, return_url).await,
+            PaymentProviderBackend::Fake(fake) => fake.payout_onboarding(email, account_id, return_url).await,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StripeProvider {
+    client: reqwest::Client,
+    secret_key: String,
+    webhook_secret: Vec<u8>,
+}
+
+impl StripeProvider {
+    pub fn new(secret_key: String, webhook_secret: Vec<u8>) -> Self {
+        Self {client: reqwest::Client::new(), secret_key, webhook_secret}
+    }
+
+    pub fn from_env() -> Self {
+        let secret_key = std::env::var(STRIPE_SECRET_KEY_ENV_KEY).unwrap_or_default();
+        Self::new(secret_key, required_secret(STRIPE_WEBHOOK_SECRET_ENV_KEY))
+    }
+
+    /// Stripe takes form encoded bodies, the idempotency key makes retried requests safe to send twice.
+    async fn post(&self, path: &str, form: &[(String, String)], idempotency_key: Option<String>) -> Result<Value, String> {
+        let mut request = self.client.post(format!("{STRIPE_API_URL}{path}")).bearer_auth(&self.secret_key).form(form);
+        if let Some(idempotency_key) = idempotency_key {
+            request = request.header("Idempotency-Key", idempotency_key);
+        }
+
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        let body: Value = response.json().await.map_err(|e| e.to_string())?;
+
+        if !!!status.is_success() {
+            return Err(body["error"]["message"].as_str().unwrap_or("stripe request failed").to_string());
+        }
+        Ok(body)
+    }
+
+    /// The Stripe-Signature header looks like t=1700000000,v1=hex, where v1 signs "{t}.{body}".
+    fn verify_signature(&self, headers: &HeaderMap, body: &[u8]) -> bool {
+        if self.webhook_secret.is_empty() {
+            return false;
+        }
+        let Some(signature_header) = headers.get(STRIPE_SIGNATURE_HEADER).and_then(|header| header.to_str().ok()) else {return false};
+
+        let mut timestamp = None;
+        let mut signatures = Vec::new();
+        for part in signature_header.split(',') {
+            match part.split_once('=') {
+                Some(("t", value)) => timestamp = value.parse::<u64>().ok(),
+                Some(("v1", value)) => signatures.extend(hex_decode(value)),
+                _ => (),
+            }
+        }
+
+        let Some(timestamp) = timestamp else {return false};
+        if current_time_in_seconds().abs_diff(timestamp) > WEBHOOK_TOLERANCE_IN_SECONDS {
+            return false;
+        }
+
+        signatures.iter().any(|signature| {
+            let Ok(mut mac) = HmacSha256::new_from_slice(&self.webhook_secret) else {return false};
+            mac.update(format!("{timestamp}.").as_bytes());
+            mac.update(body);
+            mac.verify_slice(signature).is_ok()
+        })
+    }
+}
+
+impl PaymentProvider for StripeProvider {
+    async fn create_checkout(&self, checkout: &Checkout) -> Outcome {
+        let deck_id = checkout.deck_id.to_string();
+        let form = [
+            ("mode", "payment".to_string()),
+            ("success_url", checkout.success_url.clone()),
+            ("cancel_url", checkout.cancel_url.clone()),
+            ("customer_email", checkout.user.clone()),
+            ("client_reference_id", deck_id.clone()),
+            ("line_items[0][quantity]", "1".to_string()),
+            ("line_items[0][price_data][currency]", CURRENCY.to_string()),
+            ("line_items[0][price_data][unit_amount]", checkout.amount_in_cents.to_string()),
+            ("line_items[0][price_data][product_data][name]", checkout.deck_name.clone()),
+            ("metadata[user]", checkout.user.clone()),
+            ("metadata[deck_id]", deck_id),
+        ].map(|(key, value)| (key.to_string(), value));
+
+        match self.post("/checkout/sessions", &form, None).await {
+            Ok(session) => match (session["id"].as_str(), session["url"].as_str()) {
+                (Some(checkout_id), Some(url)) => Outcome::CheckoutStarted(checkout_id.to_string(), url.to_string()),
+                _ => Outcome::PaymentProviderFailure("stripe did not return a checkout page".to_string()),
+            },
+            Err(e) => Outcome::PaymentProviderFailure(e),
+        }
+    }
+
+    fn verify_webhook(&self, headers: &HeaderMap, body: &[u8]) -> Option<PaymentEvent> {
+        if !!!self.verify_signature(headers, body) {
+            return None;
+        }
+        let event: Value = serde_json::from_slice(body).ok()?;
+        let object = &event["data"]["object"];
+
+        let payment_event = match event["type"].as_str()? {
+            "checkout.session.completed" if object["payment_status"].as_str() == Some("paid") => PaymentEvent::PaymentSucceeded {
+                checkout_id: object["id"].as_str()?.to_string(),
+                payment_id: object["payment_intent"].as_str()?.to_string(),
+                user: object["metadata"]["user"].as_str()?.to_string(),
+                deck_id: object["metadata"]["deck_id"].as_str()?.parse().ok()?,
+                amount_in_cents: object["amount_total"].as_u64()?,
+            },
+            // Partial refunds leave the buyer with the deck
+            "charge.refunded" if object["refunded"].as_bool() == Some(true) => PaymentEvent::PaymentRefunded {
+                payment_id: object["payment_intent"].as_str()?.to_string(),
+            },
+            "account.updated" if object["payouts_enabled"].as_bool() == Some(true) => PaymentEvent::PayoutAccountReady {
+                account_id: object["id"].as_str()?.to_string(),
+            },
+            _ => PaymentEvent::Ignored,
+        };
+        Some(payment_event)
+    }
+
+    async fn refund(&self, payment_id: &str) -> Outcome {
+        let form = [("payment_intent".to_string(), payment_id.to_string())];
+        match self.post("/refunds", &form, Some(format!("refund-{payment_id}"))).await {
+            Ok(refund) => Outcome::RefundIssued(refund["id"].as_str().unwrap_or_default().to_string()),
+            Err(e) => Outcome::PaymentProviderFailure(e),
+        }
+    }
+
+    async fn pay_out(&self, account_id: &str, amount_in_cents: u64, payment_id: &str) -> Outcome {
+        let form = [
+            ("amount", amount_in_cents.to_string()),
+            ("currency", CURRENCY.to_string()),
+            ("destination", account_id.to_string()),
+            ("transfer_group", payment_id.to_string()),
+        ].map(|(key, value)| (key.to_string(), value));
+
+        match self.post("/transfers", &form, Some(format!("payout-{payment_id}"))).await {
+            Ok(transfer) => match transfer["id"].as_str() {
+                Some(payout_id) => Outcome::PayoutSent(payout_id.to_string()),
+                None => Outcome::PaymentProviderFailure("stripe did not return a transfer".to_string()),
+            },
+            Err(e) => Outcome::PaymentProviderFailure(e),
+        }
+    }
+
+    async fn reverse_payout(&self, payout_id: &str) -> Outcome {
+        match self.post(&format!("/transfers/{payout_id}/reversals"), &[], Some(format!("reversal-{payout_id}"))).await {
+            Ok(_) => Outcome::PayoutReversed(payout_id.to_string()),
+            Err(e) => Outcome::PaymentProviderFailure(e),
+        }
+    }
+
+    async fn payout_onboarding(&self, email: &str, account_id: Option<&str>, return_url: &str) -> Outcome {
+        let account_id = match account_id {
+            Some(account_id) => account_id.to_string(),
+            None => {
+                let form = [("type", "express"), ("email", email)].map(|(key, value)| (key.to_string(), value.to_string()));
+                match self.post("/accounts", &form, None).await {
+                    Ok(account) => match account["id"].as_str() {
+                        Some(account_id) => account_id.to_string(),
+                        None => return Outcome::PaymentProviderFailure("stripe did not return an account".to_string()),
+                    },
+                    Err(e) => return Outcome::PaymentProviderFailure(e),
+                }
+            },
+        };
+
+        let form = [
+            ("account", account_id.as_str()),
+            ("refresh_url", return_url),
+            ("return_url", return_url),
+            ("type", "account_onboarding"),
+        ].map(|(key, value)| (key.to_string(), value.to_string()));
+
+        match self.post("/account_links", &form, None).await {
+            Ok(link) => match link["url"].as_str() {
+                Some(url) => Outcome::PayoutOnboardingStarted(account_id, url.to_string()),
+                None => Outcome::PaymentProviderFailure("stripe did not return an onboarding link".to_string()),
+            },
+            Err(e) => Outcome::PaymentProviderFailure(e),
+        }
+    }
+}
+
+/// Takes no money, checkouts are links back to this server that complete the payment when they are opened.
+/// Webhooks are the event as json signed in the Fake-Signature header, so tests can send their own.
+#[derive(Clone, Debug)]
+pub struct FakePaymentProvider {
+    secret: Vec<u8>,
+}
+
+impl FakePaymentProvider {
+    pub fn new(secret: Vec<u8>) -> Self {
+        Self {secret}
+    }
+
+    /// Uses FAKE_PAYMENT_SECRET to sign checkouts and webhooks.
+    pub fn from_env() -> Self {
+        Self::new(required_secret(FAKE_PAYMENT_SECRET_ENV_KEY))
+    }
+
+    /// Returns the body and Fake-Signature header for a webhook carrying the event.
+    pub fn signed_webhook(&self, event: &PaymentEvent) -> Option<(String, String)> {
+        let body = serde_json::to_string(event).ok()?;
+        let signature = self.signature(body.as_bytes())?;
+        Some((body, signature))
+    }
+
+    fn signature(&self, message: &[u8]) -> Option<String> {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).ok()?;
+        mac.update(message);
+        Some(hex_encode(&mac.finalize().into_bytes()))
+    }
+
+    fn verify_signature(&self, message: &[u8], signature: &str) -> bool {
+        if self.secret.is_empty() {
+            return false;
+        }
+        let Some(signature) = hex_decode(signature) else {return false};
+        let Ok(mut mac) = HmacSha256::new_from_slice(&self.secret) else {return false};
+        mac.update(message);
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    /// The payment a fake checkout link completes, or None if the link was not signed by this provider.
+    fn completed_checkout(&self, params: &HashMap<String, String>) -> Option<(PaymentEvent, String)> {
+        let param = |name: &str| params.get(name).cloned().unwrap_or_default();
+        let (checkout_id, user, deck_id, amount, success_url) = (param("checkout_id"), param("user"), param("deck_id"), param("amount"), param("success_url"));
+
+        let message = fake_checkout_message(&checkout_id, &user, &deck_id, &amount, &success_url);
+        if !!!self.verify_signature(message.as_bytes(), &param("signature")) {
+            return None;
+        }
+
+        let event = PaymentEvent::PaymentSucceeded {
+            payment_id: checkout_id.replacen("fake_cs_", "fake_pi_", 1),
+            checkout_id,
+            user,
+            deck_id: deck_id.parse().ok()?,
+            amount_in_cents: amount.parse().ok()?,
+        };
+        Some((event, success_url))
+    }
+}
+
+impl PaymentProvider for FakePaymentProvider {
+    async fn create_checkout(&self, checkout: &Checkout) -> Outcome {
+        let (deck_id, amount) = (checkout.deck_id.to_string(), checkout.amount_in_cents.to_string());
+        let checkout_id = format!("fake_cs_{}_{deck_id}", current_time_in_seconds());
+
+        let message = fake_checkout_message(&checkout_id, &checkout.user, &deck_id, &amount, &checkout.success_url);
+        let Some(signature) = self.signature(message.as_bytes()) else {
+            return Outcome::PaymentProviderFailure("fake payment secret is not valid".to_string());
+        };
+
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("checkout_id", &checkout_id)
+            .append_pair("user", &checkout.user)
+            .append_pair("deck_id", &deck_id)
+            .append_pair("amount", &amount)
+            .append_pair("success_url", &checkout.success_url)
+            .append_pair("signature", &signature)
+            .finish();
+
+        Outcome::CheckoutStarted(checkout_id, format!("{FAKE_CHECKOUT_ROUTE}?{query}"))
+    }
+
+    fn verify_webhook(&self, headers: &HeaderMap, body: &[u8]) -> Option<PaymentEvent> {
+        let signature = headers.get(FAKE_SIGNATURE_HEADER)?.to_str().ok()?;
+        if !!!self.verify_signature(body, signature) {
+            return None;
+        }
+        serde_json::from_slice(body).ok()
+    }
+
+    async fn refund(&self, payment_id: &str) -> Outcome {
+        Outcome::RefundIssued(payment_id.replacen("fake_pi_", "fake_re_", 1))
+    }
+
+    async fn pay_out(&self, _account_id: &str, _amount_in_cents: u64, payment_id: &str) -> Outcome {
+        Outcome::PayoutSent(payment_id.replacen("fake_pi_", "fake_tr_", 1))
+    }
+
+    async fn reverse_payout(&self, payout_id: &str) -> Outcome {
+        Outcome::PayoutReversed(payout_id.to_string())
+    }
+
+    async fn payout_onboarding(&self, email: &str, account_id: Option<&str>, return_url: &str) -> Outcome {
+        let account_id = account_id.map(str::to_string).unwrap_or(format!("fake_acct_{email}"));
+        Outcome::PayoutOnboardingStarted(account_id, return_url.to_string())
+    }
+}
+
+/// Receives events from the payment provider, anything not signed by it is rejected.
+/// Failures that could still go through return a server error so the provider sends the event again later,
+/// events that will fail the same way every time are logged and acknowledged.
+pub async fn payment_webhook_handler(headers: HeaderMap, body: Bytes) -> StatusCode {
+    let provider = setup_payment_provider();
+    let Some(event) = provider.verify_webhook(&headers, &body) else {return StatusCode::BAD_REQUEST};
+
+    let storage = setup_storage().await;
+    match handle_payment_event(&storage, &provider, event).await {
+        Outcome::DatabaseUpdateSuccess(_) => StatusCode::OK,
+        // A payment with no receipt behind it or a user that no longer exists is never going to be recorded
+        permanent_failure @ (Outcome::ItemsNotFound | Outcome::UserNotFound | Outcome::IncorrectType | Outcome::InvalidRequest) => {
+            error!("payment event can never be handled: {}", permanent_failure.to_string());
+            StatusCode::OK
+        },
+        any_other_outcome => {
+            error!("payment event was not handled: {}", any_other_outcome.to_string());
+            StatusCode::INTERNAL_SERVER_ERROR
+        },
+    }
+}
+
+/// Completes the payment behind a fake checkout link then sends the buyer on to the success page.
+/// Only answers when the fake provider is in use.
+pub async fn fake_checkout_handler(Query(params): Query<HashMap<String, String>>) -> Response<Body> {
+    let response = |status: StatusCode| Response::builder().status(status).body(Body::empty()).unwrap_or_default();
+
+    let PaymentProviderBackend::Fake(fake) = setup_payment_provider() else {return response(StatusCode::NOT_FOUND)};
+    let Some((event, success_url)) = fake.completed_checkout(&params) else {return response(StatusCode::FORBIDDEN)};
+
+    let storage = setup_storage().await;
+    match handle_payment_event(&storage, &PaymentProviderBackend::Fake(fake), event).await {
+        Outcome::DatabaseUpdateSuccess(_) => Response::builder().status(StatusCode::SEE_OTHER).header(LOCATION, success_url).body(Body::empty()).unwrap_or_default(),
+        _ => response(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Panics when the secret is missing or empty, anyone could sign a webhook that checks against an empty one.
+fn required_secret(env_key: &str) -> Vec<u8> {
+    std::env::var(env_key)
+        .ok()
+        .filter(|secret| !!!secret.trim().is_empty())
+        .unwrap_or_else(|| panic!("{env_key} must be set to verify payment webhooks"))
+        .into_bytes()
+}
+
+fn fake_checkout_message(checkout_id: &str, user: &str, deck_id: &str, amount: &str, success_url: &str) -> String {
+    format!("{checkout_id}\n{user}\n{deck_id}\n{amount}\n{success_url}")
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEBHOOK_SECRET: &[u8] = b"whsec_test";
+
+    fn stripe_headers(secret: &[u8], timestamp: u64, body: &str) -> HeaderMap {
+        let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+        mac.update(format!("{timestamp}.{body}").as_bytes());
+        let signature = hex_encode(&mac.finalize().into_bytes());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(STRIPE_SIGNATURE_HEADER, format!("t={timestamp},v1={signature}").parse().unwrap());
+        headers
+    }
+
+    fn refund_event() -> String {
+        r#"{"type":"charge.refunded","data":{"object":{"refunded":true,"payment_intent":"pi_1"}}}"#.to_string()
+    }
+
+    #[test]
+    fn stripe_webhooks_signed_with_the_secret_are_read() {
+        let stripe = StripeProvider::new(String::new(), WEBHOOK_SECRET.to_vec());
+        let body = refund_event();
+        let headers = stripe_headers(WEBHOOK_SECRET, current_time_in_seconds(), &body);
+
+        assert_eq!(stripe.verify_webhook(&headers, body.as_bytes()), Some(PaymentEvent::PaymentRefunded {payment_id: "pi_1".to_string()}));
+    }
+
+    #[test]
+    fn stripe_webhooks_with_a_changed_body_or_another_secret_are_rejected() {
+        let stripe = StripeProvider::new(String::new(), WEBHOOK_SECRET.to_vec());
+        let body = refund_event();
+        let now = current_time_in_seconds();
+
+        let changed_body = body.replace("pi_1", "pi_2");
+        assert_eq!(stripe.verify_webhook(&stripe_headers(WEBHOOK_SECRET, now, &body), changed_body.as_bytes()), None);
+        assert_eq!(stripe.verify_webhook(&stripe_headers(b"whsec_other", now, &body), body.as_bytes()), None);
+        assert_eq!(stripe.verify_webhook(&HeaderMap::new(), body.as_bytes()), None);
+    }
+
+    #[test]
+    fn stripe_webhooks_outside_the_tolerance_are_rejected() {
+        let stripe = StripeProvider::new(String::new(), WEBHOOK_SECRET.to_vec());
+        let body = refund_event();
+        let old_timestamp = current_time_in_seconds() - WEBHOOK_TOLERANCE_IN_SECONDS - 60;
+
+        assert_eq!(stripe.verify_webhook(&stripe_headers(WEBHOOK_SECRET, old_timestamp, &body), body.as_bytes()), None);
+    }
+
+    #[test]
+    fn an_empty_secret_rejects_every_webhook() {
+        let stripe = StripeProvider::new(String::new(), Vec::new());
+        let body = refund_event();
+        assert_eq!(stripe.verify_webhook(&stripe_headers(&[], current_time_in_seconds(), &body), body.as_bytes()), None);
+
+        let fake = FakePaymentProvider::new(Vec::new());
+        let (body, _) = FakePaymentProvider::new(WEBHOOK_SECRET.to_vec()).signed_webhook(&PaymentEvent::Ignored).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(FAKE_SIGNATURE_HEADER, fake.signature(body.as_bytes()).unwrap().parse().unwrap());
+        assert_eq!(fake.verify_webhook(&headers, body.as_bytes()), None);
+    }
+
+    #[test]
+    fn fake_webhooks_only_verify_with_their_own_secret() {
+        let fake = FakePaymentProvider::new(WEBHOOK_SECRET.to_vec());
+        let event = PaymentEvent::PayoutAccountReady {account_id: "fake_acct_owner".to_string()};
+        let (body, signature) = fake.signed_webhook(&event).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(FAKE_SIGNATURE_HEADER, signature.parse().unwrap());
+
+        assert_eq!(fake.verify_webhook(&headers, body.as_bytes()), Some(event));
+        assert_eq!(FakePaymentProvider::new(b"other".to_vec()).verify_webhook(&headers, body.as_bytes()), None);
+    }
+}
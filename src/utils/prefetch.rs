@@ -0,0 +1,150 @@
+use std::{collections::BTreeSet, str::FromStr, sync::atomic::{AtomicBool, Ordering}, time::Duration};
+
+use leptos::{logging::debug_warn, prelude::{RwSignal, Set}};
+
+use crate::utils::{
+    cache_db_interface::{begin_cache_prefetch, check_cache_status, get_asset, get_cache_status_client, get_user_info, retrieve_notes, try_get_review_states, CacheStatus},
+    cache_manager::{mark_deck_cached, put_asset, read_asset},
+    database_types::{Asset, DeckId, DeckList, NoteList},
+    date_and_time::current_time_in_seconds,
+    front_utils::{download_as_blob, sleep_client},
+    outcomes::Outcome,
+    query::ValidQueryTypes,
+    shared_truth::{MAX_LEVELS, PREFETCH_REQUEST_INTERVAL_IN_MILLIS},
+    user_types::UserState,
+};
+
+static PREFETCHING: AtomicBool = AtomicBool::new(false);
+
+/// Caches the due and upcoming notes of every active deck that changed since it was last cached, along with the deck's
+/// images. Requests are spaced out so the prefetch never trips the rate limiter for the pages the user is on.
+pub async fn prefetch_active_decks(user_state: UserState, cache_status: RwSignal<CacheStatus>) -> Outcome {
+    if !!!user_state.is_authenticated() {
+        return Outcome::UserNotSignedIn;
+    }
+    // Sign in and coming back online can both start a prefetch
+    if PREFETCHING.swap(true, Ordering::Relaxed) {
+        return Outcome::PrefetchAlreadyRunning;
+    }
+
+    let outcome = prefetch_changed_decks(user_state, cache_status).await;
+
+    PREFETCHING.store(false, Ordering::Relaxed);
+    cache_status.set(get_cache_status_client());
+    outcome
+}
+
+async fn prefetch_changed_decks(user_state: UserState, cache_status: RwSignal<CacheStatus>) -> Outcome {
+    let changed_decks = match check_cache_status(user_state.clone()).await {
+        Outcome::DeckVersionsChanged(changed_decks) => changed_decks,
+        any_other_outcome => return any_other_outcome,
+    };
+
+    match begin_cache_prefetch(user_state.clone()).await {
+        Outcome::CacheStatusSet(status) => cache_status.set(status),
+        any_other_outcome => return any_other_outcome,
+    }
+
+    let user_info = get_user_info(user_state.clone()).await;
+    let mut all_user_decks = DeckList::default();
+    all_user_decks.extend(user_info.active_decks.iter());
+    all_user_decks.extend(user_info.owned_decks.iter());
+    all_user_decks.extend(user_info.colab_decks.iter());
+
+    for deck_id in changed_decks {
+        match prefetch_deck(deck_id, user_state.clone(), all_user_decks.clone()).await {
+            Outcome::CacheSucceeded => debug_warn!("deck {} prefetched", deck_id.to_string()),
+            any_other_outcome => debug_warn!("deck {} could not be prefetched {}", deck_id.to_string(), any_other_outcome.to_string()),
+        }
+    }
+
+    // The server only marks the cache complete if every active deck is now cached at its latest version
+    check_cache_status(user_state).await
+}
+
+/// Caches the deck meta, the user's review states, the levels with due cards and the current and next level.
+async fn prefetch_deck(deck_id: DeckId, user_state: UserState, all_user_decks: DeckList) -> Outcome {
+    let user = Some(user_state.user().to_string());
+
+    let outcome = retrieve_notes(ValidQueryTypes::NotesById(deck_id, vec![0]), all_user_decks.clone(), user.clone()).await;
+    throttle().await;
+
+    let meta_str = match outcome {
+        Outcome::ItemsFound(meta_str) => meta_str,
+        any_other_outcome => return any_other_outcome,
+    };
+    let Some(meta) = NoteList::from_str(&meta_str).unwrap_or_default().notes.into_iter().find_map(|note| note.meta) else {
+        return Outcome::ItemsNotFound;
+    };
+
+    let review_states = try_get_review_states(deck_id, user_state).await;
+    throttle().await;
+    let review_states = match review_states {
+        Ok(review_states) => review_states,
+        Err(any_other_outcome) => return any_other_outcome,
+    };
+
+    let now = current_time_in_seconds();
+    let current_level = review_states.iter().map(|review_state| review_state.level as usize).max().unwrap_or(1).max(1);
+    let mut levels: BTreeSet<usize> = review_states.iter()
+        .filter(|review_state| review_state.is_due(now))
+        .map(|review_state| review_state.level as usize)
+        .collect();
+    levels.extend([current_level, current_level + 1]);
+    levels.retain(|level| *level <= MAX_LEVELS && level.checked_sub(1).and_then(|index| meta.note_count_by_level.get(index)).is_some_and(|count| *count > 0));
+
+    for level in levels {
+        let outcome = retrieve_notes(ValidQueryTypes::NotesByLevel(deck_id, vec![level]), all_user_decks.clone(), user.clone()).await;
+        throttle().await;
+
+        let notes_str = match outcome {
+            Outcome::ItemsFound(notes_str) => notes_str,
+            Outcome::ItemsNotFound => continue,
+            any_other_outcome => return any_other_outcome,
+        };
+        let Ok(notes) = NoteList::from_str(&notes_str) else {return Outcome::UnspecifiedQueryFailure("Notes could not be parsed".to_string())};
+
+        let mut images: Vec<Asset> = Vec::new();
+        for field in notes.notes.iter().flat_map(|note| note.fields.iter()) {
+            if let Some(image @ Asset::DeckImage(_)) = &field.asset {
+                if !!!images.contains(image) {
+                    images.push(image.clone());
+                }
+            }
+        }
+
+        for image in images {
+            // A missing image only costs its card the picture, so the rest of the deck is still cached
+            match prefetch_image(deck_id, image, user.clone()).await {
+                Outcome::CacheSucceeded | Outcome::AlreadyCached(_) => (),
+                any_other_outcome => debug_warn!("deck image could not be cached {}", any_other_outcome.to_string()),
+            }
+        }
+    }
+
+    mark_deck_cached(deck_id, meta.version).await
+}
+
+async fn prefetch_image(deck_id: DeckId, image: Asset, user: Option<String>) -> Outcome {
+    if read_asset(&image).await.is_some() {
+        return Outcome::AlreadyCached(image.to_string());
+    }
+
+    let outcome = get_asset(image.clone(), user).await;
+    throttle().await;
+
+    let url = match outcome {
+        Outcome::PresignedUrlRetrieved(url) => url,
+        any_other_outcome => return any_other_outcome,
+    };
+    // Presigned urls only last seconds so the image is downloaded straight away
+    let Some(blob) = download_as_blob(&url).await else {
+        return Outcome::CacheFailed("deck image could not be downloaded".to_string());
+    };
+
+    put_asset(deck_id, &image, blob).await
+}
+
+async fn throttle() {
+    sleep_client(Duration::from_millis(PREFETCH_REQUEST_INTERVAL_IN_MILLIS)).await;
+}
@@ -0,0 +1,375 @@
+use leptos::server;
+use serde::{Deserialize, Serialize};
+use server_fn::ServerFnError;
+use struct_field_names::StructFieldNames;
+use strum::{Display, EnumIter};
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::DeckId,
+    date_and_time::current_time_in_seconds,
+    outcomes::Outcome,
+    shared_truth::REFUND_WINDOW_IN_SECONDS,
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use leptos::logging::error;
+#[cfg(feature="ssr")]
+use crate::utils::{
+    back_utils::{verify_user_header, OWNER_PAYOUT_SHARE},
+    dynamo_utils::{
+        permission_if_good_standing, permission_if_in_active_decks, permission_if_in_colab_decks, permission_if_in_owned_decks, permission_if_under_active_deck_limit,
+        validate_user_existence, validate_user_standing, ACTIVE_DECKS_DB_KEY, COLAB_DECKS_DB_KEY, OWNED_DECKS_DB_KEY, STANDING_DB_KEY,
+    },
+    database_types::UpdateRecipes,
+    payments::{setup_payment_provider, Checkout, PaymentEvent, PaymentProvider},
+    proceed,
+    shared_truth::{BROWSE_DECKS_PAGE, PURCHASE_URL_PARAM},
+    storage::{setup_storage, Storage},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Display, EnumIter, Serialize, Deserialize)]
+pub enum ReceiptStatus {
+    #[default] Pending,
+    Paid,
+    Refunded,
+}
+
+/// A user's purchase of a deck, kept in LEXReceipts under the buyer with one receipt per deck.
+/// An empty payout_id on a paid receipt means the owner is still owed their share.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, StructFieldNames)]
+pub struct Receipt {
+    pub user: String,
+    pub deck_id: DeckId,
+    pub deck_name: String,
+    pub owner: String,
+    pub checkout_id: String,
+    pub payment_id: String,
+    pub payout_id: String,
+    pub amount_in_cents: u64,
+    pub owner_payout_in_cents: u64,
+    pub status: ReceiptStatus,
+    pub created: u64,
+    pub updated: u64,
+}
+
+impl Receipt {
+    pub fn new_pending(user: &str, deck_id: DeckId, deck_name: &str, owner: &str, checkout_id: &str, amount_in_cents: u64) -> Self {
+        let created = current_time_in_seconds();
+        Self {
+            user: user.to_string(),
+            deck_id,
+            deck_name: deck_name.to_string(),
+            owner: owner.to_string(),
+            checkout_id: checkout_id.to_string(),
+            amount_in_cents,
+            created,
+            updated: created,
+            ..Default::default()
+        }
+    }
+
+    pub fn amount(&self) -> f32 {
+        self.amount_in_cents as f32 / 100.0
+    }
+
+    pub fn is_refundable(&self) -> bool {
+        self.status == ReceiptStatus::Paid && current_time_in_seconds() <= self.updated + REFUND_WINDOW_IN_SECONDS
+    }
+}
+
+/// The account a deck owner's share of each sale is paid out to.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, StructFieldNames)]
+pub struct PayoutAccount {
+    pub user: String,
+    pub account_id: String,
+}
+
+pub fn price_in_cents(price: f32) -> u64 {
+    (price.max(0.0) * 100.0).round() as u64
+}
+
+/// Returns CheckoutStarted with the page the user pays on, access is granted once the provider confirms the payment.
+#[server(client=AuthClient)]
+pub async fn start_deck_purchase(deck_id: DeckId, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+    let attributes_to_get = [STANDING_DB_KEY, ACTIVE_DECKS_DB_KEY, OWNED_DECKS_DB_KEY, COLAB_DECKS_DB_KEY];
+
+    let user = match storage.get_user(&email, Some(&attributes_to_get.join(","))).await {
+        Outcome::UserFound(user) => user,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    match permission_if_good_standing(&user) {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    if let Outcome::PermissionGranted(_) = permission_if_in_active_decks(&user, deck_id) {
+        return Ok(Outcome::AlreadySubscribed);
+    }
+
+    // Owners and collaborators can already study the deck for free
+    let writes_deck = matches!(permission_if_in_owned_decks(&user, deck_id), Outcome::PermissionGranted(_))
+        || matches!(permission_if_in_colab_decks(&user, deck_id), Outcome::PermissionGranted(_));
+    if writes_deck {
+        return Ok(Outcome::InvalidRequest);
+    }
+
+    match permission_if_under_active_deck_limit(&user) {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    if let Outcome::ReceiptFound(receipt) = storage.get_receipt(&email, deck_id).await {
+        if receipt.status == ReceiptStatus::Paid {
+            return Ok(storage.subscribe_user_to_deck(&email, deck_id).await);
+        }
+    }
+
+    let entry = match storage.get_catalog_entry(deck_id).await {
+        Outcome::CatalogEntryFound(entry) => entry,
+        Outcome::ItemsNotFound => return Ok(Outcome::UserDoesNotHavePermission),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+    if entry.is_free() {
+        return Ok(Outcome::InvalidRequest);
+    }
+
+    let Outcome::NoteFound(meta_note) = storage.get_note(deck_id, 0).await else {return Ok(Outcome::ItemsNotFound)};
+    let Some(meta) = meta_note.meta else {return Ok(Outcome::ItemsNotFound)};
+
+    let checkout = Checkout {
+        user: email.clone(),
+        deck_id,
+        deck_name: entry.name.clone(),
+        amount_in_cents: price_in_cents(entry.price),
+        success_url: format!("{BROWSE_DECKS_PAGE}?{PURCHASE_URL_PARAM}={}", deck_id.to_string()),
+        cancel_url: BROWSE_DECKS_PAGE.to_string(),
+    };
+
+    let provider = setup_payment_provider();
+    let (checkout_id, checkout_url) = match provider.create_checkout(&checkout).await {
+        Outcome::CheckoutStarted(checkout_id, checkout_url) => (checkout_id, checkout_url),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let receipt = Receipt::new_pending(&email, deck_id, &entry.name, &meta.owner, &checkout_id, checkout.amount_in_cents);
+    match storage.put_receipt(&receipt, &[ReceiptStatus::Pending, ReceiptStatus::Refunded]).await {
+        Outcome::DatabaseUpdateSuccess(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    Ok(Outcome::CheckoutStarted(checkout_id, checkout_url))
+}
+
+/// Refunds a paid deck within the refund window and takes it out of the user's active decks.
+#[server(client=AuthClient)]
+pub async fn refund_deck_purchase(deck_id: DeckId, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    let receipt = match storage.get_receipt(&email, deck_id).await {
+        Outcome::ReceiptFound(receipt) => receipt,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+    if !!!receipt.is_refundable() {
+        return Ok(Outcome::UserDoesNotHavePermission);
+    }
+
+    let provider = setup_payment_provider();
+    match provider.refund(&receipt.payment_id).await {
+        Outcome::RefundIssued(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    Ok(revoke_purchase(&storage, &provider, receipt).await)
+}
+
+#[server(client=AuthClient)]
+pub async fn receipts_from_dynamo(user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    match validate_user_existence(&storage, &email).await {
+        Outcome::UserFound(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    Ok(storage.get_receipts(&email).await)
+}
+
+/// Returns PayoutOnboardingStarted with the page a deck owner sets up the account their sales are paid out to.
+#[server(client=AuthClient)]
+pub async fn start_payout_onboarding(user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    match validate_user_standing(&storage, &email).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let existing_account = match storage.get_payout_account(&email).await {
+        Outcome::PayoutAccountFound(account) => Some(account.account_id),
+        Outcome::ItemsNotFound => None,
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let provider = setup_payment_provider();
+    let (account_id, onboarding_url) = match provider.payout_onboarding(&email, existing_account.as_deref(), BROWSE_DECKS_PAGE).await {
+        Outcome::PayoutOnboardingStarted(account_id, onboarding_url) => (account_id, onboarding_url),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    if existing_account.is_none() {
+        let account = PayoutAccount {user: email.clone(), account_id: account_id.clone()};
+        match storage.put_payout_account(&account).await {
+            Outcome::DatabaseUpdateSuccess(_) => proceed(),
+            any_other_outcome => return Ok(any_other_outcome),
+        };
+    }
+
+    // Accounts that can already take payouts are paid what they are owed, the rest are paid once the provider says they are ready
+    settle_owed_payouts(&storage, &provider, &email).await;
+
+    Ok(Outcome::PayoutOnboardingStarted(account_id, onboarding_url))
+}
+
+/// Applies a verified webhook event, returning DatabaseUpdateSuccess once it has been handled or has nothing left to do.
+/// Every branch can run more than once for the same event since providers resend events they are unsure were received.
+#[cfg(feature="ssr")]
+pub async fn handle_payment_event(storage: &impl Storage, provider: &impl PaymentProvider, event: PaymentEvent) -> Outcome {
+    match event {
+        PaymentEvent::PaymentSucceeded {checkout_id, payment_id, user, deck_id, amount_in_cents} => {
+            record_payment(storage, provider, &checkout_id, &payment_id, &user, deck_id, amount_in_cents).await
+        },
+        PaymentEvent::PaymentRefunded {payment_id} => match storage.get_receipt_by_payment_id(&payment_id).await {
+            Outcome::ReceiptFound(receipt) => revoke_purchase(storage, provider, receipt).await,
+            Outcome::ItemsNotFound => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+            any_other_outcome => any_other_outcome,
+        },
+        PaymentEvent::PayoutAccountReady {account_id} => match storage.get_payout_account_by_account_id(&account_id).await {
+            Outcome::PayoutAccountFound(account) => settle_owed_payouts(storage, provider, &account.user).await,
+            Outcome::ItemsNotFound => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+            any_other_outcome => any_other_outcome,
+        },
+        PaymentEvent::Ignored => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+    }
+}
+
+/// Marks the receipt paid, gives the buyer the deck and pays the owner their share.
+#[cfg(feature="ssr")]
+async fn record_payment(storage: &impl Storage, provider: &impl PaymentProvider, checkout_id: &str, payment_id: &str, user: &str, deck_id: DeckId, amount_in_cents: u64) -> Outcome {
+    let mut receipt = match storage.get_receipt(user, deck_id).await {
+        Outcome::ReceiptFound(receipt) => receipt,
+        any_other_outcome => return any_other_outcome,
+    };
+
+    match receipt.status {
+        ReceiptStatus::Paid if receipt.payment_id == payment_id => proceed(),
+        // The user paid through a second checkout for a deck they already bought
+        ReceiptStatus::Paid => return match provider.refund(payment_id).await {
+            Outcome::RefundIssued(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+            any_other_outcome => any_other_outcome,
+        },
+        ReceiptStatus::Refunded if receipt.payment_id == payment_id => return Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+        ReceiptStatus::Pending | ReceiptStatus::Refunded => {
+            receipt.checkout_id = checkout_id.to_string();
+            receipt.payment_id = payment_id.to_string();
+            receipt.payout_id = String::new();
+            receipt.amount_in_cents = amount_in_cents;
+            receipt.owner_payout_in_cents = (amount_in_cents as f64 * OWNER_PAYOUT_SHARE).floor() as u64;
+            receipt.status = ReceiptStatus::Paid;
+            receipt.updated = current_time_in_seconds();
+
+            match storage.put_receipt(&receipt, &[ReceiptStatus::Pending, ReceiptStatus::Refunded]).await {
+                Outcome::DatabaseUpdateSuccess(_) => proceed(),
+                any_other_outcome => return any_other_outcome,
+            };
+        },
+    };
+
+    match storage.subscribe_user_to_deck(user, deck_id).await {
+        Outcome::DatabaseUpdateSuccess(_) | Outcome::AlreadySubscribed => proceed(),
+        // The deck left the catalog after checkout, the paid receipt still lets the user study it if it comes back
+        Outcome::UserDoesNotHavePermission => error!("paid deck {} is no longer in the catalog", deck_id.to_string()),
+        any_other_outcome => return any_other_outcome,
+    };
+
+    pay_owner(storage, provider, receipt).await;
+
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes::default())
+}
+
+/// Marks the receipt refunded, takes the deck out of the buyer's active decks and takes the owner's payout back.
+/// Returns the recipes for the buyer's cache.
+#[cfg(feature="ssr")]
+async fn revoke_purchase(storage: &impl Storage, provider: &impl PaymentProvider, mut receipt: Receipt) -> Outcome {
+    // Only the call that refunds the receipt reverses the payout, which is cleared in the same write so the refund
+    // webhook that follows a refund we started finds nothing left to reverse
+    let mut reversed_payout_id = String::new();
+    if receipt.status == ReceiptStatus::Paid {
+        reversed_payout_id = std::mem::take(&mut receipt.payout_id);
+        receipt.status = ReceiptStatus::Refunded;
+        receipt.updated = current_time_in_seconds();
+
+        match storage.put_receipt(&receipt, &[ReceiptStatus::Paid]).await {
+            Outcome::DatabaseUpdateSuccess(_) => proceed(),
+            any_other_outcome => return any_other_outcome,
+        };
+    }
+
+    let outcome = storage.unsubscribe_user_from_deck(&receipt.user, receipt.deck_id).await;
+
+    if !!!reversed_payout_id.is_empty() {
+        if let Outcome::PaymentProviderFailure(e) = provider.reverse_payout(&reversed_payout_id).await {
+            error!("payout {reversed_payout_id} could not be reversed: {e}");
+        }
+    }
+
+    outcome
+}
+
+/// Sends the owner their share of a paid receipt if they have a payout account, otherwise it stays owed.
+#[cfg(feature="ssr")]
+async fn pay_owner(storage: &impl Storage, provider: &impl PaymentProvider, mut receipt: Receipt) -> Outcome {
+    if receipt.status != ReceiptStatus::Paid || !!!receipt.payout_id.is_empty() || receipt.owner_payout_in_cents == 0 {
+        return Outcome::DatabaseUpdateSuccess(UpdateRecipes::default());
+    }
+
+    let account = match storage.get_payout_account(&receipt.owner).await {
+        Outcome::PayoutAccountFound(account) => account,
+        any_other_outcome => return any_other_outcome,
+    };
+
+    receipt.payout_id = match provider.pay_out(&account.account_id, receipt.owner_payout_in_cents, &receipt.payment_id).await {
+        Outcome::PayoutSent(payout_id) => payout_id,
+        any_other_outcome => return any_other_outcome,
+    };
+
+    storage.put_receipt(&receipt, &[ReceiptStatus::Paid]).await
+}
+
+#[cfg(feature="ssr")]
+async fn settle_owed_payouts(storage: &impl Storage, provider: &impl PaymentProvider, owner: &str) -> Outcome {
+    let receipts = match storage.get_owed_receipts(owner).await {
+        Outcome::ReceiptsFound(receipts) => receipts,
+        any_other_outcome => return any_other_outcome,
+    };
+
+    for receipt in receipts {
+        match pay_owner(storage, provider, receipt).await {
+            Outcome::DatabaseUpdateSuccess(_) => proceed(),
+            any_other_outcome => return any_other_outcome,
+        };
+    }
+
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes::default())
+}
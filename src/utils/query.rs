@@ -2,10 +2,10 @@ use crate::utils::{
     database_types::{DeckId, DeckMeta, Field, Note, NoteList, NoteType}, 
     outcomes::Outcome, 
     proceed, 
-    shared_truth::MAX_LEVELS,
+    shared_truth::{MAX_LEVELS, MAX_NOTE_IDS_PER_QUERY},
     auth_client::AuthClient,
 };
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::{BTreeMap, HashMap}, str::FromStr};
 use leptos::{prelude::ServerFnError, server};
 use serde::{Deserialize, Serialize};
 
@@ -19,54 +19,179 @@ pub enum ValidQueryTypes {
     NotesByType(DeckId, Vec<NoteType>),
 }
 
+/// Where a paginated query stopped, the last note id read on every level or stored note type name that still has notes left.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryCursor {
+    pub last_note_ids: BTreeMap<usize, u64>,
+    #[serde(default)]
+    pub last_note_ids_by_type: BTreeMap<String, u64>,
+}
+
+impl QueryCursor {
+    pub fn is_empty(&self) -> bool {
+        self.last_note_ids.is_empty() && self.last_note_ids_by_type.is_empty()
+    }
+
+    /// Whether the note has not been returned yet, levels missing from the cursor are already finished.
+    pub fn allows(&self, note: &Note) -> bool {
+        match self.last_note_ids.get(&(note.level as usize)) {
+            Some(last_note_id) => note.note_id > *last_note_id,
+            None => false,
+        }
+    }
+}
+
 
 /// Server Imports
 #[cfg(feature="ssr")]
 use crate::utils::{
     back_utils::{PUBLIC_DECKS_TABLE, verify_user_header},
-    dynamo_utils::{convert_attribute_value_to_string, setup_client, validate_if_in_any_decks_and_if_good_standing, DECK_ID_DB_KEY, DECK_META_DB_KEY, LEVEL_DB_KEY, NOTE_ID_DB_KEY, NOTE_TYPE_DB_KEY, REVIEWS_PER_STAGE_DB_KEY, VERSION_DB_KEY},
+    dynamo_utils::{convert_attribute_value_to_string, validate_if_in_any_decks_and_if_good_standing, DECK_ID_DB_KEY, DECK_META_DB_KEY, LEVEL_DB_KEY, NOTE_ID_DB_KEY, NOTE_TYPE_DB_KEY, REVIEWS_PER_STAGE_DB_KEY, VERSION_DB_KEY},
+    storage::{setup_storage, Storage},
 };
 #[cfg(feature="ssr")]
-use aws_sdk_dynamodb::{types::AttributeValue, Client as DDBClient};
+use aws_sdk_dynamodb::{types::{AttributeValue, KeysAndAttributes}, Client as DDBClient};
+#[cfg(feature="ssr")]
+use futures::future::join_all;
+
+/// Once a level or note type has returned this many notes the rest of it is left for the next page.
+#[cfg(feature="ssr")]
+const NOTES_PER_LEVEL_PAGE: usize = 500;
+
+/// The most keys dynamo accepts in a single BatchGetItem request.
+#[cfg(feature="ssr")]
+const BATCH_GET_LIMIT: usize = 100;
+#[cfg(feature="ssr")]
+const MAX_BATCH_GET_ATTEMPTS: usize = 5;
 
 
 #[server(client=AuthClient)]
-pub async fn query_dynamo(query_type: ValidQueryTypes, user: Option<String>) -> Result<Outcome, ServerFnError> {
+pub async fn query_dynamo(query_type: ValidQueryTypes, cursor: Option<QueryCursor>, user: Option<String>) -> Result<Outcome, ServerFnError> {
     let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
     
     let outcome = match query_type {
         ValidQueryTypes::NoQuery => Outcome::InvalidRequest,
-        query_type => query(query_type, &email).await,
+        query_type => query(query_type, cursor, &email).await,
     };
 
     Ok(outcome)
 }
 
+/// The version of every note the query matches, so a client can tell which of its cached notes are out of date
+/// without downloading them again.
+#[server(client=AuthClient)]
+pub async fn note_versions_from_dynamo(query_type: ValidQueryTypes, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = &setup_storage().await;
+
+    match is_valid_query(storage, &query_type, &email).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    }
+
+    let mut note_versions = BTreeMap::new();
+    let mut cursor = None;
+    let attributes_to_get = [NOTE_ID_DB_KEY, VERSION_DB_KEY];
+
+    loop {
+        let (notes_str, next_cursor) = match storage.query_notes(&query_type, cursor.as_ref(), Some(&attributes_to_get.join(","))).await {
+            Outcome::ItemsFound(notes_str) => (notes_str, None),
+            Outcome::PartialItemsFound(notes_str, next_cursor) => (notes_str, Some(next_cursor)),
+            any_other_outcome => return Ok(any_other_outcome),
+        };
+
+        let Ok(notes) = NoteList::from_str(&notes_str) else {return Ok(Outcome::UnspecifiedQueryFailure("Notes could not be parsed".to_string()))};
+        note_versions.extend(notes.notes.iter().map(|note| (note.note_id, note.version)));
+
+        match next_cursor {
+            Some(next_cursor) => cursor = Some(next_cursor),
+            None => break,
+        }
+    }
+
+    Ok(Outcome::NoteVersionsFound(note_versions))
+}
+
+/// Reads one level starting after the given note id, returning the notes and the last note id read if the level has more.
 #[cfg(feature="ssr")]
-async fn query_by_level(client: &DDBClient, partition_key: String, level: usize) -> Outcome {
-
-    println!("!!!!!!!!!!!!!!!!!!! AHHHHHHHHHHHHHHHHH !!!!!!!!!!!!!!!!!!!!!!!!!! pk: {}, level: {}", partition_key, level);
-    let output = match client.query()
-    .table_name(PUBLIC_DECKS_TABLE)
-    .index_name(format!("{LEVEL_DB_KEY}-index"))
-    .consistent_read(false)
-    .key_condition_expression("#DeckID = :pk AND #Level = :lvl")
-    .expression_attribute_names("#DeckID", DECK_ID_DB_KEY)
-    .expression_attribute_names("#Level", LEVEL_DB_KEY)
-    .expression_attribute_values(":pk", AttributeValue::S(partition_key))
-    .expression_attribute_values(":lvl", AttributeValue::N(level.to_string()))
-    .send().await {
-        Ok(output) => output,
-        Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
-    };
+async fn query_by_level(client: &DDBClient, deck_id: DeckId, level: usize, start_after: Option<u64>, projection_expression: Option<&str>) -> Result<(Vec<Note>, Option<u64>), Outcome> {
+    let mut notes = Vec::new();
+    let mut exclusive_start_key = start_after.map(|note_id| HashMap::from([
+        (DECK_ID_DB_KEY.to_string(), AttributeValue::S(deck_id.to_string())),
+        (NOTE_ID_DB_KEY.to_string(), AttributeValue::N(note_id.to_string())),
+        (LEVEL_DB_KEY.to_string(), AttributeValue::N(level.to_string())),
+    ]));
+
+    loop {
+        let output = match client.query()
+        .table_name(PUBLIC_DECKS_TABLE)
+        .index_name(format!("{LEVEL_DB_KEY}-index"))
+        .consistent_read(false)
+        .key_condition_expression("#DeckID = :pk AND #Level = :lvl")
+        .expression_attribute_names("#DeckID", DECK_ID_DB_KEY)
+        .expression_attribute_names("#Level", LEVEL_DB_KEY)
+        .expression_attribute_values(":pk", AttributeValue::S(deck_id.to_string()))
+        .expression_attribute_values(":lvl", AttributeValue::N(level.to_string()))
+        .set_exclusive_start_key(exclusive_start_key.take())
+        .set_projection_expression(projection_expression.map(str::to_string))
+        .send().await {
+            Ok(output) => output,
+            Err(e) => return Err(Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string())),
+        };
+
+        for item in output.items() {
+            notes.push(construct_note_from_database_item(&item));
+        }
+
+        match output.last_evaluated_key {
+            Some(last_key) if notes.len() >= NOTES_PER_LEVEL_PAGE => {
+                let last_note_id = last_key.get(NOTE_ID_DB_KEY).and_then(|note_id| note_id.as_n().ok()).and_then(|note_id| u64::from_str(note_id).ok());
+                let Some(last_note_id) = last_note_id else {return Err(Outcome::UnspecifiedQueryFailure("query cursor could not be read".to_string()))};
+                return Ok((notes, Some(last_note_id)));
+            },
+            Some(last_key) => exclusive_start_key = Some(last_key),
+            None => return Ok((notes, None)),
+        }
+    }
+}
+
+/// Reads every requested level at once, or only the unfinished levels when continuing from a cursor.
+#[cfg(feature="ssr")]
+async fn query_by_levels(client: &DDBClient, deck_id: DeckId, levels: &Vec<usize>, cursor: Option<&QueryCursor>, projection_expression: Option<&str>) -> Outcome {
+    let mut level_starts: BTreeMap<usize, Option<u64>> = BTreeMap::new();
+    for level in levels {
+        match cursor {
+            Some(cursor) => if let Some(last_note_id) = cursor.last_note_ids.get(level) {
+                level_starts.insert(*level, Some(*last_note_id));
+            },
+            None => {level_starts.insert(*level, None);},
+        }
+    }
+
+    let level_queries = level_starts.into_iter().map(|(level, start_after)| async move {
+        (level, query_by_level(client, deck_id, level, start_after, projection_expression).await)
+    });
 
     let mut note_list = NoteList::default();
+    let mut next_cursor = QueryCursor::default();
 
-    for item in output.items() {
-        note_list.push(construct_note_from_database_item(&item));
+    for (level, level_result) in join_all(level_queries).await {
+        match level_result {
+            Ok((notes, last_note_id)) => {
+                note_list.extend(notes);
+                if let Some(last_note_id) = last_note_id {
+                    next_cursor.last_note_ids.insert(level, last_note_id);
+                }
+            },
+            Err(any_other_outcome) => return any_other_outcome,
+        }
     }
 
-    Outcome::ItemsFound(note_list.to_string())
+    if next_cursor.is_empty() {
+        return Outcome::ItemsFound(note_list.to_string());
+    }
+    Outcome::PartialItemsFound(note_list.to_string(), next_cursor)
 }
 
 #[cfg(feature="ssr")]
@@ -87,30 +212,169 @@ pub fn construct_note_from_database_item(note_attribute_map: &HashMap<String, At
                 }
             },
             DECK_ID_DB_KEY => note.deck_id = DeckId::from_str(&value_as_str).unwrap_or_default(),
-            field_name => note.fields.push(Field::new(field_name.to_owned(), Some(value_as_str), None)),
+            field_name => note.fields.push(Field::from_database_string(value_as_str, field_name.to_owned())),
         }
     }
     note
 }
 
+/// Reads one stored note type name starting after the given note id, returning the notes and the last note id read if
+/// the type has more.
 #[cfg(feature="ssr")]
-async fn _query_by_note_type() -> Outcome {
-    todo!()
+async fn query_by_stored_note_type(client: &DDBClient, deck_id: DeckId, note_type: &str, start_after: Option<u64>, projection_expression: Option<&str>) -> Result<(Vec<Note>, Option<u64>), Outcome> {
+    let mut notes = Vec::new();
+    let mut exclusive_start_key = start_after.map(|note_id| HashMap::from([
+        (DECK_ID_DB_KEY.to_string(), AttributeValue::S(deck_id.to_string())),
+        (NOTE_ID_DB_KEY.to_string(), AttributeValue::N(note_id.to_string())),
+        (NOTE_TYPE_DB_KEY.to_string(), AttributeValue::S(note_type.to_string())),
+    ]));
+
+    loop {
+        let output = match client.query()
+        .table_name(PUBLIC_DECKS_TABLE)
+        .index_name(format!("{NOTE_TYPE_DB_KEY}-index"))
+        .consistent_read(false)
+        .key_condition_expression("#DeckID = :pk AND #NoteType = :type")
+        .expression_attribute_names("#DeckID", DECK_ID_DB_KEY)
+        .expression_attribute_names("#NoteType", NOTE_TYPE_DB_KEY)
+        .expression_attribute_values(":pk", AttributeValue::S(deck_id.to_string()))
+        .expression_attribute_values(":type", AttributeValue::S(note_type.to_string()))
+        .set_exclusive_start_key(exclusive_start_key.take())
+        .set_projection_expression(projection_expression.map(str::to_string))
+        .send().await {
+            Ok(output) => output,
+            Err(e) => return Err(Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string())),
+        };
+
+        for item in output.items() {
+            notes.push(construct_note_from_database_item(&item));
+        }
+
+        match output.last_evaluated_key {
+            Some(last_key) if notes.len() >= NOTES_PER_LEVEL_PAGE => {
+                let last_note_id = last_key.get(NOTE_ID_DB_KEY).and_then(|note_id| note_id.as_n().ok()).and_then(|note_id| u64::from_str(note_id).ok());
+                let Some(last_note_id) = last_note_id else {return Err(Outcome::UnspecifiedQueryFailure("query cursor could not be read".to_string()))};
+                return Ok((notes, Some(last_note_id)));
+            },
+            Some(last_key) => exclusive_start_key = Some(last_key),
+            None => return Ok((notes, None)),
+        }
+    }
 }
+
+/// Reads every stored name of the requested note types at once, or only the unfinished ones when continuing from a cursor.
 #[cfg(feature="ssr")]
-async fn _query_by_note_id() -> Outcome {
-    todo!()
+async fn query_by_note_type(client: &DDBClient, deck_id: DeckId, note_types: &Vec<NoteType>, cursor: Option<&QueryCursor>, projection_expression: Option<&str>) -> Outcome {
+    let mut type_starts: BTreeMap<String, Option<u64>> = BTreeMap::new();
+    for stored_name in note_types.iter().flat_map(|note_type| note_type.stored_names()) {
+        match cursor {
+            Some(cursor) => if let Some(last_note_id) = cursor.last_note_ids_by_type.get(stored_name) {
+                type_starts.insert(stored_name.to_string(), Some(*last_note_id));
+            },
+            None => {type_starts.insert(stored_name.to_string(), None);},
+        }
+    }
+
+    let type_queries = type_starts.into_iter().map(|(stored_name, start_after)| async move {
+        let type_result = query_by_stored_note_type(client, deck_id, &stored_name, start_after, projection_expression).await;
+        (stored_name, type_result)
+    });
+
+    let mut note_list = NoteList::default();
+    let mut next_cursor = QueryCursor::default();
+
+    for (stored_name, type_result) in join_all(type_queries).await {
+        match type_result {
+            Ok((notes, last_note_id)) => {
+                note_list.extend(notes);
+                if let Some(last_note_id) = last_note_id {
+                    next_cursor.last_note_ids_by_type.insert(stored_name, last_note_id);
+                }
+            },
+            Err(any_other_outcome) => return any_other_outcome,
+        }
+    }
+
+    if next_cursor.is_empty() {
+        return Outcome::ItemsFound(note_list.to_string());
+    }
+    Outcome::PartialItemsFound(note_list.to_string(), next_cursor)
 }
+
+/// Fetches the notes in batches of BATCH_GET_LIMIT, retrying whatever dynamo leaves unprocessed.
+#[cfg(feature="ssr")]
+async fn query_by_note_id(client: &DDBClient, deck_id: DeckId, note_ids: &Vec<usize>, projection_expression: Option<&str>) -> Outcome {
+    let mut unique_note_ids: Vec<usize> = Vec::with_capacity(note_ids.len());
+    for note_id in note_ids {
+        if !!!unique_note_ids.contains(note_id) {
+            unique_note_ids.push(*note_id);
+        }
+    }
+
+    let mut note_list = NoteList::default();
+
+    for note_id_batch in unique_note_ids.chunks(BATCH_GET_LIMIT) {
+        let keys = note_id_batch.iter().map(|note_id| HashMap::from([
+            (DECK_ID_DB_KEY.to_string(), AttributeValue::S(deck_id.to_string())),
+            (NOTE_ID_DB_KEY.to_string(), AttributeValue::N(note_id.to_string())),
+        ])).collect::<Vec<HashMap<String, AttributeValue>>>();
+
+        let keys_and_attributes = match KeysAndAttributes::builder().set_keys(Some(keys)).consistent_read(false).set_projection_expression(projection_expression.map(str::to_string)).build() {
+            Ok(keys_and_attributes) => keys_and_attributes,
+            Err(e) => return Outcome::UnspecifiedQueryFailure(e.to_string()),
+        };
+        let mut request_items = Some(HashMap::from([(PUBLIC_DECKS_TABLE.to_string(), keys_and_attributes)]));
+        let mut attempts = 0;
+
+        while let Some(items) = request_items.take() {
+            if attempts == MAX_BATCH_GET_ATTEMPTS {
+                return Outcome::UnspecifiedQueryFailure("notes were left unprocessed after retrying".to_string());
+            }
+            attempts += 1;
+
+            let output = match client.batch_get_item().set_request_items(Some(items)).send().await {
+                Ok(output) => output,
+                Err(e) => return Outcome::UnspecifiedQueryFailure(e.into_service_error().to_string()),
+            };
+
+            if let Some(mut responses) = output.responses {
+                for item in responses.remove(PUBLIC_DECKS_TABLE).unwrap_or_default() {
+                    note_list.push(construct_note_from_database_item(&item));
+                }
+            }
+
+            request_items = output.unprocessed_keys.filter(|unprocessed_keys| !!!unprocessed_keys.is_empty());
+        }
+    }
+
+    // Batch gets come back in any order so the notes are put back in the order they were asked for
+    note_list.notes.sort_by_key(|note| unique_note_ids.iter().position(|note_id| *note_id as u64 == note.note_id));
+
+    Outcome::ItemsFound(note_list.to_string())
+}
+
+/// Runs a note query against dynamo, this is what the dynamo storage backend uses for note queries.
+/// Level queries return PartialItemsFound with a cursor when some levels have more notes to read.
+#[cfg(feature="ssr")]
+pub async fn query_notes(client: &DDBClient, query_type: &ValidQueryTypes, cursor: Option<&QueryCursor>, projection_expression: Option<&str>) -> Outcome {
+    match query_type {
+        ValidQueryTypes::NotesByLevel(deck_id, levels) => query_by_levels(client, *deck_id, levels, cursor, projection_expression).await,
+        ValidQueryTypes::NotesById(deck_id, note_ids) => query_by_note_id(client, *deck_id, note_ids, projection_expression).await,
+        ValidQueryTypes::NotesByType(deck_id, note_types) => query_by_note_type(client, *deck_id, note_types, cursor, projection_expression).await,
+        ValidQueryTypes::NoQuery => Outcome::InvalidRequest,
+    }
+}
+
 #[cfg(feature="ssr")]
 async fn _query_by_reviews_per_stage() -> Outcome {
     todo!()
 }
 
 #[cfg(feature="ssr")]
-async fn is_valid_query(client: &DDBClient, query_type: &ValidQueryTypes, email: &str) -> Outcome {
+async fn is_valid_query(storage: &impl Storage, query_type: &ValidQueryTypes, email: &str) -> Outcome {
     match query_type {
         ValidQueryTypes::NotesByLevel(deck_id, levels) => {
-            match validate_if_in_any_decks_and_if_good_standing(client, email, &deck_id.to_string()).await {
+            match validate_if_in_any_decks_and_if_good_standing(storage, email, &deck_id.to_string()).await {
                 Outcome::PermissionGranted(_) => proceed(),
                 any_other_outcome => return any_other_outcome,
             }
@@ -118,14 +382,15 @@ async fn is_valid_query(client: &DDBClient, query_type: &ValidQueryTypes, email:
                 if level > &MAX_LEVELS {return Outcome::InvalidRequest}
             }
         },
-        ValidQueryTypes::NotesById(deck_id, _) => {
-            match validate_if_in_any_decks_and_if_good_standing(client, email, &deck_id.to_string()).await {
+        ValidQueryTypes::NotesById(deck_id, note_ids) => {
+            if note_ids.len() > MAX_NOTE_IDS_PER_QUERY {return Outcome::InvalidRequest}
+            match validate_if_in_any_decks_and_if_good_standing(storage, email, &deck_id.to_string()).await {
                 Outcome::PermissionGranted(_) => proceed(),
                 any_other_outcome => return any_other_outcome,
             }
         },
         ValidQueryTypes::NotesByType(deck_id, _) => {
-            match validate_if_in_any_decks_and_if_good_standing(client, email, &deck_id.to_string()).await {
+            match validate_if_in_any_decks_and_if_good_standing(storage, email, &deck_id.to_string()).await {
                 Outcome::PermissionGranted(_) => proceed(),
                 any_other_outcome => return any_other_outcome,
             }
@@ -136,22 +401,13 @@ async fn is_valid_query(client: &DDBClient, query_type: &ValidQueryTypes, email:
 }
 
 #[cfg(feature="ssr")]
-async fn query(query_type: ValidQueryTypes, email: &str) -> Outcome {
-    let client = &setup_client().await;
+async fn query(query_type: ValidQueryTypes, cursor: Option<QueryCursor>, email: &str) -> Outcome {
+    let storage = &setup_storage().await;
 
-    match is_valid_query(client, &query_type, email).await {
+    match is_valid_query(storage, &query_type, email).await {
         Outcome::PermissionGranted(_) => proceed(),
         any_other_outcome => return any_other_outcome,
     }
 
-    let outcome = match query_type {
-        ValidQueryTypes::NotesByLevel(partition_key, levels) => {
-            for level in levels {
-                return query_by_level(client, partition_key.to_string(), level).await
-            }
-            Outcome::IncorrectType
-        },
-        _any_other_query => Outcome::InvalidRequest,
-    };
-    outcome
+    storage.query_notes(&query_type, cursor.as_ref(), None).await
 }
\ No newline at end of file
@@ -0,0 +1,289 @@
+use std::{collections::HashMap, str::FromStr};
+
+use leptos::{prelude::ServerFnError, server};
+use serde::{Deserialize, Serialize};
+use struct_field_names::StructFieldNames;
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::{DeckId, Note},
+    date_and_time::{current_time_in_seconds, Date, PartialDate},
+    outcomes::Outcome,
+    shared_truth::MAX_LEVELS,
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use crate::utils::{
+    back_utils::verify_user_header,
+    dynamo_utils::validate_if_in_any_decks_and_if_good_standing,
+    note_templates::template_for_note,
+    proceed,
+    storage::{setup_storage, Storage},
+};
+
+// Stage intervals are the minimum wait before a card in that stage is due again.
+// A card that reaches BURNED_STAGE is considered learned and is never scheduled again.
+pub const STAGE_INTERVALS_IN_SECONDS: [u64; 8] = [
+    Date::SECONDS_IN_HOUR * 4,
+    Date::SECONDS_IN_HOUR * 8,
+    Date::SECONDS_IN_DAY,
+    Date::SECONDS_IN_DAY * 2,
+    Date::SECONDS_IN_DAY * 7,
+    Date::SECONDS_IN_DAY * 14,
+    Date::SECONDS_IN_DAY * 30,
+    Date::SECONDS_IN_DAY * 120,
+];
+pub const BURNED_STAGE: u8 = STAGE_INTERVALS_IN_SECONDS.len() as u8;
+
+pub const INITIAL_EASE_FACTOR: f64 = 2.5;
+pub const MIN_EASE_FACTOR: f64 = 1.3;
+pub const MAX_LEVEL_EASE_PENALTY: f64 = 0.5;
+pub const EASE_FACTOR_STEP: f64 = 0.15;
+pub const LAPSE_EASE_PENALTY: f64 = 0.2;
+pub const HARD_INTERVAL_MULTIPLIER: f64 = 1.2;
+pub const EASY_INTERVAL_BONUS: f64 = 1.3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Display, EnumIter, Serialize, Deserialize)]
+pub enum Answer {
+    Again,
+    Hard,
+    Good,
+    Easy,
+}
+
+impl FromStr for Answer {
+
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        for variant in Self::iter() {
+            if input == &variant.to_string() {
+                return Ok(variant);
+            }
+        }
+        Err(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, StructFieldNames)]
+pub struct ReviewState {
+    pub user: String,
+    pub card: String,
+    pub deck_id: DeckId,
+    pub note_id: u64,
+    #[serde(default)]
+    pub card_ord: u8,
+    pub level: u32,
+    pub stage: u8,
+    pub reviews_in_stage: u8,
+    pub ease_factor: f64,
+    pub interval: u64,
+    pub lapses: u32,
+    pub streak: u32,
+    pub last_answer: Option<Answer>,
+    pub due: u64,
+}
+
+impl ReviewState {
+    pub fn new(user: &str, note: &Note, card_ord: u8, now: u64) -> Self {
+        Self {
+            user: user.to_string(),
+            card: ReviewState::card_key(note.deck_id, note.note_id, card_ord),
+            deck_id: note.deck_id,
+            note_id: note.note_id,
+            card_ord,
+            level: note.level,
+            stage: 0,
+            reviews_in_stage: 0,
+            ease_factor: initial_ease_factor(note.level),
+            interval: STAGE_INTERVALS_IN_SECONDS[0],
+            lapses: 0,
+            streak: 0,
+            last_answer: None,
+            due: now + STAGE_INTERVALS_IN_SECONDS[0],
+        }
+    }
+
+    pub fn from_lesson(user: &str, note: &Note, card_ord: u8, answer: Answer, now: u64) -> Self {
+        let mut review_state = ReviewState::new(user, note, card_ord, now);
+        review_state.last_answer = Some(answer);
+        if answer != Answer::Again {
+            review_state.streak = 1;
+        }
+        review_state
+    }
+
+    /// The first card of a note keeps the key cards had before notes could have more than one.
+    pub fn card_key(deck_id: DeckId, note_id: u64, card_ord: u8) -> String {
+        match card_ord {
+            0 => format!("{}#{}", deck_id.to_string(), note_id),
+            card_ord => format!("{}#{}#{}", deck_id.to_string(), note_id, card_ord),
+        }
+    }
+
+    /// Every card key of the deck starts with this, the separator keeps a deck id from matching a longer one.
+    pub fn deck_key_prefix(deck_id: DeckId) -> String {
+        format!("{}#", deck_id.to_string())
+    }
+
+    pub fn is_burned(&self) -> bool {
+        self.stage >= BURNED_STAGE
+    }
+
+    pub fn is_due(&self, now: u64) -> bool {
+        !!!self.is_burned() && self.due <= now
+    }
+
+    pub fn answer(&mut self, note: &Note, answer: Answer, now: u64) {
+        let reviews_per_stage = note.reviews_per_stage.max(1);
+        self.level = note.level;
+        self.last_answer = Some(answer);
+
+        match answer {
+            Answer::Again => {
+                self.lapses += 1;
+                self.streak = 0;
+                self.ease_factor = (self.ease_factor - LAPSE_EASE_PENALTY).max(MIN_EASE_FACTOR);
+                let stages_lost = if self.stage >= 4 {2} else {1};
+                self.stage = self.stage.saturating_sub(stages_lost);
+                self.reviews_in_stage = 0;
+                self.interval = stage_interval(self.stage);
+            },
+            Answer::Hard => {
+                self.streak += 1;
+                self.ease_factor = (self.ease_factor - EASE_FACTOR_STEP).max(MIN_EASE_FACTOR);
+                let hard_interval = (self.interval as f64 * HARD_INTERVAL_MULTIPLIER) as u64;
+                self.interval = hard_interval.max(stage_interval(self.stage));
+            },
+            Answer::Good | Answer::Easy => {
+                self.streak += 1;
+                let mut interval = self.interval as f64 * self.ease_factor;
+                if answer == Answer::Easy {
+                    self.ease_factor += EASE_FACTOR_STEP;
+                    self.reviews_in_stage = reviews_per_stage;
+                    interval = interval * EASY_INTERVAL_BONUS;
+                } else {
+                    self.reviews_in_stage += 1;
+                }
+
+                if self.reviews_in_stage >= reviews_per_stage {
+                    self.stage = (self.stage + 1).min(BURNED_STAGE);
+                    self.reviews_in_stage = 0;
+                }
+
+                self.interval = (interval as u64).max(stage_interval(self.stage));
+            },
+        }
+
+        if self.is_burned() {
+            self.due = 0;
+        } else {
+            self.due = now + self.interval;
+        }
+    }
+}
+
+pub fn initial_ease_factor(level: u32) -> f64 {
+    let level_ratio = (level as f64 / MAX_LEVELS as f64).min(1.0);
+    INITIAL_EASE_FACTOR - MAX_LEVEL_EASE_PENALTY * level_ratio
+}
+
+pub fn stage_interval(stage: u8) -> u64 {
+    match STAGE_INTERVALS_IN_SECONDS.get(stage as usize) {
+        Some(interval) => *interval,
+        None => 0,
+    }
+}
+
+pub fn get_review_schedule(review_states: &[ReviewState]) -> (HashMap<PartialDate, usize>, usize) {
+    let now = current_time_in_seconds();
+    let todays_date = Date::from_secs(now);
+
+    let mut review_schedule = HashMap::with_capacity(Date::JAN.days(1970) * 3);
+
+    for review_state in review_states.iter() {
+        if review_state.is_burned() {
+            continue;
+        }
+
+        // Overdue cards are still waiting to be reviewed so they count towards today
+        let due_date = if review_state.is_due(now) {
+            todays_date
+        } else {
+            Date::from_secs(review_state.due)
+        };
+
+        let review_count = review_schedule.entry(due_date.to_month_and_day()).or_insert(0);
+        *review_count += 1;
+    }
+
+    let highest_review_amount = review_schedule.values().max().copied().unwrap_or_default();
+
+    (review_schedule, highest_review_amount)
+}
+
+#[server(client=AuthClient)]
+pub async fn review_states_from_dynamo(deck_id: DeckId, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    match validate_if_in_any_decks_and_if_good_standing(&storage, &email, &deck_id.to_string()).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    Ok(storage.get_review_states(&email, deck_id).await)
+}
+
+#[server(client=AuthClient)]
+pub async fn grade_card(deck_id: DeckId, note_id: u64, card_ord: u8, answer: Answer, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    Ok(grade_card_at(&storage, &email, deck_id, note_id, card_ord, answer, current_time_in_seconds()).await)
+}
+
+/// Grades the card as if it was answered at the given time, answers made offline are replayed with the time they were made.
+#[cfg(feature="ssr")]
+pub async fn grade_card_at(storage: &impl Storage, email: &str, deck_id: DeckId, note_id: u64, card_ord: u8, answer: Answer, answered: u64) -> Outcome {
+    match validate_if_in_any_decks_and_if_good_standing(storage, email, &deck_id.to_string()).await {
+        Outcome::PermissionGranted(_) => proceed(),
+        any_other_outcome => return any_other_outcome,
+    };
+
+    let note = match storage.get_note(deck_id, note_id).await {
+        Outcome::NoteFound(note) => note,
+        any_other_outcome => return any_other_outcome,
+    };
+
+    // Only cards the note actually makes can be graded, otherwise any ord would start a schedule of its own
+    let note_templates = match storage.get_note(deck_id, 0).await {
+        Outcome::NoteFound(meta_note) => meta_note.meta.map(|meta| meta.note_templates).unwrap_or_default(),
+        any_other_outcome => return any_other_outcome,
+    };
+    if !!!template_for_note(&note_templates, &note).card_ords(&note).contains(&card_ord) {
+        return Outcome::InvalidRequest;
+    }
+
+    // Answers cannot be dated in the future
+    let now = answered.min(current_time_in_seconds());
+
+    let review_state = match storage.get_review_state(email, deck_id, note_id, card_ord).await {
+        Outcome::ReviewStatesFound(mut review_states) if !!!review_states.is_empty() => {
+            let mut review_state = review_states.remove(0);
+            review_state.answer(&note, answer, now);
+            review_state
+        },
+        Outcome::ItemsNotFound => ReviewState::from_lesson(email, &note, card_ord, answer, now),
+        any_other_outcome => return any_other_outcome,
+    };
+
+    match storage.put_review_state(&review_state).await {
+        Outcome::DatabaseUpdateSuccess(_) => Outcome::CardGraded(review_state),
+        any_other_outcome => any_other_outcome,
+    }
+}
@@ -1,24 +1,61 @@
 // DECK META
 pub const MAX_LEVELS: usize = 100;
 pub const MAX_NOTE_FIELDS: usize = 150;
+pub const MAX_NOTE_TEMPLATES: usize = 20;
+pub const MAX_NOTE_SIZE: usize = 300000; // dynamo items are capped at 400 KB
 pub const TOKEN_COST_PER_KB: f64 = 0.00006818181;
 pub const ALLOWED_UPLOAD_FILE_TYPES: [&str; 2] = [".apkg", ".csv"];
 pub const RAW_DECK_SIZE_LIMIT: usize = 1000000000; // 1 GB
+pub const CACHE_SIZE_BUDGET_IN_BYTES: u64 = 50000000; // 50 MB of cached notes before the least recently used decks are evicted
 
 pub const DECK_ID_LENGTH: usize = 21;
 pub const DECK_LIMIT: usize = 151;
 pub const NOTE_LIMIT: usize = 100000;
+pub const MAX_NOTE_IDS_PER_QUERY: usize = 1000;
 pub const SEPARATOR: char = '\u{001F}';
 pub const SEPARATOR2: char = '\u{001E}';
 pub const SEPARATOR3: char = '\u{001D}';
 pub const SEPARATOR4: char = '\u{001C}';
 pub const SEPARATOR5: &str = "|\u{001F}|";
 pub const MAX_ASSETS_PER_REQUEST: u8 = 25;
+pub const MAX_DECK_NAME_LENGTH: usize = 100;
+pub const MAX_DECK_DESCRIPTION_LENGTH: usize = 2000;
+pub const MAX_DECK_PRICE: f32 = 500.0;
+pub const MAX_DECK_LANGUAGE_LENGTH: usize = 40;
+pub const MAX_DECK_TAGS: usize = 10;
+pub const MAX_DECK_TAG_LENGTH: usize = 30;
+pub const CATALOG_PAGE_SIZE: usize = 24;
+pub const ALLOWED_DECK_COVER_FILE_TYPES: [&str; 5] = [".avif", ".webp", ".png", ".jpg", ".jpeg"];
+pub const DECK_COVER_SIZE_LIMIT: usize = 5000000; // 5 MB
+
+// PURCHASES
+pub const REFUND_WINDOW_IN_SECONDS: u64 = ONE_DAY_IN_SECONDS * 14;
+pub const PURCHASE_URL_PARAM: &str = "purchase";
+#[cfg(not(debug_assertions))]
+pub const BROWSE_DECKS_PAGE: &str = "https://lexlingua.io/browse-decks";
+#[cfg(debug_assertions)]
+pub const BROWSE_DECKS_PAGE: &str = "https://localhost:3000/browse-decks";
+
+// COLLABORATION
+pub const MAX_COLLABORATORS: usize = 25;
+pub const COLAB_INVITE_URL_PARAM: &str = "invite";
+#[cfg(not(debug_assertions))]
+pub const COLLABORATE_PAGE: &str = "https://lexlingua.io/collaborate";
+#[cfg(debug_assertions)]
+pub const COLLABORATE_PAGE: &str = "https://localhost:3000/collaborate";
+
+// STUDY
+pub const LESSON_BATCH_SIZE: usize = 10;
+pub const REVIEW_BATCH_SIZE: usize = 100;
+pub const STUDY_DECK_URL_PARAM: &str = "deck";
 
 // Time
 pub const ONE_MONTH_IN_SECONDS: u64 = 2629800;
 pub const ONE_DAY_IN_SECONDS: u64 = 86400;
 pub const CACHE_OUT_OF_DATE_LIMIT: u64 = ONE_DAY_IN_SECONDS * 5;
+pub const SYNC_RECORD_LIFETIME_IN_SECONDS: u64 = ONE_DAY_IN_SECONDS * 30;
+pub const SYNC_CLAIM_LIFETIME_IN_SECONDS: u64 = 60; // a replay that dies mid change frees its key after this long
+pub const PREFETCH_REQUEST_INTERVAL_IN_MILLIS: u64 = 1500; // the rate limiter refills a request every 500 ms and a note query can take three
 
 // VERIFICATION
 pub const PUBLIC_KEY: [u8; 32] = [224,221,70,136,138,4,23,242,133,57,200,126,219,223,19,130,157,157,198,186,206,254,54,38,191,215,226,51,244,191,74,177];
@@ -26,6 +63,11 @@ pub const USER_CLAIM_SIGN_UP: &str = "wannabe_user";
 pub const USER_CLAIM_REFRESH: &str = "refresh_user";
 pub const USER_CLAIM_AUTH: &str = "user";
 pub const IS_TRUSTED_CLAIM: &str = "trusted";
+pub const COLAB_INVITE_CLAIM: &str = "colab_invite";
+pub const DECK_CLAIM: &str = "deck";
+pub const ROLE_CLAIM: &str = "role";
+pub const INVITED_CLAIM: &str = "invited";
+pub const RESPONSE_CLAIM: &str = "response";
 pub const AUTH_TOKEN_HEADER: &str = "Authorization";
 pub const MAX_EMAIL_SIZE: usize = 100;
 
@@ -47,8 +89,13 @@ pub const LOCAL_AUTH_TOKEN_KEY: &str = "auth-token";
 pub const LOCAL_REFRESH_TOKEN_KEY: &str = "refresh-token";
 pub const LOCAL_USER_INFO_KEY: &str = "user-info";
 pub const CACHE_STATUS_COOKIE_KEY: &str = "cache-status";
+pub const LOCAL_SYNC_QUEUE_KEY: &str = "sync-queue";
+pub const LOCAL_REVIEW_STATES_KEY: &str = "review-states";
 pub const EXP_CLAIM_KEY: &str = "exp";
 pub const EMAIL_CLAIM_KEY: &str = "user";
 
 pub const S3_CREATION_DATE_URL_PARAM: &str = "X-Amz-Date=";
 pub const S3_EXPIRATION_URL_PARAM: &str = "X-Amz-Expires=";
+pub const LOCAL_OBJECT_ROUTE: &str = "/local-objects";
+pub const PAYMENT_WEBHOOK_ROUTE: &str = "/payment-webhook";
+pub const FAKE_CHECKOUT_ROUTE: &str = "/fake-checkout";
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, future::Future, str::FromStr};
+use std::{future::Future, str::FromStr};
 
 #[cfg(not(feature = "ssr"))]
 use leptos::logging::debug_warn;
@@ -8,10 +8,9 @@ use web_sys::window;
 use crate::utils::cache::clear_cache;
 
 use crate::utils::{
-    database_types::DeckId,
-    date_and_time::{current_time_in_seconds, full_iso_to_secs, Date, PartialDate},
+    date_and_time::{current_time_in_seconds, full_iso_to_secs, Date},
     outcomes::Outcome, 
-    shared_truth::{EXP_CLAIM_KEY, LOCAL_AUTH_TOKEN_KEY, LOCAL_REFRESH_TOKEN_KEY, PUBLIC_KEY, USER_CLAIM_AUTH, USER_CLAIM_REFRESH}, 
+    shared_truth::{CACHE_STATUS_COOKIE_KEY, EXP_CLAIM_KEY, LOCAL_AUTH_TOKEN_KEY, LOCAL_OBJECT_ROUTE, LOCAL_REFRESH_TOKEN_KEY, PAYMENT_WEBHOOK_ROUTE, PUBLIC_KEY, USER_CLAIM_AUTH, USER_CLAIM_REFRESH}, 
     sign_in_lib::TokenPair,
 };
 
@@ -301,21 +300,7 @@ pub fn clear_user_cache_and_cookies() {
     }
     let _ = clear_cookie(LOCAL_AUTH_TOKEN_KEY);
     let _ = clear_cookie(LOCAL_REFRESH_TOKEN_KEY);
-}
-
-pub fn get_fake_review_schedule(_deck_id: DeckId) -> (HashMap<PartialDate, usize>, usize) {
-    let mut fake_review_schedule = HashMap::with_capacity(Date::JAN.days(1970) * 3);
-
-    fake_review_schedule.insert(PartialDate::day_and_month(2, Date::MAR), 10);
-    fake_review_schedule.insert(PartialDate::day_and_month(30, Date::MAR), 3);
-    fake_review_schedule.insert(PartialDate::day_and_month(1, Date::APR), 7);
-    fake_review_schedule.insert(PartialDate::day_and_month(16, Date::APR), 37);
-    fake_review_schedule.insert(PartialDate::day_and_month(17, Date::APR), 21);
-    fake_review_schedule.insert(PartialDate::day_and_month(6, Date::MAY), 5);
-
-    let highest_review_amount = 37;
-
-    return (fake_review_schedule, highest_review_amount)
+    let _ = clear_cookie(CACHE_STATUS_COOKIE_KEY);
 }
 
 pub fn update_signal_with_future<T, F>(signal: RwSignal<T>, future: F)
@@ -369,13 +354,16 @@ pub fn get_url_query_client(query_key: &str) -> Option<String> {
     None
 }
 
-pub fn excluded_from_auth(url: String) -> bool {
-    let excluded_server_functions = ["send_email", "use_refresh_token", "create_user"];
-
-    for function_name in excluded_server_functions {
-        if url.contains(function_name) {
-            return true;
-        }
-    }
-    false
+/// Routes reached without signing in, each checks a token or signature of its own. Only the path is compared so a
+/// query can never make another route look excluded, the server functions here are given fixed endpoints to match.
+pub fn excluded_from_auth(path: &str) -> bool {
+    let excluded_routes = [
+        "/api/send_email",
+        "/api/use_refresh_token",
+        "/api/create_user",
+        "/api/respond_to_collaborator_invite",
+        PAYMENT_WEBHOOK_ROUTE,
+    ];
+
+    excluded_routes.contains(&path) || path.strip_prefix(LOCAL_OBJECT_ROUTE).is_some_and(|object_path| object_path.starts_with('/'))
 }
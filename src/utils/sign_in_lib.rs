@@ -55,10 +55,10 @@ impl ToString for TokenPair {
     }
 }
 
-#[server]
+#[server(endpoint = "use_refresh_token")]
 pub async fn use_refresh_token(refresh_token: String) -> Result<Outcome, ServerFnError> {
     #[cfg(feature="ssr")]
-    use crate::utils::{back_utils::generate_auth_token, dynamo_utils::{setup_client, validate_user_standing}};
+    use crate::utils::{back_utils::generate_auth_token, dynamo_utils::validate_user_standing, storage::setup_storage};
     let Ok(trusted_token) = verify_token(&refresh_token) else {return Ok(Outcome::VerificationFailure)};
 
     let Some(email) = get_claim(&trusted_token, USER_CLAIM_REFRESH) else {return Ok(Outcome::VerificationFailure)};
@@ -68,9 +68,9 @@ pub async fn use_refresh_token(refresh_token: String) -> Result<Outcome, ServerF
         None => false,
     };
     
-    let client = setup_client().await;
+    let storage = setup_storage().await;
 
-    let outcome = match validate_user_standing(&client, &email).await {
+    let outcome = match validate_user_standing(&storage, &email).await {
         Outcome::PermissionGranted(_) => generate_auth_token(&email, &refresh_token, trusted_device),
         any_other_outcome => return Ok(any_other_outcome),
     };
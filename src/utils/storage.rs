@@ -0,0 +1,2125 @@
+use std::{cmp::Reverse, collections::{BTreeMap, HashMap}, future::Future, path::PathBuf, str::FromStr, sync::{Arc, OnceLock, RwLock}};
+
+use aws_sdk_dynamodb::Client;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use crate::utils::{
+    back_utils::{CATALOG_TABLE, COLLABORATORS_TABLE, PAYOUT_ACCOUNTS_TABLE, RECEIPTS_TABLE, REVIEWS_TABLE, SYNC_RECORDS_TABLE, UPLOAD_LEDGER_TABLE},
+    catalog::{CatalogCursor, CatalogEntry, CatalogPage, CatalogQuery, PUBLIC_SHELF},
+    collaborators::{Collaborator, CollaboratorRole, InviteStatus},
+    database_types::{DBItem, DeckId, DeckList, DeckMeta, Note, NoteList, UpdateRecipe, UpdateRecipes, UpdateType, UpdateValues},
+    date_and_time::current_time_in_seconds,
+    dynamo_utils::{
+        accept_collaborator_invite, add_deck_to_user_active_decks_and_owned_decks, changes_deck_meta, claim_deck_id, claim_sync_record, deck_exists, deck_meta_update_recipe, delete_collaborator, delete_notes,
+        get_catalog_entry, get_collaborator, get_collaborators, get_deck_notes, get_ledger_entries, get_note, get_owed_receipts, get_payout_account,
+        get_payout_account_by_account_id, get_receipt, get_receipt_by_payment_id, get_receipts, get_review_state, get_review_states, get_sync_record, get_user, note_update_recipe,
+        put_catalog_entry, put_collaborator_invite, put_notes, put_payout_account, put_receipt, put_review_state, put_sync_record, put_user, query_catalog, record_upload_token_transaction,
+        remove_catalog_entry, remove_deck_from_user_deck_list, setup_client, subscribe_user_to_deck, swap_deck_meta, unsubscribe_user_from_deck, update_collaborator_role, update_item,
+        write_note_and_deck_meta, NoteWrite, ACTIVE_DECKS_DB_KEY, COLAB_DECKS_DB_KEY, OWNED_DECKS_DB_KEY,
+    },
+    outcomes::Outcome,
+    proceed,
+    purchases::{PayoutAccount, Receipt, ReceiptStatus},
+    query::{query_notes, QueryCursor, ValidQueryTypes},
+    scheduler::ReviewState,
+    sync_queue::SyncRecord,
+    upload_ledger::{LedgerEntry, TransactionType},
+    user_types::UserInfo,
+};
+
+pub const STORAGE_BACKEND_ENV_KEY: &str = "STORAGE_BACKEND";
+pub const STORAGE_PATH_ENV_KEY: &str = "STORAGE_PATH";
+pub const DEFAULT_STORAGE_PATH: &str = "lex-decks.sqlite3";
+
+static MEMORY_STORAGE: OnceLock<Arc<MemoryStorage>> = OnceLock::new();
+
+/// Everything the app needs from a database for users and notes, so it can run against dynamo, memory or a local file.
+pub trait Storage: Send + Sync {
+    /// Returns UserFound or UserNotFound, backends that cannot project attributes return the whole user.
+    fn get_user(&self, email: &str, projection_expression: Option<&str>) -> impl Future<Output = Outcome> + Send;
+    /// Creates a user, returning EmailAlreadyInUse if the email is taken.
+    fn put_user(&self, user: &UserInfo) -> impl Future<Output = Outcome> + Send;
+    /// Applies the recipes and returns DatabaseUpdateSuccess with the recipes to replay on the cache. Deck metas are
+    /// refused here, they go through swap_deck_meta so their version moves.
+    fn update_item(&self, update_recipes: Vec<UpdateRecipe>) -> impl Future<Output = Outcome> + Send;
+    /// Adds a newly uploaded deck to the user's active and owned decks.
+    fn add_deck_to_user_active_decks_and_owned_decks(&self, email: &str, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+    fn put_notes(&self, notes: &Vec<Note>) -> impl Future<Output = Outcome> + Send;
+    /// Removes the notes from the deck, used to clean up a deck that could not be finished.
+    fn delete_notes(&self, deck_id: DeckId, note_ids: &[u64]) -> impl Future<Output = Outcome> + Send;
+    /// Returns ItemsFound with the matching notes as a NoteList string, or PartialItemsFound with a cursor to continue from.
+    /// Notes read with a projection only have the attributes it names, local backends return whole notes.
+    fn query_notes(&self, query_type: &ValidQueryTypes, cursor: Option<&QueryCursor>, projection_expression: Option<&str>) -> impl Future<Output = Outcome> + Send;
+    /// Returns NoteFound or ItemsNotFound, note 0 holds the deck meta.
+    fn get_note(&self, deck_id: DeckId, note_id: u64) -> impl Future<Output = Outcome> + Send;
+    /// Returns PermissionGranted with the deck id if the deck has a meta note, otherwise ItemsNotFound.
+    fn deck_exists(&self, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+    /// Writes a placeholder meta note if the deck has none, returning PermissionGranted to the one upload that gets to
+    /// write the deck and InvalidRequest to every other.
+    fn claim_deck_id(&self, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+    /// Returns DeckNotesFound with every note of the deck including the meta note, or ItemsNotFound.
+    fn get_deck_notes(&self, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+    /// Writes the meta only if the stored one is still the meta it was changed from, otherwise returns NoteConflict
+    /// with the meta note as it is now.
+    fn swap_deck_meta(&self, deck_id: DeckId, stored_meta: &DeckMeta, meta: &DeckMeta) -> impl Future<Output = Outcome> + Send;
+    /// Writes the note and the deck meta counts together, returning NoteConflict with the server copy if the note is no
+    /// longer at the version the change was made against.
+    fn write_note_and_deck_meta(&self, deck_id: DeckId, note_write: NoteWrite) -> impl Future<Output = Outcome> + Send;
+
+    /// Returns ReviewStatesFound with the one state or ItemsNotFound if the card was never answered.
+    fn get_review_state(&self, email: &str, deck_id: DeckId, note_id: u64, card_ord: u8) -> impl Future<Output = Outcome> + Send;
+    fn put_review_state(&self, review_state: &ReviewState) -> impl Future<Output = Outcome> + Send;
+    /// Returns ReviewStatesFound with every state the user has for the deck.
+    fn get_review_states(&self, email: &str, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+
+    /// Changes the user's upload tokens together with the ledger entry. An entry whose transaction id is already in the
+    /// ledger is not applied again, a charge the user cannot afford returns NotEnoughUploadTokens.
+    fn record_upload_token_transaction(&self, ledger_entry: &LedgerEntry) -> impl Future<Output = Outcome> + Send;
+    /// Returns LedgerEntriesFound with the user's entries, newest first.
+    fn get_ledger_entries(&self, email: &str) -> impl Future<Output = Outcome> + Send;
+
+    /// Writes the entry while keeping the subscriber count the deck already has.
+    fn put_catalog_entry(&self, entry: &CatalogEntry) -> impl Future<Output = Outcome> + Send;
+    fn remove_catalog_entry(&self, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+    /// Returns CatalogEntryFound or ItemsNotFound.
+    fn get_catalog_entry(&self, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+    /// Returns CatalogPageFound with up to limit matching public decks after the query's cursor, most subscribed first.
+    fn query_catalog(&self, query: &CatalogQuery, limit: usize) -> impl Future<Output = Outcome> + Send;
+    /// Adds the deck to the user's active decks and counts the subscriber, returning AlreadySubscribed or
+    /// UserDoesNotHavePermission if the deck is not in the catalog.
+    fn subscribe_user_to_deck(&self, email: &str, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+    fn unsubscribe_user_from_deck(&self, email: &str, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+    /// The recipes are empty if the deck was not in the list.
+    fn remove_deck_from_user_deck_list(&self, email: &str, deck_id: DeckId, deck_list_db_key: &str, deck_list_cache_key: &str) -> impl Future<Output = Outcome> + Send;
+
+    /// Writes the receipt if there is none yet or the stored one has one of the replaced statuses, otherwise returns InvalidRequest.
+    fn put_receipt(&self, receipt: &Receipt, replaces: &[ReceiptStatus]) -> impl Future<Output = Outcome> + Send;
+    /// Returns ReceiptFound or ItemsNotFound.
+    fn get_receipt(&self, email: &str, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+    fn get_receipt_by_payment_id(&self, payment_id: &str) -> impl Future<Output = Outcome> + Send;
+    /// Returns ReceiptsFound with every receipt the user has.
+    fn get_receipts(&self, email: &str) -> impl Future<Output = Outcome> + Send;
+    /// Returns ReceiptsFound with the paid receipts for the owner's decks that have not been paid out yet.
+    fn get_owed_receipts(&self, owner: &str) -> impl Future<Output = Outcome> + Send;
+    fn put_payout_account(&self, account: &PayoutAccount) -> impl Future<Output = Outcome> + Send;
+    /// Returns PayoutAccountFound or ItemsNotFound.
+    fn get_payout_account(&self, email: &str) -> impl Future<Output = Outcome> + Send;
+    fn get_payout_account_by_account_id(&self, account_id: &str) -> impl Future<Output = Outcome> + Send;
+
+    /// Writes the invite unless the user already accepted one for the deck, which returns InvalidRequest.
+    fn put_collaborator_invite(&self, collaborator: &Collaborator) -> impl Future<Output = Outcome> + Send;
+    /// Returns CollaboratorFound or ItemsNotFound.
+    fn get_collaborator(&self, deck_id: DeckId, email: &str) -> impl Future<Output = Outcome> + Send;
+    /// Returns CollaboratorsFound with everyone invited to the deck.
+    fn get_collaborators(&self, deck_id: DeckId) -> impl Future<Output = Outcome> + Send;
+    fn update_collaborator_role(&self, deck_id: DeckId, email: &str, role: CollaboratorRole) -> impl Future<Output = Outcome> + Send;
+    /// only_if leaves the collaborator in place unless their invite has that status.
+    fn delete_collaborator(&self, deck_id: DeckId, email: &str, only_if: Option<InviteStatus>) -> impl Future<Output = Outcome> + Send;
+    /// Marks the invite accepted and adds the deck to the user's colab decks, returning UserDoesNotHavePermission if the
+    /// invite changed since it was sent.
+    fn accept_collaborator_invite(&self, collaborator: &Collaborator) -> impl Future<Output = Outcome> + Send;
+
+    fn put_sync_record(&self, record: &SyncRecord) -> impl Future<Output = Outcome> + Send;
+    /// Puts the claim only if the key is unused or its claim went stale, otherwise returns SyncRecordFound with the stored record.
+    fn claim_sync_record(&self, claim: &SyncRecord) -> impl Future<Output = Outcome> + Send;
+    /// Returns SyncRecordFound or ItemsNotFound.
+    fn get_sync_record(&self, email: &str, idempotency_key: &str) -> impl Future<Output = Outcome> + Send;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StorageConfig {
+    Dynamo,
+    Memory,
+    Sqlite(PathBuf),
+}
+
+impl StorageConfig {
+    /// Reads STORAGE_BACKEND (dynamo, memory or sqlite) and STORAGE_PATH, defaulting to dynamo.
+    pub fn from_env() -> Self {
+        let backend = std::env::var(STORAGE_BACKEND_ENV_KEY).unwrap_or_default().to_lowercase();
+        match backend.as_str() {
+            "memory" => StorageConfig::Memory,
+            "sqlite" => StorageConfig::Sqlite(PathBuf::from(std::env::var(STORAGE_PATH_ENV_KEY).unwrap_or(DEFAULT_STORAGE_PATH.to_string()))),
+            _ => StorageConfig::Dynamo,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum StorageBackend {
+    Dynamo(Client),
+    Memory(Arc<MemoryStorage>),
+    Sqlite(SqliteStorage),
+}
+
+pub async fn setup_storage() -> StorageBackend {
+    match StorageConfig::from_env() {
+        StorageConfig::Dynamo => StorageBackend::Dynamo(setup_client().await),
+        // Every request shares the same memory so data lives as long as the server does
+        StorageConfig::Memory => StorageBackend::Memory(MEMORY_STORAGE.get_or_init(|| Arc::new(MemoryStorage::default())).clone()),
+        StorageConfig::Sqlite(path) => StorageBackend::Sqlite(SqliteStorage::new(path)),
+    }
+}
+
+impl Storage for StorageBackend {
+    async fn get_user(&self, email: &str, projection_expression: Option<&str>) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => client.get_user(email, projection_expression).await,
+            StorageBackend::Memory(memory) => memory.get_user(email, projection_expression).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_user(email, projection_expression).await,
+        }
+    }
+
+    async fn put_user(&self, user: &UserInfo) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::put_user(client, user).await,
+            StorageBackend::Memory(memory) => memory.put_user(user).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.put_user(user).await,
+        }
+    }
+
+    async fn update_item(&self, update_recipes: Vec<UpdateRecipe>) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::update_item(client, update_recipes).await,
+            StorageBackend::Memory(memory) => memory.update_item(update_recipes).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.update_item(update_recipes).await,
+        }
+    }
+
+    async fn add_deck_to_user_active_decks_and_owned_decks(&self, email: &str, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::add_deck_to_user_active_decks_and_owned_decks(client, email, deck_id).await,
+            StorageBackend::Memory(memory) => memory.add_deck_to_user_active_decks_and_owned_decks(email, deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.add_deck_to_user_active_decks_and_owned_decks(email, deck_id).await,
+        }
+    }
+
+    async fn put_notes(&self, notes: &Vec<Note>) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::put_notes(client, notes).await,
+            StorageBackend::Memory(memory) => memory.put_notes(notes).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.put_notes(notes).await,
+        }
+    }
+
+    async fn delete_notes(&self, deck_id: DeckId, note_ids: &[u64]) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::delete_notes(client, deck_id, note_ids).await,
+            StorageBackend::Memory(memory) => memory.delete_notes(deck_id, note_ids).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.delete_notes(deck_id, note_ids).await,
+        }
+    }
+
+    async fn query_notes(&self, query_type: &ValidQueryTypes, cursor: Option<&QueryCursor>, projection_expression: Option<&str>) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => client.query_notes(query_type, cursor, projection_expression).await,
+            StorageBackend::Memory(memory) => memory.query_notes(query_type, cursor, projection_expression).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.query_notes(query_type, cursor, projection_expression).await,
+        }
+    }
+
+    async fn get_note(&self, deck_id: DeckId, note_id: u64) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_note(client, deck_id, note_id).await,
+            StorageBackend::Memory(memory) => memory.get_note(deck_id, note_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_note(deck_id, note_id).await,
+        }
+    }
+
+    async fn deck_exists(&self, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::deck_exists(client, deck_id).await,
+            StorageBackend::Memory(memory) => memory.deck_exists(deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.deck_exists(deck_id).await,
+        }
+    }
+
+    async fn claim_deck_id(&self, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::claim_deck_id(client, deck_id).await,
+            StorageBackend::Memory(memory) => memory.claim_deck_id(deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.claim_deck_id(deck_id).await,
+        }
+    }
+
+    async fn get_deck_notes(&self, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_deck_notes(client, deck_id).await,
+            StorageBackend::Memory(memory) => memory.get_deck_notes(deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_deck_notes(deck_id).await,
+        }
+    }
+
+    async fn swap_deck_meta(&self, deck_id: DeckId, stored_meta: &DeckMeta, meta: &DeckMeta) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::swap_deck_meta(client, deck_id, stored_meta, meta).await,
+            StorageBackend::Memory(memory) => memory.swap_deck_meta(deck_id, stored_meta, meta).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.swap_deck_meta(deck_id, stored_meta, meta).await,
+        }
+    }
+
+    async fn write_note_and_deck_meta(&self, deck_id: DeckId, note_write: NoteWrite) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::write_note_and_deck_meta(client, deck_id, note_write).await,
+            StorageBackend::Memory(memory) => memory.write_note_and_deck_meta(deck_id, note_write).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.write_note_and_deck_meta(deck_id, note_write).await,
+        }
+    }
+
+    async fn get_review_state(&self, email: &str, deck_id: DeckId, note_id: u64, card_ord: u8) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_review_state(client, email, deck_id, note_id, card_ord).await,
+            StorageBackend::Memory(memory) => memory.get_review_state(email, deck_id, note_id, card_ord).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_review_state(email, deck_id, note_id, card_ord).await,
+        }
+    }
+
+    async fn put_review_state(&self, review_state: &ReviewState) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::put_review_state(client, review_state).await,
+            StorageBackend::Memory(memory) => memory.put_review_state(review_state).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.put_review_state(review_state).await,
+        }
+    }
+
+    async fn get_review_states(&self, email: &str, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_review_states(client, email, deck_id).await,
+            StorageBackend::Memory(memory) => memory.get_review_states(email, deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_review_states(email, deck_id).await,
+        }
+    }
+
+    async fn record_upload_token_transaction(&self, ledger_entry: &LedgerEntry) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::record_upload_token_transaction(client, ledger_entry).await,
+            StorageBackend::Memory(memory) => memory.record_upload_token_transaction(ledger_entry).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.record_upload_token_transaction(ledger_entry).await,
+        }
+    }
+
+    async fn get_ledger_entries(&self, email: &str) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_ledger_entries(client, email).await,
+            StorageBackend::Memory(memory) => memory.get_ledger_entries(email).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_ledger_entries(email).await,
+        }
+    }
+
+    async fn put_catalog_entry(&self, entry: &CatalogEntry) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::put_catalog_entry(client, entry).await,
+            StorageBackend::Memory(memory) => memory.put_catalog_entry(entry).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.put_catalog_entry(entry).await,
+        }
+    }
+
+    async fn remove_catalog_entry(&self, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::remove_catalog_entry(client, deck_id).await,
+            StorageBackend::Memory(memory) => memory.remove_catalog_entry(deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.remove_catalog_entry(deck_id).await,
+        }
+    }
+
+    async fn get_catalog_entry(&self, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_catalog_entry(client, deck_id).await,
+            StorageBackend::Memory(memory) => memory.get_catalog_entry(deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_catalog_entry(deck_id).await,
+        }
+    }
+
+    async fn query_catalog(&self, query: &CatalogQuery, limit: usize) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::query_catalog(client, query, limit).await,
+            StorageBackend::Memory(memory) => memory.query_catalog(query, limit).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.query_catalog(query, limit).await,
+        }
+    }
+
+    async fn subscribe_user_to_deck(&self, email: &str, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::subscribe_user_to_deck(client, email, deck_id).await,
+            StorageBackend::Memory(memory) => memory.subscribe_user_to_deck(email, deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.subscribe_user_to_deck(email, deck_id).await,
+        }
+    }
+
+    async fn unsubscribe_user_from_deck(&self, email: &str, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::unsubscribe_user_from_deck(client, email, deck_id).await,
+            StorageBackend::Memory(memory) => memory.unsubscribe_user_from_deck(email, deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.unsubscribe_user_from_deck(email, deck_id).await,
+        }
+    }
+
+    async fn remove_deck_from_user_deck_list(&self, email: &str, deck_id: DeckId, deck_list_db_key: &str, deck_list_cache_key: &str) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::remove_deck_from_user_deck_list(client, email, deck_id, deck_list_db_key, deck_list_cache_key).await,
+            StorageBackend::Memory(memory) => memory.remove_deck_from_user_deck_list(email, deck_id, deck_list_db_key, deck_list_cache_key).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.remove_deck_from_user_deck_list(email, deck_id, deck_list_db_key, deck_list_cache_key).await,
+        }
+    }
+
+    async fn put_receipt(&self, receipt: &Receipt, replaces: &[ReceiptStatus]) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::put_receipt(client, receipt, replaces).await,
+            StorageBackend::Memory(memory) => memory.put_receipt(receipt, replaces).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.put_receipt(receipt, replaces).await,
+        }
+    }
+
+    async fn get_receipt(&self, email: &str, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_receipt(client, email, deck_id).await,
+            StorageBackend::Memory(memory) => memory.get_receipt(email, deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_receipt(email, deck_id).await,
+        }
+    }
+
+    async fn get_receipt_by_payment_id(&self, payment_id: &str) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_receipt_by_payment_id(client, payment_id).await,
+            StorageBackend::Memory(memory) => memory.get_receipt_by_payment_id(payment_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_receipt_by_payment_id(payment_id).await,
+        }
+    }
+
+    async fn get_receipts(&self, email: &str) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_receipts(client, email).await,
+            StorageBackend::Memory(memory) => memory.get_receipts(email).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_receipts(email).await,
+        }
+    }
+
+    async fn get_owed_receipts(&self, owner: &str) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_owed_receipts(client, owner).await,
+            StorageBackend::Memory(memory) => memory.get_owed_receipts(owner).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_owed_receipts(owner).await,
+        }
+    }
+
+    async fn put_payout_account(&self, account: &PayoutAccount) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::put_payout_account(client, account).await,
+            StorageBackend::Memory(memory) => memory.put_payout_account(account).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.put_payout_account(account).await,
+        }
+    }
+
+    async fn get_payout_account(&self, email: &str) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_payout_account(client, email).await,
+            StorageBackend::Memory(memory) => memory.get_payout_account(email).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_payout_account(email).await,
+        }
+    }
+
+    async fn get_payout_account_by_account_id(&self, account_id: &str) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_payout_account_by_account_id(client, account_id).await,
+            StorageBackend::Memory(memory) => memory.get_payout_account_by_account_id(account_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_payout_account_by_account_id(account_id).await,
+        }
+    }
+
+    async fn put_collaborator_invite(&self, collaborator: &Collaborator) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::put_collaborator_invite(client, collaborator).await,
+            StorageBackend::Memory(memory) => memory.put_collaborator_invite(collaborator).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.put_collaborator_invite(collaborator).await,
+        }
+    }
+
+    async fn get_collaborator(&self, deck_id: DeckId, email: &str) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_collaborator(client, deck_id, email).await,
+            StorageBackend::Memory(memory) => memory.get_collaborator(deck_id, email).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_collaborator(deck_id, email).await,
+        }
+    }
+
+    async fn get_collaborators(&self, deck_id: DeckId) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_collaborators(client, deck_id).await,
+            StorageBackend::Memory(memory) => memory.get_collaborators(deck_id).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_collaborators(deck_id).await,
+        }
+    }
+
+    async fn update_collaborator_role(&self, deck_id: DeckId, email: &str, role: CollaboratorRole) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::update_collaborator_role(client, deck_id, email, role).await,
+            StorageBackend::Memory(memory) => memory.update_collaborator_role(deck_id, email, role).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.update_collaborator_role(deck_id, email, role).await,
+        }
+    }
+
+    async fn delete_collaborator(&self, deck_id: DeckId, email: &str, only_if: Option<InviteStatus>) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::delete_collaborator(client, deck_id, email, only_if).await,
+            StorageBackend::Memory(memory) => memory.delete_collaborator(deck_id, email, only_if).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.delete_collaborator(deck_id, email, only_if).await,
+        }
+    }
+
+    async fn accept_collaborator_invite(&self, collaborator: &Collaborator) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::accept_collaborator_invite(client, collaborator).await,
+            StorageBackend::Memory(memory) => memory.accept_collaborator_invite(collaborator).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.accept_collaborator_invite(collaborator).await,
+        }
+    }
+
+    async fn put_sync_record(&self, record: &SyncRecord) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::put_sync_record(client, record).await,
+            StorageBackend::Memory(memory) => memory.put_sync_record(record).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.put_sync_record(record).await,
+        }
+    }
+
+    async fn claim_sync_record(&self, claim: &SyncRecord) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::claim_sync_record(client, claim).await,
+            StorageBackend::Memory(memory) => memory.claim_sync_record(claim).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.claim_sync_record(claim).await,
+        }
+    }
+
+    async fn get_sync_record(&self, email: &str, idempotency_key: &str) -> Outcome {
+        match self {
+            StorageBackend::Dynamo(client) => Storage::get_sync_record(client, email, idempotency_key).await,
+            StorageBackend::Memory(memory) => memory.get_sync_record(email, idempotency_key).await,
+            StorageBackend::Sqlite(sqlite) => sqlite.get_sync_record(email, idempotency_key).await,
+        }
+    }
+}
+
+impl Storage for Client {
+    async fn get_user(&self, email: &str, projection_expression: Option<&str>) -> Outcome {
+        get_user(self, email, projection_expression).await
+    }
+
+    async fn put_user(&self, user: &UserInfo) -> Outcome {
+        put_user(self, user).await
+    }
+
+    async fn update_item(&self, update_recipes: Vec<UpdateRecipe>) -> Outcome {
+        update_item(self, update_recipes).await
+    }
+
+    async fn add_deck_to_user_active_decks_and_owned_decks(&self, email: &str, deck_id: DeckId) -> Outcome {
+        add_deck_to_user_active_decks_and_owned_decks(self.clone(), email, &deck_id.to_string()).await
+    }
+
+    async fn put_notes(&self, notes: &Vec<Note>) -> Outcome {
+        put_notes(self, notes).await
+    }
+
+    async fn delete_notes(&self, deck_id: DeckId, note_ids: &[u64]) -> Outcome {
+        delete_notes(self, deck_id, note_ids).await
+    }
+
+    async fn query_notes(&self, query_type: &ValidQueryTypes, cursor: Option<&QueryCursor>, projection_expression: Option<&str>) -> Outcome {
+        query_notes(self, query_type, cursor, projection_expression).await
+    }
+
+    async fn get_note(&self, deck_id: DeckId, note_id: u64) -> Outcome {
+        get_note(self, deck_id, note_id).await
+    }
+
+    async fn deck_exists(&self, deck_id: DeckId) -> Outcome {
+        deck_exists(self, deck_id).await
+    }
+
+    async fn claim_deck_id(&self, deck_id: DeckId) -> Outcome {
+        claim_deck_id(self, deck_id).await
+    }
+
+    async fn get_deck_notes(&self, deck_id: DeckId) -> Outcome {
+        get_deck_notes(self, deck_id).await
+    }
+
+    async fn swap_deck_meta(&self, deck_id: DeckId, stored_meta: &DeckMeta, meta: &DeckMeta) -> Outcome {
+        swap_deck_meta(self, deck_id, stored_meta, meta).await
+    }
+
+    async fn write_note_and_deck_meta(&self, deck_id: DeckId, note_write: NoteWrite) -> Outcome {
+        write_note_and_deck_meta(self, deck_id, note_write).await
+    }
+
+    async fn get_review_state(&self, email: &str, deck_id: DeckId, note_id: u64, card_ord: u8) -> Outcome {
+        get_review_state(self, email, deck_id, note_id, card_ord).await
+    }
+
+    async fn put_review_state(&self, review_state: &ReviewState) -> Outcome {
+        put_review_state(self, review_state).await
+    }
+
+    async fn get_review_states(&self, email: &str, deck_id: DeckId) -> Outcome {
+        get_review_states(self, email, deck_id).await
+    }
+
+    async fn record_upload_token_transaction(&self, ledger_entry: &LedgerEntry) -> Outcome {
+        record_upload_token_transaction(self, ledger_entry).await
+    }
+
+    async fn get_ledger_entries(&self, email: &str) -> Outcome {
+        get_ledger_entries(self, email).await
+    }
+
+    async fn put_catalog_entry(&self, entry: &CatalogEntry) -> Outcome {
+        put_catalog_entry(self, entry).await
+    }
+
+    async fn remove_catalog_entry(&self, deck_id: DeckId) -> Outcome {
+        remove_catalog_entry(self, deck_id).await
+    }
+
+    async fn get_catalog_entry(&self, deck_id: DeckId) -> Outcome {
+        get_catalog_entry(self, deck_id).await
+    }
+
+    async fn query_catalog(&self, query: &CatalogQuery, limit: usize) -> Outcome {
+        query_catalog(self, query, limit).await
+    }
+
+    async fn subscribe_user_to_deck(&self, email: &str, deck_id: DeckId) -> Outcome {
+        subscribe_user_to_deck(self, email, deck_id).await
+    }
+
+    async fn unsubscribe_user_from_deck(&self, email: &str, deck_id: DeckId) -> Outcome {
+        unsubscribe_user_from_deck(self, email, deck_id).await
+    }
+
+    async fn remove_deck_from_user_deck_list(&self, email: &str, deck_id: DeckId, deck_list_db_key: &str, deck_list_cache_key: &str) -> Outcome {
+        remove_deck_from_user_deck_list(self, email, deck_id, deck_list_db_key, deck_list_cache_key).await
+    }
+
+    async fn put_receipt(&self, receipt: &Receipt, replaces: &[ReceiptStatus]) -> Outcome {
+        put_receipt(self, receipt, replaces).await
+    }
+
+    async fn get_receipt(&self, email: &str, deck_id: DeckId) -> Outcome {
+        get_receipt(self, email, deck_id).await
+    }
+
+    async fn get_receipt_by_payment_id(&self, payment_id: &str) -> Outcome {
+        get_receipt_by_payment_id(self, payment_id).await
+    }
+
+    async fn get_receipts(&self, email: &str) -> Outcome {
+        get_receipts(self, email).await
+    }
+
+    async fn get_owed_receipts(&self, owner: &str) -> Outcome {
+        get_owed_receipts(self, owner).await
+    }
+
+    async fn put_payout_account(&self, account: &PayoutAccount) -> Outcome {
+        put_payout_account(self, account).await
+    }
+
+    async fn get_payout_account(&self, email: &str) -> Outcome {
+        get_payout_account(self, email).await
+    }
+
+    async fn get_payout_account_by_account_id(&self, account_id: &str) -> Outcome {
+        get_payout_account_by_account_id(self, account_id).await
+    }
+
+    async fn put_collaborator_invite(&self, collaborator: &Collaborator) -> Outcome {
+        put_collaborator_invite(self, collaborator).await
+    }
+
+    async fn get_collaborator(&self, deck_id: DeckId, email: &str) -> Outcome {
+        get_collaborator(self, deck_id, email).await
+    }
+
+    async fn get_collaborators(&self, deck_id: DeckId) -> Outcome {
+        get_collaborators(self, deck_id).await
+    }
+
+    async fn update_collaborator_role(&self, deck_id: DeckId, email: &str, role: CollaboratorRole) -> Outcome {
+        update_collaborator_role(self, deck_id, email, role).await
+    }
+
+    async fn delete_collaborator(&self, deck_id: DeckId, email: &str, only_if: Option<InviteStatus>) -> Outcome {
+        delete_collaborator(self, deck_id, email, only_if).await
+    }
+
+    async fn accept_collaborator_invite(&self, collaborator: &Collaborator) -> Outcome {
+        accept_collaborator_invite(self, collaborator).await
+    }
+
+    async fn put_sync_record(&self, record: &SyncRecord) -> Outcome {
+        put_sync_record(self, record).await
+    }
+
+    async fn claim_sync_record(&self, claim: &SyncRecord) -> Outcome {
+        claim_sync_record(self, claim).await
+    }
+
+    async fn get_sync_record(&self, email: &str, idempotency_key: &str) -> Outcome {
+        get_sync_record(self, email, idempotency_key).await
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    users: RwLock<HashMap<String, UserInfo>>,
+    notes: RwLock<HashMap<DeckId, BTreeMap<u64, Note>>>,
+    records: RwLock<BTreeMap<(String, String, String), Value>>,
+}
+
+impl MemoryStorage {
+    /// Locks users, notes and records in the same order as update_item so a change across them is seen all at once.
+    fn with_tables(&self, func: impl FnOnce(&mut MemoryTables) -> Outcome) -> Outcome {
+        let (Ok(mut users), Ok(mut notes), Ok(mut records)) = (self.users.write(), self.notes.write(), self.records.write()) else {
+            return Outcome::UnspecifiedQueryFailure("storage is poisoned".to_string());
+        };
+        func(&mut MemoryTables {users: &mut users, notes: &mut notes, records: &mut records})
+    }
+}
+
+impl Storage for MemoryStorage {
+    async fn get_user(&self, email: &str, _projection_expression: Option<&str>) -> Outcome {
+        let Ok(users) = self.users.read() else {return Outcome::UserNotFound};
+        match users.get(email) {
+            Some(user) => Outcome::UserFound(user.clone()),
+            None => Outcome::UserNotFound,
+        }
+    }
+
+    async fn put_user(&self, user: &UserInfo) -> Outcome {
+        let Ok(mut users) = self.users.write() else {return Outcome::CreateUserFailure("user storage is poisoned".to_string())};
+        if users.contains_key(&user.email) {
+            return Outcome::EmailAlreadyInUse;
+        }
+        users.insert(user.email.clone(), user.clone());
+        Outcome::DatabaseUpdateSuccess(UpdateRecipes::default())
+    }
+
+    async fn update_item(&self, update_recipes: Vec<UpdateRecipe>) -> Outcome {
+        if changes_deck_meta(&update_recipes) {
+            return Outcome::InvalidRequest;
+        }
+
+        let (Ok(mut users), Ok(mut decks)) = (self.users.write(), self.notes.write()) else {
+            return Outcome::UpdateUserFailure("storage is poisoned".to_string());
+        };
+
+        // Changes are made to copies first so a bad recipe leaves everything untouched
+        let mut updated_users = HashMap::new();
+        let mut updated_notes = HashMap::new();
+        for recipe in update_recipes.iter() {
+            match &recipe.update_item {
+                DBItem::User(email) => {
+                    let user = match updated_users.remove(email) {
+                        Some(user) => user,
+                        None => match users.get(email) {
+                            Some(user) => user.clone(),
+                            None => return Outcome::UserNotFound,
+                        },
+                    };
+                    let Ok(user) = apply_recipe_to_user(user, recipe) else {return Outcome::InvalidRequest};
+                    updated_users.insert(email.clone(), user);
+                },
+                DBItem::Note(deck_id, note_id) => {
+                    let note = match updated_notes.remove(&(*deck_id, *note_id)) {
+                        Some(note) => note,
+                        None => match decks.get(deck_id).and_then(|notes| notes.get(note_id)) {
+                            Some(note) => note.clone(),
+                            None => return Outcome::ItemsNotFound,
+                        },
+                    };
+                    let Ok(note) = apply_recipe_to_note(note, recipe) else {return Outcome::InvalidRequest};
+                    updated_notes.insert((*deck_id, *note_id), note);
+                },
+            }
+        }
+
+        users.extend(updated_users);
+        for ((deck_id, note_id), note) in updated_notes {
+            decks.entry(deck_id).or_default().insert(note_id, note);
+        }
+
+        Outcome::DatabaseUpdateSuccess(UpdateRecipes {recipes: update_recipes})
+    }
+
+    async fn add_deck_to_user_active_decks_and_owned_decks(&self, email: &str, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_add_deck_to_user_active_decks_and_owned_decks(tables, email, deck_id))
+    }
+
+    async fn put_notes(&self, notes: &Vec<Note>) -> Outcome {
+        let Ok(mut decks) = self.notes.write() else {return Outcome::NoteUpdateFailed("note storage is poisoned".to_string())};
+        for note in notes {
+            decks.entry(note.deck_id).or_default().insert(note.note_id, note.clone());
+        }
+        Outcome::NoteUpdateSuccess
+    }
+
+    async fn delete_notes(&self, deck_id: DeckId, note_ids: &[u64]) -> Outcome {
+        self.with_tables(|tables| local_delete_notes(tables, deck_id, note_ids))
+    }
+
+    async fn query_notes(&self, query_type: &ValidQueryTypes, cursor: Option<&QueryCursor>, _projection_expression: Option<&str>) -> Outcome {
+        let Ok(decks) = self.notes.read() else {return Outcome::UnspecifiedQueryFailure("note storage is poisoned".to_string())};
+        let Some(deck_id) = query_deck_id(query_type) else {return Outcome::InvalidRequest};
+
+        let notes = match decks.get(&deck_id) {
+            Some(notes) => notes.values().filter(|note| note_matches_query(note, query_type, cursor)).cloned().collect(),
+            None => Vec::new(),
+        };
+
+        Outcome::ItemsFound(NoteList {notes}.to_string())
+    }
+
+    async fn get_note(&self, deck_id: DeckId, note_id: u64) -> Outcome {
+        self.with_tables(|tables| local_get_note(tables, deck_id, note_id))
+    }
+
+    async fn deck_exists(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_deck_exists(tables, deck_id))
+    }
+
+    async fn claim_deck_id(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_claim_deck_id(tables, deck_id))
+    }
+
+    async fn get_deck_notes(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_get_deck_notes(tables, deck_id))
+    }
+
+    async fn swap_deck_meta(&self, deck_id: DeckId, stored_meta: &DeckMeta, meta: &DeckMeta) -> Outcome {
+        self.with_tables(|tables| local_swap_deck_meta(tables, deck_id, stored_meta, meta))
+    }
+
+    async fn write_note_and_deck_meta(&self, deck_id: DeckId, note_write: NoteWrite) -> Outcome {
+        self.with_tables(|tables| local_write_note_and_deck_meta(tables, deck_id, &note_write))
+    }
+
+    async fn get_review_state(&self, email: &str, deck_id: DeckId, note_id: u64, card_ord: u8) -> Outcome {
+        self.with_tables(|tables| local_get_review_state(tables, email, deck_id, note_id, card_ord))
+    }
+
+    async fn put_review_state(&self, review_state: &ReviewState) -> Outcome {
+        self.with_tables(|tables| local_put_review_state(tables, review_state))
+    }
+
+    async fn get_review_states(&self, email: &str, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_get_review_states(tables, email, deck_id))
+    }
+
+    async fn record_upload_token_transaction(&self, ledger_entry: &LedgerEntry) -> Outcome {
+        self.with_tables(|tables| local_record_upload_token_transaction(tables, ledger_entry))
+    }
+
+    async fn get_ledger_entries(&self, email: &str) -> Outcome {
+        self.with_tables(|tables| local_get_ledger_entries(tables, email))
+    }
+
+    async fn put_catalog_entry(&self, entry: &CatalogEntry) -> Outcome {
+        self.with_tables(|tables| local_put_catalog_entry(tables, entry))
+    }
+
+    async fn remove_catalog_entry(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_remove_catalog_entry(tables, deck_id))
+    }
+
+    async fn get_catalog_entry(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_get_catalog_entry(tables, deck_id))
+    }
+
+    async fn query_catalog(&self, query: &CatalogQuery, limit: usize) -> Outcome {
+        self.with_tables(|tables| local_query_catalog(tables, query, limit))
+    }
+
+    async fn subscribe_user_to_deck(&self, email: &str, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_subscribe_user_to_deck(tables, email, deck_id))
+    }
+
+    async fn unsubscribe_user_from_deck(&self, email: &str, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_unsubscribe_user_from_deck(tables, email, deck_id))
+    }
+
+    async fn remove_deck_from_user_deck_list(&self, email: &str, deck_id: DeckId, deck_list_db_key: &str, deck_list_cache_key: &str) -> Outcome {
+        self.with_tables(|tables| local_remove_deck_from_user_deck_list(tables, email, deck_id, deck_list_db_key, deck_list_cache_key))
+    }
+
+    async fn put_receipt(&self, receipt: &Receipt, replaces: &[ReceiptStatus]) -> Outcome {
+        self.with_tables(|tables| local_put_receipt(tables, receipt, replaces))
+    }
+
+    async fn get_receipt(&self, email: &str, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_get_receipt(tables, email, deck_id))
+    }
+
+    async fn get_receipt_by_payment_id(&self, payment_id: &str) -> Outcome {
+        self.with_tables(|tables| local_get_receipt_by_payment_id(tables, payment_id))
+    }
+
+    async fn get_receipts(&self, email: &str) -> Outcome {
+        self.with_tables(|tables| local_get_receipts(tables, email))
+    }
+
+    async fn get_owed_receipts(&self, owner: &str) -> Outcome {
+        self.with_tables(|tables| local_get_owed_receipts(tables, owner))
+    }
+
+    async fn put_payout_account(&self, account: &PayoutAccount) -> Outcome {
+        self.with_tables(|tables| local_put_payout_account(tables, account))
+    }
+
+    async fn get_payout_account(&self, email: &str) -> Outcome {
+        self.with_tables(|tables| local_get_payout_account(tables, email))
+    }
+
+    async fn get_payout_account_by_account_id(&self, account_id: &str) -> Outcome {
+        self.with_tables(|tables| local_get_payout_account_by_account_id(tables, account_id))
+    }
+
+    async fn put_collaborator_invite(&self, collaborator: &Collaborator) -> Outcome {
+        self.with_tables(|tables| local_put_collaborator_invite(tables, collaborator))
+    }
+
+    async fn get_collaborator(&self, deck_id: DeckId, email: &str) -> Outcome {
+        self.with_tables(|tables| local_get_collaborator(tables, deck_id, email))
+    }
+
+    async fn get_collaborators(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(|tables| local_get_collaborators(tables, deck_id))
+    }
+
+    async fn update_collaborator_role(&self, deck_id: DeckId, email: &str, role: CollaboratorRole) -> Outcome {
+        self.with_tables(|tables| local_update_collaborator_role(tables, deck_id, email, role))
+    }
+
+    async fn delete_collaborator(&self, deck_id: DeckId, email: &str, only_if: Option<InviteStatus>) -> Outcome {
+        self.with_tables(|tables| local_delete_collaborator(tables, deck_id, email, only_if))
+    }
+
+    async fn accept_collaborator_invite(&self, collaborator: &Collaborator) -> Outcome {
+        self.with_tables(|tables| local_accept_collaborator_invite(tables, collaborator))
+    }
+
+    async fn put_sync_record(&self, record: &SyncRecord) -> Outcome {
+        self.with_tables(|tables| local_put_sync_record(tables, record))
+    }
+
+    async fn claim_sync_record(&self, claim: &SyncRecord) -> Outcome {
+        self.with_tables(|tables| local_claim_sync_record(tables, claim))
+    }
+
+    async fn get_sync_record(&self, email: &str, idempotency_key: &str) -> Outcome {
+        self.with_tables(|tables| local_get_sync_record(tables, email, idempotency_key))
+    }
+}
+
+/// Keeps users and notes as json in a single sqlite file, each call opens its own connection off the async runtime.
+#[derive(Clone, Debug)]
+pub struct SqliteStorage {
+    path: PathBuf,
+}
+
+impl SqliteStorage {
+    pub fn new(path: PathBuf) -> Self {
+        Self {path}
+    }
+
+    async fn with_connection<T: Send + 'static>(&self, func: impl FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static) -> Result<T, String> {
+        let path = self.path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut connection = Connection::open(path)?;
+            connection.execute_batch("
+                CREATE TABLE IF NOT EXISTS users (email TEXT PRIMARY KEY, item TEXT NOT NULL);
+                CREATE TABLE IF NOT EXISTS notes (deck_id TEXT NOT NULL, note_id INTEGER NOT NULL, level INTEGER NOT NULL, note_type TEXT NOT NULL, item TEXT NOT NULL, PRIMARY KEY (deck_id, note_id));
+                CREATE INDEX IF NOT EXISTS notes_by_level ON notes (deck_id, level);
+                CREATE TABLE IF NOT EXISTS records (table_name TEXT NOT NULL, partition_key TEXT NOT NULL, sort_key TEXT NOT NULL, item TEXT NOT NULL, PRIMARY KEY (table_name, partition_key, sort_key));
+            ")?;
+            func(&mut connection)
+        }).await;
+
+        match result {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Runs the change in one transaction, anything that fails to read or write rolls back the whole change.
+    async fn with_tables(&self, func: impl FnOnce(&mut SqliteTables) -> Outcome + Send + 'static) -> Outcome {
+        let outcome = self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let mut tables = SqliteTables {transaction: &transaction, error: None};
+            let outcome = func(&mut tables);
+            match tables.error {
+                Some(e) => Ok(Outcome::UnspecifiedQueryFailure(e)),
+                None => transaction.commit().map(|_| outcome),
+            }
+        }).await;
+
+        outcome.unwrap_or_else(Outcome::UnspecifiedQueryFailure)
+    }
+}
+
+impl Storage for SqliteStorage {
+    async fn get_user(&self, email: &str, _projection_expression: Option<&str>) -> Outcome {
+        let email = email.to_string();
+        let item = self.with_connection(move |connection| {
+            connection.query_row("SELECT item FROM users WHERE email = ?1", [email], |row| row.get::<_, String>(0)).optional()
+        }).await;
+
+        match item {
+            Ok(Some(item)) => match UserInfo::from_str(&item) {
+                Ok(user) => Outcome::UserFound(user),
+                Err(_) => Outcome::IncorrectType,
+            },
+            Ok(None) | Err(_) => Outcome::UserNotFound,
+        }
+    }
+
+    async fn put_user(&self, user: &UserInfo) -> Outcome {
+        let (email, item) = (user.email.clone(), user.to_string());
+        let inserted = self.with_connection(move |connection| {
+            connection.execute("INSERT OR IGNORE INTO users (email, item) VALUES (?1, ?2)", params![email, item])
+        }).await;
+
+        match inserted {
+            Ok(0) => Outcome::EmailAlreadyInUse,
+            Ok(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes::default()),
+            Err(e) => Outcome::CreateUserFailure(e),
+        }
+    }
+
+    async fn update_item(&self, update_recipes: Vec<UpdateRecipe>) -> Outcome {
+        if changes_deck_meta(&update_recipes) {
+            return Outcome::InvalidRequest;
+        }
+
+        let recipes = update_recipes.clone();
+        let updated = self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            for recipe in recipes.iter() {
+                let applied = match &recipe.update_item {
+                    DBItem::User(email) => {
+                        let item: Option<String> = transaction.query_row("SELECT item FROM users WHERE email = ?1", [email], |row| row.get(0)).optional()?;
+                        match item.and_then(|item| UserInfo::from_str(&item).ok()).map(|user| apply_recipe_to_user(user, recipe)) {
+                            Some(Ok(user)) => transaction.execute("UPDATE users SET item = ?2 WHERE email = ?1", params![email, user.to_string()])? > 0,
+                            _ => false,
+                        }
+                    },
+                    DBItem::Note(deck_id, note_id) => {
+                        let item: Option<String> = transaction.query_row(
+                            "SELECT item FROM notes WHERE deck_id = ?1 AND note_id = ?2", params![deck_id.to_string(), *note_id as i64], |row| row.get(0)
+                        ).optional()?;
+                        match item.and_then(|item| serde_json::from_str::<Note>(&item).ok()).map(|note| apply_recipe_to_note(note, recipe)) {
+                            Some(Ok(note)) => insert_note(&transaction, &note)? > 0,
+                            _ => false,
+                        }
+                    },
+                };
+                // Dropping the transaction without committing rolls back everything applied so far
+                if !!!applied {
+                    return Ok(false);
+                }
+            }
+            transaction.commit()?;
+            Ok(true)
+        }).await;
+
+        match updated {
+            Ok(true) => Outcome::DatabaseUpdateSuccess(UpdateRecipes {recipes: update_recipes}),
+            Ok(false) => Outcome::InvalidRequest,
+            Err(e) => Outcome::UpdateUserFailure(e),
+        }
+    }
+
+    async fn add_deck_to_user_active_decks_and_owned_decks(&self, email: &str, deck_id: DeckId) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_add_deck_to_user_active_decks_and_owned_decks(tables, &email, deck_id)).await
+    }
+
+    async fn put_notes(&self, notes: &Vec<Note>) -> Outcome {
+        let notes = notes.clone();
+        let written = self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            for note in notes.iter() {
+                insert_note(&transaction, note)?;
+            }
+            transaction.commit()
+        }).await;
+
+        match written {
+            Ok(_) => Outcome::NoteUpdateSuccess,
+            Err(e) => Outcome::NoteUpdateFailed(e),
+        }
+    }
+
+    async fn delete_notes(&self, deck_id: DeckId, note_ids: &[u64]) -> Outcome {
+        let note_ids = note_ids.to_vec();
+        self.with_tables(move |tables| local_delete_notes(tables, deck_id, &note_ids)).await
+    }
+
+    async fn query_notes(&self, query_type: &ValidQueryTypes, cursor: Option<&QueryCursor>, _projection_expression: Option<&str>) -> Outcome {
+        let Some(deck_id) = query_deck_id(query_type) else {return Outcome::InvalidRequest};
+        let query_type = query_type.clone();
+        let cursor = cursor.cloned();
+
+        let notes = self.with_connection(move |connection| {
+            let mut statement = connection.prepare("SELECT item FROM notes WHERE deck_id = ?1 ORDER BY note_id")?;
+            let items = statement.query_map([deck_id.to_string()], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(items.iter()
+                .filter_map(|item| serde_json::from_str::<Note>(item).ok())
+                .filter(|note| note_matches_query(note, &query_type, cursor.as_ref()))
+                .collect::<Vec<Note>>())
+        }).await;
+
+        match notes {
+            Ok(notes) => Outcome::ItemsFound(NoteList {notes}.to_string()),
+            Err(e) => Outcome::UnspecifiedQueryFailure(e),
+        }
+    }
+
+    async fn get_note(&self, deck_id: DeckId, note_id: u64) -> Outcome {
+        self.with_tables(move |tables| local_get_note(tables, deck_id, note_id)).await
+    }
+
+    async fn deck_exists(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(move |tables| local_deck_exists(tables, deck_id)).await
+    }
+
+    async fn claim_deck_id(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(move |tables| local_claim_deck_id(tables, deck_id)).await
+    }
+
+    async fn get_deck_notes(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(move |tables| local_get_deck_notes(tables, deck_id)).await
+    }
+
+    async fn swap_deck_meta(&self, deck_id: DeckId, stored_meta: &DeckMeta, meta: &DeckMeta) -> Outcome {
+        let (stored_meta, meta) = (stored_meta.clone(), meta.clone());
+        self.with_tables(move |tables| local_swap_deck_meta(tables, deck_id, &stored_meta, &meta)).await
+    }
+
+    async fn write_note_and_deck_meta(&self, deck_id: DeckId, note_write: NoteWrite) -> Outcome {
+        self.with_tables(move |tables| local_write_note_and_deck_meta(tables, deck_id, &note_write)).await
+    }
+
+    async fn get_review_state(&self, email: &str, deck_id: DeckId, note_id: u64, card_ord: u8) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_get_review_state(tables, &email, deck_id, note_id, card_ord)).await
+    }
+
+    async fn put_review_state(&self, review_state: &ReviewState) -> Outcome {
+        let review_state = review_state.clone();
+        self.with_tables(move |tables| local_put_review_state(tables, &review_state)).await
+    }
+
+    async fn get_review_states(&self, email: &str, deck_id: DeckId) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_get_review_states(tables, &email, deck_id)).await
+    }
+
+    async fn record_upload_token_transaction(&self, ledger_entry: &LedgerEntry) -> Outcome {
+        let ledger_entry = ledger_entry.clone();
+        self.with_tables(move |tables| local_record_upload_token_transaction(tables, &ledger_entry)).await
+    }
+
+    async fn get_ledger_entries(&self, email: &str) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_get_ledger_entries(tables, &email)).await
+    }
+
+    async fn put_catalog_entry(&self, entry: &CatalogEntry) -> Outcome {
+        let entry = entry.clone();
+        self.with_tables(move |tables| local_put_catalog_entry(tables, &entry)).await
+    }
+
+    async fn remove_catalog_entry(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(move |tables| local_remove_catalog_entry(tables, deck_id)).await
+    }
+
+    async fn get_catalog_entry(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(move |tables| local_get_catalog_entry(tables, deck_id)).await
+    }
+
+    async fn query_catalog(&self, query: &CatalogQuery, limit: usize) -> Outcome {
+        let query = query.clone();
+        self.with_tables(move |tables| local_query_catalog(tables, &query, limit)).await
+    }
+
+    async fn subscribe_user_to_deck(&self, email: &str, deck_id: DeckId) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_subscribe_user_to_deck(tables, &email, deck_id)).await
+    }
+
+    async fn unsubscribe_user_from_deck(&self, email: &str, deck_id: DeckId) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_unsubscribe_user_from_deck(tables, &email, deck_id)).await
+    }
+
+    async fn remove_deck_from_user_deck_list(&self, email: &str, deck_id: DeckId, deck_list_db_key: &str, deck_list_cache_key: &str) -> Outcome {
+        let (email, deck_list_db_key, deck_list_cache_key) = (email.to_string(), deck_list_db_key.to_string(), deck_list_cache_key.to_string());
+        self.with_tables(move |tables| local_remove_deck_from_user_deck_list(tables, &email, deck_id, &deck_list_db_key, &deck_list_cache_key)).await
+    }
+
+    async fn put_receipt(&self, receipt: &Receipt, replaces: &[ReceiptStatus]) -> Outcome {
+        let (receipt, replaces) = (receipt.clone(), replaces.to_vec());
+        self.with_tables(move |tables| local_put_receipt(tables, &receipt, &replaces)).await
+    }
+
+    async fn get_receipt(&self, email: &str, deck_id: DeckId) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_get_receipt(tables, &email, deck_id)).await
+    }
+
+    async fn get_receipt_by_payment_id(&self, payment_id: &str) -> Outcome {
+        let payment_id = payment_id.to_string();
+        self.with_tables(move |tables| local_get_receipt_by_payment_id(tables, &payment_id)).await
+    }
+
+    async fn get_receipts(&self, email: &str) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_get_receipts(tables, &email)).await
+    }
+
+    async fn get_owed_receipts(&self, owner: &str) -> Outcome {
+        let owner = owner.to_string();
+        self.with_tables(move |tables| local_get_owed_receipts(tables, &owner)).await
+    }
+
+    async fn put_payout_account(&self, account: &PayoutAccount) -> Outcome {
+        let account = account.clone();
+        self.with_tables(move |tables| local_put_payout_account(tables, &account)).await
+    }
+
+    async fn get_payout_account(&self, email: &str) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_get_payout_account(tables, &email)).await
+    }
+
+    async fn get_payout_account_by_account_id(&self, account_id: &str) -> Outcome {
+        let account_id = account_id.to_string();
+        self.with_tables(move |tables| local_get_payout_account_by_account_id(tables, &account_id)).await
+    }
+
+    async fn put_collaborator_invite(&self, collaborator: &Collaborator) -> Outcome {
+        let collaborator = collaborator.clone();
+        self.with_tables(move |tables| local_put_collaborator_invite(tables, &collaborator)).await
+    }
+
+    async fn get_collaborator(&self, deck_id: DeckId, email: &str) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_get_collaborator(tables, deck_id, &email)).await
+    }
+
+    async fn get_collaborators(&self, deck_id: DeckId) -> Outcome {
+        self.with_tables(move |tables| local_get_collaborators(tables, deck_id)).await
+    }
+
+    async fn update_collaborator_role(&self, deck_id: DeckId, email: &str, role: CollaboratorRole) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_update_collaborator_role(tables, deck_id, &email, role)).await
+    }
+
+    async fn delete_collaborator(&self, deck_id: DeckId, email: &str, only_if: Option<InviteStatus>) -> Outcome {
+        let email = email.to_string();
+        self.with_tables(move |tables| local_delete_collaborator(tables, deck_id, &email, only_if)).await
+    }
+
+    async fn accept_collaborator_invite(&self, collaborator: &Collaborator) -> Outcome {
+        let collaborator = collaborator.clone();
+        self.with_tables(move |tables| local_accept_collaborator_invite(tables, &collaborator)).await
+    }
+
+    async fn put_sync_record(&self, record: &SyncRecord) -> Outcome {
+        let record = record.clone();
+        self.with_tables(move |tables| local_put_sync_record(tables, &record)).await
+    }
+
+    async fn claim_sync_record(&self, claim: &SyncRecord) -> Outcome {
+        let claim = claim.clone();
+        self.with_tables(move |tables| local_claim_sync_record(tables, &claim)).await
+    }
+
+    async fn get_sync_record(&self, email: &str, idempotency_key: &str) -> Outcome {
+        let (email, idempotency_key) = (email.to_string(), idempotency_key.to_string());
+        self.with_tables(move |tables| local_get_sync_record(tables, &email, &idempotency_key)).await
+    }
+}
+
+/// What the local backends share for the tables beyond users and notes, each item is kept as json under the dynamo
+/// table's name and its partition and sort keys. Items without a sort key use an empty one.
+trait LocalTables {
+    fn user(&mut self, email: &str) -> Option<UserInfo>;
+    fn set_user(&mut self, user: UserInfo);
+    fn note(&mut self, deck_id: DeckId, note_id: u64) -> Option<Note>;
+    fn set_note(&mut self, note: Note);
+    fn delete_note(&mut self, deck_id: DeckId, note_id: u64);
+    /// Every note of the deck in note id order.
+    fn deck_notes(&mut self, deck_id: DeckId) -> Vec<Note>;
+    fn record(&mut self, table: &str, partition_key: &str, sort_key: &str) -> Option<Value>;
+    fn set_record(&mut self, table: &str, partition_key: &str, sort_key: &str, item: Value);
+    fn delete_record(&mut self, table: &str, partition_key: &str, sort_key: &str);
+    /// Every item in the table, or only those under the partition key, in sort key order.
+    fn records(&mut self, table: &str, partition_key: Option<&str>) -> Vec<Value>;
+}
+
+struct MemoryTables<'a> {
+    users: &'a mut HashMap<String, UserInfo>,
+    notes: &'a mut HashMap<DeckId, BTreeMap<u64, Note>>,
+    records: &'a mut BTreeMap<(String, String, String), Value>,
+}
+
+impl LocalTables for MemoryTables<'_> {
+    fn user(&mut self, email: &str) -> Option<UserInfo> {
+        self.users.get(email).cloned()
+    }
+
+    fn set_user(&mut self, user: UserInfo) {
+        self.users.insert(user.email.clone(), user);
+    }
+
+    fn note(&mut self, deck_id: DeckId, note_id: u64) -> Option<Note> {
+        self.notes.get(&deck_id).and_then(|notes| notes.get(&note_id)).cloned()
+    }
+
+    fn set_note(&mut self, note: Note) {
+        self.notes.entry(note.deck_id).or_default().insert(note.note_id, note);
+    }
+
+    fn delete_note(&mut self, deck_id: DeckId, note_id: u64) {
+        if let Some(notes) = self.notes.get_mut(&deck_id) {
+            notes.remove(&note_id);
+        }
+    }
+
+    fn deck_notes(&mut self, deck_id: DeckId) -> Vec<Note> {
+        self.notes.get(&deck_id).map(|notes| notes.values().cloned().collect()).unwrap_or_default()
+    }
+
+    fn record(&mut self, table: &str, partition_key: &str, sort_key: &str) -> Option<Value> {
+        self.records.get(&(table.to_string(), partition_key.to_string(), sort_key.to_string())).cloned()
+    }
+
+    fn set_record(&mut self, table: &str, partition_key: &str, sort_key: &str, item: Value) {
+        self.records.insert((table.to_string(), partition_key.to_string(), sort_key.to_string()), item);
+    }
+
+    fn delete_record(&mut self, table: &str, partition_key: &str, sort_key: &str) {
+        self.records.remove(&(table.to_string(), partition_key.to_string(), sort_key.to_string()));
+    }
+
+    fn records(&mut self, table: &str, partition_key: Option<&str>) -> Vec<Value> {
+        self.records.iter()
+            .filter(|((record_table, record_partition_key, _), _)| record_table == table && partition_key.is_none_or(|key| key == record_partition_key))
+            .map(|(_, item)| item.clone())
+            .collect()
+    }
+}
+
+/// Keeps the first error so the transaction can be rolled back once the change is done.
+struct SqliteTables<'a> {
+    transaction: &'a Transaction<'a>,
+    error: Option<String>,
+}
+
+impl SqliteTables<'_> {
+    fn check<T>(&mut self, result: rusqlite::Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.error.get_or_insert(e.to_string());
+                None
+            },
+        }
+    }
+}
+
+impl LocalTables for SqliteTables<'_> {
+    fn user(&mut self, email: &str) -> Option<UserInfo> {
+        let item = self.transaction.query_row("SELECT item FROM users WHERE email = ?1", [email], |row| row.get::<_, String>(0)).optional();
+        self.check(item).flatten().and_then(|item| UserInfo::from_str(&item).ok())
+    }
+
+    fn set_user(&mut self, user: UserInfo) {
+        let written = self.transaction.execute("INSERT OR REPLACE INTO users (email, item) VALUES (?1, ?2)", params![user.email, user.to_string()]);
+        self.check(written);
+    }
+
+    fn note(&mut self, deck_id: DeckId, note_id: u64) -> Option<Note> {
+        let item = self.transaction.query_row(
+            "SELECT item FROM notes WHERE deck_id = ?1 AND note_id = ?2", params![deck_id.to_string(), note_id as i64], |row| row.get::<_, String>(0)
+        ).optional();
+        self.check(item).flatten().and_then(|item| serde_json::from_str(&item).ok())
+    }
+
+    fn set_note(&mut self, note: Note) {
+        let written = insert_note(self.transaction, &note);
+        self.check(written);
+    }
+
+    fn delete_note(&mut self, deck_id: DeckId, note_id: u64) {
+        let deleted = self.transaction.execute("DELETE FROM notes WHERE deck_id = ?1 AND note_id = ?2", params![deck_id.to_string(), note_id as i64]);
+        self.check(deleted);
+    }
+
+    fn deck_notes(&mut self, deck_id: DeckId) -> Vec<Note> {
+        let items = select_deck_notes(self.transaction, deck_id);
+        self.check(items).unwrap_or_default().iter().filter_map(|item| serde_json::from_str(item).ok()).collect()
+    }
+
+    fn record(&mut self, table: &str, partition_key: &str, sort_key: &str) -> Option<Value> {
+        let item = self.transaction.query_row(
+            "SELECT item FROM records WHERE table_name = ?1 AND partition_key = ?2 AND sort_key = ?3", params![table, partition_key, sort_key], |row| row.get::<_, String>(0)
+        ).optional();
+        self.check(item).flatten().and_then(|item| serde_json::from_str(&item).ok())
+    }
+
+    fn set_record(&mut self, table: &str, partition_key: &str, sort_key: &str, item: Value) {
+        let written = self.transaction.execute(
+            "INSERT OR REPLACE INTO records (table_name, partition_key, sort_key, item) VALUES (?1, ?2, ?3, ?4)", params![table, partition_key, sort_key, item.to_string()]
+        );
+        self.check(written);
+    }
+
+    fn delete_record(&mut self, table: &str, partition_key: &str, sort_key: &str) {
+        let deleted = self.transaction.execute(
+            "DELETE FROM records WHERE table_name = ?1 AND partition_key = ?2 AND sort_key = ?3", params![table, partition_key, sort_key]
+        );
+        self.check(deleted);
+    }
+
+    fn records(&mut self, table: &str, partition_key: Option<&str>) -> Vec<Value> {
+        let items = select_records(self.transaction, table, partition_key);
+        self.check(items).unwrap_or_default().iter().filter_map(|item| serde_json::from_str(item).ok()).collect()
+    }
+}
+
+fn select_deck_notes(connection: &Connection, deck_id: DeckId) -> rusqlite::Result<Vec<String>> {
+    let mut statement = connection.prepare("SELECT item FROM notes WHERE deck_id = ?1 ORDER BY note_id")?;
+    let items = statement.query_map([deck_id.to_string()], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(items)
+}
+
+fn select_records(connection: &Connection, table: &str, partition_key: Option<&str>) -> rusqlite::Result<Vec<String>> {
+    let mut statement = connection.prepare("SELECT item FROM records WHERE table_name = ?1 AND (?2 IS NULL OR partition_key = ?2) ORDER BY partition_key, sort_key")?;
+    let items = statement.query_map(params![table, partition_key], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(items)
+}
+
+fn typed_record<T: DeserializeOwned>(tables: &mut impl LocalTables, table: &str, partition_key: &str, sort_key: &str) -> Option<T> {
+    tables.record(table, partition_key, sort_key).and_then(|item| serde_json::from_value(item).ok())
+}
+
+fn typed_records<T: DeserializeOwned>(tables: &mut impl LocalTables, table: &str, partition_key: Option<&str>) -> Vec<T> {
+    tables.records(table, partition_key).into_iter().filter_map(|item| serde_json::from_value(item).ok()).collect()
+}
+
+fn set_typed_record<T: Serialize>(tables: &mut impl LocalTables, table: &str, partition_key: &str, sort_key: &str, item: &T) -> Outcome {
+    match serde_json::to_value(item) {
+        Ok(item) => {
+            tables.set_record(table, partition_key, sort_key, item);
+            Outcome::DatabaseUpdateSuccess(UpdateRecipes::default())
+        },
+        Err(_) => Outcome::IncorrectType,
+    }
+}
+
+fn found_or_not<T>(item: Option<T>, found: impl FnOnce(T) -> Outcome) -> Outcome {
+    match item {
+        Some(item) => found(item),
+        None => Outcome::ItemsNotFound,
+    }
+}
+
+fn local_add_deck_to_user_active_decks_and_owned_decks(tables: &mut impl LocalTables, email: &str, deck_id: DeckId) -> Outcome {
+    let Some(mut user) = tables.user(email) else {return Outcome::UserNotFound};
+    user.active_decks.push(deck_id);
+    user.owned_decks.push(deck_id);
+    tables.set_user(user);
+
+    let mut cache_recipes = UpdateRecipes::default();
+    for update_key in [UserInfo::ACTIVE_DECKS_CACHE_KEY, UserInfo::OWNED_DECKS_CACHE_KEY] {
+        cache_recipes.recipes.push(UpdateRecipe {
+            update_type: UpdateType::Add,
+            update_key: update_key.to_string(),
+            update_item: DBItem::User(email.to_string()),
+            value: UpdateValues::DeckList(DeckList {decks: vec![deck_id]}),
+        });
+    }
+
+    Outcome::DatabaseUpdateSuccess(cache_recipes)
+}
+
+fn local_delete_notes(tables: &mut impl LocalTables, deck_id: DeckId, note_ids: &[u64]) -> Outcome {
+    for note_id in note_ids {
+        tables.delete_note(deck_id, *note_id);
+    }
+    Outcome::NoteUpdateSuccess
+}
+
+fn local_get_note(tables: &mut impl LocalTables, deck_id: DeckId, note_id: u64) -> Outcome {
+    found_or_not(tables.note(deck_id, note_id), Outcome::NoteFound)
+}
+
+fn local_deck_exists(tables: &mut impl LocalTables, deck_id: DeckId) -> Outcome {
+    match tables.note(deck_id, 0) {
+        Some(_) => Outcome::PermissionGranted(deck_id.to_string()),
+        None => Outcome::ItemsNotFound,
+    }
+}
+
+fn local_claim_deck_id(tables: &mut impl LocalTables, deck_id: DeckId) -> Outcome {
+    if tables.note(deck_id, 0).is_some() {
+        return Outcome::InvalidRequest;
+    }
+    tables.set_note(Note {deck_id, note_id: 0, ..Default::default()});
+    Outcome::PermissionGranted(deck_id.to_string())
+}
+
+fn local_get_deck_notes(tables: &mut impl LocalTables, deck_id: DeckId) -> Outcome {
+    let notes = tables.deck_notes(deck_id);
+    if notes.is_empty() {
+        return Outcome::ItemsNotFound;
+    }
+    Outcome::DeckNotesFound(notes)
+}
+
+fn local_swap_deck_meta(tables: &mut impl LocalTables, deck_id: DeckId, stored_meta: &DeckMeta, meta: &DeckMeta) -> Outcome {
+    let Some(mut meta_note) = tables.note(deck_id, 0) else {return Outcome::ItemsNotFound};
+    if meta_note.meta.as_ref() != Some(stored_meta) {
+        return Outcome::NoteConflict(meta_note);
+    }
+
+    meta_note.meta = Some(meta.clone());
+    tables.set_note(meta_note);
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes {recipes: vec![deck_meta_update_recipe(deck_id, meta.clone())]})
+}
+
+/// The whole change happens under one lock or transaction, so unlike dynamo the meta can never move in between.
+fn local_write_note_and_deck_meta(tables: &mut impl LocalTables, deck_id: DeckId, note_write: &NoteWrite) -> Outcome {
+    let Some(mut meta_note) = tables.note(deck_id, 0) else {return Outcome::ItemsNotFound};
+    let Some(meta) = meta_note.meta.as_mut() else {return Outcome::IncorrectType};
+
+    let note_recipe = match note_write {
+        NoteWrite::Add(note) => {
+            let mut note = note.clone();
+            note.note_id = meta.next_note_id();
+            note.level = meta.level_for_new_note(note.note_id);
+            meta.count_note(&note);
+            tables.set_note(note.clone());
+            note_update_recipe(UpdateType::Swap, note)
+        },
+        NoteWrite::Edit {current, edited} => {
+            match tables.note(deck_id, current.note_id) {
+                Some(stored) if stored.version == current.version => proceed(),
+                Some(stored) => return Outcome::NoteConflict(stored),
+                None => return Outcome::ItemsNotFound,
+            };
+            meta.uncount_note(current);
+            meta.count_note(edited);
+            tables.set_note(edited.clone());
+            note_update_recipe(UpdateType::Swap, edited.clone())
+        },
+        NoteWrite::Delete(current) => {
+            match tables.note(deck_id, current.note_id) {
+                Some(stored) if stored.version == current.version => proceed(),
+                Some(stored) => return Outcome::NoteConflict(stored),
+                None => return Outcome::ItemsNotFound,
+            };
+            meta.uncount_note(current);
+            tables.delete_note(deck_id, current.note_id);
+            note_update_recipe(UpdateType::Subtract, current.clone())
+        },
+    };
+
+    meta.bump_version();
+    let meta = meta.clone();
+    tables.set_note(meta_note);
+
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes {recipes: vec![note_recipe, deck_meta_update_recipe(deck_id, meta)]})
+}
+
+fn local_get_review_state(tables: &mut impl LocalTables, email: &str, deck_id: DeckId, note_id: u64, card_ord: u8) -> Outcome {
+    let review_state = typed_record(tables, REVIEWS_TABLE, email, &ReviewState::card_key(deck_id, note_id, card_ord));
+    found_or_not(review_state, |review_state| Outcome::ReviewStatesFound(vec![review_state]))
+}
+
+fn local_put_review_state(tables: &mut impl LocalTables, review_state: &ReviewState) -> Outcome {
+    set_typed_record(tables, REVIEWS_TABLE, &review_state.user, &review_state.card, review_state)
+}
+
+fn local_get_review_states(tables: &mut impl LocalTables, email: &str, deck_id: DeckId) -> Outcome {
+    let deck_key_prefix = ReviewState::deck_key_prefix(deck_id);
+    let review_states = typed_records::<ReviewState>(tables, REVIEWS_TABLE, Some(email)).into_iter()
+        .filter(|review_state| review_state.card.starts_with(&deck_key_prefix))
+        .collect();
+    Outcome::ReviewStatesFound(review_states)
+}
+
+fn local_record_upload_token_transaction(tables: &mut impl LocalTables, ledger_entry: &LedgerEntry) -> Outcome {
+    let update_type = match ledger_entry.transaction_type {
+        TransactionType::Charge => UpdateType::Subtract,
+        TransactionType::Refund => UpdateType::Add,
+    };
+    let cache_recipes = UpdateRecipes {
+        recipes: vec![UpdateRecipe {
+            update_type,
+            update_key: UserInfo::UPLOAD_TOKENS_CACHE_KEY.to_string(),
+            update_item: DBItem::User(ledger_entry.user.clone()),
+            value: UpdateValues::Float64(ledger_entry.amount),
+        }],
+    };
+
+    // A retried transaction whose first attempt went through finds its own ledger entry, so it is already done
+    if tables.record(UPLOAD_LEDGER_TABLE, &ledger_entry.user, &ledger_entry.transaction_id).is_some() {
+        return Outcome::DatabaseUpdateSuccess(cache_recipes);
+    }
+
+    let Some(mut user) = tables.user(&ledger_entry.user) else {return Outcome::UserNotFound};
+    match ledger_entry.transaction_type {
+        TransactionType::Charge if user.upload_tokens < ledger_entry.amount => return Outcome::NotEnoughUploadTokens(user.upload_tokens),
+        TransactionType::Charge => user.upload_tokens -= ledger_entry.amount,
+        TransactionType::Refund => user.upload_tokens += ledger_entry.amount,
+    }
+
+    match set_typed_record(tables, UPLOAD_LEDGER_TABLE, &ledger_entry.user, &ledger_entry.transaction_id, ledger_entry) {
+        Outcome::DatabaseUpdateSuccess(_) => {
+            tables.set_user(user);
+            Outcome::DatabaseUpdateSuccess(cache_recipes)
+        },
+        any_other_outcome => any_other_outcome,
+    }
+}
+
+fn local_get_ledger_entries(tables: &mut impl LocalTables, email: &str) -> Outcome {
+    let mut ledger_entries = typed_records::<LedgerEntry>(tables, UPLOAD_LEDGER_TABLE, Some(email));
+    ledger_entries.sort_by_key(|ledger_entry| Reverse(ledger_entry.created));
+    Outcome::LedgerEntriesFound(ledger_entries)
+}
+
+fn local_put_catalog_entry(tables: &mut impl LocalTables, entry: &CatalogEntry) -> Outcome {
+    let deck_key = entry.deck_id.to_string();
+    let subscribers = typed_record::<CatalogEntry>(tables, CATALOG_TABLE, &deck_key, "").map(|stored| stored.subscribers).unwrap_or(0);
+    set_typed_record(tables, CATALOG_TABLE, &deck_key, "", &CatalogEntry {subscribers, ..entry.clone()})
+}
+
+fn local_remove_catalog_entry(tables: &mut impl LocalTables, deck_id: DeckId) -> Outcome {
+    tables.delete_record(CATALOG_TABLE, &deck_id.to_string(), "");
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes::default())
+}
+
+fn local_get_catalog_entry(tables: &mut impl LocalTables, deck_id: DeckId) -> Outcome {
+    found_or_not(typed_record(tables, CATALOG_TABLE, &deck_id.to_string(), ""), Outcome::CatalogEntryFound)
+}
+
+/// Decks with the same subscriber count are ordered by id so a cursor always lands between the same two decks.
+fn local_query_catalog(tables: &mut impl LocalTables, query: &CatalogQuery, limit: usize) -> Outcome {
+    let position = |deck_id: &DeckId, subscribers: u64| (Reverse(subscribers), Reverse(deck_id.to_string()));
+    let start = query.cursor.as_ref().map(|cursor| position(&cursor.deck_id, cursor.subscribers));
+
+    let mut entries: Vec<CatalogEntry> = typed_records::<CatalogEntry>(tables, CATALOG_TABLE, None).into_iter()
+        .filter(|entry| entry.shelf == PUBLIC_SHELF)
+        .filter(|entry| query.name.is_empty() || entry.search_name.contains(&query.name))
+        .filter(|entry| query.language.is_empty() || entry.language == query.language)
+        .filter(|entry| query.tag.is_empty() || entry.tags.contains(&query.tag))
+        .filter(|entry| start.as_ref().is_none_or(|start| &position(&entry.deck_id, entry.subscribers) > start))
+        .collect();
+    entries.sort_by_key(|entry| position(&entry.deck_id, entry.subscribers));
+
+    let has_more = entries.len() > limit;
+    entries.truncate(limit);
+    let next_cursor = entries.last().filter(|_| has_more).map(CatalogCursor::after);
+
+    Outcome::CatalogPageFound(CatalogPage {entries, next_cursor})
+}
+
+fn local_subscribe_user_to_deck(tables: &mut impl LocalTables, email: &str, deck_id: DeckId) -> Outcome {
+    let Some(mut user) = tables.user(email) else {return Outcome::UserNotFound};
+    if user.active_decks.contains(&deck_id) {
+        return Outcome::AlreadySubscribed;
+    }
+    let deck_key = deck_id.to_string();
+    let Some(mut entry) = typed_record::<CatalogEntry>(tables, CATALOG_TABLE, &deck_key, "") else {return Outcome::UserDoesNotHavePermission};
+
+    user.active_decks.push(deck_id);
+    tables.set_user(user);
+    entry.subscribers += 1;
+    match set_typed_record(tables, CATALOG_TABLE, &deck_key, "", &entry) {
+        Outcome::DatabaseUpdateSuccess(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes {
+            recipes: vec![UpdateRecipe {
+                update_type: UpdateType::Add,
+                update_key: UserInfo::ACTIVE_DECKS_CACHE_KEY.to_string(),
+                update_item: DBItem::User(email.to_string()),
+                value: UpdateValues::DeckList(DeckList {decks: vec![deck_id]}),
+            }],
+        }),
+        any_other_outcome => any_other_outcome,
+    }
+}
+
+fn local_unsubscribe_user_from_deck(tables: &mut impl LocalTables, email: &str, deck_id: DeckId) -> Outcome {
+    let cache_recipes = match local_remove_deck_from_user_deck_list(tables, email, deck_id, ACTIVE_DECKS_DB_KEY, UserInfo::ACTIVE_DECKS_CACHE_KEY) {
+        Outcome::DatabaseUpdateSuccess(cache_recipes) => cache_recipes,
+        any_other_outcome => return any_other_outcome,
+    };
+    if cache_recipes.recipes.is_empty() {
+        return Outcome::DatabaseUpdateSuccess(cache_recipes);
+    }
+
+    // Decks that have left the catalog have no subscriber count to change
+    let deck_key = deck_id.to_string();
+    if let Some(mut entry) = typed_record::<CatalogEntry>(tables, CATALOG_TABLE, &deck_key, "") {
+        entry.subscribers = entry.subscribers.saturating_sub(1);
+        let _ = set_typed_record(tables, CATALOG_TABLE, &deck_key, "", &entry);
+    }
+
+    Outcome::DatabaseUpdateSuccess(cache_recipes)
+}
+
+fn local_remove_deck_from_user_deck_list(tables: &mut impl LocalTables, email: &str, deck_id: DeckId, deck_list_db_key: &str, deck_list_cache_key: &str) -> Outcome {
+    let Some(mut user) = tables.user(email) else {return Outcome::UserNotFound};
+
+    let deck_list = match deck_list_db_key {
+        ACTIVE_DECKS_DB_KEY => &mut user.active_decks,
+        OWNED_DECKS_DB_KEY => &mut user.owned_decks,
+        COLAB_DECKS_DB_KEY => &mut user.colab_decks,
+        _ => return Outcome::InvalidRequest,
+    };
+
+    let Some(position) = deck_list.iter().position(|listed_deck| listed_deck == &deck_id) else {
+        return Outcome::DatabaseUpdateSuccess(UpdateRecipes::default());
+    };
+    deck_list.remove(position);
+    tables.set_user(user);
+
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes {
+        recipes: vec![UpdateRecipe {
+            update_type: UpdateType::Subtract,
+            update_key: deck_list_cache_key.to_string(),
+            update_item: DBItem::User(email.to_string()),
+            value: UpdateValues::DeckList(DeckList {decks: vec![deck_id]}),
+        }],
+    })
+}
+
+fn local_put_receipt(tables: &mut impl LocalTables, receipt: &Receipt, replaces: &[ReceiptStatus]) -> Outcome {
+    let deck_key = receipt.deck_id.to_string();
+    if let Some(stored) = typed_record::<Receipt>(tables, RECEIPTS_TABLE, &receipt.user, &deck_key) {
+        if !!!replaces.contains(&stored.status) {
+            return Outcome::InvalidRequest;
+        }
+    }
+    set_typed_record(tables, RECEIPTS_TABLE, &receipt.user, &deck_key, receipt)
+}
+
+fn local_get_receipt(tables: &mut impl LocalTables, email: &str, deck_id: DeckId) -> Outcome {
+    found_or_not(typed_record(tables, RECEIPTS_TABLE, email, &deck_id.to_string()), Outcome::ReceiptFound)
+}
+
+fn local_get_receipt_by_payment_id(tables: &mut impl LocalTables, payment_id: &str) -> Outcome {
+    let receipt = typed_records::<Receipt>(tables, RECEIPTS_TABLE, None).into_iter().find(|receipt| receipt.payment_id == payment_id);
+    found_or_not(receipt, Outcome::ReceiptFound)
+}
+
+fn local_get_receipts(tables: &mut impl LocalTables, email: &str) -> Outcome {
+    Outcome::ReceiptsFound(typed_records(tables, RECEIPTS_TABLE, Some(email)))
+}
+
+fn local_get_owed_receipts(tables: &mut impl LocalTables, owner: &str) -> Outcome {
+    let receipts = typed_records::<Receipt>(tables, RECEIPTS_TABLE, None).into_iter()
+        .filter(|receipt| receipt.owner == owner && receipt.status == ReceiptStatus::Paid && receipt.payout_id.is_empty())
+        .collect();
+    Outcome::ReceiptsFound(receipts)
+}
+
+fn local_put_payout_account(tables: &mut impl LocalTables, account: &PayoutAccount) -> Outcome {
+    set_typed_record(tables, PAYOUT_ACCOUNTS_TABLE, &account.user, "", account)
+}
+
+fn local_get_payout_account(tables: &mut impl LocalTables, email: &str) -> Outcome {
+    found_or_not(typed_record(tables, PAYOUT_ACCOUNTS_TABLE, email, ""), Outcome::PayoutAccountFound)
+}
+
+fn local_get_payout_account_by_account_id(tables: &mut impl LocalTables, account_id: &str) -> Outcome {
+    let account = typed_records::<PayoutAccount>(tables, PAYOUT_ACCOUNTS_TABLE, None).into_iter().find(|account| account.account_id == account_id);
+    found_or_not(account, Outcome::PayoutAccountFound)
+}
+
+fn local_put_collaborator_invite(tables: &mut impl LocalTables, collaborator: &Collaborator) -> Outcome {
+    let deck_key = collaborator.deck_id.to_string();
+    if let Some(stored) = typed_record::<Collaborator>(tables, COLLABORATORS_TABLE, &deck_key, &collaborator.user) {
+        if stored.status != InviteStatus::Invited {
+            return Outcome::InvalidRequest;
+        }
+    }
+    set_typed_record(tables, COLLABORATORS_TABLE, &deck_key, &collaborator.user, collaborator)
+}
+
+fn local_get_collaborator(tables: &mut impl LocalTables, deck_id: DeckId, email: &str) -> Outcome {
+    found_or_not(typed_record(tables, COLLABORATORS_TABLE, &deck_id.to_string(), email), Outcome::CollaboratorFound)
+}
+
+fn local_get_collaborators(tables: &mut impl LocalTables, deck_id: DeckId) -> Outcome {
+    Outcome::CollaboratorsFound(typed_records(tables, COLLABORATORS_TABLE, Some(&deck_id.to_string())))
+}
+
+fn local_update_collaborator_role(tables: &mut impl LocalTables, deck_id: DeckId, email: &str, role: CollaboratorRole) -> Outcome {
+    let deck_key = deck_id.to_string();
+    let Some(stored) = typed_record::<Collaborator>(tables, COLLABORATORS_TABLE, &deck_key, email) else {
+        return Outcome::UpdateUserFailure("the collaborator does not exist".to_string());
+    };
+    set_typed_record(tables, COLLABORATORS_TABLE, &deck_key, email, &Collaborator {role, ..stored})
+}
+
+fn local_delete_collaborator(tables: &mut impl LocalTables, deck_id: DeckId, email: &str, only_if: Option<InviteStatus>) -> Outcome {
+    let deck_key = deck_id.to_string();
+    if let Some(status) = only_if {
+        let stored = typed_record::<Collaborator>(tables, COLLABORATORS_TABLE, &deck_key, email);
+        if stored.is_none_or(|stored| stored.status != status) {
+            return Outcome::UpdateUserFailure(format!("the collaborator's invite is not {status}"));
+        }
+    }
+    tables.delete_record(COLLABORATORS_TABLE, &deck_key, email);
+    Outcome::DatabaseUpdateSuccess(UpdateRecipes::default())
+}
+
+fn local_accept_collaborator_invite(tables: &mut impl LocalTables, collaborator: &Collaborator) -> Outcome {
+    let deck_key = collaborator.deck_id.to_string();
+    let Some(stored) = typed_record::<Collaborator>(tables, COLLABORATORS_TABLE, &deck_key, &collaborator.user) else {return Outcome::UserDoesNotHavePermission};
+    if stored.status != InviteStatus::Invited || stored.invited != collaborator.invited {
+        return Outcome::UserDoesNotHavePermission;
+    }
+    let Some(mut user) = tables.user(&collaborator.user) else {return Outcome::InvalidRequest};
+    if user.colab_decks.contains(&collaborator.deck_id) {
+        return Outcome::InvalidRequest;
+    }
+
+    user.colab_decks.push(collaborator.deck_id);
+    tables.set_user(user);
+    match set_typed_record(tables, COLLABORATORS_TABLE, &deck_key, &collaborator.user, &Collaborator {status: InviteStatus::Accepted, ..stored}) {
+        Outcome::DatabaseUpdateSuccess(_) => Outcome::DatabaseUpdateSuccess(UpdateRecipes {
+            recipes: vec![UpdateRecipe {
+                update_type: UpdateType::Add,
+                update_key: UserInfo::COLAB_DECKS_CACHE_KEY.to_string(),
+                update_item: DBItem::User(collaborator.user.clone()),
+                value: UpdateValues::DeckList(DeckList {decks: vec![collaborator.deck_id]}),
+            }],
+        }),
+        any_other_outcome => any_other_outcome,
+    }
+}
+
+/// Local records never expire, replays only look records up by key so old ones are harmless.
+fn local_put_sync_record(tables: &mut impl LocalTables, record: &SyncRecord) -> Outcome {
+    set_typed_record(tables, SYNC_RECORDS_TABLE, &record.user, &record.idempotency_key, record)
+}
+
+fn local_claim_sync_record(tables: &mut impl LocalTables, claim: &SyncRecord) -> Outcome {
+    match typed_record::<SyncRecord>(tables, SYNC_RECORDS_TABLE, &claim.user, &claim.idempotency_key) {
+        Some(stored) if !!!stored.is_stale_claim(current_time_in_seconds()) => Outcome::SyncRecordFound(stored),
+        _ => set_typed_record(tables, SYNC_RECORDS_TABLE, &claim.user, &claim.idempotency_key, claim),
+    }
+}
+
+fn local_get_sync_record(tables: &mut impl LocalTables, email: &str, idempotency_key: &str) -> Outcome {
+    found_or_not(typed_record(tables, SYNC_RECORDS_TABLE, email, idempotency_key), Outcome::SyncRecordFound)
+}
+
+fn insert_note(connection: &Connection, note: &Note) -> rusqlite::Result<usize> {
+    let Ok(item) = serde_json::to_string(note) else {return Ok(0)};
+    connection.execute(
+        "INSERT OR REPLACE INTO notes (deck_id, note_id, level, note_type, item) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![note.deck_id.to_string(), note.note_id as i64, note.level, note.note_type.to_string(), item],
+    )
+}
+
+fn query_deck_id(query_type: &ValidQueryTypes) -> Option<DeckId> {
+    match query_type {
+        ValidQueryTypes::NotesByLevel(deck_id, _) | ValidQueryTypes::NotesById(deck_id, _) | ValidQueryTypes::NotesByType(deck_id, _) => Some(*deck_id),
+        ValidQueryTypes::NoQuery => None,
+    }
+}
+
+/// Local backends return everything in one page, so a cursor only ever comes from a query that started against dynamo.
+fn note_matches_query(note: &Note, query_type: &ValidQueryTypes, cursor: Option<&QueryCursor>) -> bool {
+    match query_type {
+        ValidQueryTypes::NotesByLevel(_, levels) => levels.contains(&(note.level as usize)) && cursor.is_none_or(|cursor| cursor.allows(note)),
+        ValidQueryTypes::NotesById(_, note_ids) => note_ids.contains(&(note.note_id as usize)),
+        ValidQueryTypes::NotesByType(_, note_types) => note_types.contains(&note.note_type),
+        ValidQueryTypes::NoQuery => false,
+    }
+}
+
+fn apply_recipe_to_user(user: UserInfo, recipe: &UpdateRecipe) -> Result<UserInfo, ()> {
+    let Ok(Value::Object(mut user_map)) = serde_json::to_value(&user) else {return Err(())};
+    apply_recipe_to_map(&mut user_map, recipe)?;
+    serde_json::from_value(Value::Object(user_map)).map_err(|_| ())
+}
+
+/// Recipes for keys that are not part of the note itself are treated as changes to the field with that name.
+fn apply_recipe_to_note(mut note: Note, recipe: &UpdateRecipe) -> Result<Note, ()> {
+    let Ok(Value::Object(mut note_map)) = serde_json::to_value(&note) else {return Err(())};
+
+    if note_map.contains_key(&recipe.update_key) {
+        apply_recipe_to_map(&mut note_map, recipe)?;
+        return serde_json::from_value(Value::Object(note_map)).map_err(|_| ());
+    }
+
+    let (UpdateType::Swap, UpdateValues::String(text)) = (recipe.update_type, &recipe.value) else {return Err(())};
+    match note.fields.iter_mut().find(|field| field.name == recipe.update_key) {
+        Some(field) => {
+            field.text = Some(text.clone());
+            field.asset = None;
+        },
+        None => return Err(()),
+    }
+    Ok(note)
+}
+
+fn apply_recipe_to_map(map: &mut Map<String, Value>, recipe: &UpdateRecipe) -> Result<(), ()> {
+    let Some(current_value) = map.get_mut(&recipe.update_key) else {return Err(())};
+    let Ok(mut new_value) = update_value_to_json(&recipe.value) else {return Err(())};
+
+    // A single value added to or removed from a list is treated as a list of one
+    if current_value.is_array() && !!!new_value.is_array() && recipe.update_type != UpdateType::Swap {
+        new_value = Value::Array(vec![new_value]);
+    }
+
+    match (recipe.update_type, current_value, new_value) {
+        (UpdateType::Swap, current_value, new_value) => *current_value = new_value,
+        (update_type, Value::Number(current), Value::Number(change)) => {
+            // Whole numbers stay whole so they can still be read back as integers
+            let updated = match (current.as_u64(), change.as_u64(), update_type) {
+                (Some(current), Some(change), UpdateType::Add) => Value::from(current + change),
+                (Some(current), Some(change), UpdateType::Subtract) => Value::from(current.saturating_sub(change)),
+                _ => {
+                    let (Some(current), Some(change)) = (current.as_f64(), change.as_f64()) else {return Err(())};
+                    match update_type {
+                        UpdateType::Add => Value::from(current + change),
+                        _ => Value::from(current - change),
+                    }
+                },
+            };
+            *current_value = updated;
+        },
+        (UpdateType::Add, Value::Array(current), Value::Array(additions)) => {
+            for addition in additions {
+                if !!!current.contains(&addition) {
+                    current.push(addition);
+                }
+            }
+        },
+        (UpdateType::Subtract, Value::Array(current), Value::Array(removals)) => current.retain(|value| !!!removals.contains(value)),
+        _ => return Err(()),
+    }
+
+    Ok(())
+}
+
+fn update_value_to_json(update_value: &UpdateValues) -> Result<Value, serde_json::Error> {
+    match update_value {
+        UpdateValues::Float64(number) => serde_json::to_value(number),
+        UpdateValues::String(string) => serde_json::to_value(string),
+        UpdateValues::DeckId(deck_id) => serde_json::to_value(deck_id),
+        UpdateValues::UserInfo(user) => serde_json::to_value(user),
+        UpdateValues::Note(note) => serde_json::to_value(note),
+        UpdateValues::DeckList(deck_list) => serde_json::to_value(deck_list),
+        UpdateValues::Unsigned64(number) => serde_json::to_value(number),
+        UpdateValues::DeckMeta(meta) => serde_json::to_value(meta),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::shared_truth::DECK_ID_LENGTH;
+    use crate::utils::database_types::NoteType;
+
+    const EMAIL: &str = "learner@lexlingua.io";
+
+    fn deck_id() -> DeckId {
+        DeckId {id: ['a'; DECK_ID_LENGTH]}
+    }
+
+    fn other_deck_id() -> DeckId {
+        DeckId {id: ['b'; DECK_ID_LENGTH]}
+    }
+
+    fn note(deck_id: DeckId, note_id: u64, level: u32, note_type: &str) -> Note {
+        Note {deck_id, note_id, level, note_type: NoteType::new(note_type), version: 1, ..Default::default()}
+    }
+
+    fn meta_note(meta: &DeckMeta) -> Note {
+        Note {deck_id: deck_id(), note_id: 0, meta: Some(meta.clone()), ..Default::default()}
+    }
+
+    fn user(upload_tokens: f64) -> UserInfo {
+        UserInfo {email: EMAIL.to_string(), upload_tokens, ..Default::default()}
+    }
+
+    fn note_ids(outcome: Outcome) -> Vec<u64> {
+        match outcome {
+            Outcome::ItemsFound(notes) => NoteList::from_str(&notes).unwrap().notes.iter().map(|note| note.note_id).collect(),
+            any_other_outcome => panic!("expected notes, got {}", any_other_outcome.to_string()),
+        }
+    }
+
+    /// Runs the check against fresh memory and sqlite storage so both keep to the same contract.
+    fn on_local_backends<F: Future<Output = ()>>(check: impl Fn(StorageBackend) -> F) {
+        let directory = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(check(StorageBackend::Memory(Arc::new(MemoryStorage::default()))));
+        runtime.block_on(check(StorageBackend::Sqlite(SqliteStorage::new(directory.path().join("storage.sqlite3")))));
+    }
+
+    #[test]
+    fn users_are_only_created_once_and_change_through_recipes() {
+        on_local_backends(|storage| async move {
+            assert!(matches!(storage.put_user(&user(10.0)).await, Outcome::DatabaseUpdateSuccess(_)));
+            assert_eq!(storage.put_user(&user(0.0)).await, Outcome::EmailAlreadyInUse);
+
+            let add_tokens = UpdateRecipe {
+                update_type: UpdateType::Add,
+                update_key: UserInfo::UPLOAD_TOKENS_CACHE_KEY.to_string(),
+                update_item: DBItem::User(EMAIL.to_string()),
+                value: UpdateValues::Float64(5.0),
+            };
+            assert!(matches!(storage.update_item(vec![add_tokens.clone()]).await, Outcome::DatabaseUpdateSuccess(_)));
+            assert_eq!(storage.get_user(EMAIL, None).await, Outcome::UserFound(user(15.0)));
+
+            let missing_user = UpdateRecipe {update_item: DBItem::User("nobody@lexlingua.io".to_string()), ..add_tokens.clone()};
+            assert!(!!!matches!(storage.update_item(vec![add_tokens, missing_user]).await, Outcome::DatabaseUpdateSuccess(_)));
+            assert_eq!(storage.get_user(EMAIL, None).await, Outcome::UserFound(user(15.0)));
+        });
+    }
+
+    #[test]
+    fn metas_are_not_changed_through_recipes() {
+        on_local_backends(|storage| async move {
+            let meta = DeckMeta::new_deck_meta(EMAIL, 0);
+            storage.put_notes(&vec![meta_note(&meta)]).await;
+
+            let recipe = deck_meta_update_recipe(deck_id(), DeckMeta {name: "Renamed".to_string(), ..meta.clone()});
+            assert_eq!(storage.update_item(vec![recipe]).await, Outcome::InvalidRequest);
+            assert_eq!(storage.get_note(deck_id(), 0).await, Outcome::NoteFound(meta_note(&meta)));
+        });
+    }
+
+    #[test]
+    fn notes_are_queried_by_level_id_and_type() {
+        on_local_backends(|storage| async move {
+            let notes = vec![
+                note(deck_id(), 1, 1, "Vocab"),
+                note(deck_id(), 2, 1, "Grammar"),
+                note(deck_id(), 3, 2, "Vocab"),
+                note(deck_id(), 4, 2, "Grammar"),
+                note(other_deck_id(), 1, 1, "Vocab"),
+            ];
+            storage.put_notes(&notes).await;
+
+            assert_eq!(note_ids(storage.query_notes(&ValidQueryTypes::NotesByLevel(deck_id(), vec![1]), None, None).await), vec![1, 2]);
+            assert_eq!(note_ids(storage.query_notes(&ValidQueryTypes::NotesById(deck_id(), vec![2, 4, 9]), None, None).await), vec![2, 4]);
+            assert_eq!(note_ids(storage.query_notes(&ValidQueryTypes::NotesByType(deck_id(), vec![NoteType::new("Grammar")]), None, None).await), vec![2, 4]);
+            assert_eq!(storage.query_notes(&ValidQueryTypes::NoQuery, None, None).await, Outcome::InvalidRequest);
+        });
+    }
+
+    #[test]
+    fn level_queries_continue_after_the_cursor() {
+        on_local_backends(|storage| async move {
+            storage.put_notes(&(1..=6).map(|note_id| note(deck_id(), note_id, if note_id <= 3 {1} else {2}, "Vocab")).collect::<Vec<Note>>()).await;
+
+            // Level 2 is missing from the cursor so it was already read to the end
+            let cursor = QueryCursor {last_note_ids: BTreeMap::from([(1, 1)]), ..Default::default()};
+            let query = ValidQueryTypes::NotesByLevel(deck_id(), vec![1, 2]);
+            assert_eq!(note_ids(storage.query_notes(&query, Some(&cursor), None).await), vec![2, 3]);
+        });
+    }
+
+    #[test]
+    fn metas_are_only_swapped_from_the_stored_meta() {
+        on_local_backends(|storage| async move {
+            let meta = DeckMeta::new_deck_meta(EMAIL, 0);
+            let renamed = DeckMeta {name: "Renamed".to_string(), ..meta.clone()};
+            let repriced = DeckMeta {price: 5.0, ..meta.clone()};
+            storage.put_notes(&vec![meta_note(&meta)]).await;
+
+            assert!(matches!(storage.swap_deck_meta(deck_id(), &meta, &renamed).await, Outcome::DatabaseUpdateSuccess(_)));
+            assert_eq!(storage.swap_deck_meta(deck_id(), &meta, &repriced).await, Outcome::NoteConflict(meta_note(&renamed)));
+            assert_eq!(storage.get_note(deck_id(), 0).await, Outcome::NoteFound(meta_note(&renamed)));
+            assert_eq!(storage.swap_deck_meta(other_deck_id(), &meta, &renamed).await, Outcome::ItemsNotFound);
+        });
+    }
+
+    #[test]
+    fn only_one_upload_claims_a_deck() {
+        on_local_backends(|storage| async move {
+            assert_eq!(storage.claim_deck_id(deck_id()).await, Outcome::PermissionGranted(deck_id().to_string()));
+            assert_eq!(storage.claim_deck_id(deck_id()).await, Outcome::InvalidRequest);
+            assert_eq!(storage.deck_exists(deck_id()).await, Outcome::PermissionGranted(deck_id().to_string()));
+
+            storage.delete_notes(deck_id(), &[0]).await;
+            assert_eq!(storage.deck_exists(deck_id()).await, Outcome::ItemsNotFound);
+            assert_eq!(storage.claim_deck_id(deck_id()).await, Outcome::PermissionGranted(deck_id().to_string()));
+        });
+    }
+
+    #[test]
+    fn a_repeated_charge_is_only_taken_once() {
+        on_local_backends(|storage| async move {
+            storage.put_user(&user(10.0)).await;
+            let charge = LedgerEntry::new(EMAIL, deck_id(), TransactionType::Charge, 4.0, "deck upload");
+
+            assert!(matches!(storage.record_upload_token_transaction(&charge).await, Outcome::DatabaseUpdateSuccess(_)));
+            assert!(matches!(storage.record_upload_token_transaction(&charge).await, Outcome::DatabaseUpdateSuccess(_)));
+            assert_eq!(storage.get_user(EMAIL, None).await, Outcome::UserFound(user(6.0)));
+
+            let too_much = LedgerEntry::new(EMAIL, other_deck_id(), TransactionType::Charge, 7.0, "deck upload");
+            assert_eq!(storage.record_upload_token_transaction(&too_much).await, Outcome::NotEnoughUploadTokens(6.0));
+            assert_eq!(storage.get_ledger_entries(EMAIL).await, Outcome::LedgerEntriesFound(vec![charge]));
+        });
+    }
+
+    #[test]
+    fn stale_sync_claims_are_taken_over() {
+        on_local_backends(|storage| async move {
+            let claim = |expires: u64| SyncRecord {user: EMAIL.to_string(), idempotency_key: "1700000000000-1".to_string(), outcome: String::new(), expires};
+            let held = claim(current_time_in_seconds() + 60);
+            let finished = SyncRecord {outcome: Outcome::ItemsNotFound.to_string(), expires: 0, ..held.clone()};
+
+            assert!(matches!(storage.claim_sync_record(&held).await, Outcome::DatabaseUpdateSuccess(_)));
+            assert_eq!(storage.claim_sync_record(&claim(current_time_in_seconds() + 60)).await, Outcome::SyncRecordFound(held.clone()));
+
+            storage.put_sync_record(&claim(0)).await;
+            assert!(matches!(storage.claim_sync_record(&held).await, Outcome::DatabaseUpdateSuccess(_)));
+
+            // Finished records are never stale, their outcome is what every later replay gets
+            storage.put_sync_record(&finished).await;
+            assert_eq!(storage.claim_sync_record(&held).await, Outcome::SyncRecordFound(finished));
+        });
+    }
+
+    #[test]
+    fn receipts_only_replace_the_statuses_they_name() {
+        on_local_backends(|storage| async move {
+            let pending = Receipt {user: EMAIL.to_string(), deck_id: deck_id(), status: ReceiptStatus::Pending, ..Default::default()};
+            let paid = Receipt {status: ReceiptStatus::Paid, ..pending.clone()};
+            let refunded = Receipt {status: ReceiptStatus::Refunded, ..pending.clone()};
+
+            assert!(matches!(storage.put_receipt(&pending, &[]).await, Outcome::DatabaseUpdateSuccess(_)));
+            assert_eq!(storage.put_receipt(&pending, &[]).await, Outcome::InvalidRequest);
+            assert!(matches!(storage.put_receipt(&paid, &[ReceiptStatus::Pending]).await, Outcome::DatabaseUpdateSuccess(_)));
+            assert_eq!(storage.put_receipt(&refunded, &[ReceiptStatus::Pending]).await, Outcome::InvalidRequest);
+            assert_eq!(storage.get_receipt(EMAIL, deck_id()).await, Outcome::ReceiptFound(paid));
+        });
+    }
+}
@@ -0,0 +1,232 @@
+use std::str::FromStr;
+
+use leptos::server;
+use serde::{Deserialize, Serialize};
+use server_fn::ServerFnError;
+use struct_field_names::StructFieldNames;
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::{DeckId, Note},
+    outcomes::Outcome,
+    scheduler::Answer,
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use std::future::Future;
+#[cfg(feature="ssr")]
+use crate::utils::{
+    back_utils::verify_user_header,
+    date_and_time::current_time_in_seconds,
+    note_editing::{add_note_to_deck_for, delete_deck_note_for, edit_deck_note_for},
+    proceed,
+    scheduler::grade_card_at,
+    shared_truth::{SYNC_CLAIM_LIFETIME_IN_SECONDS, SYNC_RECORD_LIFETIME_IN_SECONDS},
+    storage::{setup_storage, Storage},
+};
+
+/// Something done on the client that still has to reach the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SyncChange {
+    Answer {deck_id: DeckId, note_id: u64, card_ord: u8, answer: Answer, answered: u64},
+    AddNote {deck_id: DeckId, note: Note},
+    EditNote {deck_id: DeckId, note: Note},
+    DeleteNote {deck_id: DeckId, note_id: u64, version: u64},
+}
+
+/// The idempotency key is made when the change is queued so every replay of it carries the same key.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueuedChange {
+    pub idempotency_key: String,
+    pub change: SyncChange,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncQueue {
+    pub changes: Vec<QueuedChange>,
+    pub next_key: u64,
+    /// Edits the server turned down because the note changed first, kept so the user can see what did not make it in.
+    #[serde(default)]
+    pub conflicts: Vec<QueuedChange>,
+}
+
+impl SyncQueue {
+    /// Keys only need to be unique per user, the time keeps them apart across devices and the counter within one.
+    pub fn push(&mut self, change: SyncChange, now_in_millis: u64) {
+        self.next_key += 1;
+        self.changes.push(QueuedChange {
+            idempotency_key: format!("{now_in_millis}-{}", self.next_key),
+            change,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl ToString for SyncQueue {
+    fn to_string(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+impl FromStr for SyncQueue {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(input).map_err(|_| ())
+    }
+}
+
+/// The outcome a replayed change got the first time it reached the server. A record with no outcome yet is a claim
+/// by the replay that is applying the change.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, StructFieldNames)]
+pub struct SyncRecord {
+    pub user: String,
+    pub idempotency_key: String,
+    pub outcome: String,
+    pub expires: u64,
+}
+
+impl SyncRecord {
+    pub fn is_claim(&self) -> bool {
+        self.outcome.is_empty()
+    }
+
+    /// A claim whose replay gave up or never finished, the next replay can take it over.
+    pub fn is_stale_claim(&self, now: u64) -> bool {
+        self.is_claim() && self.expires < now
+    }
+}
+
+/// Failures that say nothing about the change itself, the change stays queued and is tried again.
+pub fn is_transient_failure(outcome: &Outcome) -> bool {
+    matches!(outcome,
+        Outcome::VerificationFailure
+        | Outcome::NoteUpdateFailed(_)
+        | Outcome::ReviewStateUpdateFailed(_)
+        | Outcome::UpdateUserFailure(_)
+        | Outcome::UnspecifiedQueryFailure(_)
+    )
+}
+
+/// Applies a queued change once, replays with a key that was already applied get the outcome it had the first time.
+#[server(client=AuthClient)]
+pub async fn replay_queued_change(queued: QueuedChange, user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    if queued.idempotency_key.is_empty() {
+        return Ok(Outcome::InvalidRequest);
+    }
+
+    let storage = setup_storage().await;
+
+    let QueuedChange {idempotency_key, change} = queued;
+    apply_once(&storage, &email, &idempotency_key, async {
+        let outcome = match change {
+            SyncChange::Answer {deck_id, note_id, card_ord, answer, answered} => grade_card_at(&storage, &email, deck_id, note_id, card_ord, answer, answered).await,
+            SyncChange::AddNote {deck_id, note} => add_note_to_deck_for(&storage, &email, deck_id, note).await,
+            SyncChange::EditNote {deck_id, note} => edit_deck_note_for(&storage, &email, deck_id, note).await,
+            SyncChange::DeleteNote {deck_id, note_id, version} => delete_deck_note_for(&storage, &email, deck_id, note_id, version).await,
+        };
+        Ok::<Outcome, ServerFnError>(outcome)
+    }).await
+}
+
+/// Claims the key before applying the change so replays racing each other never both apply it. A replay that finds
+/// the claim still held gets a transient failure and is tried again once the outcome has been stored.
+#[cfg(feature="ssr")]
+async fn apply_once(storage: &impl Storage, email: &str, idempotency_key: &str, change: impl Future<Output = Result<Outcome, ServerFnError>>) -> Result<Outcome, ServerFnError> {
+    let now = current_time_in_seconds();
+    let claim = SyncRecord {
+        user: email.to_string(),
+        idempotency_key: idempotency_key.to_string(),
+        outcome: String::new(),
+        expires: now + SYNC_CLAIM_LIFETIME_IN_SECONDS,
+    };
+
+    match storage.claim_sync_record(&claim).await {
+        Outcome::DatabaseUpdateSuccess(_) => proceed(),
+        Outcome::SyncRecordFound(record) if record.is_claim() => return Ok(Outcome::UnspecifiedQueryFailure("the change is still being applied".to_string())),
+        Outcome::SyncRecordFound(record) => return Ok(Outcome::from_str(&record.outcome).unwrap_or(Outcome::InvalidRequest)),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    let outcome = change.await?;
+
+    // A transient failure gives the claim up straight away so the next replay applies the change again
+    let record = if is_transient_failure(&outcome) {
+        SyncRecord {expires: 0, ..claim}
+    } else {
+        SyncRecord {outcome: outcome.to_string(), expires: now + SYNC_RECORD_LIFETIME_IN_SECONDS, ..claim}
+    };
+    // Failing to store the outcome leaves the claim to go stale, after which the change could be applied twice
+    let _ = storage.put_sync_record(&record).await;
+
+    Ok(outcome)
+}
+
+#[cfg(all(test, feature="ssr"))]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use futures::executor::block_on;
+    use crate::utils::storage::MemoryStorage;
+
+    const EMAIL: &str = "learner@lexlingua.io";
+    const KEY: &str = "1700000000000-1";
+
+    fn apply(storage: &MemoryStorage, applied: &Cell<u32>, outcome: Outcome) -> Outcome {
+        block_on(apply_once(storage, EMAIL, KEY, async {
+            applied.set(applied.get() + 1);
+            Ok(outcome)
+        })).unwrap()
+    }
+
+    fn claim(expires: u64) -> SyncRecord {
+        SyncRecord {user: EMAIL.to_string(), idempotency_key: KEY.to_string(), outcome: String::new(), expires}
+    }
+
+    #[test]
+    fn replays_get_the_first_outcome_without_applying_again() {
+        let storage = MemoryStorage::default();
+        let applied = Cell::new(0);
+
+        assert_eq!(apply(&storage, &applied, Outcome::ItemsNotFound), Outcome::ItemsNotFound);
+        assert_eq!(apply(&storage, &applied, Outcome::ChangeQueued), Outcome::ItemsNotFound);
+        assert_eq!(applied.get(), 1);
+    }
+
+    #[test]
+    fn a_held_claim_is_a_transient_failure() {
+        let storage = MemoryStorage::default();
+        let applied = Cell::new(0);
+        block_on(storage.put_sync_record(&claim(current_time_in_seconds() + SYNC_CLAIM_LIFETIME_IN_SECONDS)));
+
+        let outcome = apply(&storage, &applied, Outcome::ItemsNotFound);
+        assert!(is_transient_failure(&outcome));
+        assert_eq!(applied.get(), 0);
+    }
+
+    #[test]
+    fn transient_failures_are_applied_again() {
+        let storage = MemoryStorage::default();
+        let applied = Cell::new(0);
+
+        assert!(is_transient_failure(&apply(&storage, &applied, Outcome::NoteUpdateFailed("busy".to_string()))));
+        assert_eq!(apply(&storage, &applied, Outcome::ItemsNotFound), Outcome::ItemsNotFound);
+        assert_eq!(applied.get(), 2);
+    }
+
+    #[test]
+    fn stale_claims_are_taken_over() {
+        let storage = MemoryStorage::default();
+        let applied = Cell::new(0);
+        block_on(storage.put_sync_record(&claim(0)));
+
+        assert_eq!(apply(&storage, &applied, Outcome::ItemsNotFound), Outcome::ItemsNotFound);
+        assert_eq!(applied.get(), 1);
+    }
+}
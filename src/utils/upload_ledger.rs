@@ -0,0 +1,87 @@
+use std::str::FromStr;
+
+use leptos::server;
+use serde::{Deserialize, Serialize};
+use server_fn::ServerFnError;
+use struct_field_names::StructFieldNames;
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::utils::{
+    auth_client::AuthClient,
+    database_types::DeckId,
+    outcomes::Outcome,
+};
+
+/// Server Imports
+#[cfg(feature="ssr")]
+use crate::utils::{
+    back_utils::verify_user_header,
+    date_and_time::current_time_in_seconds,
+    dynamo_utils::validate_user_existence,
+    proceed,
+    storage::{setup_storage, Storage},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Display, EnumIter, Serialize, Deserialize)]
+pub enum TransactionType {
+    Charge,
+    Refund,
+}
+
+impl FromStr for TransactionType {
+
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        for variant in Self::iter() {
+            if input == &variant.to_string() {
+                return Ok(variant);
+            }
+        }
+        Err(())
+    }
+}
+
+/// Entries are only ever added, the balance in LEXUsers should always equal the sum of a user's ledger.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, StructFieldNames)]
+pub struct LedgerEntry {
+    pub user: String,
+    pub transaction_id: String,
+    pub deck_id: DeckId,
+    pub transaction_type: TransactionType,
+    pub amount: f64,
+    pub reason: String,
+    pub created: u64,
+}
+
+impl LedgerEntry {
+    /// An upload is charged and refunded at most once, so the transaction id only names the deck and the transaction
+    /// type. Writing the same charge again then finds its own entry and leaves the balance alone.
+    #[cfg(feature="ssr")]
+    pub fn new(user: &str, deck_id: DeckId, transaction_type: TransactionType, amount: f64, reason: &str) -> Self {
+        let created = current_time_in_seconds();
+        Self {
+            user: user.to_string(),
+            transaction_id: format!("{}#{}", deck_id.to_string(), transaction_type),
+            deck_id,
+            transaction_type,
+            amount,
+            reason: reason.to_string(),
+            created,
+        }
+    }
+}
+
+#[server(client=AuthClient)]
+pub async fn upload_ledger_from_dynamo(user: Option<String>) -> Result<Outcome, ServerFnError> {
+    let Outcome::VerificationSuccess(email) = verify_user_header(user).await else {return Ok(Outcome::VerificationFailure)};
+
+    let storage = setup_storage().await;
+
+    match validate_user_existence(&storage, &email).await {
+        Outcome::UserFound(_) => proceed(),
+        any_other_outcome => return Ok(any_other_outcome),
+    };
+
+    Ok(storage.get_ledger_entries(&email).await)
+}
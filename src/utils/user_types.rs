@@ -14,20 +14,18 @@ use crate::utils::{
     outcomes::Outcome, proceed, 
     shared_truth::{LOCAL_USER_INFO_KEY, CACHE_OUT_OF_DATE_LIMIT, EMAIL_CLAIM_KEY, EXP_CLAIM_KEY, LOCAL_AUTH_TOKEN_KEY, LOCAL_REFRESH_TOKEN_KEY, USER_CLAIM_AUTH, USER_CLAIM_REFRESH, USER_CLAIM_SIGN_UP}, 
     shared_utilities::{clear_user_cache_and_cookies, get_claim, get_cookie_value, get_item_from_local_storage, get_url_query, is_expired, set_token_cookie, store_item_in_local_storage, verify_then_return_outcome, verify_token}, 
-    sign_in_lib::{use_refresh_token, TokenPair}
+    sign_in_lib::{use_refresh_token, TokenPair},
+    sync_queue::QueuedChange,
 };
 
 /// Server Imports
 #[cfg(feature="ssr")]
 use crate::utils::{
     shared_truth::IS_TRUSTED_CLAIM,
-    dynamo_utils::{get_user, permission_if_good_standing, setup_client, EMAIL_DB_KEY},
-    back_utils::{verify_user_header, get_default_pfp, USERS_TABLE, build_auth_token, build_refresh_token},
+    dynamo_utils::permission_if_good_standing,
+    back_utils::{verify_user_header, get_default_pfp, build_auth_token, build_refresh_token},
+    storage::{setup_storage, Storage},
 };
-#[cfg(feature="ssr")]
-use aws_sdk_dynamodb::{Client, operation::put_item::PutItemError};
-#[cfg(feature="ssr")]
-use serde_dynamo::to_item;
 
 #[derive(Partial)]
 #[derive(Clone, Debug, Default, PartialEq, StructFieldNames, Serialize, Deserialize)]
@@ -409,9 +407,9 @@ impl UserState {
 pub async fn user_from_dynamo(email: Option<String>) -> Result<Outcome, ServerFnError> {
     let Outcome::VerificationSuccess(email) = verify_user_header(email).await else {return Ok(Outcome::VerificationFailure)};
 
-    let client = setup_client().await;
+    let storage = setup_storage().await;
 
-    let user = match get_user(&client, &email, None).await {
+    let user = match storage.get_user(&email, None).await {
         Outcome::UserFound(user) => user,
         any_other_outcome => return Ok(any_other_outcome),
     };
@@ -436,6 +434,12 @@ pub fn setup_user() {
     );
     provide_context(user_info);
 
+    // Starts as NoCache on both sides so hydration matches, the cookie is read once the client is running
+    let cache_status = RwSignal::new(CacheStatus::NoCache);
+    provide_context(cache_status);
+    let sync_conflicts = RwSignal::new(Vec::<QueuedChange>::new());
+    provide_context(sync_conflicts);
+
     Effect::new(move || {
         user_resource.refetch();
     });
@@ -451,6 +455,32 @@ pub fn setup_user() {
             None => proceed(),
         }
     });
+
+    // Changes made offline are sent and active decks are prefetched once the user is known and again whenever the connection comes back
+    #[cfg(feature="hydrate")]
+    {
+        use leptos::task::spawn_local;
+        use crate::utils::{cache_db_interface::{flush_sync_queue, get_sync_conflicts}, prefetch::prefetch_active_decks};
+
+        let flush = move || {
+            let user_state = user_state.get_untracked();
+            spawn_local(async move {
+                flush_sync_queue(user_state.clone()).await;
+                sync_conflicts.set(get_sync_conflicts(&user_state));
+                // Decks that changed while the user was away are cached again, the server then marks the cache complete
+                prefetch_active_decks(user_state, cache_status).await;
+            });
+        };
+        let _ = window_event_listener(leptos::ev::online, move |_| flush());
+        Effect::new(move || {
+            if user_state.get().is_authenticated() {
+                flush();
+            } else {
+                cache_status.set(get_cache_status_client());
+                sync_conflicts.set(Vec::new());
+            }
+        });
+    }
 }
 
 pub fn sign_out(user_state: RwSignal<UserState>, user_resource: Resource<UserState>) {
@@ -461,7 +491,7 @@ pub fn sign_out(user_state: RwSignal<UserState>, user_resource: Resource<UserSta
     user_resource.set(Some(sign_out_state));
 }
 
-#[server]
+#[server(endpoint = "create_user")]
 async fn create_user(token: String) -> Result<Outcome, ServerFnError> {
     println!("create user");
     let Ok(trusted_token) = verify_token(&token) else {return Ok(Outcome::VerificationFailure)};
@@ -472,15 +502,15 @@ async fn create_user(token: String) -> Result<Outcome, ServerFnError> {
     };
     let Some(user_email) = get_claim(&trusted_token, USER_CLAIM_SIGN_UP) else {return Ok(Outcome::VerificationFailure)};
     
-    let dynamo_client = setup_client().await;
+    let storage = setup_storage().await;
 
-    let outcome = add_user_to_db(&dynamo_client, &user_email, trusted_device).await;
+    let outcome = add_user_to_db(&storage, &user_email, trusted_device).await;
 
     Ok(outcome)
 }
 
 #[cfg(feature = "ssr")]
-async fn add_user_to_db(dynamo_client: &Client, user_email: &str, trusted_device: bool) -> Outcome {
+async fn add_user_to_db(storage: &impl Storage, user_email: &str, trusted_device: bool) -> Outcome {
     let Ok(token_pair) = create_token_pair(user_email, trusted_device).await else {
         return Outcome::CreateUserFailure("Could not create token pair".to_string());
     };
@@ -495,20 +525,9 @@ async fn add_user_to_db(dynamo_client: &Client, user_email: &str, trusted_device
     user.last_login = current_time;
     user.lex_name = "Lex".to_string();
     
-    let item = match to_item(user) {
-        Ok(itm) => {itm},
-        Err(e) => return Outcome::CreateUserFailure(e.to_string()),
-    };
-    
-    match dynamo_client.put_item().table_name(USERS_TABLE).set_item(Some(item))
-    .condition_expression(format!("attribute_not_exists({EMAIL_DB_KEY})")).send().await {
-        Ok(_) => proceed(),
-        Err(e) => {
-            match e.into_service_error() {
-                PutItemError::ConditionalCheckFailedException(_) => return Outcome::EmailAlreadyInUse,
-                error => return Outcome::CreateUserFailure(error.to_string()),
-            }
-        },
+    match storage.put_user(&user).await {
+        Outcome::DatabaseUpdateSuccess(_) => proceed(),
+        any_other_outcome => return any_other_outcome,
     }
 
     Outcome::UserCreationSuccess(token_pair)
@@ -0,0 +1,127 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Bumped whenever the layout of an encoded type changes, including fields added with a serde default.
+/// 1: notes, fields and deck metas as first encoded.
+/// 2: metas with note templates, descriptions, covers, languages, tags, the highest note id and a version,
+///    fields holding text beside media and deck audio assets.
+pub const WIRE_FORMAT_VERSION: u8 = 2;
+const WIRE_FORMAT_PREFIX: &str = "lex";
+const WIRE_HEADER_END: char = ':';
+
+/// Note lists are the bulk of what crosses the wire so they use the compact encoding.
+pub const NOTE_LIST_WIRE_ENCODING: WireEncoding = WireEncoding::Postcard;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WireEncoding {
+    #[default]
+    Json,
+    Postcard,
+}
+
+impl WireEncoding {
+    fn tag(&self) -> char {
+        match self {
+            WireEncoding::Json => 'j',
+            WireEncoding::Postcard => 'p',
+        }
+    }
+
+    fn from_tag(tag: char) -> Option<Self> {
+        match tag {
+            'j' => Some(WireEncoding::Json),
+            'p' => Some(WireEncoding::Postcard),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes the value behind a header like "lex1j:", json follows as is and postcard bytes follow as base64.
+pub fn encode_wire<T: Serialize>(value: &T, encoding: WireEncoding) -> String {
+    let payload = match encoding {
+        WireEncoding::Json => serde_json::to_string(value).unwrap_or_default(),
+        WireEncoding::Postcard => URL_SAFE_NO_PAD.encode(postcard::to_allocvec(value).unwrap_or_default()),
+    };
+    format!("{WIRE_FORMAT_PREFIX}{WIRE_FORMAT_VERSION}{}{WIRE_HEADER_END}{payload}", encoding.tag())
+}
+
+/// Decodes json from this version or an earlier one, serde defaults fill in whatever older versions did not have.
+/// Postcard has no field names and ignores serde defaults, so only postcard from this exact version can be read.
+pub fn decode_wire<T: DeserializeOwned>(input: &str) -> Result<T, String> {
+    let Some(versioned) = input.strip_prefix(WIRE_FORMAT_PREFIX) else {return Err("not in the wire format".to_string())};
+    let Some((header, payload)) = versioned.split_once(WIRE_HEADER_END) else {return Err("wire header is missing".to_string())};
+
+    let Some(tag) = header.chars().last() else {return Err("wire header is empty".to_string())};
+    let Some(encoding) = WireEncoding::from_tag(tag) else {return Err(format!("unknown wire encoding {tag}"))};
+    let Ok(version) = header.trim_end_matches(tag).parse::<u8>() else {return Err("wire version could not be read".to_string())};
+    if version > WIRE_FORMAT_VERSION {
+        return Err(format!("wire version {version} is newer than {WIRE_FORMAT_VERSION}"));
+    }
+
+    match encoding {
+        WireEncoding::Json => serde_json::from_str(payload).map_err(|e| e.to_string()),
+        WireEncoding::Postcard if version != WIRE_FORMAT_VERSION => Err(format!("postcard from wire version {version} cannot be read by {WIRE_FORMAT_VERSION}")),
+        WireEncoding::Postcard => {
+            let bytes = URL_SAFE_NO_PAD.decode(payload).map_err(|e| e.to_string())?;
+            postcard::from_bytes(&bytes).map_err(|e| e.to_string())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Before {
+        name: String,
+    }
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct After {
+        name: String,
+        #[serde(default)]
+        tags: Vec<String>,
+    }
+
+    fn with_version(encoded: &str, version: u8) -> String {
+        encoded.replacen(&format!("{WIRE_FORMAT_PREFIX}{WIRE_FORMAT_VERSION}"), &format!("{WIRE_FORMAT_PREFIX}{version}"), 1)
+    }
+
+    #[test]
+    fn both_encodings_round_trip() {
+        let value = After {name: "deck".to_string(), tags: vec!["spanish".to_string()]};
+        for encoding in [WireEncoding::Json, WireEncoding::Postcard] {
+            let encoded = encode_wire(&value, encoding);
+            assert!(encoded.starts_with(&format!("{WIRE_FORMAT_PREFIX}{WIRE_FORMAT_VERSION}{}{WIRE_HEADER_END}", encoding.tag())));
+            assert_eq!(decode_wire::<After>(&encoded), Ok(After {name: "deck".to_string(), tags: vec!["spanish".to_string()]}));
+        }
+    }
+
+    #[test]
+    fn older_json_is_migrated_with_defaults() {
+        let encoded = with_version(&encode_wire(&Before {name: "deck".to_string()}, WireEncoding::Json), 1);
+        assert_eq!(decode_wire::<After>(&encoded), Ok(After {name: "deck".to_string(), tags: Vec::new()}));
+    }
+
+    #[test]
+    fn older_postcard_is_rejected() {
+        let encoded = with_version(&encode_wire(&Before {name: "deck".to_string()}, WireEncoding::Postcard), 1);
+        assert!(decode_wire::<After>(&encoded).is_err());
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        for encoding in [WireEncoding::Json, WireEncoding::Postcard] {
+            let encoded = with_version(&encode_wire(&After::default(), encoding), WIRE_FORMAT_VERSION + 1);
+            assert!(decode_wire::<After>(&encoded).is_err());
+        }
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        for input in ["", "{\"name\":\"deck\"}", "lex2j{}", "lex:{}", "lexj:{}", "lex2x:{}"] {
+            assert!(decode_wire::<After>(input).is_err(), "{input}");
+        }
+    }
+}